@@ -0,0 +1,72 @@
+//! Measures the throughput of the readers on a large generated document.
+//!
+//! Run with `cargo bench`; the size of the document in megabytes can be given as an argument.
+
+extern crate xml;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use xml::reader::{EventReader, SliceReader, XmlEvent, BorrowedEvent};
+
+fn generate(size: usize) -> String {
+    let mut doc = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<catalog xmlns:x=\"urn:example\">\n");
+    let mut i = 0;
+    while doc.len() < size {
+        doc.push_str(&format!(
+            "  <x:book id=\"b{}\" lang=\"en\">\n    <title>Book number {} &amp; friends</title>\n    \
+             <summary><![CDATA[Some <unparsed> text]]> and a long run of character data which \
+             makes the document look like a real one, with \u{444}\u{430}\u{439}\u{43b} names</summary>\n    \
+             <!-- comment {} -->\n  </x:book>\n", i, i, i));
+        i += 1;
+    }
+    doc.push_str("</catalog>\n");
+    doc
+}
+
+fn report(name: &str, bytes: usize, events: usize, time: Duration) {
+    let secs = time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9;
+    println!("{:<40} {:>8} events {:>10.1} MB/s", name, events, bytes as f64 / secs / 1e6);
+}
+
+fn count_events<R: Read>(reader: EventReader<R>) -> usize {
+    let mut count = 0;
+    for event in reader {
+        match event.unwrap() {
+            XmlEvent::EndDocument => break,
+            _ => count += 1
+        }
+    }
+    count
+}
+
+fn main() {
+    let megabytes = env::args().skip(1).filter_map(|arg| arg.parse().ok()).next().unwrap_or(16);
+    let doc = generate(megabytes * 1000 * 1000);
+    let bytes = doc.len();
+
+    let start = Instant::now();
+    let events = count_events(EventReader::new(doc.as_bytes()));
+    report("EventReader, &[u8]", bytes, events, start.elapsed());
+
+    // a file is read with a system call for every `read()`, which shows the cost
+    // of reading the source in small pieces
+    let path = env::temp_dir().join("xml-rs-bench-reader.xml");
+    File::create(&path).unwrap().write_all(doc.as_bytes()).unwrap();
+    let start = Instant::now();
+    let events = count_events(EventReader::new(File::open(&path).unwrap()));
+    report("EventReader, unbuffered File", bytes, events, start.elapsed());
+    fs::remove_file(&path).unwrap();
+
+    let start = Instant::now();
+    let mut events = 0;
+    for event in SliceReader::from_str(&doc) {
+        match event.unwrap() {
+            BorrowedEvent::EndDocument => break,
+            _ => events += 1
+        }
+    }
+    report("SliceReader", bytes, events, start.elapsed());
+}
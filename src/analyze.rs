@@ -2,7 +2,7 @@ extern crate xml;
 
 use std::cmp;
 use std::env;
-use std::io::{self, Read, Write, BufReader};
+use std::io::{self, Read, Write};
 use std::fs::File;
 use std::collections::HashSet;
 
@@ -35,7 +35,7 @@ fn main() {
     let reader = ParserConfig::new()
         .whitespace_to_characters(true)
         .ignore_comments(false)
-        .create_reader(BufReader::new(source));
+        .create_reader(source);
 
     let mut processing_instructions = 0;
     let mut elements = 0;
@@ -57,6 +57,11 @@ fn main() {
                         version, encoding, if standalone.unwrap_or(false) { "" } else { "not " }
                     ),
                 XmlEvent::EndDocument => println!("Document finished"),
+                XmlEvent::Doctype { name, dtd, .. } =>
+                    println!(
+                        "Document type {}, {} element types and {} entities declared internally",
+                        name, dtd.elements.len(), dtd.entities.len()
+                    ),
                 XmlEvent::ProcessingInstruction { .. } => processing_instructions += 1,
                 XmlEvent::Whitespace(_) => {}  // can't happen due to configuration
                 XmlEvent::Characters(s) => {
@@ -30,7 +30,8 @@ impl<'a> Attribute<'a> {
     pub fn to_owned(&self) -> OwnedAttribute {
         OwnedAttribute {
             name: self.name.into(),
-            value: self.value.into()
+            value: self.value.into(),
+            defaulted: false
         }
     }
 
@@ -46,14 +47,19 @@ impl<'a> Attribute<'a> {
 
 /// An owned version of an XML attribute.
 ///
-/// Consists of an owned qualified name and an owned string value.
+/// Consists of an owned qualified name, an owned string value and a flag telling
+/// whether the attribute was specified in the document.
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct OwnedAttribute {
     /// Attribute name.
     pub name: OwnedName,
 
     /// Attribute value.
-    pub value: String
+    pub value: String,
+
+    /// Whether the attribute was not specified in the start tag, but added by the parser
+    /// from a default value declared in the DTD.
+    pub defaulted: bool
 }
 
 impl OwnedAttribute {
@@ -70,7 +76,8 @@ impl OwnedAttribute {
     pub fn new<S: Into<String>>(name: OwnedName, value: S) -> OwnedAttribute {
         OwnedAttribute {
             name: name,
-            value: value.into()
+            value: value.into(),
+            defaulted: false
         }
     }
 }
@@ -53,6 +53,61 @@ impl fmt::Display for TextPosition {
     }
 }
 
+/// Represents a range of bytes in a document: `start` is the offset of its first byte,
+/// and `end` is the offset of the byte following it.
+///
+/// Offsets count bytes of the input stream from its beginning, including the byte order mark.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    /// Offset of the first byte
+    pub start: u64,
+    /// Offset of the byte following the last one
+    pub end: u64
+}
+
+impl Span {
+    /// Creates an empty span at the given offset
+    #[inline]
+    pub fn empty(offset: u64) -> Span {
+        Span { start: offset, end: offset }
+    }
+
+    /// Returns the number of bytes in the span
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Checks if the span contains no bytes
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl fmt::Debug for Span {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+impl fmt::Display for Span {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// Spans of an attribute in an opening tag.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AttributeSpan {
+    /// Span of the qualified name of the attribute
+    pub name: Span,
+    /// Span of the value of the attribute, without the quotes
+    pub value: Span
+}
+
 /// Get the position in the document corresponding to the object
 ///
 /// This trait is implemented by parsers, lexers and errors.
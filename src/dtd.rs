@@ -0,0 +1,254 @@
+//! Contains types which describe a document type definition (DTD).
+//!
+//! Instances of these types are produced by the parser when it reads a `<!DOCTYPE>`
+//! declaration; see `reader::XmlEvent::Doctype`.
+
+use std::collections::HashMap;
+
+/// A document type definition.
+///
+/// Contains all markup declarations which were read from the internal subset
+/// of the document type declaration. Declarations are keyed by the names they
+/// declare; DTDs are not namespace-aware, so all names are stored exactly as they
+/// were written, prefixes included.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Dtd {
+    /// Element type declarations (`<!ELEMENT>`), keyed by element name.
+    pub elements: HashMap<String, ElementDecl>,
+
+    /// Attribute definitions (`<!ATTLIST>`), keyed by element name.
+    ///
+    /// Definitions from all attribute list declarations for the same element are merged
+    /// in the order of their appearance. When the same attribute is defined more than once,
+    /// only the first definition is kept, as required by the XML specification.
+    pub attributes: HashMap<String, Vec<AttributeDecl>>,
+
+    /// General entity declarations (`<!ENTITY name ...>`), keyed by entity name.
+    pub entities: HashMap<String, EntityDecl>,
+
+    /// Parameter entity declarations (`<!ENTITY % name ...>`), keyed by entity name.
+    pub parameter_entities: HashMap<String, EntityDecl>,
+
+    /// Notation declarations (`<!NOTATION>`), keyed by notation name.
+    pub notations: HashMap<String, NotationDecl>,
+}
+
+impl Dtd {
+    /// Returns an empty document type definition.
+    #[inline]
+    pub fn new() -> Dtd {
+        Dtd::default()
+    }
+
+    /// Returns the declaration of the given element type, if it is present.
+    #[inline]
+    pub fn element(&self, name: &str) -> Option<&ElementDecl> {
+        self.elements.get(name)
+    }
+
+    /// Returns the definition of the attribute `attribute` of the element `element`,
+    /// if it is present.
+    pub fn attribute(&self, element: &str, attribute: &str) -> Option<&AttributeDecl> {
+        self.attributes.get(element)
+            .and_then(|attrs| attrs.iter().find(|a| a.name == attribute))
+    }
+
+    /// Returns the declaration of the given general entity, if it is present.
+    #[inline]
+    pub fn entity(&self, name: &str) -> Option<&EntityDecl> {
+        self.entities.get(name)
+    }
+
+    /// Returns the declaration of the given parameter entity, if it is present.
+    #[inline]
+    pub fn parameter_entity(&self, name: &str) -> Option<&EntityDecl> {
+        self.parameter_entities.get(name)
+    }
+
+    /// Returns the declaration of the given notation, if it is present.
+    #[inline]
+    pub fn notation(&self, name: &str) -> Option<&NotationDecl> {
+        self.notations.get(name)
+    }
+
+    /// Checks whether this DTD does not contain any declarations.
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty() && self.attributes.is_empty() && self.entities.is_empty() &&
+        self.parameter_entities.is_empty() && self.notations.is_empty()
+    }
+}
+
+/// An element type declaration, e.g. `<!ELEMENT p (#PCDATA|em)*>`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ElementDecl {
+    /// Element name.
+    pub name: String,
+
+    /// Allowed content of the element.
+    pub content: ContentSpec,
+}
+
+/// A content specification of an element type declaration.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ContentSpec {
+    /// `EMPTY`: the element must not have any content.
+    Empty,
+
+    /// `ANY`: the element may contain any declared elements and character data.
+    Any,
+
+    /// Mixed content, e.g. `(#PCDATA|a|b)*`.
+    ///
+    /// Contains names of the elements which may appear among the character data, if any.
+    Mixed(Vec<String>),
+
+    /// Element content, e.g. `(head, body)`.
+    Children(ContentParticle),
+}
+
+/// A content particle of an element content model.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ContentParticle {
+    /// What this particle matches.
+    pub kind: ParticleKind,
+
+    /// How many times this particle may occur.
+    pub occurrence: Occurrence,
+}
+
+/// A kind of a content particle.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ParticleKind {
+    /// A single element with the given name.
+    Name(String),
+
+    /// A sequence of particles, e.g. `(a, b, c)`.
+    Seq(Vec<ContentParticle>),
+
+    /// A choice between particles, e.g. `(a | b | c)`.
+    Choice(Vec<ContentParticle>),
+}
+
+/// An occurrence indicator of a content particle.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Occurrence {
+    /// No indicator: exactly one occurrence.
+    Once,
+
+    /// `?`: zero or one occurrence.
+    Optional,
+
+    /// `*`: zero or more occurrences.
+    ZeroOrMore,
+
+    /// `+`: one or more occurrences.
+    OneOrMore,
+}
+
+/// An attribute definition from an attribute list declaration,
+/// e.g. `id ID #REQUIRED` in `<!ATTLIST p id ID #REQUIRED>`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AttributeDecl {
+    /// Attribute name.
+    pub name: String,
+
+    /// Attribute type.
+    pub attribute_type: AttributeType,
+
+    /// Default declaration of the attribute.
+    pub default: AttributeDefault,
+}
+
+/// An attribute type.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AttributeType {
+    /// `CDATA`: arbitrary character data.
+    CData,
+    /// `ID`: a unique identifier.
+    Id,
+    /// `IDREF`: a reference to an identifier.
+    IdRef,
+    /// `IDREFS`: space-separated references to identifiers.
+    IdRefs,
+    /// `ENTITY`: a name of an unparsed entity.
+    Entity,
+    /// `ENTITIES`: space-separated names of unparsed entities.
+    Entities,
+    /// `NMTOKEN`: a name token.
+    NmToken,
+    /// `NMTOKENS`: space-separated name tokens.
+    NmTokens,
+    /// `NOTATION (a|b)`: one of the listed notation names.
+    Notation(Vec<String>),
+    /// `(a|b)`: one of the listed name tokens.
+    Enumeration(Vec<String>),
+}
+
+/// A default declaration of an attribute.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AttributeDefault {
+    /// `#REQUIRED`: the attribute must always be specified.
+    Required,
+
+    /// `#IMPLIED`: the attribute is optional and has no default value.
+    Implied,
+
+    /// `#FIXED "value"`: the attribute must always have the given value.
+    Fixed(String),
+
+    /// `"value"`: the attribute is optional and has the given default value.
+    Value(String),
+}
+
+/// A general or a parameter entity declaration.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EntityDecl {
+    /// Entity name.
+    pub name: String,
+
+    /// Entity definition.
+    pub definition: EntityDef,
+}
+
+/// A definition of an entity.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EntityDef {
+    /// An internal entity with the given replacement text.
+    ///
+    /// Character references in the entity value are already replaced with the corresponding
+    /// characters, while references to general entities are left intact, as prescribed
+    /// by the XML specification.
+    Internal(String),
+
+    /// An external entity.
+    External {
+        /// Public and system identifiers of the entity.
+        id: ExternalId,
+
+        /// The notation of an unparsed entity (`NDATA`), if any.
+        notation: Option<String>,
+    },
+}
+
+/// An external identifier, e.g. `PUBLIC "-//W3C//DTD XHTML 1.0 Strict//EN" "xhtml1-strict.dtd"`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ExternalId {
+    /// Public identifier, if any.
+    pub public_id: Option<String>,
+
+    /// System identifier.
+    pub system_id: String,
+}
+
+/// A notation declaration, e.g. `<!NOTATION gif SYSTEM "image/gif">`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NotationDecl {
+    /// Notation name.
+    pub name: String,
+
+    /// Public identifier, if any.
+    pub public_id: Option<String>,
+
+    /// System identifier, if any.
+    pub system_id: Option<String>,
+}
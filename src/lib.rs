@@ -7,6 +7,8 @@
 
 #[macro_use]
 extern crate bitflags;
+#[cfg(feature = "async")]
+extern crate futures_io;
 
 pub use reader::EventReader;
 pub use reader::ParserConfig;
@@ -19,6 +21,11 @@ pub mod attribute;
 pub mod common;
 pub mod escape;
 pub mod namespace;
+pub mod dtd;
+pub mod encoding;
 pub mod reader;
 pub mod writer;
+pub mod sax;
+pub mod schema;
+pub mod relaxng;
 mod util;
@@ -0,0 +1,131 @@
+//! Contains `AsyncEventReader`, a pull parser reading from an asynchronous stream.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_io::AsyncRead;
+
+use common::{Position, TextPosition, Span, AttributeSpan};
+use reader::{ParserConfig, XmlEvent, Result};
+use reader::parser::PullParser;
+use reader::push::Input;
+
+const BUFFER_SIZE: usize = 8192;
+
+/// A wrapper around a `futures_io::AsyncRead` instance which provides pull-based XML parsing
+/// without blocking.
+///
+/// This type is only available with the `async` feature. It uses the same parser as
+/// `EventReader` and produces the same events and errors; when the stream has no data
+/// available, parsing is suspended and continues from the same place once the stream
+/// is readable again.
+///
+/// ```rust,ignore
+/// let mut reader = AsyncEventReader::new(socket);
+/// loop {
+///     match reader.next().await? {
+///         XmlEvent::EndDocument => break,
+///         e => println!("{:?}", e)
+///     }
+/// }
+/// ```
+pub struct AsyncEventReader<R> {
+    source: R,
+    buf: Box<[u8]>,
+    input: Input,
+    parser: PullParser
+}
+
+impl<R: AsyncRead + Unpin> AsyncEventReader<R> {
+    /// Creates a new reader, consuming the given stream.
+    #[inline]
+    pub fn new(source: R) -> AsyncEventReader<R> {
+        AsyncEventReader::new_with_config(source, ParserConfig::new())
+    }
+
+    /// Creates a new reader with the provded configuration, consuming the given stream.
+    pub fn new_with_config(source: R, config: ParserConfig) -> AsyncEventReader<R> {
+        AsyncEventReader {
+            source: source,
+            buf: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            input: Input::new(),
+            parser: PullParser::new(config)
+        }
+    }
+
+    /// Returns a future which resolves to the next XML event from the stream.
+    ///
+    /// If the returned event is `XmlEvent::EndDocument` or an error, then further calls
+    /// to this method will return it again.
+    #[inline]
+    pub fn next(&mut self) -> NextEvent<R> {
+        NextEvent { reader: self }
+    }
+
+    /// Attempts to read the next XML event from the stream, registering the current task
+    /// to be woken up if the stream has no data available yet.
+    pub fn poll_next(&mut self, cx: &mut Context) -> Poll<Result<XmlEvent>> {
+        loop {
+            match self.parser.next(&mut self.input) {
+                Err(ref e) if e.is_would_block() => {}
+                result => return Poll::Ready(result)
+            }
+            match Pin::new(&mut self.source).poll_read(cx, &mut self.buf) {
+                Poll::Ready(Ok(0)) => self.input.eof = true,
+                Poll::Ready(Ok(n)) => self.input.push(&self.buf[..n]),
+                Poll::Ready(Err(e)) => self.input.error = Some(e),
+                Poll::Pending => return Poll::Pending
+            }
+        }
+    }
+
+    /// Returns the span of bytes of the stream taken by the last event; see
+    /// `EventReader::span()`.
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.parser.span()
+    }
+
+    /// Returns the spans of the attributes of the last event; see
+    /// `EventReader::attribute_spans()`.
+    #[inline]
+    pub fn attribute_spans(&self) -> &[AttributeSpan] {
+        self.parser.attribute_spans()
+    }
+
+    pub fn source(&self) -> &R { &self.source }
+    pub fn source_mut(&mut self) -> &mut R { &mut self.source }
+
+    /// Unwraps this `AsyncEventReader`, returning the underlying stream.
+    ///
+    /// Since the stream is read in chunks, the returned stream may be positioned after
+    /// the end of the last event.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+}
+
+impl<R> Position for AsyncEventReader<R> {
+    /// Returns the position of the last event produced by the reader.
+    #[inline]
+    fn position(&self) -> TextPosition {
+        self.parser.position()
+    }
+}
+
+/// A future which resolves to the next event of an `AsyncEventReader`.
+///
+/// It is returned by `AsyncEventReader::next()`.
+pub struct NextEvent<'a, R: 'a> {
+    reader: &'a mut AsyncEventReader<R>
+}
+
+impl<'a, R: AsyncRead + Unpin> Future for NextEvent<'a, R> {
+    type Output = Result<XmlEvent>;
+
+    #[inline]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<XmlEvent>> {
+        self.reader.poll_next(cx)
+    }
+}
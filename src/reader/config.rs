@@ -2,7 +2,9 @@
 use std::io::Read;
 use std::collections::HashMap;
 
+use encoding::SingleByteEncoding;
 use reader::EventReader;
+use reader::resolver::{EntityResolver, ResolverHandle};
 
 /// Parser configuration structure.
 ///
@@ -74,7 +76,76 @@ pub struct ParserConfig {
     ///
     /// Note that support for this functionality is incomplete; for example, the parser will fail if
     /// the premature end of stream happens inside PCDATA. Therefore, use this option at your own risk.
-    pub ignore_end_of_stream: bool
+    /// `PushReader` handles documents whose input is supplied progressively properly.
+    pub ignore_end_of_stream: bool,
+
+    /// A resolver used to load external entities and the external DTD subset. Default is `None`.
+    ///
+    /// When no resolver is set, the parser never tries to access external entities: the external
+    /// DTD subset is skipped, and references to external general entities are reported as errors.
+    /// See `entity_resolver()` method and the `EntityResolver` trait.
+    pub entity_resolver: Option<ResolverHandle>,
+
+    /// A map of extra single-byte encodings recognized by the parser, keyed by their names
+    /// as they are written in XML and text declarations. Default is an empty map.
+    ///
+    /// Names are compared ignoring ASCII case, and encodings in this map take precedence
+    /// over the built-in ones, see `SingleByteEncoding::for_label()`.
+    pub encodings: HashMap<String, SingleByteEncoding>,
+
+    /// The maximum total number of characters which may be produced by expanding entities
+    /// declared in the DTD, in the whole document. Default is 10 000 000.
+    ///
+    /// Entity references which are expanded inside other entities count too, so this limit
+    /// bounds the work done on documents like the "billion laughs" attack. The text of each
+    /// external entity and of the external DTD subset may not be longer either. When the limit
+    /// is exceeded, the parser fails with `ErrorKind::EntityExpansionLimit` error.
+    pub max_entity_expansion_size: usize,
+
+    /// The maximum number of nested entity expansions, i.e. the maximum depth of references
+    /// to entities inside replacement texts of other entities. Default is 32.
+    ///
+    /// When the limit is exceeded, the parser fails with `ErrorKind::EntityExpansionLimit` error.
+    pub max_entity_expansion_depth: usize,
+
+    /// The maximum ratio between the number of characters produced by expanding entities
+    /// and the number of characters read from the document itself. Default is 100.
+    ///
+    /// The ratio is only checked once entity expansion has produced more than 100 000 characters,
+    /// so small documents are free to use entities with long replacement texts. When the limit
+    /// is exceeded, the parser fails with `ErrorKind::EntityExpansionLimit` error.
+    pub max_entity_expansion_ratio: usize,
+
+    /// Whether or not the document should be validated against its DTD. Default is false.
+    ///
+    /// When this option is enabled, the parser checks element content models, attribute
+    /// declarations, required and fixed attributes, enumerated attribute values, uniqueness
+    /// of IDs and targets of IDREFs while reading the document, and fails with
+    /// `ErrorKind::Validity` error at the first violation. Documents without a `<!DOCTYPE>`
+    /// declaration are invalid in this mode.
+    pub validate_dtd: bool,
+
+    /// The maximum number of events which `EventReader::peek_nth()` may read ahead.
+    /// Default is 64.
+    ///
+    /// Events which are read ahead are kept in memory until they are returned by `next()`,
+    /// so this limit bounds the memory used for them. When more events are requested,
+    /// `peek_nth()` fails with `ErrorKind::LookaheadLimit` error, and the reader can still
+    /// be used.
+    pub max_lookahead: usize,
+
+    /// Whether or not attribute values should be normalized. Default is true.
+    ///
+    /// When true, each whitespace character written literally in an attribute value or in
+    /// the replacement text of an entity referenced from it, including default values declared
+    /// in the DTD, is replaced with a space, as described in section 3.3.3 of the XML
+    /// specification; whitespace produced by character references like `&#10;` is kept as is.
+    /// When false, attribute values keep tabs and line breaks as they appear in the document.
+    ///
+    /// Values of attributes declared in the DTD with a type other than `CDATA` are
+    /// additionally trimmed, and sequences of spaces in them are collapsed, regardless
+    /// of this option; when it is false, tabs and line breaks are collapsed as well.
+    pub normalize_attribute_values: bool
 }
 
 impl ParserConfig {
@@ -98,7 +169,15 @@ impl ParserConfig {
             ignore_comments: true,
             coalesce_characters: true,
             extra_entities: HashMap::new(),
-            ignore_end_of_stream: false
+            ignore_end_of_stream: false,
+            entity_resolver: None,
+            encodings: HashMap::new(),
+            max_entity_expansion_size: 10000000,
+            max_entity_expansion_depth: 32,
+            max_entity_expansion_ratio: 100,
+            validate_dtd: false,
+            max_lookahead: 64,
+            normalize_attribute_values: true
         }
     }
 
@@ -145,6 +224,47 @@ impl ParserConfig {
         self.extra_entities.insert(entity.into(), value.into());
         self
     }
+
+    /// Sets the resolver for external entities and returns an updated config object.
+    ///
+    /// The resolver is asked to provide the contents of the external DTD subset and of each
+    /// external parsed entity referenced in the document:
+    ///
+    /// ```rust
+    /// use xml::reader::{ParserConfig, DirectoryResolver};
+    ///
+    /// let mut source: &[u8] = b"...";
+    ///
+    /// let reader = ParserConfig::new()
+    ///     .entity_resolver(DirectoryResolver::new("schemas"))
+    ///     .create_reader(&mut source);
+    /// ```
+    pub fn entity_resolver<R>(mut self, resolver: R) -> ParserConfig
+        where R: EntityResolver + Send + Sync + 'static
+    {
+        self.entity_resolver = Some(ResolverHandle::new(resolver));
+        self
+    }
+
+    /// Adds a single-byte encoding and returns an updated config object.
+    ///
+    /// The parser switches to the encoding when the XML declaration of the document, or
+    /// the text declaration of an external entity, declares an encoding with this name:
+    ///
+    /// ```rust
+    /// use xml::reader::ParserConfig;
+    /// use xml::encoding::SingleByteEncoding;
+    ///
+    /// let mut source: &[u8] = b"...";
+    ///
+    /// let reader = ParserConfig::new()
+    ///     .add_encoding("latin-9", SingleByteEncoding::for_label("ISO-8859-15").unwrap())
+    ///     .create_reader(&mut source);
+    /// ```
+    pub fn add_encoding<S: Into<String>>(mut self, name: S, encoding: SingleByteEncoding) -> ParserConfig {
+        self.encodings.insert(name.into(), encoding);
+        self
+    }
 }
 
 impl Default for ParserConfig {
@@ -154,11 +274,50 @@ impl Default for ParserConfig {
     }
 }
 
+/// The number of characters produced by entity expansion after which
+/// `ParserConfig::max_entity_expansion_ratio` is enforced.
+static ENTITY_EXPANSION_RATIO_THRESHOLD: usize = 100000;
+
+/// Checks that producing `expanded` characters in total by entity expansion, with `depth`
+/// nested entities being expanded at the moment and `read` characters read from the document,
+/// does not exceed the limits set in `config`; returns an error message otherwise.
+pub fn entity_expansion_error(config: &ParserConfig, expanded: usize, depth: usize, read: usize) -> Option<String> {
+    if depth > config.max_entity_expansion_depth {
+        Some(format!("Entity expansion depth limit of {} exceeded", config.max_entity_expansion_depth))
+    } else if expanded > config.max_entity_expansion_size {
+        Some(entity_size_error(config))
+    } else if expanded > ENTITY_EXPANSION_RATIO_THRESHOLD &&
+              expanded > read.saturating_mul(config.max_entity_expansion_ratio) {
+        Some(format!("Entity expansion ratio limit of {} exceeded", config.max_entity_expansion_ratio))
+    } else {
+        None
+    }
+}
+
+/// Returns the error message reported when entity expansion exceeds the size limit set in `config`.
+pub fn entity_size_error(config: &ParserConfig) -> String {
+    format!("Entity expansion size limit of {} characters exceeded", config.max_entity_expansion_size)
+}
+
+/// Finds a single-byte encoding with the given name, either added to `config` or built-in.
+pub fn find_encoding(config: &ParserConfig, name: &str) -> Option<SingleByteEncoding> {
+    config.encodings.iter()
+        .find(|&(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, e)| e.clone())
+        .or_else(|| SingleByteEncoding::for_label(name))
+}
+
 gen_setters! { ParserConfig,
     trim_whitespace: val bool,
     whitespace_to_characters: val bool,
     cdata_to_characters: val bool,
     ignore_comments: val bool,
     coalesce_characters: val bool,
-    ignore_end_of_stream: val bool
+    ignore_end_of_stream: val bool,
+    max_entity_expansion_size: val usize,
+    max_entity_expansion_depth: val usize,
+    max_entity_expansion_ratio: val usize,
+    validate_dtd: val bool,
+    max_lookahead: val usize,
+    normalize_attribute_values: val bool
 }
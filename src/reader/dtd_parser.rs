@@ -0,0 +1,1325 @@
+//! Contains a parser for document type declarations.
+//!
+//! This module is for internal use. The pull parser collects the text of a `<!DOCTYPE>`
+//! declaration and hands it over to `DtdParser`, which turns it into a `dtd::Dtd` model.
+
+use std::borrow::Cow;
+use std::result;
+
+use common::{Position, TextPosition, is_whitespace_char, is_name_start_char, is_name_char};
+use dtd::{
+    Dtd, ElementDecl, ContentSpec, ContentParticle, ParticleKind, Occurrence,
+    AttributeDecl, AttributeType, AttributeDefault, EntityDecl, EntityDef, ExternalId,
+    NotationDecl
+};
+use name::OwnedName;
+use reader::Result;
+use reader::config::{self, ParserConfig};
+use reader::resolver;
+use reader::error;
+use util;
+
+/// Contents of a document type declaration.
+pub struct Doctype {
+    pub name: String,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+    pub dtd: Dtd
+}
+
+/// A text the DTD parser reads from: the parsed text itself or the replacement text
+/// of a parameter entity.
+struct Input<'a> {
+    text: Cow<'a, str>,
+    offset: usize,
+
+    /// The name of the parameter entity this replacement text belongs to.
+    entity: Option<String>,
+
+    /// Whether the text comes from the external subset or an external parameter entity,
+    /// where parameter entity references are allowed inside markup declarations.
+    external: bool,
+
+    /// Whether the text was included inside a markup declaration or a literal. Such inputs
+    /// are left as soon as they end, while a parameter entity referenced between declarations
+    /// must contain complete declarations.
+    nested: bool
+}
+
+impl<'a> Input<'a> {
+    #[inline]
+    fn is_finished(&self) -> bool {
+        self.offset == self.text.len()
+    }
+}
+
+/// The place where a parameter entity reference occurs.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Inclusion {
+    BetweenDeclarations,
+    InDeclaration,
+    InLiteral
+}
+
+/// A recursive descent parser for the text of a document type declaration.
+///
+/// The parser keeps track of its position in the document, so errors are reported
+/// relative to the beginning of the declaration. Errors inside replacement texts
+/// of parameter entities are reported at the end of the reference.
+pub struct DtdParser<'a> {
+    inputs: Vec<Input<'a>>,
+    pos: TextPosition,
+    dtd: Dtd,
+    config: &'a ParserConfig,
+
+    /// The number of characters produced by parameter entity expansion so far.
+    expanded_chars: usize,
+
+    /// The number of characters of the parsed text read so far, which entity expansion
+    /// limits are relative to.
+    chars_read: usize,
+
+    /// Set when a parameter entity reference is skipped; as required by the XML specification,
+    /// after that no attribute list and entity declarations are processed.
+    skip_declarations: bool
+}
+
+impl<'a> Position for DtdParser<'a> {
+    #[inline]
+    fn position(&self) -> TextPosition { self.pos }
+}
+
+impl<'a> DtdParser<'a> {
+    /// Creates a new parser over `src`, whose first character is located at `pos`.
+    ///
+    /// External parameter entities are loaded with the entity resolver set in `config`,
+    /// and its entity expansion limits apply to parameter entities.
+    pub fn new(src: &'a str, pos: TextPosition, config: &'a ParserConfig) -> DtdParser<'a> {
+        DtdParser {
+            inputs: vec![Input { text: Cow::Borrowed(src), offset: 0, entity: None, external: false, nested: false }],
+            pos: pos,
+            dtd: Dtd::new(),
+            config: config,
+            expanded_chars: 0,
+            chars_read: 0,
+            skip_declarations: false
+        }
+    }
+
+    /// Parses the text between `<!DOCTYPE` and the closing `>`.
+    pub fn parse_doctype(mut self) -> Result<Doctype> {
+        try!(self.expect_whitespace("DOCTYPE declaration"));
+        let name = try!(self.read_name("DOCTYPE declaration"));
+
+        let (public_id, system_id) = if self.skip_whitespace() &&
+                                        (self.looking_at("SYSTEM") || self.looking_at("PUBLIC")) {
+            let id = try!(self.read_external_id(false));
+            self.skip_whitespace();
+            id
+        } else {
+            (None, None)
+        };
+
+        if self.eat("[") {
+            try!(self.parse_internal_subset());
+            self.bump();  // ']'
+            self.skip_whitespace();
+        }
+
+        match self.peek() {
+            None => Ok(Doctype {
+                name: name,
+                public_id: public_id,
+                system_id: system_id,
+                dtd: self.dtd
+            }),
+            Some(_) => self.unexpected("DOCTYPE declaration")
+        }
+    }
+
+    /// Parses the text of an external DTD subset, adding its declarations to `dtd`.
+    ///
+    /// Declarations from the internal subset must already be in `dtd`, so they take
+    /// precedence over the external ones.
+    pub fn parse_external_subset(mut self, dtd: Dtd) -> Result<Dtd> {
+        self.dtd = dtd;
+        self.inputs[0].external = true;
+        try!(self.parse_markup_declarations(false));
+        Ok(self.dtd)
+    }
+
+    fn parse_internal_subset(&mut self) -> Result<()> {
+        self.parse_markup_declarations(true)
+    }
+
+    fn parse_markup_declarations(&mut self, internal: bool) -> Result<()> {
+        let ctx = if internal { "internal DTD subset" } else { "external DTD subset" };
+        let mut open_sections = 0;
+        loop {
+            self.skip_whitespace();
+            self.leave_nested_inputs();
+            if self.inputs.len() > 1 {
+                let finished = {
+                    let input = self.inputs.last().unwrap();
+                    if input.nested {
+                        return self.error(format!("Parameter entity {} does not contain complete markup declarations",
+                                                  input.entity.as_ref().unwrap()));
+                    }
+                    input.is_finished()
+                };
+                if finished {
+                    self.inputs.pop();
+                    continue;
+                }
+            }
+
+            let top_level = self.inputs.len() == 1;
+            match self.peek() {
+                Some(']') if internal && top_level => return Ok(()),
+                None if !internal && top_level && open_sections == 0 => return Ok(()),
+                None if !internal && top_level => return self.unexpected("conditional section"),
+                Some(']') if open_sections > 0 && self.looking_at("]]>") => {
+                    self.eat("]]>");
+                    open_sections -= 1;
+                }
+                Some('%') => try!(self.include_parameter_entity(Inclusion::BetweenDeclarations)),
+                Some('<') if self.looking_at("<!--") => try!(self.parse_comment()),
+                Some('<') if self.looking_at("<?") => try!(self.parse_processing_instruction()),
+                Some('<') if self.looking_at("<!ELEMENT") => try!(self.parse_element_decl()),
+                Some('<') if self.looking_at("<!ATTLIST") => try!(self.parse_attlist_decl()),
+                Some('<') if self.looking_at("<!ENTITY") => try!(self.parse_entity_decl()),
+                Some('<') if self.looking_at("<!NOTATION") => try!(self.parse_notation_decl()),
+                Some('<') if self.looking_at("<![") => if try!(self.parse_conditional_section()) {
+                    open_sections += 1;
+                },
+                Some('<') => return self.error("Unexpected markup declaration inside DOCTYPE"),
+                _ => return self.unexpected(ctx)
+            }
+        }
+    }
+
+    /// Reads a parameter entity reference and includes the replacement text of the entity
+    /// in place of it.
+    ///
+    /// Outside of literals the replacement text is enlarged by a leading and a trailing space,
+    /// as required by the XML specification. References to undeclared entities and to external
+    /// entities refused by the entity resolver are skipped.
+    fn include_parameter_entity(&mut self, inclusion: Inclusion) -> Result<()> {
+        const CTX: &'static str = "parameter entity reference";
+
+        self.bump();  // '%'
+        let name = try!(self.read_name(CTX));
+        try!(self.expect(";", CTX));
+
+        if self.inputs.iter().any(|i| i.entity.as_ref() == Some(&name)) {
+            return self.error(format!("Recursive parameter entity reference: {}", name));
+        }
+        let replacement = match self.dtd.parameter_entity(&name).map(|e| &e.definition) {
+            Some(&EntityDef::Internal(ref text)) => Some((text.clone(), self.input().external)),
+            Some(&EntityDef::External { ref id, .. }) => {
+                let text = try!(resolver::load_entity(self, self.config, id.public_id.as_ref().map(|s| &s[..]),
+                                                      &id.system_id));
+                text.map(|text| (text, true))
+            }
+            None => None
+        };
+        let (text, external) = match replacement {
+            Some(replacement) => replacement,
+            None => {
+                // Subsequent declarations could depend on something we have not read
+                self.skip_declarations = true;
+                return Ok(());
+            }
+        };
+
+        self.expanded_chars += text.chars().count();
+        let depth = self.inputs.iter().filter(|i| i.entity.is_some()).count() + 1;
+        if let Some(msg) = config::entity_expansion_error(self.config, self.expanded_chars, depth, self.chars_read) {
+            return Err(error::entity_expansion_limit(self, msg));
+        }
+
+        let text = if inclusion == Inclusion::InLiteral { text } else { format!(" {} ", text) };
+        self.inputs.push(Input {
+            text: Cow::Owned(text),
+            offset: 0,
+            entity: Some(name),
+            external: external,
+            nested: inclusion != Inclusion::BetweenDeclarations
+        });
+        Ok(())
+    }
+
+    /// Parses the beginning of a conditional section up to the opening bracket of its contents,
+    /// returning true if the contents should be included. Ignored sections are skipped entirely.
+    fn parse_conditional_section(&mut self) -> Result<bool> {
+        const CTX: &'static str = "conditional section";
+
+        if !self.input().external {
+            return self.error("Conditional sections are not allowed in the internal subset");
+        }
+        self.eat("<![");
+        try!(self.skip_separators());
+        let keyword = try!(self.read_name(CTX));
+        try!(self.skip_separators());
+        try!(self.expect("[", CTX));
+
+        match &keyword[..] {
+            "INCLUDE" => Ok(true),
+            "IGNORE" => {
+                // Ignored sections may contain nested conditional sections
+                let mut depth = 1;
+                while depth > 0 {
+                    if self.eat("<![") {
+                        depth += 1;
+                    } else if self.eat("]]>") {
+                        depth -= 1;
+                    } else if self.bump().is_none() {
+                        return self.unexpected(CTX);
+                    }
+                }
+                Ok(false)
+            }
+            other => self.error(format!("Unexpected conditional section keyword: {}", other))
+        }
+    }
+
+    fn parse_comment(&mut self) -> Result<()> {
+        self.eat("<!--");
+        loop {
+            if self.eat("-->") {
+                return Ok(());
+            }
+            if self.looking_at("--") {
+                return self.error("Unexpected token inside a comment: --");
+            }
+            if self.bump().is_none() {
+                return self.unexpected("comment");
+            }
+        }
+    }
+
+    fn parse_processing_instruction(&mut self) -> Result<()> {
+        self.eat("<?");
+        let name = try!(self.read_name("processing instruction"));
+        if name.eq_ignore_ascii_case("xml") {
+            return self.error(format!("Invalid processing instruction: <?{}", name));
+        }
+        if self.eat("?>") {
+            return Ok(());
+        }
+        try!(self.expect_whitespace("processing instruction"));
+        loop {
+            if self.eat("?>") {
+                return Ok(());
+            }
+            if self.bump().is_none() {
+                return self.unexpected("processing instruction");
+            }
+        }
+    }
+
+    fn parse_element_decl(&mut self) -> Result<()> {
+        const CTX: &'static str = "element type declaration";
+
+        self.eat("<!ELEMENT");
+        try!(self.expect_whitespace(CTX));
+        let name = try!(self.read_name(CTX));
+        try!(self.expect_whitespace(CTX));
+
+        let content = if self.eat("(") {
+            try!(self.skip_separators());
+            if self.eat("#PCDATA") {
+                try!(self.parse_mixed_content())
+            } else {
+                ContentSpec::Children(try!(self.parse_content_group()))
+            }
+        } else {
+            match &try!(self.read_name(CTX))[..] {
+                "EMPTY" => ContentSpec::Empty,
+                "ANY" => ContentSpec::Any,
+                other => return self.error(format!("Unexpected content specification: {}", other))
+            }
+        };
+
+        try!(self.skip_separators());
+        try!(self.expect(">", CTX));
+
+        if !self.dtd.elements.contains_key(&name) {
+            self.dtd.elements.insert(name.clone(), ElementDecl { name: name, content: content });
+        }
+        Ok(())
+    }
+
+    /// Parses the rest of a mixed content declaration after `(#PCDATA`.
+    fn parse_mixed_content(&mut self) -> Result<ContentSpec> {
+        const CTX: &'static str = "mixed content declaration";
+
+        let mut names = Vec::new();
+        loop {
+            try!(self.skip_separators());
+            if self.eat(")") {
+                if names.is_empty() {
+                    self.eat("*");
+                } else {
+                    try!(self.expect("*", CTX));
+                }
+                return Ok(ContentSpec::Mixed(names));
+            }
+            try!(self.expect("|", CTX));
+            try!(self.skip_separators());
+            names.push(try!(self.read_name(CTX)));
+        }
+    }
+
+    /// Parses a choice or a sequence after the opening parenthesis.
+    fn parse_content_group(&mut self) -> Result<ContentParticle> {
+        const CTX: &'static str = "element content declaration";
+
+        let mut particles = vec![try!(self.parse_content_particle())];
+        let mut separator = None;
+        loop {
+            try!(self.skip_separators());
+            if self.eat(")") {
+                break;
+            }
+            match self.peek() {
+                Some(c) if (c == ',' || c == '|') && (separator.is_none() || separator == Some(c)) => {
+                    self.bump();
+                    separator = Some(c);
+                }
+                Some(',') | Some('|') =>
+                    return self.error("Cannot mix ',' and '|' in the same content particle"),
+                _ => return self.unexpected(CTX)
+            }
+            try!(self.skip_separators());
+            particles.push(try!(self.parse_content_particle()));
+        }
+
+        let kind = if separator == Some('|') {
+            ParticleKind::Choice(particles)
+        } else {
+            ParticleKind::Seq(particles)
+        };
+        Ok(ContentParticle { kind: kind, occurrence: self.read_occurrence() })
+    }
+
+    fn parse_content_particle(&mut self) -> Result<ContentParticle> {
+        try!(self.skip_separators());
+        if self.eat("(") {
+            try!(self.skip_separators());
+            self.parse_content_group()
+        } else {
+            let name = try!(self.read_name("element content declaration"));
+            Ok(ContentParticle { kind: ParticleKind::Name(name), occurrence: self.read_occurrence() })
+        }
+    }
+
+    fn read_occurrence(&mut self) -> Occurrence {
+        if self.eat("?") { Occurrence::Optional }
+        else if self.eat("*") { Occurrence::ZeroOrMore }
+        else if self.eat("+") { Occurrence::OneOrMore }
+        else { Occurrence::Once }
+    }
+
+    fn parse_attlist_decl(&mut self) -> Result<()> {
+        const CTX: &'static str = "attribute list declaration";
+
+        self.eat("<!ATTLIST");
+        try!(self.expect_whitespace(CTX));
+        let element = try!(self.read_name(CTX));
+
+        let mut decls = Vec::new();
+        loop {
+            let had_whitespace = try!(self.skip_separators());
+            if self.eat(">") {
+                break;
+            }
+            if !had_whitespace {
+                return self.unexpected(CTX);
+            }
+
+            let name = try!(self.read_name(CTX));
+            if name.parse::<OwnedName>().is_err() {
+                return self.error(format!("Qualified name is invalid: {}", name));
+            }
+            try!(self.expect_whitespace(CTX));
+            let attribute_type = try!(self.read_attribute_type());
+            try!(self.expect_whitespace(CTX));
+            let default = try!(self.read_attribute_default());
+
+            decls.push(AttributeDecl { name: name, attribute_type: attribute_type, default: default });
+        }
+
+        if !self.skip_declarations {
+            let attrs = self.dtd.attributes.entry(element).or_insert_with(Vec::new);
+            for decl in decls {
+                if !attrs.iter().any(|a| a.name == decl.name) {
+                    attrs.push(decl);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_attribute_type(&mut self) -> Result<AttributeType> {
+        const CTX: &'static str = "attribute type";
+
+        if self.eat("(") {
+            return Ok(AttributeType::Enumeration(try!(self.read_enumeration(true))));
+        }
+        Ok(match &try!(self.read_name(CTX))[..] {
+            "CDATA" => AttributeType::CData,
+            "ID" => AttributeType::Id,
+            "IDREF" => AttributeType::IdRef,
+            "IDREFS" => AttributeType::IdRefs,
+            "ENTITY" => AttributeType::Entity,
+            "ENTITIES" => AttributeType::Entities,
+            "NMTOKEN" => AttributeType::NmToken,
+            "NMTOKENS" => AttributeType::NmTokens,
+            "NOTATION" => {
+                try!(self.expect_whitespace(CTX));
+                try!(self.expect("(", CTX));
+                AttributeType::Notation(try!(self.read_enumeration(false)))
+            }
+            other => return self.error(format!("Unexpected attribute type: {}", other))
+        })
+    }
+
+    /// Reads a list of names or name tokens after the opening parenthesis.
+    fn read_enumeration(&mut self, nmtokens: bool) -> Result<Vec<String>> {
+        const CTX: &'static str = "enumerated attribute type";
+
+        let mut values = Vec::new();
+        loop {
+            try!(self.skip_separators());
+            values.push(if nmtokens {
+                try!(self.read_nmtoken(CTX))
+            } else {
+                try!(self.read_name(CTX))
+            });
+            try!(self.skip_separators());
+            if self.eat(")") {
+                return Ok(values);
+            }
+            try!(self.expect("|", CTX));
+        }
+    }
+
+    fn read_attribute_default(&mut self) -> Result<AttributeDefault> {
+        const CTX: &'static str = "attribute default declaration";
+
+        if self.eat("#") {
+            match &try!(self.read_name(CTX))[..] {
+                "REQUIRED" => Ok(AttributeDefault::Required),
+                "IMPLIED" => Ok(AttributeDefault::Implied),
+                "FIXED" => {
+                    try!(self.expect_whitespace(CTX));
+                    Ok(AttributeDefault::Fixed(try!(self.read_attribute_value())))
+                }
+                other => self.error(format!("Unexpected attribute default declaration: #{}", other))
+            }
+        } else {
+            Ok(AttributeDefault::Value(try!(self.read_attribute_value())))
+        }
+    }
+
+    /// Reads a quoted default attribute value, replacing character and entity references
+    /// and normalizing whitespace unless disabled in the config.
+    ///
+    /// As in the content, general entities must be declared before they are referenced.
+    fn read_attribute_value(&mut self) -> Result<String> {
+        const CTX: &'static str = "attribute value";
+
+        let quote = try!(self.read_quote(CTX));
+        let mut value = String::new();
+        loop {
+            if self.peek() == Some('<') {
+                return self.error("Unexpected token inside attribute value: <");
+            }
+            match self.bump() {
+                Some(c) if c == quote => return Ok(value),
+                Some('&') => {
+                    let name = try!(self.read_reference_name());
+                    match util::resolve_predefined_reference(&name) {
+                        Some(Ok(c)) => value.push(c),
+                        Some(Err(msg)) => return self.error(msg),
+                        None => match self.config.extra_entities.get(&name) {
+                            Some(v) => value.push_str(v),
+                            // The declaration is discarded, and the entity may have been declared
+                            // in the skipped parameter entity
+                            None if self.skip_declarations && self.dtd.entity(&name).is_none() => {}
+                            None => {
+                                let mut expansion = AttributeExpansion::new(&self.dtd, self.config,
+                                                                            self.expanded_chars, self.chars_read);
+                                try!(expansion.expand(self, &name, &mut value));
+                                self.expanded_chars = expansion.expanded;
+                            }
+                        }
+                    }
+                }
+                Some(c) if is_whitespace_char(c) && self.config.normalize_attribute_values => value.push(' '),
+                Some(c) => value.push(c),
+                None => return self.unexpected(CTX)
+            }
+        }
+    }
+
+    fn parse_entity_decl(&mut self) -> Result<()> {
+        const CTX: &'static str = "entity declaration";
+
+        self.eat("<!ENTITY");
+        try!(self.expect_whitespace(CTX));
+        let is_parameter = self.eat("%");
+        if is_parameter {
+            try!(self.expect_whitespace(CTX));
+        }
+        let name = try!(self.read_name(CTX));
+        try!(self.expect_whitespace(CTX));
+
+        let definition = match self.peek() {
+            Some('"') | Some('\'') => EntityDef::Internal(try!(self.read_entity_value())),
+            _ => {
+                let (public_id, system_id) = try!(self.read_external_id(false));
+                let notation = if !is_parameter && try!(self.skip_separators()) && self.eat("NDATA") {
+                    try!(self.expect_whitespace(CTX));
+                    Some(try!(self.read_name(CTX)))
+                } else {
+                    None
+                };
+                EntityDef::External {
+                    id: ExternalId { public_id: public_id, system_id: system_id.unwrap() },
+                    notation: notation
+                }
+            }
+        };
+
+        try!(self.skip_separators());
+        try!(self.expect(">", CTX));
+
+        if !self.skip_declarations {
+            let entities = if is_parameter {
+                &mut self.dtd.parameter_entities
+            } else {
+                &mut self.dtd.entities
+            };
+            // The first declaration of an entity is binding
+            if !entities.contains_key(&name) {
+                entities.insert(name.clone(), EntityDecl { name: name, definition: definition });
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads an entity value literal, replacing character and parameter entity references
+    /// and leaving general entity references intact.
+    fn read_entity_value(&mut self) -> Result<String> {
+        const CTX: &'static str = "entity value";
+
+        let quote = try!(self.read_quote(CTX));
+        // only the quote in the same input as the opening one closes the literal
+        let depth = self.inputs.len();
+        let mut value = String::new();
+        loop {
+            if self.peek() == Some('%') {
+                if !self.input().external {
+                    return self.error("Parameter entity references are not allowed inside markup \
+                                       declarations in the internal subset");
+                }
+                try!(self.include_parameter_entity(Inclusion::InLiteral));
+                continue;
+            }
+            match self.bump() {
+                Some(c) if c == quote && self.inputs.len() == depth => return Ok(value),
+                Some('&') => {
+                    let name = try!(self.read_reference_name());
+                    if name.starts_with('#') {
+                        match util::parse_char_reference(&name[1..]) {
+                            Ok(c) => value.push(c),
+                            Err(msg) => return self.error(msg)
+                        }
+                    } else {
+                        value.push('&');
+                        value.push_str(&name);
+                        value.push(';');
+                    }
+                }
+                Some(c) => value.push(c),
+                None => return self.unexpected(CTX)
+            }
+        }
+    }
+
+    /// Reads the name of an entity or a character reference after `&`, consuming the final `;`.
+    fn read_reference_name(&mut self) -> Result<String> {
+        const CTX: &'static str = "reference";
+
+        let name = if self.eat("#") {
+            let mut name = String::from("#");
+            while let Some(c) = self.peek() {
+                if c == ';' { break; }
+                name.push(c);
+                self.bump();
+            }
+            name
+        } else {
+            try!(self.read_name(CTX))
+        };
+        try!(self.expect(";", CTX));
+        Ok(name)
+    }
+
+    fn parse_notation_decl(&mut self) -> Result<()> {
+        const CTX: &'static str = "notation declaration";
+
+        self.eat("<!NOTATION");
+        try!(self.expect_whitespace(CTX));
+        let name = try!(self.read_name(CTX));
+        try!(self.expect_whitespace(CTX));
+        let (public_id, system_id) = try!(self.read_external_id(true));
+        try!(self.skip_separators());
+        try!(self.expect(">", CTX));
+
+        if !self.dtd.notations.contains_key(&name) {
+            self.dtd.notations.insert(name.clone(), NotationDecl {
+                name: name,
+                public_id: public_id,
+                system_id: system_id
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads `SYSTEM "system-id"` or `PUBLIC "public-id" "system-id"`.
+    ///
+    /// If `public_only_allowed` is true, the system identifier after a public identifier
+    /// is optional, as in notation declarations.
+    fn read_external_id(&mut self, public_only_allowed: bool) -> Result<(Option<String>, Option<String>)> {
+        const CTX: &'static str = "external identifier";
+
+        if self.eat("SYSTEM") {
+            try!(self.expect_whitespace(CTX));
+            let system_id = try!(self.read_system_literal());
+            Ok((None, Some(system_id)))
+        } else if self.eat("PUBLIC") {
+            try!(self.expect_whitespace(CTX));
+            let public_id = try!(self.read_pubid_literal());
+            if public_only_allowed {
+                let had_whitespace = try!(self.skip_separators());
+                match self.peek() {
+                    Some('"') | Some('\'') if had_whitespace =>
+                        Ok((Some(public_id), Some(try!(self.read_system_literal())))),
+                    _ => Ok((Some(public_id), None))
+                }
+            } else {
+                try!(self.expect_whitespace(CTX));
+                Ok((Some(public_id), Some(try!(self.read_system_literal()))))
+            }
+        } else {
+            self.unexpected(CTX)
+        }
+    }
+
+    fn read_system_literal(&mut self) -> Result<String> {
+        const CTX: &'static str = "system literal";
+
+        let quote = try!(self.read_quote(CTX));
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(value),
+                Some(c) => value.push(c),
+                None => return self.unexpected(CTX)
+            }
+        }
+    }
+
+    /// Reads a public identifier literal, normalizing white space in it.
+    fn read_pubid_literal(&mut self) -> Result<String> {
+        const CTX: &'static str = "public identifier literal";
+
+        let quote = try!(self.read_quote(CTX));
+        let mut value = String::new();
+        let mut pending_space = false;
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(value),
+                Some(c) if is_whitespace_char(c) => pending_space = !value.is_empty(),
+                Some(c) if is_pubid_char(c) => {
+                    if pending_space {
+                        value.push(' ');
+                        pending_space = false;
+                    }
+                    value.push(c);
+                }
+                Some(c) => return self.error(format!("Unexpected character inside {}: {}", CTX, c)),
+                None => return self.unexpected(CTX)
+            }
+        }
+    }
+
+    fn read_quote(&mut self, ctx: &str) -> Result<char> {
+        match self.peek() {
+            Some(c) if c == '"' || c == '\'' => {
+                self.bump();
+                Ok(c)
+            }
+            _ => self.unexpected(ctx)
+        }
+    }
+
+    fn read_name(&mut self, ctx: &str) -> Result<String> {
+        match self.peek() {
+            Some(c) if is_name_start_char(c) => Ok(self.read_while(is_name_char)),
+            _ => self.unexpected(ctx)
+        }
+    }
+
+    fn read_nmtoken(&mut self, ctx: &str) -> Result<String> {
+        match self.peek() {
+            Some(c) if is_name_char(c) => Ok(self.read_while(is_name_char)),
+            _ => self.unexpected(ctx)
+        }
+    }
+
+    fn read_while<F: Fn(char) -> bool>(&mut self, pred: F) -> String {
+        let mut result = String::new();
+        while let Some(c) = self.peek() {
+            if !pred(c) { break; }
+            result.push(c);
+            self.bump();
+        }
+        result
+    }
+
+    /// Skips white space, returning true if there was any.
+    fn skip_whitespace(&mut self) -> bool {
+        let mut skipped = false;
+        while let Some(c) = self.peek() {
+            if !is_whitespace_char(c) { break; }
+            self.bump();
+            skipped = true;
+        }
+        skipped
+    }
+
+    /// Skips white space and parameter entity references inside a markup declaration,
+    /// returning true if there were any. The replacement texts of the entities are included,
+    /// so they are read next.
+    fn skip_separators(&mut self) -> Result<bool> {
+        let mut skipped = false;
+        loop {
+            match self.peek() {
+                Some(c) if is_whitespace_char(c) => { self.bump(); }
+                Some('%') if self.rest()[1..].starts_with(is_name_start_char) => {
+                    if !self.input().external {
+                        return self.error("Parameter entity references are not allowed inside markup \
+                                           declarations in the internal subset");
+                    }
+                    try!(self.include_parameter_entity(Inclusion::InDeclaration));
+                }
+                _ => return Ok(skipped)
+            }
+            skipped = true;
+        }
+    }
+
+    fn expect_whitespace(&mut self, ctx: &str) -> Result<()> {
+        if try!(self.skip_separators()) { Ok(()) } else { self.unexpected(ctx) }
+    }
+
+    fn expect(&mut self, s: &str, ctx: &str) -> Result<()> {
+        if self.eat(s) { Ok(()) } else { self.unexpected(ctx) }
+    }
+
+    /// Returns the index of the input the next character is read from, skipping
+    /// the nested inputs which have ended.
+    fn input_index(&self) -> usize {
+        let mut i = self.inputs.len() - 1;
+        while i > 0 && self.inputs[i].nested && self.inputs[i].is_finished() {
+            i -= 1;
+        }
+        i
+    }
+
+    #[inline]
+    fn input(&self) -> &Input<'a> {
+        &self.inputs[self.input_index()]
+    }
+
+    #[inline]
+    fn leave_nested_inputs(&mut self) {
+        let i = self.input_index();
+        self.inputs.truncate(i + 1);
+    }
+
+    #[inline]
+    fn rest(&self) -> &str {
+        let input = self.input();
+        &input.text[input.offset..]
+    }
+
+    #[inline]
+    fn looking_at(&self, s: &str) -> bool {
+        self.rest().starts_with(s)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.looking_at(s) {
+            for _ in s.chars() {
+                self.bump();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        self.leave_nested_inputs();
+        let c = self.peek();
+        if let Some(c) = c {
+            self.inputs.last_mut().unwrap().offset += c.len_utf8();
+            // the position is only tracked in the parsed text itself
+            if self.inputs.len() > 1 {
+                return Some(c);
+            }
+            self.chars_read += 1;
+            if c == '\n' {
+                self.pos.new_line();
+            } else {
+                self.pos.advance(1);
+            }
+        }
+        c
+    }
+
+    fn unexpected<T>(&self, ctx: &str) -> Result<T> {
+        match self.peek() {
+            Some(c) => self.error(format!("Unexpected character inside {}: {}", ctx, c)),
+            None => self.error(format!("Unexpected end of {}", ctx))
+        }
+    }
+
+    #[inline]
+    fn error<T, M: Into<Cow<'static, str>>>(&self, msg: M) -> Result<T> {
+        Err((self, msg).into())
+    }
+}
+
+/// Expands references to general entities inside attribute values, both in the content
+/// and in default values of attribute list declarations.
+pub struct AttributeExpansion<'a> {
+    dtd: &'a Dtd,
+    config: &'a ParserConfig,
+
+    /// Names of the entities which are being expanded at the moment.
+    open: Vec<String>,
+
+    /// The number of characters produced by entity expansion so far.
+    pub expanded: usize,
+
+    /// The number of characters read from the document so far.
+    read: usize
+}
+
+impl<'a> AttributeExpansion<'a> {
+    pub fn new(dtd: &'a Dtd, config: &'a ParserConfig, expanded: usize, read: usize) -> AttributeExpansion<'a> {
+        AttributeExpansion {
+            dtd: dtd,
+            config: config,
+            open: Vec::new(),
+            expanded: expanded,
+            read: read
+        }
+    }
+
+    /// Appends the replacement text of the given entity to `target`, recursively expanding
+    /// all references inside it and normalizing whitespace unless disabled in the config.
+    ///
+    /// Errors are reported at the position of `pos`.
+    pub fn expand<P: Position>(&mut self, pos: &P, name: &str, target: &mut String) -> Result<()> {
+        macro_rules! error(($msg:expr) => (Err((pos, $msg).into())));
+
+        if self.open.iter().any(|n| n == name) {
+            return error!(format!("Recursive entity reference: {}", name));
+        }
+        let mut text = match replacement_text(self.dtd, name) {
+            Ok(text) => text,
+            Err(msg) => return error!(msg)
+        };
+        self.expanded += text.chars().count();
+        if let Some(msg) = config::entity_expansion_error(self.config, self.expanded, self.open.len() + 1, self.read) {
+            return Err(error::entity_expansion_limit(pos, msg));
+        }
+        self.open.push(name.into());
+
+        while let Some(i) = text.find(&['&', '<'][..]) {
+            self.push_text(target, &text[..i]);
+            if text[i..].starts_with('<') {
+                return error!("Unexpected token inside attribute value: <");
+            }
+            let end = match text[i..].find(';') {
+                Some(n) => i + n,
+                None => return error!(format!("Unterminated reference inside entity: {}", name))
+            };
+            let ref_name = &text[i+1..end];
+            if ref_name.is_empty() {
+                return error!("Encountered empty entity");
+            }
+            match util::resolve_predefined_reference(ref_name) {
+                Some(Ok(c)) => target.push(c),
+                Some(Err(msg)) => return error!(msg),
+                None => match self.config.extra_entities.get(ref_name) {
+                    Some(v) => target.push_str(v),
+                    None => try!(self.expand(pos, ref_name, target))
+                }
+            }
+            text = &text[end+1..];
+        }
+        self.push_text(target, text);
+
+        self.open.pop();
+        Ok(())
+    }
+
+    fn push_text(&self, target: &mut String, text: &str) {
+        if self.config.normalize_attribute_values {
+            target.extend(text.chars().map(|c| if is_whitespace_char(c) { ' ' } else { c }));
+        } else {
+            target.push_str(text);
+        }
+    }
+}
+
+/// Returns the replacement text of an internal general entity declared in the DTD.
+///
+/// References to external entities are only allowed in the content, so they are
+/// reported as errors.
+pub fn replacement_text<'a>(dtd: &'a Dtd, name: &str) -> result::Result<&'a str, Cow<'static, str>> {
+    match dtd.entity(name).map(|e| &e.definition) {
+        Some(&EntityDef::Internal(ref text)) => Ok(text),
+        Some(&EntityDef::External { notation: Some(_), .. }) =>
+            Err(format!("Unparsed entity cannot be referenced: {}", name).into()),
+        Some(&EntityDef::External { .. }) =>
+            Err(format!("Unexpected external entity reference inside attribute value: {}", name).into()),
+        None => Err(format!("Unexpected entity: {}", name).into())
+    }
+}
+
+fn is_pubid_char(c: char) -> bool {
+    match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | ' ' | '\r' | '\n' => true,
+        '-' | '\'' | '(' | ')' | '+' | ',' | '.' | '/' | ':' | '=' | '?' | ';' | '!' | '*' |
+        '#' | '@' | '$' | '_' | '%' => true,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{Position, TextPosition};
+    use dtd::{
+        Dtd, ContentSpec, ContentParticle, ParticleKind, Occurrence, AttributeType, AttributeDefault,
+        EntityDef, ExternalId
+    };
+    use reader::{ParserConfig, MapResolver};
+
+    use super::{DtdParser, Doctype};
+
+    fn parse(s: &str) -> Doctype {
+        DtdParser::new(s, TextPosition::new(), &ParserConfig::new()).parse_doctype().unwrap()
+    }
+
+    fn parse_external(s: &str) -> Dtd {
+        DtdParser::new(s, TextPosition::new(), &ParserConfig::new()).parse_external_subset(Dtd::new()).unwrap()
+    }
+
+    fn name(n: &str, occurrence: Occurrence) -> ContentParticle {
+        ContentParticle { kind: ParticleKind::Name(n.into()), occurrence: occurrence }
+    }
+
+    #[test]
+    fn external_ids() {
+        let d = parse(" html");
+        assert_eq!(d.name, "html");
+        assert_eq!(d.public_id, None);
+        assert_eq!(d.system_id, None);
+
+        let d = parse(" html SYSTEM 'about:legacy-compat' ");
+        assert_eq!(d.public_id, None);
+        assert_eq!(d.system_id, Some("about:legacy-compat".into()));
+
+        let d = parse(r#" html PUBLIC "-//W3C//DTD  XHTML 1.0
+                         Strict//EN" "xhtml1-strict.dtd""#);
+        assert_eq!(d.public_id, Some("-//W3C//DTD XHTML 1.0 Strict//EN".into()));
+        assert_eq!(d.system_id, Some("xhtml1-strict.dtd".into()));
+        assert!(d.dtd.is_empty());
+    }
+
+    #[test]
+    fn element_declarations() {
+        let d = parse(r#" doc [
+            <!ELEMENT doc (head, (p | list)*, foot?)>
+            <!ELEMENT head EMPTY>
+            <!ELEMENT foot ANY>
+            <!ELEMENT p (#PCDATA|em| strong )*>
+            <!ELEMENT em (#PCDATA)>
+            <!ELEMENT list (item+)>
+        ]"#);
+
+        assert_eq!(d.dtd.element("doc").unwrap().content, ContentSpec::Children(ContentParticle {
+            kind: ParticleKind::Seq(vec![
+                name("head", Occurrence::Once),
+                ContentParticle {
+                    kind: ParticleKind::Choice(vec![
+                        name("p", Occurrence::Once),
+                        name("list", Occurrence::Once)
+                    ]),
+                    occurrence: Occurrence::ZeroOrMore
+                },
+                name("foot", Occurrence::Optional)
+            ]),
+            occurrence: Occurrence::Once
+        }));
+        assert_eq!(d.dtd.element("head").unwrap().content, ContentSpec::Empty);
+        assert_eq!(d.dtd.element("foot").unwrap().content, ContentSpec::Any);
+        assert_eq!(d.dtd.element("p").unwrap().content,
+                   ContentSpec::Mixed(vec!["em".into(), "strong".into()]));
+        assert_eq!(d.dtd.element("em").unwrap().content, ContentSpec::Mixed(vec![]));
+        assert_eq!(d.dtd.element("list").unwrap().content, ContentSpec::Children(ContentParticle {
+            kind: ParticleKind::Seq(vec![name("item", Occurrence::OneOrMore)]),
+            occurrence: Occurrence::Once
+        }));
+    }
+
+    #[test]
+    fn attribute_list_declarations() {
+        let d = parse(r#" doc [
+            <!ATTLIST doc
+                id ID #REQUIRED
+                kind (a|b| c) "b"
+                note NOTATION (gif | png) #IMPLIED
+                version CDATA #FIXED '1 &amp; &#x32;'>
+            <!ATTLIST doc id CDATA #IMPLIED refs IDREFS #IMPLIED>
+        ]"#);
+
+        let attrs = &d.dtd.attributes["doc"];
+        assert_eq!(attrs.len(), 5);
+        assert_eq!(attrs[0].attribute_type, AttributeType::Id);
+        assert_eq!(attrs[0].default, AttributeDefault::Required);
+        assert_eq!(attrs[1].attribute_type,
+                   AttributeType::Enumeration(vec!["a".into(), "b".into(), "c".into()]));
+        assert_eq!(attrs[1].default, AttributeDefault::Value("b".into()));
+        assert_eq!(attrs[2].attribute_type,
+                   AttributeType::Notation(vec!["gif".into(), "png".into()]));
+        assert_eq!(attrs[3].default, AttributeDefault::Fixed("1 & 2".into()));
+        // the first definition of an attribute is binding
+        assert_eq!(d.dtd.attribute("doc", "id").unwrap().attribute_type, AttributeType::Id);
+        assert_eq!(d.dtd.attribute("doc", "refs").unwrap().attribute_type, AttributeType::IdRefs);
+    }
+
+    #[test]
+    fn entity_and_notation_declarations() {
+        let d = parse(r#" doc [
+            <!-- entities -->
+            <!ENTITY company "Acme &amp; &#169; > co.">
+            <!ENTITY company "ignored">
+            <!ENTITY logo SYSTEM "logo.gif" NDATA gif>
+            <!ENTITY % common PUBLIC "-//Acme//Common//EN" "common.ent">
+            <?acme pi?>
+            <!NOTATION gif PUBLIC "-//Acme//GIF//EN">
+        ]"#);
+
+        assert_eq!(d.dtd.entity("company").unwrap().definition,
+                   EntityDef::Internal("Acme &amp; © > co.".into()));
+        assert_eq!(d.dtd.entity("logo").unwrap().definition, EntityDef::External {
+            id: ExternalId { public_id: None, system_id: "logo.gif".into() },
+            notation: Some("gif".into())
+        });
+        assert_eq!(d.dtd.parameter_entity("common").unwrap().definition, EntityDef::External {
+            id: ExternalId { public_id: Some("-//Acme//Common//EN".into()), system_id: "common.ent".into() },
+            notation: None
+        });
+        let gif = d.dtd.notation("gif").unwrap();
+        assert_eq!(gif.public_id, Some("-//Acme//GIF//EN".into()));
+        assert_eq!(gif.system_id, None);
+    }
+
+    #[test]
+    fn entity_references_in_attribute_defaults() {
+        let d = parse(r#" doc [
+            <!ENTITY c "Acme">
+            <!ENTITY d "&c; &amp;&#38;#x20;Co">
+            <!ATTLIST doc a CDATA "&c;" b CDATA #FIXED "The &d;">
+        ]"#);
+        assert_eq!(d.dtd.attribute("doc", "a").unwrap().default, AttributeDefault::Value("Acme".into()));
+        assert_eq!(d.dtd.attribute("doc", "b").unwrap().default, AttributeDefault::Fixed("The Acme & Co".into()));
+
+        fn error(s: &str) -> String {
+            DtdParser::new(s, TextPosition::new(), &ParserConfig::new()).parse_doctype().err().unwrap().msg().into()
+        }
+        assert_eq!(error(" doc [<!ATTLIST doc a CDATA '&c;'><!ENTITY c 'Acme'>]"), "Unexpected entity: c");
+        assert_eq!(error(" doc [<!ENTITY c '&c;'><!ATTLIST doc a CDATA '&c;'>]"), "Recursive entity reference: c");
+        assert_eq!(error(" doc [<!ENTITY c '&#60;'><!ATTLIST doc a CDATA '&c;'>]"),
+                   "Unexpected token inside attribute value: <");
+        assert_eq!(error(" doc [<!ENTITY c SYSTEM 'c.xml'><!ATTLIST doc a CDATA '&c;'>]"),
+                   "Unexpected external entity reference inside attribute value: c");
+    }
+
+    #[test]
+    fn errors() {
+        fn error(s: &str) -> (TextPosition, String) {
+            let e = DtdParser::new(s, TextPosition::new(), &ParserConfig::new()).parse_doctype().err().unwrap();
+            (e.position(), e.msg().into())
+        }
+
+        assert_eq!(error(" doc [<!ELEMENT doc (a, b | c)>]"),
+                   (TextPosition { row: 0, column: 26 },
+                    "Cannot mix ',' and '|' in the same content particle".into()));
+        assert_eq!(error(" doc [\n<!ELEMENT doc (#PCDATA|a)>]"),
+                   (TextPosition { row: 1, column: 25 },
+                    "Unexpected character inside mixed content declaration: >".into()));
+        assert_eq!(error(" doc [<!ENTITY a '%b;'>]"),
+                   (TextPosition { row: 0, column: 18 },
+                    "Parameter entity references are not allowed inside markup declarations \
+                     in the internal subset".into()));
+        assert_eq!(error(" doc [<!ATTLIST doc a CDATA '<'>]"),
+                   (TextPosition { row: 0, column: 29 },
+                    "Unexpected token inside attribute value: <".into()));
+        assert_eq!(error(" doc [<!ATTLIST doc a:b:c CDATA 'x'>]"),
+                   (TextPosition { row: 0, column: 25 },
+                    "Qualified name is invalid: a:b:c".into()));
+        assert_eq!(error(" doc [<!FOO>]"),
+                   (TextPosition { row: 0, column: 6 },
+                    "Unexpected markup declaration inside DOCTYPE".into()));
+        assert_eq!(error(" doc ["),
+                   (TextPosition { row: 0, column: 6 },
+                    "Unexpected end of internal DTD subset".into()));
+        assert_eq!(error(" doc [<!ENTITY % e 'ANY'><!ELEMENT doc %e;>]"),
+                   (TextPosition { row: 0, column: 39 },
+                    "Parameter entity references are not allowed inside markup declarations \
+                     in the internal subset".into()));
+        assert_eq!(error(" doc [<!ENTITY % a '%b;'>]"),
+                   (TextPosition { row: 0, column: 20 },
+                    "Parameter entity references are not allowed inside markup declarations \
+                     in the internal subset".into()));
+        assert_eq!(error(" doc [<![INCLUDE[<!ELEMENT doc ANY>]]>]"),
+                   (TextPosition { row: 0, column: 6 },
+                    "Conditional sections are not allowed in the internal subset".into()));
+        assert_eq!(error(" doc [<!ENTITY % e '<!ELEMENT doc'> %e; ANY>]"),
+                   (TextPosition { row: 0, column: 39 },
+                    "Unexpected end of element type declaration".into()));
+        assert_eq!(error(" doc [<!ENTITY % a '%a;'>]"),
+                   (TextPosition { row: 0, column: 20 },
+                    "Parameter entity references are not allowed inside markup declarations \
+                     in the internal subset".into()));
+        assert_eq!(error(" doc [<!ENTITY % a '<!ELEMENT a ANY> &#37;a;'> %a;]"),
+                   (TextPosition { row: 0, column: 50 },
+                    "Recursive parameter entity reference: a".into()));
+    }
+
+    #[test]
+    fn parameter_entities_in_internal_subset() {
+        let d = parse(r#" doc [
+            <!ENTITY % decls '<!ELEMENT doc (#PCDATA)> <!ENTITY e "&#38;#x25;text">'>
+            %decls;
+            <!ATTLIST doc a CDATA #IMPLIED>
+            %undeclared;
+            <!ELEMENT p ANY>
+            <!ATTLIST doc b CDATA #IMPLIED>
+            <!ENTITY f "ignored">
+        ]"#);
+
+        assert_eq!(d.dtd.element("doc").unwrap().content, ContentSpec::Mixed(vec![]));
+        assert_eq!(d.dtd.entity("e").unwrap().definition, EntityDef::Internal("%text".into()));
+        assert!(d.dtd.attribute("doc", "a").is_some());
+        // declarations after a skipped parameter entity reference are not processed,
+        // except for element type declarations
+        assert!(d.dtd.element("p").is_some());
+        assert!(d.dtd.attribute("doc", "b").is_none());
+        assert!(d.dtd.entity("f").is_none());
+    }
+
+    #[test]
+    fn parameter_entities_in_external_subset() {
+        let dtd = parse_external(r#"
+            <!ENTITY % inline "em|strong">
+            <!ENTITY % attrs "id ID #IMPLIED">
+            <!ENTITY % name "para">
+            <!ENTITY % title "The %name; element">
+            <!ELEMENT %name; (#PCDATA|%inline;)*>
+            <!ATTLIST %name; %attrs; class CDATA "%name;">
+            <!ENTITY title "%title;">
+        "#);
+
+        assert_eq!(dtd.element("para").unwrap().content,
+                   ContentSpec::Mixed(vec!["em".into(), "strong".into()]));
+        assert_eq!(dtd.attribute("para", "id").unwrap().attribute_type, AttributeType::Id);
+        // parameter entity references are not recognized inside attribute values
+        assert_eq!(dtd.attribute("para", "class").unwrap().default,
+                   AttributeDefault::Value("%name;".into()));
+        assert_eq!(dtd.entity("title").unwrap().definition,
+                   EntityDef::Internal("The para element".into()));
+    }
+
+    #[test]
+    fn conditional_sections() {
+        let dtd = parse_external(r#"
+            <!ENTITY % draft "INCLUDE">
+            <!ENTITY % final "IGNORE">
+            <![%draft;[
+                <!ELEMENT note ANY>
+                <![ IGNORE [ <!ELEMENT ignored ANY> ]]>
+                <![INCLUDE[ <!ELEMENT included ANY> ]]>
+            ]]>
+            <![ %final; [
+                <!ELEMENT note EMPTY>
+                <![INCLUDE[ <!ELEMENT nested ANY> ]]> <!ELEMENT ignored2 ANY>
+            ]]>
+        "#);
+
+        assert_eq!(dtd.element("note").unwrap().content, ContentSpec::Any);
+        assert!(dtd.element("included").is_some());
+        for name in &["ignored", "nested", "ignored2"] {
+            assert!(dtd.element(name).is_none());
+        }
+
+        let error = |s: &str| DtdParser::new(s, TextPosition::new(), &ParserConfig::new())
+            .parse_external_subset(Dtd::new()).err().unwrap().msg().to_owned();
+        assert_eq!(error("<![INCLUDE[ <!ELEMENT a ANY>"), "Unexpected end of conditional section");
+        assert_eq!(error("<![IGNORE[ <![INCLUDE[ ]]>"), "Unexpected end of conditional section");
+        assert_eq!(error("<![FOO[ ]]>"), "Unexpected conditional section keyword: FOO");
+    }
+
+    #[test]
+    fn external_parameter_entities() {
+        let config = ParserConfig::new().entity_resolver(MapResolver::new()
+            .add("decls.ent", "<?xml version='1.0' encoding='utf-8'?><!ELEMENT doc %model;>")
+            .add("model.ent", "(a, b)"));
+        let d = DtdParser::new(r#" doc [
+            <!ENTITY % decls SYSTEM "decls.ent">
+            <!ENTITY % model SYSTEM "model.ent">
+            %decls;
+        ]"#, TextPosition::new(), &config).parse_doctype().unwrap();
+
+        assert_eq!(d.dtd.element("doc").unwrap().content, ContentSpec::Children(ContentParticle {
+            kind: ParticleKind::Seq(vec![name("a", Occurrence::Once), name("b", Occurrence::Once)]),
+            occurrence: Occurrence::Once
+        }));
+    }
+
+    #[test]
+    fn parameter_entity_expansion_limits() {
+        let config = ParserConfig::new().max_entity_expansion_size(1000);
+        let e = DtdParser::new(r#" doc [
+            <!ENTITY % a "<!-- aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa -->">
+            %a; %a; %a; %a; %a; %a; %a; %a; %a; %a; %a; %a; %a; %a; %a; %a; %a; %a; %a; %a;
+        ]"#, TextPosition::new(), &config).parse_doctype().err().unwrap();
+        assert_eq!(e.msg(), "Entity expansion size limit of 1000 characters exceeded");
+    }
+}
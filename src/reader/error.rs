@@ -14,6 +14,13 @@ pub enum ErrorKind {
     Io(io::Error),
     Utf8(str::Utf8Error),
     UnexpectedEof,
+    /// One of the entity expansion limits set in `ParserConfig` was exceeded.
+    EntityExpansionLimit(Cow<'static, str>),
+    /// The document violates a validity constraint of its DTD; only reported when
+    /// `ParserConfig::validate_dtd` is enabled.
+    Validity(Cow<'static, str>),
+    /// More events were requested to be read ahead than `ParserConfig::max_lookahead` allows.
+    LookaheadLimit(Cow<'static, str>),
 }
 
 /// An XML parsing error.
@@ -46,10 +53,25 @@ impl Error {
             Utf8(ref reason) => error_description(reason),
             Io(ref io_error) => error_description(io_error),
             Syntax(ref msg) => msg.as_ref(),
+            EntityExpansionLimit(ref msg) => msg.as_ref(),
+            Validity(ref msg) => msg.as_ref(),
+            LookaheadLimit(ref msg) => msg.as_ref(),
         }
     }
 
     pub fn kind(&self) -> &ErrorKind { &self.kind }
+
+    /// Returns true if this error is an I/O error of kind `WouldBlock`, which is reported
+    /// when a non-blocking stream has no data available yet.
+    ///
+    /// Such an error does not stop the parser; it can be asked for the next event again
+    /// once the stream is readable.
+    pub fn is_would_block(&self) -> bool {
+        match self.kind {
+            ErrorKind::Io(ref e) => e.kind() == io::ErrorKind::WouldBlock,
+            _ => false
+        }
+    }
 }
 
 impl error::Error for Error {
@@ -66,6 +88,30 @@ impl<'a, P, M> From<(&'a P, M)> for Error where P: Position, M: Into<Cow<'static
     }
 }
 
+/// Creates an error which is reported when an entity expansion limit is exceeded.
+pub fn entity_expansion_limit<P, M>(pos: &P, msg: M) -> Error where P: Position, M: Into<Cow<'static, str>> {
+    Error {
+        pos: pos.position(),
+        kind: ErrorKind::EntityExpansionLimit(msg.into())
+    }
+}
+
+/// Creates an error which is reported when the document violates a validity constraint.
+pub fn validity<P, M>(pos: &P, msg: M) -> Error where P: Position, M: Into<Cow<'static, str>> {
+    Error {
+        pos: pos.position(),
+        kind: ErrorKind::Validity(msg.into())
+    }
+}
+
+/// Creates an error which is reported when the lookahead limit is exceeded.
+pub fn lookahead_limit<P, M>(pos: &P, msg: M) -> Error where P: Position, M: Into<Cow<'static, str>> {
+    Error {
+        pos: pos.position(),
+        kind: ErrorKind::LookaheadLimit(msg.into())
+    }
+}
+
 impl From<util::CharReadError> for Error {
     fn from(e: util::CharReadError) -> Self {
         use util::CharReadError::*;
@@ -74,6 +120,7 @@ impl From<util::CharReadError> for Error {
             kind: match e {
                 UnexpectedEof => ErrorKind::UnexpectedEof,
                 Utf8(reason) => ErrorKind::Utf8(reason),
+                Encoding(msg) => ErrorKind::Syntax(msg),
                 Io(io_error) => ErrorKind::Io(io_error),
             }
         }
@@ -97,6 +144,9 @@ impl Clone for ErrorKind {
             Utf8(ref reason) => Utf8(reason.clone()),
             Io(ref io_error) => Io(io::Error::new(io_error.kind(), error_description(io_error))),
             Syntax(ref msg) => Syntax(msg.clone()),
+            EntityExpansionLimit(ref msg) => EntityExpansionLimit(msg.clone()),
+            Validity(ref msg) => Validity(msg.clone()),
+            LookaheadLimit(ref msg) => LookaheadLimit(msg.clone()),
         }
     }
 }
@@ -111,6 +161,12 @@ impl PartialEq for ErrorKind {
                 error_description(left) == error_description(right),
             (&Syntax(ref left), &Syntax(ref right)) =>
                 left == right,
+            (&EntityExpansionLimit(ref left), &EntityExpansionLimit(ref right)) =>
+                left == right,
+            (&Validity(ref left), &Validity(ref right)) =>
+                left == right,
+            (&LookaheadLimit(ref left), &LookaheadLimit(ref right)) =>
+                left == right,
 
             (_, _) => false,
         }
@@ -1,12 +1,13 @@
-//! Contains `XmlEvent` datatype, instances of which are emitted by the parser.
+//! Contains `XmlEvent` and `BorrowedEvent` datatypes, instances of which are emitted by the parser.
 
 use std::fmt;
 use std::borrow::Cow;
 
-use name::OwnedName;
-use attribute::OwnedAttribute;
+use name::{Name, OwnedName};
+use attribute::{Attribute, OwnedAttribute};
 use common::XmlVersion;
 use namespace::Namespace;
+use dtd::Dtd;
 
 /// An element of an XML input stream.
 ///
@@ -27,8 +28,9 @@ pub enum XmlEvent {
         /// XML document encoding.
         ///
         /// If XML declaration is not present or does not contain `encoding` attribute,
-        /// defaults to `"UTF-8"`. This field is currently used for no other purpose than
-        /// informational.
+        /// defaults to the encoding detected from the first bytes of the document, that is,
+        /// `"UTF-16"` when the document starts with a UTF-16 byte order mark and `"UTF-8"`
+        /// in most other cases.
         encoding: String,
 
         /// XML standalone declaration.
@@ -57,6 +59,25 @@ pub enum XmlEvent {
         data: Option<String>
     },
 
+    /// Denotes a document type declaration.
+    ///
+    /// This event is emitted at most once, before the root element. It contains the name
+    /// of the root element, external identifiers of the DTD and declarations read from
+    /// the internal DTD subset.
+    Doctype {
+        /// Declared name of the root element.
+        name: String,
+
+        /// Public identifier of the external DTD subset, if any.
+        public_id: Option<String>,
+
+        /// System identifier of the external DTD subset, if any.
+        system_id: Option<String>,
+
+        /// Markup declarations from the internal DTD subset.
+        dtd: Dtd
+    },
+
     /// Denotes a beginning of an XML element.
     ///
     /// This event is emitted after parsing opening tags or after parsing bodiless tags. In the
@@ -126,6 +147,8 @@ impl fmt::Debug for XmlEvent {
                     Some(ref data) => format!(", {}", data),
                     None       => String::new()
                 }),
+            XmlEvent::Doctype { ref name, ref public_id, ref system_id, .. } =>
+                write!(f, "Doctype({}, {:?}, {:?})", name, public_id, system_id),
             XmlEvent::StartElement { ref name, ref attributes, namespace: Namespace(ref namespace) } =>
                 write!(f, "StartElement({}, {:?}{})", name, namespace, if attributes.is_empty() {
                     String::new()
@@ -217,3 +240,240 @@ impl XmlEvent {
         }
     }
 }
+
+/// A qualified name of an element or an attribute in a `BorrowedEvent`.
+///
+/// The prefix and the local name borrow from the document. So does the namespace URI,
+/// unless its declaration contains references.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BorrowedName<'a> {
+    /// A local name, e.g. `string` in `xsi:string`.
+    pub local_name: Cow<'a, str>,
+
+    /// A namespace URI, e.g. `http://www.w3.org/2000/xmlns/`.
+    pub namespace: Option<Cow<'a, str>>,
+
+    /// A name prefix, e.g. `xsi` in `xsi:string`.
+    pub prefix: Option<Cow<'a, str>>
+}
+
+impl<'a> BorrowedName<'a> {
+    /// Returns a `Name` which borrows from this one.
+    pub fn borrow(&self) -> Name {
+        Name {
+            local_name: &self.local_name,
+            namespace: self.namespace.as_ref().map(|s| &s[..]),
+            prefix: self.prefix.as_ref().map(|s| &s[..])
+        }
+    }
+
+    /// Converts this name into an `OwnedName`, copying the borrowed parts.
+    pub fn into_owned(self) -> OwnedName {
+        OwnedName {
+            local_name: self.local_name.into_owned(),
+            namespace: self.namespace.map(Cow::into_owned),
+            prefix: self.prefix.map(Cow::into_owned)
+        }
+    }
+}
+
+impl<'a> From<OwnedName> for BorrowedName<'a> {
+    fn from(name: OwnedName) -> BorrowedName<'a> {
+        BorrowedName {
+            local_name: name.local_name.into(),
+            namespace: name.namespace.map(Cow::Owned),
+            prefix: name.prefix.map(Cow::Owned)
+        }
+    }
+}
+
+impl<'a> fmt::Display for BorrowedName<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.borrow(), f)
+    }
+}
+
+/// An attribute of an element in a `BorrowedEvent`.
+///
+/// The value borrows from the document unless it contains references or its white space
+/// is normalized.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BorrowedAttribute<'a> {
+    /// Attribute name.
+    pub name: BorrowedName<'a>,
+
+    /// Attribute value.
+    pub value: Cow<'a, str>
+}
+
+impl<'a> BorrowedAttribute<'a> {
+    /// Returns an `Attribute` which borrows from this one.
+    pub fn borrow(&self) -> Attribute {
+        Attribute {
+            name: self.name.borrow(),
+            value: &self.value
+        }
+    }
+
+    /// Converts this attribute into an `OwnedAttribute`, copying the borrowed parts.
+    pub fn into_owned(self) -> OwnedAttribute {
+        OwnedAttribute::new(self.name.into_owned(), self.value.into_owned())
+    }
+}
+
+impl<'a> From<OwnedAttribute> for BorrowedAttribute<'a> {
+    fn from(attribute: OwnedAttribute) -> BorrowedAttribute<'a> {
+        BorrowedAttribute {
+            name: attribute.name.into(),
+            value: attribute.value.into()
+        }
+    }
+}
+
+/// An element of an XML input stream which borrows its data from the document.
+///
+/// Items of this enum are emitted by `reader::SliceReader`. They correspond to variants of
+/// `XmlEvent` with the same names, except that `StartElement` does not contain the namespace
+/// mappings in scope. Names, attribute values and character data borrow from the document
+/// unless references, normalization of line breaks or attribute values, trimming or
+/// coalescing of character data change them.
+#[derive(PartialEq, Clone)]
+pub enum BorrowedEvent<'a> {
+    /// Corresponds to XML document declaration, see `XmlEvent::StartDocument`.
+    StartDocument {
+        /// XML version.
+        version: XmlVersion,
+
+        /// XML document encoding.
+        encoding: Cow<'a, str>,
+
+        /// XML standalone declaration.
+        standalone: Option<bool>
+    },
+
+    /// Denotes to the end of the document stream.
+    EndDocument,
+
+    /// Denotes an XML processing instruction.
+    ProcessingInstruction {
+        /// Processing instruction target.
+        name: Cow<'a, str>,
+
+        /// Processing instruction content.
+        data: Option<Cow<'a, str>>
+    },
+
+    /// Denotes a document type declaration.
+    ///
+    /// Documents with a document type declaration are not parsed in place, so this event
+    /// and all other events of such documents own their data.
+    Doctype {
+        /// Declared name of the root element.
+        name: Cow<'a, str>,
+
+        /// Public identifier of the external DTD subset, if any.
+        public_id: Option<Cow<'a, str>>,
+
+        /// System identifier of the external DTD subset, if any.
+        system_id: Option<Cow<'a, str>>,
+
+        /// Markup declarations from the internal DTD subset; boxed, since this event
+        /// is rare.
+        dtd: Box<Dtd>
+    },
+
+    /// Denotes a beginning of an XML element.
+    StartElement {
+        /// Qualified name of the element.
+        name: BorrowedName<'a>,
+
+        /// A list of attributes associated with the element.
+        attributes: Vec<BorrowedAttribute<'a>>
+    },
+
+    /// Denotes an end of an XML element.
+    EndElement {
+        /// Qualified name of the element.
+        name: BorrowedName<'a>
+    },
+
+    /// Denotes CDATA content.
+    CData(Cow<'a, str>),
+
+    /// Denotes a comment.
+    Comment(Cow<'a, str>),
+
+    /// Denotes character data outside of tags.
+    Characters(Cow<'a, str>),
+
+    /// Denotes a chunk of whitespace outside of tags.
+    Whitespace(Cow<'a, str>)
+}
+
+impl<'a> fmt::Debug for BorrowedEvent<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BorrowedEvent::StartDocument { ref version, ref encoding, ref standalone } =>
+                write!(f, "StartDocument({}, {}, {:?})", version, encoding, standalone),
+            BorrowedEvent::EndDocument =>
+                write!(f, "EndDocument"),
+            BorrowedEvent::ProcessingInstruction { ref name, ref data } =>
+                write!(f, "ProcessingInstruction({}{})", name, match *data {
+                    Some(ref data) => format!(", {}", data),
+                    None       => String::new()
+                }),
+            BorrowedEvent::Doctype { ref name, ref public_id, ref system_id, .. } =>
+                write!(f, "Doctype({}, {:?}, {:?})", name, public_id, system_id),
+            BorrowedEvent::StartElement { ref name, ref attributes } =>
+                write!(f, "StartElement({}{})", name, if attributes.is_empty() {
+                    String::new()
+                } else {
+                    let attributes: Vec<String> = attributes.iter().map(
+                        |a| format!("{} -> {}", a.name, a.value)
+                    ).collect();
+                    format!(", [{}]", attributes.join(", "))
+                }),
+            BorrowedEvent::EndElement { ref name } =>
+                write!(f, "EndElement({})", name),
+            BorrowedEvent::Comment(ref data) =>
+                write!(f, "Comment({})", data),
+            BorrowedEvent::CData(ref data) =>
+                write!(f, "CData({})", data),
+            BorrowedEvent::Characters(ref data) =>
+                write!(f, "Characters({})", data),
+            BorrowedEvent::Whitespace(ref data) =>
+                write!(f, "Whitespace({})", data)
+        }
+    }
+}
+
+impl<'a> From<XmlEvent> for BorrowedEvent<'a> {
+    /// Converts an event of `EventReader` into an event which owns all of its data; the
+    /// namespace mappings of `StartElement` are dropped.
+    fn from(event: XmlEvent) -> BorrowedEvent<'a> {
+        match event {
+            XmlEvent::StartDocument { version, encoding, standalone } =>
+                BorrowedEvent::StartDocument { version: version, encoding: encoding.into(), standalone: standalone },
+            XmlEvent::EndDocument => BorrowedEvent::EndDocument,
+            XmlEvent::ProcessingInstruction { name, data } =>
+                BorrowedEvent::ProcessingInstruction { name: name.into(), data: data.map(Cow::Owned) },
+            XmlEvent::Doctype { name, public_id, system_id, dtd } =>
+                BorrowedEvent::Doctype {
+                    name: name.into(),
+                    public_id: public_id.map(Cow::Owned),
+                    system_id: system_id.map(Cow::Owned),
+                    dtd: Box::new(dtd)
+                },
+            XmlEvent::StartElement { name, attributes, .. } =>
+                BorrowedEvent::StartElement {
+                    name: name.into(),
+                    attributes: attributes.into_iter().map(BorrowedAttribute::from).collect()
+                },
+            XmlEvent::EndElement { name } => BorrowedEvent::EndElement { name: name.into() },
+            XmlEvent::CData(data) => BorrowedEvent::CData(data.into()),
+            XmlEvent::Comment(data) => BorrowedEvent::Comment(data.into()),
+            XmlEvent::Characters(data) => BorrowedEvent::Characters(data.into()),
+            XmlEvent::Whitespace(data) => BorrowedEvent::Whitespace(data.into())
+        }
+    }
+}
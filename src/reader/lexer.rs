@@ -9,6 +9,7 @@ use std::result;
 use std::borrow::Cow;
 
 use common::{Position, TextPosition, is_whitespace_char, is_name_char};
+use encoding::SingleByteEncoding;
 use reader::Error;
 use util;
 
@@ -54,6 +55,11 @@ pub enum Token {
     ReferenceStart,
     /// `;`
     ReferenceEnd,
+    /// A run of `Character` and `Whitespace` tokens, see `Lexer::text()`. Either all of
+    /// its characters are whitespace or the first one is not.
+    ///
+    /// Only produced when enabled with `Lexer::set_text_runs()`.
+    Text,
 }
 
 impl fmt::Display for Token {
@@ -126,7 +132,7 @@ impl Token {
     #[inline]
     pub fn contains_char_data(&self) -> bool {
         match *self {
-            Token::Whitespace(_) | Token::Chunk(_) | Token::Character(_) | Token::CommentEnd |
+            Token::Whitespace(_) | Token::Chunk(_) | Token::Character(_) | Token::Text | Token::CommentEnd |
             Token::TagEnd | Token::EqualsSign | Token::DoubleQuote | Token::SingleQuote => true,
             _ => false
         }
@@ -204,6 +210,14 @@ macro_rules! dispatch_on_enum_state(
     )
 );
 
+/// Replacement text of an entity which is being read instead of the input stream.
+struct EntityFrame {
+    chars: VecDeque<char>,
+    /// Position of the input stream where the entity was referenced
+    pos: TextPosition,
+    offset: u64
+}
+
 /// `Lexer` is a lexer for XML documents, which implements pull API.
 ///
 /// Main method is `next_token` which accepts an `std::io::Read` instance and
@@ -214,9 +228,19 @@ macro_rules! dispatch_on_enum_state(
 /// By default this flag is not set. Use `enable_errors` and `disable_errors` methods
 /// to toggle the behavior.
 pub struct Lexer {
+    reader: util::CharReader,
     pos: TextPosition,
     head_pos: TextPosition,
+    /// Byte offsets of the start of the last token and of the end of the last
+    /// consumed character, see `offset()` and `head_offset()`.
+    offset: u64,
+    head_offset: u64,
+    /// Whether `offset` is not yet known because no character of the token is read.
+    offset_pending: bool,
     char_queue: VecDeque<char>,
+    entities: Vec<EntityFrame>,
+    text_runs: bool,
+    chars_read: usize,
     st: State,
     skip_errors: bool,
     inside_comment: bool,
@@ -234,9 +258,16 @@ impl Lexer {
     /// Returns a new lexer with default state.
     pub fn new() -> Lexer {
         Lexer {
+            reader: util::CharReader::new(),
             pos: TextPosition::new(),
             head_pos: TextPosition::new(),
+            offset: 0,
+            head_offset: 0,
+            offset_pending: false,
             char_queue: VecDeque::with_capacity(4),  // TODO: check size
+            entities: Vec::new(),
+            text_runs: false,
+            chars_read: 0,
             st: State::Normal,
             skip_errors: false,
             inside_comment: false,
@@ -264,10 +295,83 @@ impl Lexer {
     #[inline]
     pub fn outside_comment(&mut self) { self.inside_comment = false; }
 
+    /// Enables or disables reading runs of plain characters of the input stream as
+    /// `Token::Text` tokens.
+    ///
+    /// Runs are found by scanning the buffered input, which is much faster than reading
+    /// characters one by one, so the parser enables them where it only collects text.
+    #[inline]
+    pub fn set_text_runs(&mut self, enabled: bool) { self.text_runs = enabled; }
+
+    /// Returns the characters of the last `Token::Text`; they are borrowed from the buffer
+    /// of the reader, so they are only available until the next token is read.
+    #[inline]
+    pub fn text(&self) -> &str { self.reader.last_run() }
+
     /// Reset the eof handled flag of the lexer.
     #[inline]
     pub fn reset_eof_handled(&mut self) { self.eof_handled = false; }
 
+    /// Makes the lexer read the given entity replacement text before continuing with
+    /// the input stream.
+    ///
+    /// Tokens never span the boundaries of a replacement text. All tokens read from it
+    /// are positioned right after the entity reference.
+    pub fn push_entity(&mut self, text: &str) {
+        self.entities.push(EntityFrame {
+            chars: text.chars().collect(),
+            pos: self.head_pos,
+            offset: self.head_offset
+        });
+    }
+
+    /// Returns the number of entity replacement texts which are being read at the moment.
+    ///
+    /// A replacement text is considered read when the token following its last token
+    /// is returned by `next_token()`.
+    #[inline]
+    pub fn entity_depth(&self) -> usize { self.entities.len() }
+
+    /// Returns the byte offset of the last token produced by the lexer in the input stream.
+    ///
+    /// Like positions, offsets of tokens read from an entity replacement text are the offset
+    /// right after the entity reference.
+    #[inline]
+    pub fn offset(&self) -> u64 { self.offset }
+
+    /// Returns the byte offset of the end of the last token produced by the lexer in
+    /// the input stream.
+    #[inline]
+    pub fn head_offset(&self) -> u64 { self.head_offset }
+
+    /// Returns the number of characters read from the input stream so far, not counting
+    /// characters of entity replacement texts.
+    #[inline]
+    pub fn chars_read(&self) -> usize { self.chars_read }
+
+    /// Returns the encoding of the input stream, which is detected when the first token
+    /// is read.
+    #[inline]
+    pub fn encoding(&self) -> util::Encoding { self.reader.encoding() }
+
+    /// Returns true if the input stream starts with a byte order mark.
+    #[inline]
+    pub fn has_bom(&self) -> bool { self.reader.has_bom() }
+
+    /// Checks the encoding declared in the XML declaration and switches to it if needed,
+    /// see `util::CharReader::declare_encoding()`.
+    #[inline]
+    pub fn declare_encoding<F>(&mut self, name: &str, find: F) -> result::Result<(), String>
+        where F: FnOnce(&str) -> Option<SingleByteEncoding>
+    {
+        self.reader.declare_encoding(name, find)
+    }
+
+    /// Makes NEL and LINE SEPARATOR characters of the input stream line breaks, which
+    /// is done after an XML 1.1 declaration is read.
+    #[inline]
+    pub fn set_xml11_line_breaks(&mut self) { self.reader.set_xml11_line_breaks() }
+
     /// Tries to read the next token from the buffer.
     ///
     /// It is possible to pass different instaces of `BufReader` each time
@@ -285,11 +389,14 @@ impl Lexer {
 
         if !self.inside_token {
             self.pos = self.head_pos;
+            self.offset = self.head_offset;
+            self.offset_pending = true;
             self.inside_token = true;
         }
 
         // Check if we have saved a char or two for ourselves
         while let Some(c) = self.char_queue.pop_front() {
+            self.offset_pending = false;
             match try!(self.read_next_token(c)) {
                 Some(t) => {
                     self.inside_token = false;
@@ -300,10 +407,58 @@ impl Lexer {
         }
 
         loop {
-            // TODO: this should handle multiple encodings
-            let c = match try!(util::next_char_from(b)) {
-                Some(c) => c,   // got next char
-                None => break,  // nothing to read left
+            if self.text_runs && self.entities.is_empty() && self.char_queue.is_empty() {
+                if let State::Normal = self.st {
+                    if let Some(run) = try!(self.reader.read_run(b)) {
+                        for c in run.chars() {
+                            self.chars_read += 1;
+                            if c == '\n' {
+                                self.head_pos.new_line();
+                            } else {
+                                self.head_pos.advance(1);
+                            }
+                        }
+                        self.start_token_at_reader();
+                        self.head_offset = self.reader.offset();
+                        self.inside_token = false;
+                        return Ok(Some(Token::Text));
+                    }
+                }
+            }
+
+            let c = if let Some(frame) = self.entities.last_mut() {
+                self.offset_pending = false;
+                frame.chars.pop_front()
+            } else {
+                match try!(self.reader.next_char_from(b)) {
+                    Some(c) => {  // got next char
+                        self.chars_read += 1;
+                        self.start_token_at_reader();
+                        Some(c)
+                    }
+                    None => break,  // nothing to read left
+                }
+            };
+
+            let c = match c {
+                Some(c) => c,
+                None => {
+                    // End of the replacement text, finish the last token inside it
+                    let res = self.finish_token("Unexpected end of entity");
+                    self.st = State::Normal;
+                    match try!(res) {
+                        Some(t) => {
+                            self.inside_token = false;
+                            return Ok(Some(t));
+                        }
+                        None => {
+                            let frame = self.entities.pop().unwrap();
+                            self.head_pos = frame.pos;
+                            self.head_offset = frame.offset;
+                            continue;
+                        }
+                    }
+                }
             };
 
             match try!(self.read_next_token(c)) {
@@ -320,11 +475,31 @@ impl Lexer {
         // Handle end of stream
         self.eof_handled = true;
         self.pos = self.head_pos;
+        self.head_offset = self.reader.offset();
+        self.offset = self.head_offset;
+        self.finish_token("Unexpected end of stream")
+    }
+
+    /// Sets the offset of the current token to the offset of the last character read from
+    /// the input stream if it is the first character of the token.
+    ///
+    /// The offset is not simply `head_offset`, since a line feed following a carriage return
+    /// is skipped when the next character is read.
+    #[inline]
+    fn start_token_at_reader(&mut self) {
+        if self.offset_pending {
+            self.offset_pending = false;
+            self.offset = self.reader.char_start();
+        }
+    }
+
+    /// Returns the remains of the token which was interrupted by the end of input.
+    fn finish_token(&self, msg: &'static str) -> Result {
         match self.st {
             State::TagStarted | State::CommentOrCDataOrDoctypeStarted |
             State::CommentStarted | State::CDataStarted(_)| State::DoctypeStarted(_) |
             State::CommentClosing(ClosingSubstate::Second)  =>
-                Err(self.error("Unexpected end of stream")),
+                Err(self.error(msg)),
             State::ProcessingInstructionClosing =>
                 Ok(Some(Token::Character('?'))),
             State::EmptyTagClosing =>
@@ -348,12 +523,13 @@ impl Lexer {
     #[inline]
     fn read_next_token(&mut self, c: char) -> Result {
         let res = self.dispatch_char(c);
-        if self.char_queue.is_empty() {
+        if self.char_queue.is_empty() && self.entities.is_empty() {
             if c == '\n' {
                 self.head_pos.new_line();
             } else {
                 self.head_pos.advance(1);
             }
+            self.head_offset = self.reader.offset();
         }
         res
     }
@@ -554,7 +730,7 @@ mod tests {
 
     macro_rules! assert_none(
         (for $lex:ident and $buf:ident) => (
-            assert_eq!(Ok(None), $lex.next_token(&mut $buf));
+            assert_eq!(Ok(None), $lex.next_token(&mut $buf))
         )
     );
 
@@ -841,4 +1017,72 @@ mod tests {
         );
         assert_none!(for lex and buf);
     }
+
+    #[test]
+    fn entity_replacement_text() {
+        let (mut lex, mut buf) = make_lex_and_buf(
+            r#"&e;>&e;"#
+        );
+
+        assert_oks!(for lex and buf ;
+            Token::ReferenceStart
+            Token::Character('e')
+            Token::ReferenceEnd
+        );
+        lex.push_entity("<a/>]");
+        assert_eq!(1, lex.entity_depth());
+        assert_oks!(for lex and buf ;
+            Token::OpeningTagStart
+            Token::Character('a')
+            Token::EmptyTagEnd
+        );
+        // tokens never span the end of replacement text
+        assert_oks!(for lex and buf ; Token::Character(']'));
+        assert_eq!((0, 3), (lex.position().row, lex.position().column));
+        assert_eq!(1, lex.entity_depth());
+        assert_oks!(for lex and buf ; Token::TagEnd);
+        assert_eq!(0, lex.entity_depth());
+        assert_eq!((0, 3), (lex.position().row, lex.position().column));
+
+        assert_oks!(for lex and buf ;
+            Token::ReferenceStart
+            Token::Character('e')
+            Token::ReferenceEnd
+        );
+        lex.push_entity("<!-");
+        assert_err!(for lex and buf expect row 0 ; 7, "Unexpected end of entity");
+    }
+
+    #[test]
+    fn text_runs() {
+        let (mut lex, mut buf) = make_lex_and_buf(
+            "<a>  \n x y\u{444}\r\nz&amp;w</a>"
+        );
+
+        assert_oks!(for lex and buf ; Token::OpeningTagStart);
+        lex.set_text_runs(true);
+        // runs are not read in the middle of a token
+        assert_oks!(for lex and buf ; Token::Character('a') Token::TagEnd);
+
+        // leading whitespace is a separate run
+        assert_oks!(for lex and buf ; Token::Text);
+        assert_eq!("  \n ", lex.text());
+        assert_eq!((0, 3), (lex.position().row, lex.position().column));
+        assert_oks!(for lex and buf ; Token::Text);
+        assert_eq!("x y\u{444}", lex.text());
+        assert_eq!((1, 1), (lex.position().row, lex.position().column));
+
+        // carriage returns are normalized by the character reader
+        assert_oks!(for lex and buf ; Token::Whitespace('\n'));
+        assert_eq!((1, 5), (lex.position().row, lex.position().column));
+        assert_oks!(for lex and buf ; Token::Text);
+        assert_eq!("z", lex.text());
+        assert_eq!((2, 0), (lex.position().row, lex.position().column));
+
+        assert_oks!(for lex and buf ; Token::ReferenceStart Token::Text);
+        assert_eq!("amp", lex.text());
+        assert_oks!(for lex and buf ; Token::ReferenceEnd);
+        lex.set_text_runs(false);
+        assert_oks!(for lex and buf ; Token::Character('w') Token::ClosingTagStart);
+    }
 }
@@ -3,31 +3,83 @@
 //! The most important type in this module is `EventReader`, which provides an iterator
 //! view for events in XML document.
 
+use std::cmp;
+use std::collections::VecDeque;
 use std::io::{Read};
 use std::result;
 
-use common::{Position, TextPosition};
+use common::{Position, TextPosition, Span, AttributeSpan};
+use name::{Name, OwnedName};
+use namespace::NamespaceStack;
 
 pub use self::config::ParserConfig;
-pub use self::events::XmlEvent;
+pub use self::events::{XmlEvent, BorrowedEvent, BorrowedName, BorrowedAttribute};
+pub use self::resolver::{
+    EntityResolver, ResolverHandle, RefusingResolver, DirectoryResolver, MapResolver
+};
+pub use self::slice::{SliceReader, SliceEvents};
+pub use self::push::PushReader;
+#[cfg(feature = "async")]
+pub use self::async_reader::{AsyncEventReader, NextEvent};
 
 use self::parser::PullParser;
 
 mod lexer;
 mod parser;
+mod dtd_parser;
 mod config;
 mod events;
+mod resolver;
+mod validator;
+mod slice;
+mod push;
+#[cfg(feature = "async")]
+mod async_reader;
 
 mod error;
 pub use self::error::{Error, ErrorKind};
+pub(crate) use self::error::validity;
 
 /// A result type yielded by `XmlReader`.
 pub type Result<T> = result::Result<T, Error>;
 
 /// A wrapper around an `std::io::Read` instance which provides pull-based XML parsing.
+///
+/// The stream is read in chunks of several kilobytes, so it does not need to be buffered.
 pub struct EventReader<R: Read> {
     source: R,
-    parser: PullParser
+    parser: PullParser,
+    /// Events read ahead by `peek_nth()`, with their locations
+    peeked: VecDeque<(Result<XmlEvent>, Location)>,
+    max_lookahead: usize,
+    location: Location
+}
+
+/// The position and spans of an event.
+struct Location {
+    pos: TextPosition,
+    span: Span,
+    attribute_spans: Vec<AttributeSpan>,
+    /// The element path and namespace mappings of the event; they are only copied from
+    /// the parser when it reads ahead of the event.
+    context: Option<(Vec<OwnedName>, NamespaceStack)>
+}
+
+impl Location {
+    fn take_from(parser: &mut PullParser) -> Location {
+        Location {
+            pos: parser.position(),
+            span: parser.span(),
+            attribute_spans: parser.take_attribute_spans(),
+            context: None
+        }
+    }
+
+    fn save_context(&mut self, parser: &PullParser) {
+        if self.context.is_none() {
+            self.context = Some((parser.element_path().to_vec(), parser.namespace_stack().clone()));
+        }
+    }
 }
 
 impl<R: Read> EventReader<R> {
@@ -40,16 +92,225 @@ impl<R: Read> EventReader<R> {
     /// Creates a new reader with the provded configuration, consuming the given stream.
     #[inline]
     pub fn new_with_config(source: R, config: ParserConfig) -> EventReader<R> {
-        EventReader { source: source, parser: PullParser::new(config) }
+        let max_lookahead = config.max_lookahead;
+        let mut parser = PullParser::new(config);
+        let location = Location::take_from(&mut parser);
+        EventReader {
+            source: source,
+            parser: parser,
+            peeked: VecDeque::new(),
+            max_lookahead: max_lookahead,
+            location: location
+        }
     }
 
     /// Pulls and returns next XML event from the stream.
     ///
     /// If returned event is `XmlEvent::Error` or `XmlEvent::EndDocument`, then
-    /// further calls to this method will return this event again.
-    #[inline]
+    /// further calls to this method will return this event again. The only exception
+    /// is an error for which `Error::is_would_block()` is true: it is returned when a
+    /// non-blocking stream has no data yet, and the next call continues parsing.
     pub fn next(&mut self) -> Result<XmlEvent> {
-        self.parser.next(&mut self.source)
+        match self.peeked.pop_front() {
+            Some((ev, location)) => {
+                self.location = location;
+                ev
+            }
+            None => {
+                let ev = self.parser.next(&mut self.source);
+                self.location = Location::take_from(&mut self.parser);
+                ev
+            }
+        }
+    }
+
+    /// Returns the next event without consuming it, so that the next call to `next()`
+    /// returns the same event.
+    ///
+    /// `position()` and `span()` still return the location of the last event returned
+    /// by `next()`.
+    #[inline]
+    pub fn peek(&mut self) -> Result<&XmlEvent> {
+        self.peek_nth(0)
+    }
+
+    /// Returns the event which follows the next one by `n` events without consuming
+    /// any events; `peek_nth(0)` is the same as `peek()`.
+    ///
+    /// Up to `n + 1` events are read from the stream and kept until they are returned by
+    /// `next()`. If the document ends or an error occurs before that, the last event
+    /// (`XmlEvent::EndDocument` or the error) is returned.
+    ///
+    /// `n` must be less than `ParserConfig::max_lookahead`; otherwise, an error of kind
+    /// `ErrorKind::LookaheadLimit` is returned and no events are read.
+    pub fn peek_nth(&mut self, n: usize) -> Result<&XmlEvent> {
+        if n >= self.max_lookahead {
+            return Err(error::lookahead_limit(self, format!("Lookahead limit of {} events exceeded", self.max_lookahead)));
+        }
+        while self.peeked.len() <= n {
+            match self.peeked.back() {
+                Some(&(Ok(XmlEvent::EndDocument), _)) | Some(&(Err(_), _)) => break,
+                _ => {}
+            }
+            // the parser is about to move past the context of the last event
+            match self.peeked.back_mut() {
+                Some(&mut (_, ref mut location)) => location.save_context(&self.parser),
+                None => self.location.save_context(&self.parser)
+            }
+            let ev = self.parser.next(&mut self.source);
+            if let Err(ref e) = ev {
+                // the read is retried by the next call
+                if e.is_would_block() {
+                    return Err(e.clone());
+                }
+            }
+            let location = Location::take_from(&mut self.parser);
+            self.peeked.push_back((ev, location));
+        }
+        let i = cmp::min(n, self.peeked.len() - 1);
+        match self.peeked[i].0 {
+            Ok(ref ev) => Ok(ev),
+            Err(ref e) => Err(e.clone())
+        }
+    }
+
+    /// Returns the span of bytes of the input stream taken by the last event returned
+    /// by `next()`.
+    ///
+    /// Character data ends where the next markup starts, and `EndDocument` has an empty span
+    /// at the end of the stream. `StartDocument` without an XML declaration has an empty span
+    /// at the first markup, and both events of an empty element have the span of its tag.
+    /// Events produced by an entity reference have an empty span right after it.
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.location.span
+    }
+
+    /// Returns the spans of the attribute names and values of the last event returned by
+    /// `next()`, if it is `StartElement`; otherwise, the returned slice is empty.
+    ///
+    /// The spans are in the order of the attributes of the event. Attributes with default
+    /// values declared in the DTD come last and have no spans.
+    #[inline]
+    pub fn attribute_spans(&self) -> &[AttributeSpan] {
+        &self.location.attribute_spans
+    }
+
+    /// Skips the rest of the current element, up to and including its `EndElement` event.
+    ///
+    /// This method is meant to be called after `next()` has returned a `StartElement` event.
+    /// Character data, comments and namespace mappings inside the skipped content are not
+    /// collected, so skipping large subtrees is cheap; the content is still checked for
+    /// well-formedness.
+    pub fn skip_element(&mut self) -> Result<()> {
+        self.parser.set_discard_text(true);
+        let result = self.skip_to_end(None);
+        self.parser.set_discard_text(false);
+        result
+    }
+
+    /// Skips events up to and including the `EndElement` event of the enclosing element
+    /// with the given name, like `skip_element()` does.
+    ///
+    /// The local name of the element must match; the namespace and the prefix must also
+    /// match if they are present in `name`. Closing tags of other enclosing elements are
+    /// skipped as well.
+    pub fn read_to_end<'a, N: Into<Name<'a>>>(&mut self, name: N) -> Result<()> {
+        let name = name.into();
+        self.parser.set_discard_text(true);
+        let result = self.skip_to_end(Some(name));
+        self.parser.set_discard_text(false);
+        result
+    }
+
+    /// Reads the character data of the current element up to and including its
+    /// `EndElement` event.
+    ///
+    /// This method is meant to be called after `next()` has returned a `StartElement` event.
+    /// Characters, CDATA and white space of the element and of its nested elements are
+    /// concatenated; other events are skipped.
+    pub fn read_text(&mut self) -> Result<String> {
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match try!(self.next()) {
+                XmlEvent::StartElement { .. } => depth += 1,
+                XmlEvent::EndElement { .. } if depth == 0 => return Ok(text),
+                XmlEvent::EndElement { .. } => depth -= 1,
+                XmlEvent::Characters(ref data) | XmlEvent::CData(ref data) |
+                XmlEvent::Whitespace(ref data) => text.push_str(data),
+                XmlEvent::EndDocument =>
+                    return Err((&*self, "Unexpected end of document while reading text").into()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the names of the elements enclosing the last event, starting with the root
+    /// element.
+    ///
+    /// For `StartElement` and `EndElement` events the path ends with the element itself.
+    /// Events read ahead with `peek()` do not affect it.
+    #[inline]
+    pub fn element_path(&self) -> &[OwnedName] {
+        match self.location.context {
+            Some((ref path, _)) => path,
+            None => self.parser.element_path()
+        }
+    }
+
+    /// Returns the number of elements enclosing the last event, that is, the length of
+    /// `element_path()`.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.element_path().len()
+    }
+
+    /// Returns the namespace mappings in scope at the last event.
+    ///
+    /// For `StartElement` and `EndElement` events these include the mappings declared by
+    /// the element itself. Events read ahead with `peek()` do not affect them.
+    #[inline]
+    pub fn namespace(&self) -> &NamespaceStack {
+        match self.location.context {
+            Some((_, ref namespace)) => namespace,
+            None => self.parser.namespace_stack()
+        }
+    }
+
+    /// Resolves a qualified name found in the content of the document, e.g. in an attribute
+    /// value like `xsi:type="ns:Foo"`, using the namespace mappings in scope.
+    ///
+    /// A name without a prefix belongs to the default namespace. Returns `None` if `qname`
+    /// is not a valid qualified name or if its prefix is not bound.
+    pub fn resolve_qname(&self, qname: &str) -> Option<OwnedName> {
+        let mut name: OwnedName = match qname.parse() {
+            Ok(name) => name,
+            Err(_) => return None
+        };
+        match self.namespace().get(name.borrow().prefix_repr()) {
+            Some("") => name.namespace = None,  // default namespace
+            Some(ns) => name.namespace = Some(ns.into()),
+            None => return None
+        }
+        Some(name)
+    }
+
+    fn skip_to_end(&mut self, name: Option<Name>) -> Result<()> {
+        let mut depth = 0;
+        loop {
+            match try!(self.next()) {
+                XmlEvent::StartElement { .. } => depth += 1,
+                XmlEvent::EndElement { name: ref end } if depth == 0 => match name {
+                    Some(ref name) if !names_match(name, end) => {}
+                    _ => return Ok(())
+                },
+                XmlEvent::EndElement { .. } => depth -= 1,
+                XmlEvent::EndDocument =>
+                    return Err((&*self, "Unexpected end of document while skipping an element").into()),
+                _ => {}
+            }
+        }
     }
 
     pub fn source(&self) -> &R { &self.source }
@@ -59,17 +320,25 @@ impl<R: Read> EventReader<R> {
     ///
     /// Note that this operation is destructive; unwrapping the reader and wrapping it
     /// again with `EventReader::new()` will create a fresh reader which will attempt
-    /// to parse an XML document from the beginning.
+    /// to parse an XML document from the beginning. Since the stream is read in chunks,
+    /// the returned reader may be positioned after the end of the last event; events
+    /// read ahead by `peek()` are lost.
     pub fn into_inner(self) -> R {
         self.source
     }
 }
 
+fn names_match(name: &Name, end: &OwnedName) -> bool {
+    name.local_name == end.local_name &&
+        (name.namespace.is_none() || name.namespace == end.namespace_ref()) &&
+        (name.prefix.is_none() || name.prefix == end.prefix_ref())
+}
+
 impl<B: Read> Position for EventReader<B> {
     /// Returns the position of the last event produced by the reader.
     #[inline]
     fn position(&self) -> TextPosition {
-        self.parser.position()
+        self.location.pos
     }
 }
 
@@ -85,7 +354,8 @@ impl<R: Read> IntoIterator for EventReader<R> {
 /// An iterator over XML events created from some type implementing `Read`.
 ///
 /// When the next event is `xml::event::Error` or `xml::event::EndDocument`, then
-/// it will be returned by the iterator once, and then it will stop producing events.
+/// it will be returned by the iterator once, and then it will stop producing events,
+/// unless the error is a `WouldBlock` one (see `EventReader::next()`).
 pub struct Events<R: Read> {
     reader: EventReader<R>,
     finished: bool
@@ -112,6 +382,7 @@ impl<R: Read> Iterator for Events<R> {
         else {
             let ev = self.reader.next();
             match ev {
+                Err(ref e) if e.is_would_block() => {}
                 Ok(XmlEvent::EndDocument) | Err(_) => self.finished = true,
                 _ => {}
             }
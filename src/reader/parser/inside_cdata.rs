@@ -9,9 +9,11 @@ impl PullParser {
             Token::CDataEnd => {
                 self.lexer.enable_errors();
                 let event = if self.config.cdata_to_characters {
+                    // the section starts character data
+                    self.text_pos_pushed = true;
                     None
                 } else {
-                    let data = self.take_buf();
+                    let data = self.take_text();
                     Some(Ok(XmlEvent::CData(data)))
                 };
                 self.into_state(State::OutsideTag, event)
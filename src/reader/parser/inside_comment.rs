@@ -16,7 +16,7 @@ impl PullParser {
 
             Token::CommentEnd => {
                 self.lexer.outside_comment();
-                let data = self.take_buf();
+                let data = self.take_text();
                 self.into_state_emit(State::OutsideTag, Ok(XmlEvent::Comment(data)))
             }
 
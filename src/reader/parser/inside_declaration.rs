@@ -2,11 +2,12 @@
 use common::XmlVersion;
 
 use reader::events::XmlEvent;
+use reader::config;
 use reader::lexer::Token;
 
 use super::{
     Result, PullParser, State, DeclarationSubstate, QualifiedNameTarget,
-    DEFAULT_VERSION, DEFAULT_ENCODING
+    DEFAULT_VERSION
 };
 
 impl PullParser {
@@ -21,11 +22,11 @@ impl PullParser {
         fn emit_start_document(this: &mut PullParser) -> Option<Result> {
             this.parsed_declaration = true;
             let version = this.data.take_version();
-            let encoding = this.data.take_encoding();
+            let encoding = this.data.take_encoding().unwrap_or_else(|| this.detected_encoding());
             let standalone = this.data.take_standalone();
             this.into_state_emit(State::OutsideTag, Ok(XmlEvent::StartDocument {
                 version: version.unwrap_or(DEFAULT_VERSION),
-                encoding: encoding.unwrap_or(DEFAULT_ENCODING.into()),
+                encoding: encoding,
                 standalone: standalone
             }))
         }
@@ -63,6 +64,9 @@ impl PullParser {
                     "1.1" => Some(XmlVersion::Version11),
                     _     => None
                 };
+                if this.data.version == Some(XmlVersion::Version11) {
+                    this.lexer.set_xml11_line_breaks();
+                }
                 if this.data.version.is_some() {
                     this.into_state_continue(State::InsideDeclaration(DeclarationSubstate::AfterVersionValue))
                 } else {
@@ -95,6 +99,13 @@ impl PullParser {
             },
 
             DeclarationSubstate::InsideEncodingValue => self.read_attribute_value(t, |this, value| {
+                let result = {
+                    let cfg = &this.config;
+                    this.lexer.declare_encoding(&value, |name| config::find_encoding(cfg, name))
+                };
+                if let Err(msg) = result {
+                    return Some(this.error(msg));
+                }
                 this.data.encoding = Some(value);
                 this.into_state_continue(State::InsideDeclaration(DeclarationSubstate::BeforeStandaloneDecl))
             }),
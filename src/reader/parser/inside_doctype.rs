@@ -1,16 +1,132 @@
+use std::mem;
+
+use common::TextPosition;
+use dtd::Dtd;
+use reader::events::XmlEvent;
 use reader::lexer::Token;
+use reader::dtd_parser::DtdParser;
 
-use super::{Result, PullParser, State};
+use super::{Result, PullParser, State, DoctypeSubstate, QuoteToken};
 
 impl PullParser {
-    pub fn inside_doctype(&mut self, t: Token) -> Option<Result> {
-        match t {
-            Token::TagEnd => {
-                self.lexer.enable_errors();
-                self.into_state_continue(State::OutsideTag)
+    /// Accumulates the text of a document type declaration.
+    ///
+    /// Lexing errors are disabled inside the declaration, so every token can be pushed
+    /// to the buffer as it is. Literals, comments and processing instructions are tracked
+    /// only to find the `>` which closes the declaration; the collected text is then
+    /// parsed by `DtdParser`.
+    pub fn inside_doctype(&mut self, t: Token, s: DoctypeSubstate) -> Option<Result> {
+        match s {
+            DoctypeSubstate::Outside => match t {
+                Token::TagEnd => self.emit_doctype(),
+
+                Token::Character('[') => {
+                    self.buf.push('[');
+                    self.into_state_continue(State::InsideDoctype(DoctypeSubstate::InsideSubset))
+                }
+
+                Token::SingleQuote | Token::DoubleQuote => {
+                    t.push_to_string(&mut self.buf);
+                    self.into_state_continue(State::InsideDoctype(DoctypeSubstate::InsideLiteral {
+                        quote: QuoteToken::from_token(&t),
+                        in_subset: false
+                    }))
+                }
+
+                _ => {
+                    t.push_to_string(&mut self.buf);
+                    None
+                }
+            },
+
+            DoctypeSubstate::InsideSubset => {
+                let next = match t {
+                    Token::Character(']') => Some(DoctypeSubstate::Outside),
+                    Token::SingleQuote | Token::DoubleQuote => Some(DoctypeSubstate::InsideLiteral {
+                        quote: QuoteToken::from_token(&t),
+                        in_subset: true
+                    }),
+                    Token::CommentStart => Some(DoctypeSubstate::InsideComment),
+                    Token::ProcessingInstructionStart => Some(DoctypeSubstate::InsideProcessingInstruction),
+
+                    // `]]>` cannot close a conditional section here, so the declaration ends;
+                    // the extra bracket will be reported by the DTD parser
+                    Token::CDataEnd => {
+                        self.buf.push_str("]]");
+                        return self.emit_doctype();
+                    }
+                    _ => None
+                };
+                t.push_to_string(&mut self.buf);
+                match next {
+                    Some(s) => self.into_state_continue(State::InsideDoctype(s)),
+                    None => None
+                }
+            }
+
+            DoctypeSubstate::InsideLiteral { quote, in_subset } => {
+                t.push_to_string(&mut self.buf);
+                if t == quote.as_token() {
+                    let s = if in_subset { DoctypeSubstate::InsideSubset } else { DoctypeSubstate::Outside };
+                    self.into_state_continue(State::InsideDoctype(s))
+                } else {
+                    None
+                }
             }
 
-            _ => None
+            DoctypeSubstate::InsideComment => {
+                t.push_to_string(&mut self.buf);
+                if t == Token::CommentEnd {
+                    self.into_state_continue(State::InsideDoctype(DoctypeSubstate::InsideSubset))
+                } else {
+                    None
+                }
+            }
+
+            DoctypeSubstate::InsideProcessingInstruction => {
+                t.push_to_string(&mut self.buf);
+                if t == Token::ProcessingInstructionEnd {
+                    self.into_state_continue(State::InsideDoctype(DoctypeSubstate::InsideSubset))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn emit_doctype(&mut self) -> Option<Result> {
+        self.lexer.enable_errors();
+        let text = self.take_buf();
+
+        // the text starts right after `<!DOCTYPE`
+        let mut pos = self.pos.last().unwrap().0;
+        pos.advance("<!DOCTYPE".len() as u8);
+
+        match DtdParser::new(&text, pos, &self.config).parse_doctype() {
+            Ok(mut doctype) => {
+                if let Some(ref system_id) = doctype.system_id {
+                    let external = self.read_external_entity(doctype.public_id.as_ref().map(|s| &s[..]), system_id);
+                    match external {
+                        Ok(Some(text)) => {
+                            let dtd = mem::replace(&mut doctype.dtd, Dtd::new());
+                            match DtdParser::new(&text, TextPosition::new(), &self.config).parse_external_subset(dtd) {
+                                Ok(dtd) => doctype.dtd = dtd,
+                                Err(e) => return Some(Err(e))
+                            }
+                        }
+                        Ok(None) => {}  // the external subset is optional for non-validating parsers
+                        Err(e) => return Some(Err(e))
+                    }
+                }
+                self.dtd = doctype.dtd.clone();
+                self.into_state_emit(State::OutsideTag, Ok(XmlEvent::Doctype {
+                    name: doctype.name,
+                    public_id: doctype.public_id,
+                    system_id: doctype.system_id,
+                    dtd: doctype.dtd
+                }))
+            }
+            Err(e) => Some(Err(e))
         }
     }
 }
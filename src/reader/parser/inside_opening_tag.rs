@@ -1,6 +1,9 @@
-use common::is_name_start_char;
+use common::{AttributeSpan, is_name_start_char};
 use attribute::OwnedAttribute;
+use name::OwnedName;
+use dtd::{AttributeType, AttributeDefault};
 use namespace;
+use util;
 
 use reader::lexer::Token;
 
@@ -31,6 +34,7 @@ impl PullParser {
                 Token::Whitespace(_) => None,  // skip whitespace
                 Token::Character(c) if is_name_start_char(c) => {
                     self.buf.push(c);
+                    self.data.attr_name_span.start = self.lexer.offset();
                     self.into_state_continue(State::InsideOpeningTag(OpeningTagSubstate::InsideAttributeName))
                 }
                 Token::TagEnd => self.emit_start_element(false),
@@ -40,6 +44,7 @@ impl PullParser {
 
             OpeningTagSubstate::InsideAttributeName => self.read_qualified_name(t, QualifiedNameTarget::AttributeNameTarget, |this, token, name| {
                 this.data.attr_name = Some(name);
+                this.data.attr_name_span.end = this.lexer.offset();
                 match token {
                     Token::Whitespace(_) => this.into_state_continue(State::InsideOpeningTag(OpeningTagSubstate::AfterAttributeName)),
                     Token::EqualsSign => this.into_state_continue(State::InsideOpeningTag(OpeningTagSubstate::InsideAttributeValue)),
@@ -95,7 +100,12 @@ impl PullParser {
                         _ => {
                             this.data.attributes.push(OwnedAttribute {
                                 name: name.clone(),
-                                value: value
+                                value: value,
+                                defaulted: false
+                            });
+                            this.data.attribute_spans.push(AttributeSpan {
+                                name: this.data.attr_name_span,
+                                value: this.data.attr_value_span
                             });
                             this.into_state_continue(State::InsideOpeningTag(OpeningTagSubstate::InsideTag))
                         }
@@ -105,4 +115,50 @@ impl PullParser {
         }
     }
 
+    /// Applies attribute list declarations from the DTD to the attributes of an element:
+    /// normalizes values of tokenized attributes and adds attributes with default values
+    /// which are not specified in the start tag, including namespace declarations.
+    pub fn apply_attribute_declarations(&mut self, element: &OwnedName, attributes: &mut Vec<OwnedAttribute>) {
+        let all_whitespace = !self.config.normalize_attribute_values;
+        let decls = match self.dtd.attributes.get(&element.borrow().to_repr()) {
+            Some(decls) => decls,
+            None => return
+        };
+
+        for attr in attributes.iter_mut() {
+            let name = attr.name.borrow().to_repr();
+            match decls.iter().find(|d| d.name == name) {
+                Some(decl) if decl.attribute_type != AttributeType::CData =>
+                    attr.value = util::normalize_tokenized(&attr.value, all_whitespace),
+                _ => {}
+            }
+        }
+
+        for decl in decls {
+            let value = match decl.default {
+                AttributeDefault::Value(ref value) | AttributeDefault::Fixed(ref value) => value,
+                AttributeDefault::Required | AttributeDefault::Implied => continue
+            };
+            let value = if decl.attribute_type == AttributeType::CData {
+                value.clone()
+            } else {
+                util::normalize_tokenized(value, all_whitespace)
+            };
+
+            if decl.name == namespace::NS_XMLNS_PREFIX || decl.name.starts_with("xmlns:") {
+                let prefix = if decl.name == namespace::NS_XMLNS_PREFIX {
+                    namespace::NS_NO_PREFIX
+                } else {
+                    &decl.name["xmlns:".len()..]
+                };
+                if !self.nst.peek().contains(prefix) && !value.is_empty() {
+                    self.nst.put(prefix, value);
+                }
+            } else if !attributes.iter().any(|a| a.name.borrow().to_repr() == decl.name) {
+                // unwrap() will always succeed here, declared names are checked by the DTD parser
+                let name = decl.name.parse().unwrap();
+                attributes.push(OwnedAttribute { name: name, value: value, defaulted: true });
+            }
+        }
+    }
 }
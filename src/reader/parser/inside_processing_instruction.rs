@@ -74,7 +74,7 @@ impl PullParser {
                 Token::ProcessingInstructionEnd => {
                     self.lexer.enable_errors();
                     let name = self.data.take_name();
-                    let data = self.take_buf();
+                    let data = self.take_text();
                     self.into_state_emit(
                         State::OutsideTag,
                         Ok(XmlEvent::ProcessingInstruction {
@@ -1,7 +1,10 @@
-use std::char;
+use std::borrow::Cow;
 
 use common::{is_name_start_char, is_name_char, is_whitespace_str};
+use dtd::EntityDef;
+use util;
 
+use reader::dtd_parser::{AttributeExpansion, replacement_text};
 use reader::lexer::Token;
 
 use super::{Result, PullParser, State};
@@ -18,43 +21,19 @@ impl PullParser {
             Token::ReferenceEnd => {
                 // TODO: check for unicode correctness
                 let name = self.data.take_ref_data();
-                let name_len = name.len();  // compute once
                 let c = match &name[..] {
-                    "lt"   => Ok('<'.to_string()),
-                    "gt"   => Ok('>'.to_string()),
-                    "amp"  => Ok('&'.to_string()),
-                    "apos" => Ok('\''.to_string()),
-                    "quot" => Ok('"'.to_string()),
-                    ""     => Err(self_error!(self; "Encountered empty entity")),
-                    _ if name_len > 2 && name.starts_with("#x") => {
-                        let num_str = &name[2..name_len];
-                        if num_str == "0" {
-                            Err(self_error!(self; "Null character entity is not allowed"))
-                        } else {
-                            match u32::from_str_radix(num_str, 16).ok().and_then(char::from_u32) {
-                                Some(c) => Ok(c.to_string()),
-                                None    => Err(self_error!(self; "Invalid hexadecimal character number in an entity: {}", name))
+                    "" => Err(self_error!(self; "Encountered empty entity")),
+                    _ => match util::resolve_predefined_reference(&name) {
+                        Some(Ok(c)) => Ok(c.to_string()),
+                        Some(Err(msg)) => Err(self_error!(self; msg)),
+                        None => {
+                            if let Some(v) = self.config.extra_entities.get(&name) {
+                                Ok(v.clone())
+                            } else {
+                                return self.expand_declared_entity(name, prev_st);
                             }
                         }
                     }
-                    _ if name_len > 1 && name.starts_with('#') => {
-                        let num_str = &name[1..name_len];
-                        if num_str == "0" {
-                            Err(self_error!(self; "Null character entity is not allowed"))
-                        } else {
-                            match u32::from_str_radix(num_str, 10).ok().and_then(char::from_u32) {
-                                Some(c) => Ok(c.to_string()),
-                                None    => Err(self_error!(self; "Invalid decimal character number in an entity: {}", name))
-                            }
-                        }
-                    },
-                    _ => {
-                        if let Some(v) = self.config.extra_entities.get(&name) {
-                            Ok(v.clone())
-                        } else {
-                            Err(self_error!(self; "Unexpected entity: {}", name))
-                        }
-                    }
                 };
                 match c {
                     Ok(c) => {
@@ -71,4 +50,51 @@ impl PullParser {
             _ => Some(self_error!(self; "Unexpected token inside an entity: {}", t))
         }
     }
+
+    /// Expands a reference to a general entity declared in the DTD.
+    ///
+    /// Inside the content the replacement text is handed over to the lexer, so any markup
+    /// it contains is parsed as if it appeared in place of the reference; its well-formedness
+    /// is checked when the lexer finishes reading it, see `finish_entities()`. Inside attribute
+    /// values the replacement text is expanded right away.
+    fn expand_declared_entity(&mut self, name: String, prev_st: State) -> Option<Result> {
+        if prev_st == State::OutsideTag {
+            if self.entity_stack.iter().any(|&(ref n, _)| *n == name) {
+                return Some(self_error!(self; "Recursive entity reference: {}", name));
+            }
+            let text = match self.dtd.entity(&name).map(|e| &e.definition) {
+                Some(&EntityDef::External { ref id, notation: None }) =>
+                    match self.read_external_entity(id.public_id.as_ref().map(|s| &s[..]), &id.system_id) {
+                        Ok(Some(text)) => Cow::Owned(text),
+                        Ok(None) => return Some(self_error!(self; "Cannot resolve external entity: {}", name)),
+                        Err(e) => return Some(Err(e))
+                    },
+                _ => match replacement_text(&self.dtd, &name) {
+                    Ok(text) => Cow::Borrowed(text),
+                    Err(msg) => return Some(self_error!(self; msg))
+                }
+            };
+            let expanded = self.expanded_chars + text.chars().count();
+            if let Err(e) = self.check_entity_expansion(expanded, self.entity_stack.len() + 1) {
+                return Some(Err(e));
+            }
+            self.expanded_chars = expanded;
+            self.lexer.push_entity(&text);
+            let depth = self.depth();
+            self.entity_stack.push((name, depth));
+        } else {
+            let mut value = String::new();
+            let expanded = {
+                let mut expansion = AttributeExpansion::new(&self.dtd, &self.config, self.expanded_chars,
+                                                            self.lexer.chars_read());
+                if let Err(e) = expansion.expand(&self.lexer, &name, &mut value) {
+                    return Some(Err(e));
+                }
+                expansion.expanded
+            };
+            self.expanded_chars = expanded;
+            self.buf.push_str(&value);
+        }
+        self.into_state_continue(prev_st)
+    }
 }
@@ -6,16 +6,20 @@ use std::io::prelude::*;
 
 use common::{
     self,
-    XmlVersion, Position, TextPosition,
-    is_name_start_char, is_name_char,
+    XmlVersion, Position, TextPosition, Span, AttributeSpan,
+    is_name_start_char, is_name_char, is_whitespace_char,
 };
 use name::OwnedName;
 use attribute::OwnedAttribute;
-use namespace::NamespaceStack;
+use namespace::{Namespace, NamespaceStack};
+use dtd::Dtd;
 
 use reader::events::XmlEvent;
-use reader::config::ParserConfig;
+use reader::config::{self, ParserConfig};
 use reader::lexer::{Lexer, Token};
+use reader::resolver;
+use reader::validator::Validator;
+use reader::error;
 
 macro_rules! gen_takes(
     ($($field:ident -> $method:ident, $t:ty, $def:expr);+) => (
@@ -41,7 +45,8 @@ gen_takes!(
     element_name -> take_element_name, Option<OwnedName>, None;
 
     attr_name    -> take_attr_name, Option<OwnedName>, None;
-    attributes   -> take_attributes, Vec<OwnedAttribute>, vec!()
+    attributes   -> take_attributes, Vec<OwnedAttribute>, vec!();
+    attribute_spans -> take_attribute_spans, Vec<AttributeSpan>, vec!()
 );
 
 macro_rules! self_error(
@@ -60,10 +65,10 @@ mod inside_cdata;
 mod inside_reference;
 
 static DEFAULT_VERSION: XmlVersion      = XmlVersion::Version10;
-static DEFAULT_ENCODING: &'static str   = "UTF-8";
 static DEFAULT_STANDALONE: Option<bool> = None;
 
 type ElementStack = Vec<OwnedName>;
+type EntityStack = Vec<(String, usize)>;  // entity name and element depth where it was referenced
 pub type Result = super::Result<XmlEvent>;
 
 /// Pull-based XML parser.
@@ -78,18 +83,30 @@ pub struct PullParser {
     final_result: Option<Result>,
     next_event: Option<Result>,
     est: ElementStack,
-    pos: Vec<TextPosition>,
+    entity_stack: EntityStack,
+    /// Positions and offsets of the last event and of the events which are being read
+    pos: Vec<(TextPosition, u64)>,
+    span: Span,
+    attribute_spans: Vec<AttributeSpan>,
+    dtd: Dtd,
+    expanded_chars: usize,
+    validator: Option<Validator>,
 
     encountered_element: bool,
+    encountered_doctype: bool,
     parsed_declaration: bool,
     inside_whitespace: bool,
     read_prefix_separator: bool,
-    pop_namespace: bool
+    pop_element: bool,
+    discard_text: bool,
+    /// Whether the position of the character data which is being read is pushed
+    text_pos_pushed: bool
 }
 
 impl PullParser {
     /// Returns a new parser using the given config.
     pub fn new(config: ParserConfig) -> PullParser {
+        let validator = if config.validate_dtd { Some(Validator::new(!config.normalize_attribute_values)) } else { None };
         PullParser {
             config: config,
             lexer: Lexer::new(),
@@ -106,30 +123,85 @@ impl PullParser {
                 element_name: None,
                 quote: None,
                 attr_name: None,
-                attributes: Vec::new()
+                attr_name_span: Span::default(),
+                attr_value_span: Span::default(),
+                attributes: Vec::new(),
+                attribute_spans: Vec::new()
             },
             final_result: None,
             next_event: None,
             est: Vec::new(),
-            pos: vec![TextPosition::new()],
+            entity_stack: Vec::new(),
+            pos: vec![(TextPosition::new(), 0)],
+            span: Span::default(),
+            attribute_spans: Vec::new(),
+            dtd: Dtd::new(),
+            expanded_chars: 0,
+            validator: validator,
 
             encountered_element: false,
+            encountered_doctype: false,
             parsed_declaration: false,
             inside_whitespace: true,
             read_prefix_separator: false,
-            pop_namespace: false
+            pop_element: false,
+            discard_text: false,
+            text_pos_pushed: false
         }
     }
 
     /// Checks if this parser ignores the end of stream errors.
     pub fn is_ignoring_end_of_stream(&self) -> bool { self.config.ignore_end_of_stream }
+
+    /// Returns the span of the last event produced by the parser.
+    ///
+    /// `StartElement` and `EndElement` events of an empty element have the same span.
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns the spans of the attributes of the last event if it is `StartElement`, in
+    /// the order of its attributes; attributes with default values do not have spans.
+    #[inline]
+    pub fn attribute_spans(&self) -> &[AttributeSpan] {
+        &self.attribute_spans
+    }
+
+    /// Takes the spans returned by `attribute_spans()`.
+    #[inline]
+    pub fn take_attribute_spans(&mut self) -> Vec<AttributeSpan> {
+        mem::replace(&mut self.attribute_spans, Vec::new())
+    }
+
+    /// Returns the names of the elements enclosing the last event, starting with the root
+    /// element. For `StartElement` and `EndElement` events the element itself is the last one.
+    #[inline]
+    pub fn element_path(&self) -> &[OwnedName] {
+        &self.est
+    }
+
+    /// Returns the namespace mappings in scope at the last event; for `StartElement` and
+    /// `EndElement` events these include the mappings declared by the element itself.
+    #[inline]
+    pub fn namespace_stack(&self) -> &NamespaceStack {
+        &self.nst
+    }
+
+    /// Makes the parser drop character data, comments and processing instruction data
+    /// instead of collecting them, so that events have empty strings in their place, and
+    /// leave out namespace mappings from `StartElement` events. This makes skipping parts
+    /// of a document cheap. Has no effect when DTD validation is enabled.
+    pub fn set_discard_text(&mut self, discard: bool) {
+        self.discard_text = discard && self.validator.is_none();
+    }
 }
 
 impl Position for PullParser {
     /// Returns the position of the last event produced by the parser
     #[inline]
     fn position(&self) -> TextPosition {
-        self.pos[0]
+        self.pos[0].0
     }
 }
 
@@ -142,7 +214,7 @@ pub enum State {
     InsideComment,
     InsideCData,
     InsideDeclaration(DeclarationSubstate),
-    InsideDoctype,
+    InsideDoctype(DoctypeSubstate),
     InsideReference(Box<State>)
 }
 
@@ -192,6 +264,15 @@ pub enum DeclarationSubstate {
     AfterStandaloneDeclValue
 }
 
+#[derive(Clone, PartialEq)]
+pub enum DoctypeSubstate {
+    Outside,
+    InsideSubset,
+    InsideLiteral { quote: QuoteToken, in_subset: bool },
+    InsideComment,
+    InsideProcessingInstruction
+}
+
 #[derive(PartialEq)]
 enum QualifiedNameTarget {
     AttributeNameTarget,
@@ -200,7 +281,7 @@ enum QualifiedNameTarget {
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
-enum QuoteToken {
+pub enum QuoteToken {
     SingleQuoteToken,
     DoubleQuoteToken
 }
@@ -234,7 +315,10 @@ struct MarkupData {
 
     quote: Option<QuoteToken>,  // used to hold opening quote for attribute value
     attr_name: Option<OwnedName>,  // used to hold attribute name
-    attributes: Vec<OwnedAttribute>   // used to hold all accumulated attributes
+    attr_name_span: Span,  // used to hold the span of attribute name
+    attr_value_span: Span,  // used to hold the span of attribute value
+    attributes: Vec<OwnedAttribute>,   // used to hold all accumulated attributes
+    attribute_spans: Vec<AttributeSpan>  // used to hold spans of accumulated attributes
 }
 
 impl PullParser {
@@ -247,25 +331,50 @@ impl PullParser {
             return ev.clone();
         }
 
+        let result = self.read_event(r);
+        if let Ok(ref ev) = result {
+            let pos = self.position();
+            if let Some(ref mut validator) = self.validator {
+                if let Err(e) = validator.validate(ev, &self.dtd, pos) {
+                    return self.set_final_result(Err(e));
+                }
+            }
+        }
+        result
+    }
+
+    fn read_event<R: Read>(&mut self, r: &mut R) -> Result {
         if let Some(ev) = self.next_event.take() {
+            self.attribute_spans.clear();
             return ev;
         }
 
-        if self.pop_namespace {
-            self.pop_namespace = false;
+        // the element closed by the last event is kept until the next one is read,
+        // see `element_path()`
+        if self.pop_element {
+            self.pop_element = false;
+            self.est.pop();
             self.nst.pop();
         }
 
         loop {
             // While lexer gives us Ok(maybe_token) -- we loop.
             // Upon having a complete XML-event -- we return from the whole function.
+            let text_runs = self.accepts_text_runs();
+            self.lexer.set_text_runs(text_runs);
             match self.lexer.next_token(r) {
-                Ok(maybe_token) =>
+                Ok(maybe_token) => {
+                    if let Some(Err(e)) = self.finish_entities() {
+                        self.next_pos();
+                        return self.set_final_result(Err(e));
+                    }
                     match maybe_token {
                         None => break,
                         Some(token) =>
                             match self.dispatch_token(token) {
-                                None => {} // continue
+                                None => if self.discard_text {  // continue
+                                    self.truncate_text();
+                                },
                                 Some(Ok(XmlEvent::EndDocument)) =>
                                     return {
                                         self.next_pos();
@@ -282,7 +391,12 @@ impl PullParser {
                                         self.set_final_result(Err(xml_error))
                                     },
                             }
-                    },
+                    }
+                },
+                // The stream has no data yet; the lexer keeps its state, so the next call
+                // resumes reading
+                Err(lexer_error) if lexer_error.is_would_block() =>
+                    return Err(lexer_error),
                 Err(lexer_error) =>
                     return self.set_final_result(Err(lexer_error)),
             }
@@ -323,18 +437,52 @@ impl PullParser {
         Err((&self.lexer, msg).into())
     }
 
+    /// Returns the name of the encoding detected by the lexer, which is reported when
+    /// the XML declaration does not specify one.
+    fn detected_encoding(&self) -> String {
+        self.lexer.encoding().name(self.lexer.has_bom()).into()
+    }
+
+    /// Moves to the position of the event which is being emitted and computes its span.
     #[inline]
     fn next_pos(&mut self) {
         if self.pos.len() > 1 {
             self.pos.remove(0);
         } else {
-            self.pos[0] = self.lexer.position();
+            self.pos[0] = (self.lexer.position(), self.lexer.offset());
+        }
+        // an event is emitted either at its last token, or at the first token
+        // of the next event if it ends with it, like character data
+        let end = match self.pos.get(1) {
+            Some(&(_, offset)) => offset,
+            None => self.lexer.head_offset()
+        };
+        self.span = Span { start: self.pos[0].1, end: end };
+        // only an opening tag leaves spans of attributes
+        self.attribute_spans = self.data.take_attribute_spans();
+    }
+
+    /// Returns true if the current state handles `Token::Text`: the parser is in the
+    /// content of the root element or inside an attribute value of an opening tag.
+    fn accepts_text_runs(&self) -> bool {
+        match self.st {
+            State::OutsideTag => self.depth() > 0,
+            State::InsideOpeningTag(OpeningTagSubstate::InsideAttributeValue) => self.data.quote.is_some(),
+            _ => false
         }
     }
 
     #[inline]
     fn push_pos(&mut self) {
-        self.pos.push(self.lexer.position());
+        self.pos.push((self.lexer.position(), self.lexer.offset()));
+    }
+
+    /// Pushes the position of the current token if it starts character data.
+    fn push_text_pos(&mut self) {
+        if !self.text_pos_pushed {
+            self.text_pos_pushed = true;
+            self.push_pos();
+        }
     }
 
     fn dispatch_token(&mut self, t: Token) -> Option<Result> {
@@ -342,7 +490,7 @@ impl PullParser {
             State::OutsideTag                     => self.outside_tag(t),
             State::InsideProcessingInstruction(s) => self.inside_processing_instruction(t, s),
             State::InsideDeclaration(s)           => self.inside_declaration(t, s),
-            State::InsideDoctype                  => self.inside_doctype(t),
+            State::InsideDoctype(s)               => self.inside_doctype(t, s),
             State::InsideOpeningTag(s)            => self.inside_opening_tag(t, s),
             State::InsideClosingTag(s)            => self.inside_closing_tag_name(t, s),
             State::InsideComment                  => self.inside_comment(t),
@@ -351,6 +499,35 @@ impl PullParser {
         }
     }
 
+    /// Checks that the entities whose replacement text was completely read by the lexer
+    /// are well-formed, i.e. that each of them has left the parser in the same state
+    /// it was referenced in.
+    fn finish_entities(&mut self) -> Option<Result> {
+        while self.entity_stack.len() > self.lexer.entity_depth() {
+            let (name, depth) = self.entity_stack.pop().unwrap();
+            if self.st != State::OutsideTag || self.depth() != depth {
+                return Some(self_error!(self; "Unexpected end of entity: {}", name));
+            }
+        }
+        None
+    }
+
+    /// Checks that producing `expanded` characters in total by entity expansion, with `depth`
+    /// nested entities being expanded at the moment, does not exceed the configured limits.
+    fn check_entity_expansion(&self, expanded: usize, depth: usize) -> super::Result<()> {
+        match config::entity_expansion_error(&self.config, expanded, depth, self.lexer.chars_read()) {
+            Some(msg) => Err(error::entity_expansion_limit(&self.lexer, msg)),
+            None => Ok(())
+        }
+    }
+
+    /// Loads the text of an external entity or the external DTD subset with the configured
+    /// entity resolver, see `resolver::load_entity()`.
+    #[inline]
+    fn read_external_entity(&self, public_id: Option<&str>, system_id: &str) -> super::Result<Option<String>> {
+        resolver::load_entity(&self.lexer, &self.config, public_id, system_id)
+    }
+
     #[inline]
     fn depth(&self) -> usize {
         self.est.len()
@@ -366,6 +543,32 @@ impl PullParser {
         mem::replace(&mut self.buf, String::new())
     }
 
+    /// Takes the collected character data, comment or processing instruction data;
+    /// returns an empty string if text is discarded.
+    fn take_text(&mut self) -> String {
+        if self.discard_text {
+            self.buf.clear();
+            String::new()
+        } else {
+            self.take_buf()
+        }
+    }
+
+    /// Drops the text collected so far except for its first character while text is discarded,
+    /// so that the buffer does not grow and still tells whether there was any text.
+    fn truncate_text(&mut self) {
+        let collects_text = match self.st {
+            State::OutsideTag | State::InsideComment | State::InsideCData |
+            State::InsideProcessingInstruction(ProcessingInstructionSubstate::PIInsideData) => true,
+            State::InsideReference(ref prev_st) => **prev_st == State::OutsideTag,
+            _ => false
+        };
+        if collects_text {
+            let len = self.buf.chars().next().map_or(0, |c| c.len_utf8());
+            self.buf.truncate(len);
+        }
+    }
+
     #[inline]
     fn append_char_continue(&mut self, c: char) -> Option<Result> {
         self.buf.push(c);
@@ -448,10 +651,12 @@ impl PullParser {
             Token::DoubleQuote | Token::SingleQuote => match self.data.quote {
                 None => {  // Entered attribute value
                     self.data.quote = Some(QuoteToken::from_token(&t));
+                    self.data.attr_value_span.start = self.lexer.head_offset();
                     None
                 }
                 Some(q) if q.as_token() == t => {
                     self.data.quote = None;
+                    self.data.attr_value_span.end = self.lexer.offset();
                     let value = self.take_buf();
                     on_value(self, value)
                 }
@@ -469,6 +674,22 @@ impl PullParser {
             Token::OpeningTagStart =>
                 Some(self_error!(self; "Unexpected token inside attribute value: <")),
 
+            Token::Whitespace(_) if self.config.normalize_attribute_values => {
+                self.buf.push(' ');
+                None
+            }
+
+            Token::Text if self.config.normalize_attribute_values => {
+                let text = self.lexer.text();
+                self.buf.extend(text.chars().map(|c| if is_whitespace_char(c) { ' ' } else { c }));
+                None
+            }
+
+            Token::Text => {
+                self.buf.push_str(self.lexer.text());
+                None
+            }
+
             // Every character except " and ' and < is okay
             _  => {
                 t.push_to_string(&mut self.buf);
@@ -480,6 +701,7 @@ impl PullParser {
     fn emit_start_element(&mut self, emit_end_element: bool) -> Option<Result> {
         let mut name = self.data.take_element_name().unwrap();
         let mut attributes = self.data.take_attributes();
+        self.apply_attribute_declarations(&name, &mut attributes);
 
         // check whether the name prefix is bound and fix its namespace
         match self.nst.get(name.borrow().prefix_repr()) {
@@ -501,14 +723,13 @@ impl PullParser {
         }
 
         if emit_end_element {
-            self.pop_namespace = true;
+            self.pop_element = true;
             self.next_event = Some(Ok(XmlEvent::EndElement {
                 name: name.clone()
             }));
-        } else {
-            self.est.push(name.clone());
         }
-        let namespace = self.nst.squash();
+        self.est.push(name.clone());
+        let namespace = if self.discard_text { Namespace::empty() } else { self.nst.squash() };
         self.into_state_emit(State::OutsideTag, Ok(XmlEvent::StartElement {
             name: name,
             attributes: attributes,
@@ -526,13 +747,18 @@ impl PullParser {
             None => return Some(self_error!(self; "Element {} prefix is unbound", name))
         }
 
-        let op_name = self.est.pop().unwrap();
+        // an element opened outside of an entity cannot be closed inside it
+        if let Some(&(ref entity, depth)) = self.entity_stack.last() {
+            if self.depth() <= depth {
+                return Some(self_error!(self; "Unexpected closing tag inside entity {}: {}", entity, name));
+            }
+        }
 
-        if name == op_name {
-            self.pop_namespace = true;
+        if name == *self.est.last().unwrap() {
+            self.pop_element = true;
             self.into_state_emit(State::OutsideTag, Ok(XmlEvent::EndElement { name: name }))
         } else {
-            Some(self_error!(self; "Unexpected closing tag: {}, expected {}", name, op_name))
+            Some(self_error!(self; "Unexpected closing tag: {}, expected {}", name, self.est.last().unwrap()))
         }
     }
 
@@ -1,3 +1,5 @@
+use std::mem;
+
 use common::is_whitespace_char;
 
 use reader::events::XmlEvent;
@@ -5,14 +7,17 @@ use reader::lexer::Token;
 
 use super::{
     Result, PullParser, State, ClosingTagSubstate, OpeningTagSubstate,
-    ProcessingInstructionSubstate, DEFAULT_VERSION, DEFAULT_ENCODING, DEFAULT_STANDALONE
+    ProcessingInstructionSubstate, DoctypeSubstate, DEFAULT_VERSION, DEFAULT_STANDALONE
 };
 
 impl PullParser {
     pub fn outside_tag(&mut self, t: Token) -> Option<Result> {
         match t {
-            Token::ReferenceStart =>
-                self.into_state_continue(State::InsideReference(Box::new(State::OutsideTag))),
+            Token::ReferenceStart => {
+                // character data may start with a reference
+                self.push_text_pos();
+                self.into_state_continue(State::InsideReference(Box::new(State::OutsideTag)))
+            }
 
             Token::Whitespace(_) if self.depth() == 0 => None,  // skip whitespace outside of the root element
 
@@ -21,17 +26,27 @@ impl PullParser {
 
             Token::Whitespace(_) if self.config.trim_whitespace && !self.buf_has_data() => None,
 
-            Token::Whitespace(c) => {
-                if !self.buf_has_data() {
-                    self.push_pos();
+            // Runs are only read inside the root element, see `accepts_text_runs()`
+            Token::Text => {
+                let whitespace = self.lexer.text().starts_with(is_whitespace_char);
+                if whitespace && self.config.trim_whitespace && !self.buf_has_data() {
+                    return None;
                 }
+                self.push_text_pos();
+                if !whitespace {
+                    self.inside_whitespace = false;
+                }
+                self.buf.push_str(self.lexer.text());
+                None
+            }
+
+            Token::Whitespace(c) => {
+                self.push_text_pos();
                 self.append_char_continue(c)
             }
 
             _ if t.contains_char_data() => {  // Non-whitespace char data
-                if !self.buf_has_data() {
-                    self.push_pos();
-                }
+                self.push_text_pos();
                 self.inside_whitespace = false;
                 t.push_to_string(&mut self.buf);
                 None
@@ -50,9 +65,7 @@ impl PullParser {
             }
 
             Token::CDataStart if self.config.coalesce_characters && self.config.cdata_to_characters => {
-                if !self.buf_has_data() {
-                    self.push_pos();
-                }
+                self.push_text_pos();
                 // We need to disable lexing errors inside CDATA
                 self.lexer.disable_errors();
                 self.into_state_continue(State::InsideCData)
@@ -61,8 +74,9 @@ impl PullParser {
             _ => {
                 // Encountered some markup event, flush the buffer as characters
                 // or a whitespace
+                let text_pos_pushed = mem::replace(&mut self.text_pos_pushed, false);
                 let mut next_event = if self.buf_has_data() {
-                    let buf = self.take_buf();
+                    let buf = self.take_text();
                     if self.inside_whitespace && self.config.trim_whitespace {
                         None
                     } else if self.inside_whitespace && !self.config.whitespace_to_characters {
@@ -72,19 +86,35 @@ impl PullParser {
                     } else {
                         Some(Ok(XmlEvent::Characters(buf)))
                     }
-                } else { None };
+                } else {
+                    // references have not produced any character data
+                    if text_pos_pushed {
+                        self.pos.pop();
+                    }
+                    None
+                };
                 self.inside_whitespace = true;  // Reset inside_whitespace flag
                 self.push_pos();
                 match t {
                     Token::ProcessingInstructionStart =>
                         self.into_state(State::InsideProcessingInstruction(ProcessingInstructionSubstate::PIInsideName), next_event),
 
-                    Token::DoctypeStart if !self.encountered_element => {
-                        // We don't have a doctype event so skip this position
-                        // FIXME: update when we have a doctype event
-                        self.next_pos();
+                    Token::DoctypeStart if !self.encountered_element && !self.encountered_doctype => {
+                        // Emit the default declaration before the doctype, just like
+                        // it is done for the root element
+                        if !self.parsed_declaration {
+                            self.parsed_declaration = true;
+                            next_event = Some(Ok(XmlEvent::StartDocument {
+                                version: DEFAULT_VERSION,
+                                encoding: self.detected_encoding(),
+                                standalone: DEFAULT_STANDALONE
+                            }));
+                            self.push_pos();
+                        }
+                        self.encountered_doctype = true;
+                        // Declaration contents are collected verbatim, see `inside_doctype()`
                         self.lexer.disable_errors();
-                        self.into_state(State::InsideDoctype, next_event)
+                        self.into_state(State::InsideDoctype(DoctypeSubstate::Outside), next_event)
                     }
 
                     Token::OpeningTagStart => {
@@ -94,7 +124,7 @@ impl PullParser {
                             self.parsed_declaration = true;
                             let sd_event = XmlEvent::StartDocument {
                                 version: DEFAULT_VERSION,
-                                encoding: DEFAULT_ENCODING.into(),
+                                encoding: self.detected_encoding(),
                                 standalone: DEFAULT_STANDALONE
                             };
                             // next_event is always none here because we're outside of
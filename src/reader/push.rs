@@ -0,0 +1,157 @@
+//! Contains `PushReader`, a parser for documents which arrive in chunks.
+
+use std::cmp;
+use std::io::{self, Read};
+
+use common::{Position, TextPosition, Span, AttributeSpan};
+use reader::{ParserConfig, XmlEvent, Result};
+use reader::parser::PullParser;
+
+/// A push-based XML parser, which is given the document in chunks as they arrive.
+///
+/// It uses the same parser as `EventReader` and produces the same events and errors.
+/// Each chunk is passed to `feed()`, after which `next()` returns the events which are
+/// complete; the rest of the chunk, even if it ends in the middle of a token or of
+/// a multi-byte character, is kept until the following chunks complete it.
+///
+/// ```rust
+/// use xml::reader::{PushReader, XmlEvent};
+///
+/// let mut reader = PushReader::new();
+/// let mut names = Vec::new();
+/// for chunk in &["<stream><mess", "age>Hel", "lo</message>"] {
+///     reader.feed(chunk.as_bytes());
+///     while let Some(event) = reader.next() {
+///         if let XmlEvent::StartElement { name, .. } = event.unwrap() {
+///             names.push(name.local_name);
+///         }
+///     }
+/// }
+/// assert_eq!(names, ["stream", "message"]);
+/// ```
+pub struct PushReader {
+    input: Input,
+    parser: PullParser,
+    finished: bool
+}
+
+impl PushReader {
+    /// Creates a new reader with the default configuration.
+    #[inline]
+    pub fn new() -> PushReader {
+        PushReader::new_with_config(ParserConfig::new())
+    }
+
+    /// Creates a new reader with the provided configuration.
+    pub fn new_with_config(config: ParserConfig) -> PushReader {
+        PushReader {
+            input: Input::new(),
+            parser: PullParser::new(config),
+            finished: false
+        }
+    }
+
+    /// Adds the next chunk of the document.
+    ///
+    /// Data fed after `finish()` is ignored.
+    pub fn feed(&mut self, data: &[u8]) {
+        if !self.input.eof {
+            self.input.push(data);
+        }
+    }
+
+    /// Marks the end of the document, so that `next()` returns the remaining events
+    /// followed by `XmlEvent::EndDocument` or an error if the document is incomplete.
+    #[inline]
+    pub fn finish(&mut self) {
+        self.input.eof = true;
+    }
+
+    /// Returns the next event of the document, or `None` if more data needs to be fed
+    /// to complete it.
+    ///
+    /// `XmlEvent::EndDocument` and errors are returned once, and `None` is returned
+    /// after them.
+    pub fn next(&mut self) -> Option<Result<XmlEvent>> {
+        if self.finished {
+            return None;
+        }
+        match self.parser.next(&mut self.input) {
+            Err(ref e) if e.is_would_block() => None,
+            result => {
+                if let Ok(XmlEvent::EndDocument) | Err(_) = result {
+                    self.finished = true;
+                }
+                Some(result)
+            }
+        }
+    }
+
+    /// Returns the span of bytes of the document taken by the last event returned by
+    /// `next()`; see `EventReader::span()`.
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.parser.span()
+    }
+
+    /// Returns the spans of the attributes of the last event returned by `next()`;
+    /// see `EventReader::attribute_spans()`.
+    #[inline]
+    pub fn attribute_spans(&self) -> &[AttributeSpan] {
+        self.parser.attribute_spans()
+    }
+}
+
+impl Default for PushReader {
+    #[inline]
+    fn default() -> PushReader {
+        PushReader::new()
+    }
+}
+
+impl Position for PushReader {
+    /// Returns the position of the last event produced by the reader.
+    #[inline]
+    fn position(&self) -> TextPosition {
+        self.parser.position()
+    }
+}
+
+/// The data passed to the parser by `PushReader` and `AsyncEventReader`.
+///
+/// When the data is exhausted, reading fails with `WouldBlock`, which suspends the parser
+/// until more data is pushed, unless the end of the input or an error has been reached.
+pub struct Input {
+    buf: Vec<u8>,
+    pos: usize,
+    pub eof: bool,
+    pub error: Option<io::Error>
+}
+
+impl Input {
+    pub fn new() -> Input {
+        Input { buf: Vec::new(), pos: 0, eof: false, error: None }
+    }
+
+    /// Appends data to the unread part of the input.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.buf.extend_from_slice(data);
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.pos == self.buf.len() {
+            return if self.eof { Ok(0) } else { Err(io::ErrorKind::WouldBlock.into()) };
+        }
+        let n = cmp::min(buf.len(), self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
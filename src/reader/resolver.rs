@@ -0,0 +1,292 @@
+//! Contains the `EntityResolver` trait and its implementations.
+//!
+//! External entities and the external DTD subset are never loaded by the parser on its own;
+//! instead, the parser asks an entity resolver set in `ParserConfig` to provide their contents.
+//! When no resolver is set, all external entities are refused.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Cursor};
+use std::path::{Path, PathBuf, Component};
+use std::sync::Arc;
+
+use common::{Position, is_whitespace_char};
+use reader::Result;
+use reader::config::{self, ParserConfig};
+use reader::error;
+use util;
+
+/// A source of external entities.
+///
+/// The parser calls `resolve()` for each external general entity referenced in the document
+/// and for the external DTD subset, passing their public and system identifiers. The system
+/// identifier is passed exactly as it is written in the document, so it is up to the resolver
+/// to interpret it.
+pub trait EntityResolver {
+    /// Returns a reader for the entity with the given identifiers.
+    ///
+    /// Returning `Ok(None)` refuses to load the entity. Refusing to load the external subset
+    /// is not an error; references to refused external entities are reported as errors.
+    fn resolve(&self, public_id: Option<&str>, system_id: &str) -> io::Result<Option<Box<Read>>>;
+}
+
+/// A shared entity resolver which is stored in `ParserConfig`.
+///
+/// Two handles are equal when they point to the same resolver.
+#[derive(Clone)]
+pub struct ResolverHandle(Arc<EntityResolver + Send + Sync>);
+
+impl ResolverHandle {
+    /// Wraps the given resolver into a handle.
+    pub fn new<R: EntityResolver + Send + Sync + 'static>(resolver: R) -> ResolverHandle {
+        ResolverHandle(Arc::new(resolver))
+    }
+}
+
+impl EntityResolver for ResolverHandle {
+    #[inline]
+    fn resolve(&self, public_id: Option<&str>, system_id: &str) -> io::Result<Option<Box<Read>>> {
+        self.0.resolve(public_id, system_id)
+    }
+}
+
+impl PartialEq for ResolverHandle {
+    fn eq(&self, other: &ResolverHandle) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ResolverHandle {}
+
+impl fmt::Debug for ResolverHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ResolverHandle")
+    }
+}
+
+/// An entity resolver which refuses all entities.
+///
+/// This is the behavior of the parser when no resolver is set.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct RefusingResolver;
+
+impl EntityResolver for RefusingResolver {
+    #[inline]
+    fn resolve(&self, _: Option<&str>, _: &str) -> io::Result<Option<Box<Read>>> {
+        Ok(None)
+    }
+}
+
+/// An entity resolver which loads entities from files in a local directory.
+///
+/// System identifiers are treated as paths relative to the directory. Identifiers which
+/// are absolute paths, contain `..` components or a URI scheme are refused, so the
+/// resolver never reads files outside of the directory. Public identifiers are ignored.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DirectoryResolver {
+    base: PathBuf
+}
+
+impl DirectoryResolver {
+    /// Creates a resolver which loads entities from the given directory.
+    pub fn new<P: Into<PathBuf>>(base: P) -> DirectoryResolver {
+        DirectoryResolver { base: base.into() }
+    }
+
+    /// Returns the directory entities are loaded from.
+    #[inline]
+    pub fn base(&self) -> &Path { &self.base }
+}
+
+impl EntityResolver for DirectoryResolver {
+    fn resolve(&self, _: Option<&str>, system_id: &str) -> io::Result<Option<Box<Read>>> {
+        if system_id.is_empty() || system_id.contains(':') {
+            return Ok(None);
+        }
+        let path = Path::new(system_id);
+        let is_local = path.components().all(|c| match c {
+            Component::Normal(_) | Component::CurDir => true,
+            _ => false
+        });
+        if !is_local {
+            return Ok(None);
+        }
+        let file = try!(File::open(self.base.join(path)));
+        Ok(Some(Box::new(file)))
+    }
+}
+
+/// An entity resolver which provides entities from an in-memory map.
+///
+/// Entities are looked up by their public identifier first, if it is present,
+/// and then by their system identifier. Unknown entities are refused.
+///
+/// ```rust
+/// use xml::reader::{ParserConfig, MapResolver};
+///
+/// let resolver = MapResolver::new()
+///     .add("chapter1.xml", "<chapter>Introduction</chapter>")
+///     .add("-//Acme//Letters//EN", "<!ENTITY sig 'Acme'>");
+/// let config = ParserConfig::new().entity_resolver(resolver);
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct MapResolver {
+    entities: HashMap<String, Vec<u8>>
+}
+
+impl MapResolver {
+    /// Creates an empty resolver.
+    #[inline]
+    pub fn new() -> MapResolver {
+        MapResolver::default()
+    }
+
+    /// Adds the contents of an entity with the given public or system identifier
+    /// and returns the updated resolver.
+    pub fn add<K: Into<String>, V: Into<Vec<u8>>>(mut self, id: K, content: V) -> MapResolver {
+        self.entities.insert(id.into(), content.into());
+        self
+    }
+}
+
+impl EntityResolver for MapResolver {
+    fn resolve(&self, public_id: Option<&str>, system_id: &str) -> io::Result<Option<Box<Read>>> {
+        let content = public_id.and_then(|id| self.entities.get(id))
+            .or_else(|| self.entities.get(system_id));
+        Ok(content.map(|c| Box::new(Cursor::new(c.clone())) as Box<Read>))
+    }
+}
+
+/// Loads the text of an external entity or the external DTD subset with the resolver set
+/// in `config`, decoding it in the encoding detected from its first bytes or declared in its
+/// text declaration, and stripping its byte order mark and text declaration.
+///
+/// Returns `Ok(None)` if there is no resolver or the entity was refused. Errors are reported
+/// at the position of `pos`; texts longer than `max_entity_expansion_size` are not read.
+pub fn load_entity<P: Position>(pos: &P, config: &ParserConfig, public_id: Option<&str>, system_id: &str)
+    -> Result<Option<String>>
+{
+    macro_rules! error(($msg:expr) => (Err((pos, $msg).into())));
+
+    let resolver = match config.entity_resolver {
+        Some(ref resolver) => resolver,
+        None => return Ok(None)
+    };
+    let mut source = match resolver.resolve(public_id, system_id) {
+        Ok(Some(source)) => source,
+        Ok(None) => return Ok(None),
+        Err(e) => return error!(format!("Cannot read external entity {}: {}", system_id, e))
+    };
+
+    let mut reader = util::CharReader::new();
+    let mut text = String::new();
+    let mut chars = 0;
+    // The length of the text declaration, once it is known
+    let mut declaration = None;
+    loop {
+        match reader.next_char_from(&mut source) {
+            Ok(Some(c)) => text.push(c),
+            Ok(None) => break,
+            Err(e) => return error!(format!("Cannot read external entity {}: {}", system_id, e))
+        }
+        chars += 1;
+        if chars > config.max_entity_expansion_size {
+            return Err(error::entity_expansion_limit(pos, config::entity_size_error(config)));
+        }
+        if declaration.is_some() {
+            continue;
+        }
+        if !starts_text_declaration(&text) {
+            declaration = Some(0);
+        } else if text.ends_with("?>") {
+            declaration = Some(text.len());
+            if let Some(name) = declared_encoding(&text) {
+                if let Err(msg) = reader.declare_encoding(name, |name| config::find_encoding(config, name)) {
+                    return error!(format!("Cannot read external entity {}: {}", system_id, msg));
+                }
+            }
+        }
+    }
+
+    match declaration {
+        Some(start) => Ok(Some(text.split_off(start))),
+        None if text.len() > 5 => error!(format!("Unterminated text declaration in external entity {}",
+                                                 system_id)),
+        None => Ok(Some(text))
+    }
+}
+
+/// Returns true if `text` is or can be the beginning of a text declaration.
+fn starts_text_declaration(text: &str) -> bool {
+    if text.len() <= 5 {
+        "<?xml".starts_with(text)
+    } else {
+        text.starts_with("<?xml") && text[5..].starts_with(is_whitespace_char)
+    }
+}
+
+/// Returns the value of the `encoding` pseudo-attribute of a text declaration.
+pub fn declared_encoding(declaration: &str) -> Option<&str> {
+    let rest = &declaration[declaration.find("encoding").map(|i| i + 8).unwrap_or(declaration.len())..];
+    let rest = rest.trim_start_matches(is_whitespace_char);
+    if !rest.starts_with('=') {
+        return None;
+    }
+    let rest = rest[1..].trim_start_matches(is_whitespace_char);
+    let quote = match rest.chars().next() {
+        Some(c @ '"') | Some(c @ '\'') => c,
+        _ => return None
+    };
+    rest[1..].find(quote).map(|end| &rest[1..end + 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+
+    use super::{EntityResolver, RefusingResolver, DirectoryResolver, MapResolver};
+
+    fn read<R: EntityResolver>(resolver: &R, public_id: Option<&str>, system_id: &str) -> Option<String> {
+        resolver.resolve(public_id, system_id).unwrap().map(|mut r| {
+            let mut s = String::new();
+            r.read_to_string(&mut s).unwrap();
+            s
+        })
+    }
+
+    #[test]
+    fn refusing_resolver() {
+        assert_eq!(read(&RefusingResolver, None, "/etc/passwd"), None);
+    }
+
+    #[test]
+    fn map_resolver() {
+        let resolver = MapResolver::new()
+            .add("a.xml", "system")
+            .add("-//A//EN", "public");
+
+        assert_eq!(read(&resolver, None, "a.xml"), Some("system".into()));
+        assert_eq!(read(&resolver, Some("-//A//EN"), "a.xml"), Some("public".into()));
+        assert_eq!(read(&resolver, Some("-//B//EN"), "a.xml"), Some("system".into()));
+        assert_eq!(read(&resolver, None, "b.xml"), None);
+    }
+
+    #[test]
+    fn directory_resolver() {
+        let dir = env::temp_dir().join("xml-rs-directory-resolver");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        File::create(dir.join("sub").join("a.ent")).unwrap().write_all(b"entity").unwrap();
+
+        let resolver = DirectoryResolver::new(dir.join("sub"));
+        assert_eq!(read(&resolver, None, "a.ent"), Some("entity".into()));
+        assert_eq!(read(&resolver, None, "./a.ent"), Some("entity".into()));
+        assert!(resolver.resolve(None, "b.ent").is_err());
+
+        for id in &["../sub/a.ent", "/etc/passwd", "file:///etc/passwd", ""] {
+            assert_eq!(read(&resolver, None, id), None);
+        }
+    }
+}
@@ -0,0 +1,1123 @@
+//! Contains a reader which parses documents held in memory in place.
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::mem;
+use std::str;
+
+use common::{
+    Position, TextPosition, XmlVersion,
+    is_whitespace_char, is_whitespace_str, is_name_start_char, is_name_char
+};
+use namespace::{NS_XML_PREFIX, NS_XML_URI, NS_XMLNS_PREFIX, NS_XMLNS_URI, NS_NO_PREFIX, NS_EMPTY_URI};
+use util;
+
+use reader::{Result, EventReader};
+use reader::config::ParserConfig;
+use reader::events::{BorrowedEvent, BorrowedName, BorrowedAttribute};
+use reader::resolver;
+
+/// A reader which parses a document held in memory, producing events which borrow
+/// from it.
+///
+/// Names, attribute values and character data of the events are slices of the document
+/// whenever possible, so reading a document allocates little memory besides the list of
+/// attributes of each element:
+///
+/// ```rust
+/// use std::borrow::Cow;
+///
+/// use xml::reader::{SliceReader, BorrowedEvent};
+///
+/// let mut reader = SliceReader::from_str("<greeting lang='en'>Hello &amp; welcome</greeting>");
+/// reader.next().unwrap();  // StartDocument
+///
+/// match reader.next().unwrap() {
+///     BorrowedEvent::StartElement { name, attributes } => {
+///         assert_eq!(name.local_name, "greeting");
+///         assert!(match attributes[0].value { Cow::Borrowed("en") => true, _ => false });
+///     }
+///     _ => unreachable!()
+/// }
+///
+/// // the reference is replaced, so the text is copied
+/// match reader.next().unwrap() {
+///     BorrowedEvent::Characters(Cow::Owned(data)) => assert_eq!(data, "Hello & welcome"),
+///     _ => unreachable!()
+/// }
+/// ```
+///
+/// The reader produces the same events at the same positions as `EventReader` with the same
+/// configuration.
+///
+/// Documents which cannot be parsed in place are read with `EventReader`, and their events
+/// own all of their data. These are documents with a document type declaration, documents
+/// in encodings other than UTF-8, and all documents when `validate_dtd` option is enabled.
+/// Since the whole document is available, `ignore_end_of_stream` option has no effect.
+pub struct SliceReader<'a> {
+    inner: Inner<'a>
+}
+
+enum Inner<'a> {
+    InPlace(Box<Parser<'a>>),
+    Copying(Box<EventReader<&'a [u8]>>)
+}
+
+impl<'a> SliceReader<'a> {
+    /// Creates a new reader of the given document.
+    #[inline]
+    pub fn new(source: &'a [u8]) -> SliceReader<'a> {
+        SliceReader::new_with_config(source, ParserConfig::new())
+    }
+
+    /// Creates a new reader of the given document with the provided configuration.
+    pub fn new_with_config(source: &'a [u8], config: ParserConfig) -> SliceReader<'a> {
+        // UTF-16 and UCS-4 documents start with a zero byte in one of the first two bytes,
+        // which, however, may be valid UTF-8
+        let wide = source.len() >= 2 && (source[0] == 0 || source[1] == 0);
+        match str::from_utf8(source) {
+            Ok(s) if !wide => SliceReader::with_config(s, config),
+            _ => SliceReader { inner: Inner::Copying(Box::new(EventReader::new_with_config(source, config))) }
+        }
+    }
+
+    /// A convenience method to create a reader of a string slice.
+    #[inline]
+    pub fn from_str(source: &'a str) -> SliceReader<'a> {
+        SliceReader::with_config(source, ParserConfig::new())
+    }
+
+    fn with_config(source: &'a str, config: ParserConfig) -> SliceReader<'a> {
+        let inner = if config.validate_dtd || needs_event_reader(source) {
+            Inner::Copying(Box::new(EventReader::new_with_config(source.as_bytes(), config)))
+        } else {
+            Inner::InPlace(Box::new(Parser::new(source, config)))
+        };
+        SliceReader { inner: inner }
+    }
+
+    /// Pulls and returns next XML event from the document.
+    ///
+    /// If returned event is an error or `BorrowedEvent::EndDocument`, then
+    /// further calls to this method will return this event again.
+    pub fn next(&mut self) -> Result<BorrowedEvent<'a>> {
+        match self.inner {
+            Inner::InPlace(ref mut parser) => parser.next(),
+            Inner::Copying(ref mut reader) => reader.next().map(BorrowedEvent::from)
+        }
+    }
+}
+
+impl<'a> Position for SliceReader<'a> {
+    /// Returns the position of the last event produced by the reader.
+    #[inline]
+    fn position(&self) -> TextPosition {
+        match self.inner {
+            Inner::InPlace(ref parser) => parser.position(),
+            Inner::Copying(ref reader) => reader.position()
+        }
+    }
+}
+
+impl<'a> IntoIterator for SliceReader<'a> {
+    type Item = Result<BorrowedEvent<'a>>;
+    type IntoIter = SliceEvents<'a>;
+
+    fn into_iter(self) -> SliceEvents<'a> {
+        SliceEvents { reader: self, finished: false }
+    }
+}
+
+/// An iterator over events of a document read by `SliceReader`.
+///
+/// When the next event is an error or `BorrowedEvent::EndDocument`, then it will be
+/// returned by the iterator once, and then it will stop producing events.
+pub struct SliceEvents<'a> {
+    reader: SliceReader<'a>,
+    finished: bool
+}
+
+impl<'a> SliceEvents<'a> {
+    /// Unwraps the iterator, returning the internal `SliceReader`.
+    #[inline]
+    pub fn into_inner(self) -> SliceReader<'a> {
+        self.reader
+    }
+}
+
+impl<'a> Iterator for SliceEvents<'a> {
+    type Item = Result<BorrowedEvent<'a>>;
+
+    fn next(&mut self) -> Option<Result<BorrowedEvent<'a>>> {
+        if self.finished {
+            return None;
+        }
+        let ev = self.reader.next();
+        match ev {
+            Ok(BorrowedEvent::EndDocument) | Err(_) => self.finished = true,
+            _ => {}
+        }
+        Some(ev)
+    }
+}
+
+/// Returns true if the prolog of the document contains a document type declaration or
+/// the XML declaration declares an encoding other than UTF-8.
+fn needs_event_reader(src: &str) -> bool {
+    let mut rest = src.trim_start_matches('\u{feff}');
+    if rest.starts_with("<?xml") && rest[5..].starts_with(is_whitespace_char) {
+        let end = match rest.find("?>") {
+            Some(end) => end,
+            None => return false
+        };
+        match resolver::declared_encoding(&rest[..end]) {
+            Some(encoding) if !encoding.eq_ignore_ascii_case("UTF-8") => return true,
+            _ => {}
+        }
+        rest = &rest[end + 2..];
+    }
+    loop {
+        rest = rest.trim_start_matches(is_whitespace_char);
+        let end = if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<?") {
+            "?>"
+        } else {
+            return rest.starts_with("<!DOCTYPE");
+        };
+        rest = match rest.find(end) {
+            Some(i) => &rest[i + end.len()..],
+            None => return false
+        };
+    }
+}
+
+/// Character data of the next event, which borrows from the document as long as it is
+/// a single contiguous piece of it.
+struct TextBuf {
+    start: usize,
+    end: usize,
+    owned: Option<String>
+}
+
+impl TextBuf {
+    fn new() -> TextBuf {
+        TextBuf { start: 0, end: 0, owned: None }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self.owned {
+            Some(ref s) => s.is_empty(),
+            None => self.start == self.end
+        }
+    }
+
+    fn push_slice(&mut self, src: &str, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        if self.owned.is_none() {
+            if self.start == self.end {
+                self.start = start;
+                self.end = end;
+                return;
+            } else if self.end == start {
+                self.end = end;
+                return;
+            }
+        }
+        self.owned_mut(src).push_str(&src[start..end]);
+    }
+
+    #[inline]
+    fn push_str(&mut self, src: &str, s: &str) {
+        self.owned_mut(src).push_str(s);
+    }
+
+    #[inline]
+    fn push(&mut self, src: &str, c: char) {
+        self.owned_mut(src).push(c);
+    }
+
+    fn owned_mut(&mut self, src: &str) -> &mut String {
+        if self.owned.is_none() {
+            self.owned = Some(src[self.start..self.end].to_owned());
+        }
+        self.owned.as_mut().unwrap()
+    }
+
+    fn take<'a>(&mut self, src: &'a str) -> Cow<'a, str> {
+        let text = match self.owned.take() {
+            Some(s) => Cow::Owned(s),
+            None => Cow::Borrowed(&src[self.start..self.end])
+        };
+        self.start = 0;
+        self.end = 0;
+        text
+    }
+}
+
+/// An element whose end tag has not been read yet.
+struct OpenElement<'a> {
+    /// The name as it is written in the start tag.
+    repr: &'a str,
+    name: BorrowedName<'a>,
+    /// The number of namespace declarations in scope outside of the element.
+    namespaces: usize
+}
+
+/// An attribute of the start tag being read.
+struct RawAttribute<'a> {
+    repr: &'a str,
+    prefix: Option<&'a str>,
+    local_name: &'a str,
+    value: Cow<'a, str>
+}
+
+/// A recursive descent parser of documents without a document type declaration, which
+/// implements the same rules as `PullParser`.
+struct Parser<'a> {
+    src: &'a str,
+    offset: usize,
+    config: ParserConfig,
+    xml11: bool,
+    started: bool,
+    /// Set when the document has no XML declaration and `StartDocument` is not reported yet.
+    declaration_pending: bool,
+    encountered_element: bool,
+
+    elements: Vec<OpenElement<'a>>,
+    /// Namespace declarations of the open elements, in document order.
+    namespaces: Vec<(&'a str, Cow<'a, str>)>,
+    attributes: Vec<RawAttribute<'a>>,
+
+    text: TextBuf,
+    text_start: usize,
+    inside_whitespace: bool,
+
+    next_event: Option<BorrowedEvent<'a>>,
+    final_result: Option<Result<BorrowedEvent<'a>>>,
+
+    /// The offset where the last event starts.
+    event_start: usize,
+    /// The offset of the byte order mark, which is not counted in positions.
+    bom_len: usize,
+    /// The last computed position and its offset; positions are computed on demand.
+    position_cache: Cell<(usize, TextPosition)>
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str, config: ParserConfig) -> Parser<'a> {
+        let bom_len = if src.starts_with('\u{feff}') { 3 } else { 0 };
+        Parser {
+            src: src,
+            offset: bom_len,
+            config: config,
+            xml11: false,
+            started: false,
+            declaration_pending: false,
+            encountered_element: false,
+
+            elements: Vec::new(),
+            namespaces: Vec::new(),
+            attributes: Vec::new(),
+
+            text: TextBuf::new(),
+            text_start: 0,
+            inside_whitespace: true,
+
+            next_event: None,
+            final_result: None,
+
+            event_start: bom_len,
+            bom_len: bom_len,
+            position_cache: Cell::new((bom_len, TextPosition::new()))
+        }
+    }
+
+    fn next(&mut self) -> Result<BorrowedEvent<'a>> {
+        if let Some(ref result) = self.final_result {
+            return result.clone();
+        }
+        if let Some(event) = self.next_event.take() {
+            return Ok(event);
+        }
+
+        let result = if self.started {
+            self.read_event()
+        } else {
+            self.started = true;
+            self.read_start_document()
+        };
+        match result {
+            Ok(BorrowedEvent::EndDocument) | Err(_) => self.final_result = Some(result.clone()),
+            _ => {}
+        }
+        result
+    }
+
+    fn position(&self) -> TextPosition {
+        match self.final_result {
+            Some(Err(ref e)) => e.position(),
+            _ => self.position_at(self.event_start)
+        }
+    }
+
+    /// Reads the XML declaration if the document starts with it.
+    ///
+    /// Otherwise, like in `PullParser`, `StartDocument` is reported right before the root
+    /// element, see `read_markup()`.
+    fn read_start_document(&mut self) -> Result<BorrowedEvent<'a>> {
+        if !self.looking_at("<?xml") || !self.src[self.offset + 5..].starts_with(is_whitespace_char) {
+            self.declaration_pending = true;
+            return self.read_event();
+        }
+        let start = self.offset;
+        self.offset += 5;
+        self.read_declaration(start)
+    }
+
+    /// Reads the XML declaration starting at `start`, after `<?xml`.
+    fn read_declaration(&mut self, start: usize) -> Result<BorrowedEvent<'a>> {
+        self.declaration_pending = false;
+        let version = match try!(self.read_declaration_value("version")) {
+            "1.0" => XmlVersion::Version10,
+            "1.1" => XmlVersion::Version11,
+            value => return self.error_at(self.offset - 1, format!("Unexpected XML version value: {}", value))
+        };
+        self.xml11 = version == XmlVersion::Version11;
+        self.skip_whitespace();
+        let encoding = if self.looking_at("encoding") {
+            Some(try!(self.read_declaration_value("encoding")))
+        } else {
+            None
+        };
+        self.skip_whitespace();
+        let standalone = if self.looking_at("standalone") {
+            match try!(self.read_declaration_value("standalone")) {
+                "yes" => Some(true),
+                "no" => Some(false),
+                value => return self.error_at(self.offset - 1, format!("Invalid standalone declaration value: {}", value))
+            }
+        } else {
+            None
+        };
+        self.skip_whitespace();
+        if !self.eat("?>") {
+            return self.unexpected("Unexpected token inside XML declaration");
+        }
+        Ok(self.emit(start, BorrowedEvent::StartDocument {
+            version: version,
+            encoding: Cow::Borrowed(encoding.unwrap_or("UTF-8")),
+            standalone: standalone
+        }))
+    }
+
+    /// Reads a pseudo-attribute of the XML declaration with the given name.
+    fn read_declaration_value(&mut self, name: &str) -> Result<&'a str> {
+        self.skip_whitespace();
+        if !self.eat(name) {
+            return self.unexpected("Unexpected token inside XML declaration");
+        }
+        self.skip_whitespace();
+        if !self.eat("=") {
+            return self.unexpected("Unexpected token inside XML declaration");
+        }
+        self.skip_whitespace();
+        let quote = match self.peek() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => return self.unexpected("Unexpected token inside XML declaration")
+        };
+        let start = self.offset + 1;
+        match self.src[start..].find(quote) {
+            Some(len) => {
+                self.offset = start + len + 1;
+                Ok(&self.src[start..start + len])
+            }
+            None => self.unexpected_end()
+        }
+    }
+
+    fn read_event(&mut self) -> Result<BorrowedEvent<'a>> {
+        loop {
+            let event = match self.src.as_bytes().get(self.offset) {
+                None => return self.end_of_document(),
+                Some(&b'<') => try!(self.read_markup()),
+                Some(&b'&') if self.elements.is_empty() =>
+                    return self.error("Unexpected characters outside the root element: &"),
+                Some(&b'&') => {
+                    self.start_text(self.offset);
+                    let mut text = mem::replace(&mut self.text, TextBuf::new());
+                    let result = self.read_reference(&mut text);
+                    self.text = text;
+                    if !try!(result) {
+                        self.inside_whitespace = false;
+                    }
+                    None
+                }
+                Some(_) => {
+                    try!(self.read_text());
+                    None
+                }
+            };
+            if let Some(event) = event {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Reads character data up to the next markup or reference.
+    fn read_text(&mut self) -> Result<()> {
+        let mut start = self.offset;
+        let end = find_byte(self.src, start, |b| b == b'<' || b == b'&');
+        self.offset = end;
+        let text = &self.src[start..end];
+
+        if self.elements.is_empty() {
+            return match text.char_indices().find(|&(_, c)| !self.is_whitespace(c)) {
+                Some((i, c)) => self.error_at(start + i, format!("Unexpected characters outside the root element: {}", c)),
+                None => Ok(())  // skip whitespace outside of the root element
+            };
+        }
+        if let Some(i) = text.find("]]>") {
+            return self.error_at(start + i, "Unexpected token: ]]>");
+        }
+
+        if self.text.is_empty() && self.config.trim_whitespace {
+            start = end - text.trim_start_matches(|c| self.is_whitespace(c)).len();
+            if start == end {
+                return Ok(());
+            }
+        }
+        self.start_text(start);
+        if self.inside_whitespace && !self.is_whitespace_str(&self.src[start..end]) {
+            self.inside_whitespace = false;
+        }
+        let mut text = mem::replace(&mut self.text, TextBuf::new());
+        self.push_normalized(&mut text, start, end, false);
+        self.text = text;
+        Ok(())
+    }
+
+    #[inline]
+    fn start_text(&mut self, start: usize) {
+        if self.text.is_empty() {
+            self.text_start = start;
+        }
+    }
+
+    /// Returns the character data read so far as an event, if there is any.
+    fn flush_text(&mut self) -> Option<BorrowedEvent<'a>> {
+        if self.text.is_empty() {
+            return None;
+        }
+        let data = self.text.take(self.src);
+        let event = if mem::replace(&mut self.inside_whitespace, true) {
+            if self.config.trim_whitespace {
+                return None;
+            } else if self.config.whitespace_to_characters {
+                BorrowedEvent::Characters(data)
+            } else {
+                BorrowedEvent::Whitespace(data)
+            }
+        } else if self.config.trim_whitespace {
+            BorrowedEvent::Characters(trim(data))
+        } else {
+            BorrowedEvent::Characters(data)
+        };
+        let start = self.text_start;
+        Some(self.emit(start, event))
+    }
+
+    /// Reads markup starting with `<`, returning an event if the markup produces one.
+    fn read_markup(&mut self) -> Result<Option<BorrowedEvent<'a>>> {
+        let start = self.offset;
+        let coalesce = self.config.coalesce_characters;
+
+        if self.looking_at("<!--") {
+            if !self.config.ignore_comments || !coalesce {
+                if let Some(event) = self.flush_text() {
+                    return Ok(Some(event));
+                }
+            }
+            let (data_start, data_end) = try!(self.read_comment());
+            if self.config.ignore_comments {
+                return Ok(None);
+            }
+            let mut data = TextBuf::new();
+            self.push_normalized(&mut data, data_start, data_end, false);
+            return Ok(Some(self.emit(start, BorrowedEvent::Comment(data.take(self.src)))));
+        }
+
+        if self.looking_at("<![CDATA[") {
+            if !self.config.cdata_to_characters || !coalesce {
+                if let Some(event) = self.flush_text() {
+                    return Ok(Some(event));
+                }
+            }
+            let data_start = start + "<![CDATA[".len();
+            let data_end = match self.src[data_start..].find("]]>") {
+                Some(len) => data_start + len,
+                None => return self.unexpected_end()
+            };
+            self.offset = data_end + "]]>".len();
+            if self.config.cdata_to_characters {
+                self.start_text(start);
+                if self.inside_whitespace && !self.is_whitespace_str(&self.src[data_start..data_end]) {
+                    self.inside_whitespace = false;
+                }
+                let mut text = mem::replace(&mut self.text, TextBuf::new());
+                self.push_normalized(&mut text, data_start, data_end, false);
+                self.text = text;
+                return Ok(None);
+            }
+            // like `PullParser`, treat the text following the section as character data
+            // even if it is white space
+            if !self.is_whitespace_str(&self.src[data_start..data_end]) {
+                self.inside_whitespace = false;
+            }
+            let mut data = TextBuf::new();
+            self.push_normalized(&mut data, data_start, data_end, false);
+            return Ok(Some(self.emit(start, BorrowedEvent::CData(data.take(self.src)))));
+        }
+
+        if let Some(event) = self.flush_text() {
+            return Ok(Some(event));
+        }
+
+        if self.looking_at("<?") {
+            self.read_processing_instruction().map(Some)
+        } else if self.looking_at("</") {
+            if self.elements.is_empty() {
+                return self.error("Unexpected token: </");
+            }
+            self.read_end_tag().map(Some)
+        } else if self.looking_at("<!DOCTYPE") {
+            // a declaration in the prolog makes the document read by `EventReader`
+            self.error("Unexpected token: <!DOCTYPE")
+        } else if self.looking_at("<!") {
+            let prefix_len = ["<![CDATA[", "<!DOCTYPE", "<!--"].iter()
+                .map(|s| common_prefix_len(s, &self.src[start..]))
+                .max().unwrap();
+            let token = &self.src[start..start + prefix_len];
+            match self.src[start + prefix_len..].chars().next() {
+                Some(c) => self.error(format!("Unexpected token '{}' before '{}'", token, c)),
+                None => self.unexpected_end()
+            }
+        } else {
+            try!(self.check_tag_start());
+            if self.declaration_pending {
+                self.declaration_pending = false;
+                return Ok(Some(self.emit(start, BorrowedEvent::StartDocument {
+                    version: XmlVersion::Version10,
+                    encoding: Cow::Borrowed("UTF-8"),
+                    standalone: None
+                })));
+            }
+            self.read_start_tag().map(Some)
+        }
+    }
+
+    /// Checks that `<` at the current offset starts a tag, that is, it is followed by
+    /// white space or a name character.
+    fn check_tag_start(&self) -> Result<()> {
+        match self.src[self.offset + 1..].chars().next() {
+            Some(c) if self.is_whitespace(c) || is_name_char(c) => Ok(()),
+            Some(c) => self.error(format!("Unexpected token '<' before '{}'", c)),
+            None => self.unexpected_end()
+        }
+    }
+
+    /// Reads a comment, returning the offsets of its text.
+    fn read_comment(&mut self) -> Result<(usize, usize)> {
+        let start = self.offset + "<!--".len();
+        match self.src[start..].find("--") {
+            Some(len) => {
+                self.offset = start + len;
+                if self.eat("-->") {
+                    Ok((start, start + len))
+                } else {
+                    match self.src[self.offset + 2..].chars().next() {
+                        Some(c) => self.error(format!("Unexpected token '--' before '{}'", c)),
+                        None => self.unexpected_end()
+                    }
+                }
+            }
+            None => self.unexpected_end()
+        }
+    }
+
+    fn read_processing_instruction(&mut self) -> Result<BorrowedEvent<'a>> {
+        let start = self.offset;
+        self.offset += 2;
+        let name = match self.peek() {
+            Some(c) if is_name_start_char(c) => self.read_while(is_name_char),
+            _ => ""
+        };
+        // like `PullParser`, accept the XML declaration after comments and processing
+        // instructions, and treat names which differ in case as usual ones there
+        let is_xml = name.eq_ignore_ascii_case("xml");
+        let data = if self.looking_at("?>") {
+            if is_xml {
+                return self.error(format!("Invalid processing instruction: <?{}", name));
+            }
+            self.offset += 2;
+            None
+        } else {
+            match self.peek() {
+                Some(c) if self.is_whitespace(c) && name == "xml" && self.declaration_pending =>
+                    return self.read_declaration(start),
+                Some(c) if self.is_whitespace(c) && is_xml && !self.declaration_pending =>
+                    return self.error(format!("Invalid processing instruction: <?{}", name)),
+                Some(c) if self.is_whitespace(c) => {
+                    let data_start = self.offset + c.len_utf8();
+                    let data_end = match self.src[data_start..].find("?>") {
+                        Some(len) => data_start + len,
+                        None => return self.unexpected_end()
+                    };
+                    self.offset = data_end + 2;
+                    let mut data = TextBuf::new();
+                    self.push_normalized(&mut data, data_start, data_end, false);
+                    Some(data.take(self.src))
+                }
+                Some(c) => return self.error(format!("Unexpected token: <?{}{}", name, c)),
+                None => return self.unexpected_end()
+            }
+        };
+        if name.is_empty() {
+            return self.error_at(start, "Encountered processing instruction without name");
+        }
+        Ok(self.emit(start, BorrowedEvent::ProcessingInstruction { name: Cow::Borrowed(name), data: data }))
+    }
+
+    fn read_start_tag(&mut self) -> Result<BorrowedEvent<'a>> {
+        let start = self.offset;
+        self.offset += 1;
+        let (repr, prefix, local_name) = try!(self.read_qualified_name(|c| c == '>' || c == '/'));
+        if prefix == Some(NS_XML_PREFIX) || prefix == Some(NS_XMLNS_PREFIX) {
+            return self.error(format!("'{:?}' cannot be an element name prefix", prefix));
+        }
+        let namespaces = self.namespaces.len();
+        self.attributes.clear();
+
+        let mut end;
+        let empty = loop {
+            self.skip_whitespace();
+            end = self.offset;
+            match self.peek() {
+                Some('>') => {
+                    self.offset += 1;
+                    break false;
+                }
+                Some('/') if self.looking_at("/>") => {
+                    self.offset += 2;
+                    break true;
+                }
+                Some(c) if is_name_start_char(c) => try!(self.read_attribute()),
+                Some(c) => return self.error(format!("Unexpected token inside opening tag: {}", c)),
+                None => return self.unexpected_end()
+            }
+        };
+
+        let name = BorrowedName {
+            local_name: Cow::Borrowed(local_name),
+            namespace: match self.resolve(prefix.unwrap_or(NS_NO_PREFIX)) {
+                Some(namespace) => namespace,
+                None => return self.error_at(end, format!("Element {} prefix is unbound", repr))
+            },
+            prefix: prefix.map(Cow::Borrowed)
+        };
+        let mut raw_attributes = mem::replace(&mut self.attributes, Vec::new());
+        let mut attributes = Vec::with_capacity(raw_attributes.len());
+        for attribute in raw_attributes.drain(..) {
+            let namespace = match attribute.prefix {
+                Some(prefix) => match self.resolve(prefix) {
+                    Some(namespace) => namespace,
+                    None => return self.error_at(end, format!("Attribute {} prefix is unbound", attribute.repr))
+                },
+                None => None
+            };
+            attributes.push(BorrowedAttribute {
+                name: BorrowedName {
+                    local_name: Cow::Borrowed(attribute.local_name),
+                    namespace: namespace,
+                    prefix: attribute.prefix.map(Cow::Borrowed)
+                },
+                value: attribute.value
+            });
+        }
+
+        self.attributes = raw_attributes;
+
+        self.encountered_element = true;
+        if empty {
+            self.namespaces.truncate(namespaces);
+            self.next_event = Some(BorrowedEvent::EndElement { name: name.clone() });
+        } else {
+            self.elements.push(OpenElement { repr: repr, name: name.clone(), namespaces: namespaces });
+        }
+        Ok(self.emit(start, BorrowedEvent::StartElement { name: name, attributes: attributes }))
+    }
+
+    /// Reads an attribute of a start tag; namespace declarations are put in scope at once.
+    fn read_attribute(&mut self) -> Result<()> {
+        let (repr, prefix, local_name) = try!(self.read_qualified_name(|c| c == '='));
+        self.skip_whitespace();
+        if !self.eat("=") {
+            return self.unexpected("Unexpected token inside opening tag");
+        }
+        self.skip_whitespace();
+        let value = try!(self.read_attribute_value());
+        // errors are reported at the closing quote, like in `PullParser`
+        let end = self.offset - 1;
+
+        match prefix {
+            Some(NS_XMLNS_PREFIX) => {
+                if local_name == NS_XMLNS_PREFIX {
+                    self.error_at(end, format!("Cannot redefine prefix '{}'", NS_XMLNS_PREFIX))
+                } else if local_name == NS_XML_PREFIX && value != NS_XML_URI {
+                    self.error_at(end, format!("Prefix '{}' cannot be rebound to another value", NS_XML_PREFIX))
+                } else if value.is_empty() {
+                    self.error_at(end, format!("Cannot undefine prefix '{}'", local_name))
+                } else {
+                    self.namespaces.push((local_name, value));
+                    Ok(())
+                }
+            }
+            None if local_name == NS_XMLNS_PREFIX => {
+                if value == NS_XMLNS_PREFIX || value == NS_XML_PREFIX {
+                    self.error_at(end, format!("Namespace '{}' cannot be default", value))
+                } else {
+                    self.namespaces.push((NS_NO_PREFIX, value));
+                    Ok(())
+                }
+            }
+            _ => {
+                if self.attributes.iter().any(|a| a.repr == repr) {
+                    return self.error_at(end, format!("Attribute '{}' is redefined", repr));
+                }
+                self.attributes.push(RawAttribute {
+                    repr: repr,
+                    prefix: prefix,
+                    local_name: local_name,
+                    value: value
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn read_attribute_value(&mut self) -> Result<Cow<'a, str>> {
+        let quote = match self.peek() {
+            Some(c) if c == '"' || c == '\'' => c as u8,
+            _ => return self.unexpected("Unexpected token inside opening tag")
+        };
+        self.offset += 1;
+        let mut value = TextBuf::new();
+        loop {
+            let start = self.offset;
+            let end = find_byte(self.src, start, |b| b == quote || b == b'<' || b == b'&');
+            self.push_normalized(&mut value, start, end, true);
+            self.offset = end;
+            match self.src.as_bytes().get(end) {
+                Some(&b'<') => {
+                    try!(self.check_tag_start());
+                    return self.error("Unexpected token inside attribute value: <");
+                }
+                Some(&b'&') => { try!(self.read_reference(&mut value)); }
+                Some(_) => {
+                    self.offset += 1;
+                    return Ok(value.take(self.src));
+                }
+                None => return self.unexpected_end()
+            }
+        }
+    }
+
+    fn read_end_tag(&mut self) -> Result<BorrowedEvent<'a>> {
+        let start = self.offset;
+        self.offset += 2;
+        let (repr, prefix, local_name) = try!(self.read_qualified_name(|c| c == '>'));
+        if prefix == Some(NS_XML_PREFIX) || prefix == Some(NS_XMLNS_PREFIX) {
+            return self.error(format!("'{:?}' cannot be an element name prefix", prefix));
+        }
+        self.skip_whitespace();
+        if !self.eat(">") {
+            return self.unexpected("Unexpected token inside closing tag");
+        }
+        let namespace = match self.resolve(prefix.unwrap_or(NS_NO_PREFIX)) {
+            Some(namespace) => namespace,
+            None => return self.error_at(self.offset - 1, format!("Element {} prefix is unbound", repr))
+        };
+
+        let element = self.elements.pop().unwrap();
+        if element.repr != repr {
+            let name = BorrowedName {
+                local_name: Cow::Borrowed(local_name),
+                namespace: namespace,
+                prefix: prefix.map(Cow::Borrowed)
+            };
+            return self.error_at(self.offset - 1, format!("Unexpected closing tag: {}, expected {}", name, element.name));
+        }
+        self.namespaces.truncate(element.namespaces);
+        Ok(self.emit(start, BorrowedEvent::EndElement { name: element.name }))
+    }
+
+    /// Reads a qualified name which is followed by white space or a character for which
+    /// `is_end` returns true; returns the name with its prefix and local part.
+    fn read_qualified_name<F>(&mut self, is_end: F) -> Result<(&'a str, Option<&'a str>, &'a str)>
+        where F: Fn(char) -> bool
+    {
+        let start = self.offset;
+        let mut colon = None;
+        loop {
+            match self.peek() {
+                Some(c) if self.offset == start && self.is_whitespace(c) =>
+                    return self.error("Qualified name is invalid: "),
+                Some(':') if self.offset > start && colon.is_none() => colon = Some(self.offset),
+                Some(c) if c != ':' && (self.offset == start && is_name_start_char(c) ||
+                                        self.offset > start && is_name_char(c)) => {}
+                Some(c) if self.offset > start && (self.is_whitespace(c) || is_end(c)) => break,
+                Some(c) => return self.error(format!("Unexpected token inside qualified name: {}", c)),
+                None => return self.unexpected_end()
+            }
+            self.offset += self.peek().unwrap().len_utf8();
+        }
+        let repr = &self.src[start..self.offset];
+        match colon {
+            Some(i) if i + 1 == self.offset => self.error(format!("Qualified name is invalid: {}", repr)),
+            Some(i) => Ok((repr, Some(&self.src[start..i]), &self.src[i + 1..self.offset])),
+            None => Ok((repr, None, repr))
+        }
+    }
+
+    /// Reads an entity or character reference, appending its replacement text to `target`;
+    /// returns true if the replacement text is white space.
+    fn read_reference(&mut self, target: &mut TextBuf) -> Result<bool> {
+        self.offset += 1;
+        let start = self.offset;
+        loop {
+            match self.peek() {
+                Some(';') => break,
+                Some(c) if self.offset > start && is_name_char(c) ||
+                           self.offset == start && (is_name_start_char(c) || c == '#') => {
+                    self.offset += c.len_utf8();
+                }
+                Some(c) => return self.error(format!("Unexpected token inside an entity: {}", c)),
+                None => return self.unexpected_end()
+            }
+        }
+        // errors are reported at the semicolon, like in `PullParser`
+        let end = self.offset;
+        let name = &self.src[start..end];
+        self.offset += 1;
+        if name.is_empty() {
+            return self.error_at(end, "Encountered empty entity");
+        }
+        match util::resolve_predefined_reference(name) {
+            Some(Ok(c)) => {
+                target.push(self.src, c);
+                Ok(is_whitespace_char(c))
+            }
+            Some(Err(msg)) => self.error_at(end, msg),
+            None => match self.config.extra_entities.get(name) {
+                Some(value) => {
+                    target.push_str(self.src, value);
+                    Ok(is_whitespace_str(value))
+                }
+                None => self.error_at(end, format!("Unexpected entity: {}", name))
+            }
+        }
+    }
+
+    /// Appends the text between the given offsets to `target`, normalizing line breaks;
+    /// inside attribute values all white space characters are replaced with spaces,
+    /// unless disabled in the config.
+    fn push_normalized(&self, target: &mut TextBuf, start: usize, end: usize, in_attribute: bool) {
+        let replace_whitespace = in_attribute && self.config.normalize_attribute_values;
+        let bytes = self.src.as_bytes();
+        let mut last = start;
+        let mut i = start;
+        while i < end {
+            let len = match bytes[i] {
+                b'\r' => match bytes.get(i + 1) {
+                    Some(&b'\n') => 2,
+                    Some(&0xC2) if self.xml11 && bytes.get(i + 2) == Some(&0x85) => 3,
+                    _ => 1
+                },
+                b'\t' | b'\n' if replace_whitespace => 1,
+                0xC2 if self.xml11 && bytes.get(i + 1) == Some(&0x85) => 2,
+                0xE2 if self.xml11 && bytes[i..].starts_with(&[0xE2, 0x80, 0xA8]) => 3,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            target.push_slice(self.src, last, i);
+            target.push(self.src, if replace_whitespace { ' ' } else { '\n' });
+            i += len;
+            last = i;
+        }
+        target.push_slice(self.src, last, end);
+    }
+
+    /// Resolves a namespace prefix; returns `Some(None)` for the empty namespace and `None`
+    /// if the prefix is not bound.
+    fn resolve(&self, prefix: &str) -> Option<Option<Cow<'a, str>>> {
+        let namespace = match self.namespaces.iter().rev().find(|&&(p, _)| p == prefix) {
+            Some(&(_, ref namespace)) => namespace.clone(),
+            None => match prefix {
+                NS_XML_PREFIX => Cow::Borrowed(NS_XML_URI),
+                NS_XMLNS_PREFIX => Cow::Borrowed(NS_XMLNS_URI),
+                NS_NO_PREFIX => Cow::Borrowed(NS_EMPTY_URI),
+                _ => return None
+            }
+        };
+        Some(if namespace.is_empty() { None } else { Some(namespace) })
+    }
+
+    fn end_of_document(&mut self) -> Result<BorrowedEvent<'a>> {
+        if !self.elements.is_empty() {
+            self.unexpected_end()
+        } else if !self.encountered_element {
+            let end = self.src.len();
+            self.error_at(end, "Unexpected end of stream: no root element found")
+        } else {
+            let end = self.src.len();
+            Ok(self.emit(end, BorrowedEvent::EndDocument))
+        }
+    }
+
+    #[inline]
+    fn emit(&mut self, start: usize, event: BorrowedEvent<'a>) -> BorrowedEvent<'a> {
+        self.event_start = start;
+        event
+    }
+
+    /// Returns the position of the given offset, counting line breaks the same way
+    /// as `CharReader` normalizes them.
+    fn position_at(&self, offset: usize) -> TextPosition {
+        let (mut i, mut pos) = self.position_cache.get();
+        if offset < i {
+            i = self.bom_len;
+            pos = TextPosition::new();
+        }
+        let bytes = self.src.as_bytes();
+        while i < offset {
+            let len = match bytes[i] {
+                b'\n' => 1,
+                b'\r' => match bytes.get(i + 1) {
+                    Some(&b'\n') => 2,
+                    Some(&0xC2) if self.xml11 && bytes.get(i + 2) == Some(&0x85) => 3,
+                    _ => 1
+                },
+                0xC2 if self.xml11 && bytes.get(i + 1) == Some(&0x85) => 2,
+                0xE2 if self.xml11 && bytes[i..].starts_with(&[0xE2, 0x80, 0xA8]) => 3,
+                b => {
+                    // continuation bytes of UTF-8 sequences do not start characters
+                    if b & 0xC0 != 0x80 {
+                        pos.advance(1);
+                    }
+                    i += 1;
+                    continue;
+                }
+            };
+            pos.new_line();
+            i += len;
+        }
+        self.position_cache.set((i, pos));
+        pos
+    }
+
+    #[inline]
+    fn looking_at(&self, s: &str) -> bool {
+        self.src[self.offset..].starts_with(s)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.looking_at(s) {
+            self.offset += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.src[self.offset..].chars().next()
+    }
+
+    fn read_while<F: Fn(char) -> bool>(&mut self, pred: F) -> &'a str {
+        let start = self.offset;
+        let rest = &self.src[start..];
+        self.offset += rest.len() - rest.trim_start_matches(pred).len();
+        &self.src[start..self.offset]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let xml11 = self.xml11;
+        self.read_while(|c| is_whitespace_char(c) || xml11 && is_xml11_line_break(c));
+    }
+
+    /// Returns true if `c` is white space once line breaks are normalized.
+    #[inline]
+    fn is_whitespace(&self, c: char) -> bool {
+        is_whitespace_char(c) || self.xml11 && is_xml11_line_break(c)
+    }
+
+    fn is_whitespace_str(&self, s: &str) -> bool {
+        s.chars().all(|c| self.is_whitespace(c))
+    }
+
+    /// Reports an unexpected character at the current offset.
+    fn unexpected<T>(&self, context: &str) -> Result<T> {
+        match self.peek() {
+            Some(c) => self.error(format!("{}: {}", context, c)),
+            None => self.unexpected_end()
+        }
+    }
+
+    fn unexpected_end<T>(&self) -> Result<T> {
+        let end = self.src.len();
+        if self.elements.is_empty() {
+            self.error_at(end, "Unexpected end of stream")
+        } else {
+            self.error_at(end, "Unexpected end of stream: still inside the root element")
+        }
+    }
+
+    #[inline]
+    fn error<T, M: Into<Cow<'static, str>>>(&self, msg: M) -> Result<T> {
+        self.error_at(self.offset, msg)
+    }
+
+    fn error_at<T, M: Into<Cow<'static, str>>>(&self, offset: usize, msg: M) -> Result<T> {
+        Err((&self.position_at(offset), msg).into())
+    }
+}
+
+/// Returns the offset of the first byte at or after `start` for which `pred` returns true,
+/// or the length of `s`.
+#[inline]
+fn find_byte<F: Fn(u8) -> bool>(s: &str, start: usize, pred: F) -> usize {
+    s.as_bytes()[start..].iter().position(|&b| pred(b)).map(|i| start + i).unwrap_or(s.len())
+}
+
+/// Returns true if `c` is one of the line breaks which only XML 1.1 documents have.
+#[inline]
+fn is_xml11_line_break(c: char) -> bool {
+    c == '\u{85}' || c == '\u{2028}'
+}
+
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|&(x, y)| x == y).count()
+}
+
+/// Trims white space of character data, keeping it borrowed if it is.
+fn trim<'a>(data: Cow<'a, str>) -> Cow<'a, str> {
+    match data {
+        Cow::Borrowed(s) => Cow::Borrowed(s.trim_matches(is_whitespace_char)),
+        Cow::Owned(s) => Cow::Owned(s.trim_matches(is_whitespace_char).to_owned())
+    }
+}
@@ -0,0 +1,408 @@
+//! Contains a DTD validator used by the pull parser.
+//!
+//! This module is for internal use. When `ParserConfig::validate_dtd` is enabled, the parser
+//! passes each event it produces to `Validator` along with the DTD it has read, and the
+//! validator checks the events against it.
+
+use std::collections::{HashMap, HashSet};
+
+use common::{TextPosition, is_name_start_char, is_name_char, is_whitespace_str};
+use dtd::{Dtd, ContentSpec, ContentParticle, ParticleKind, Occurrence, AttributeType, AttributeDefault, EntityDef};
+use attribute::OwnedAttribute;
+use reader::events::XmlEvent;
+use reader::error;
+use reader::Result;
+use util;
+
+/// A finite automaton recognizing sequences of child elements allowed by an element
+/// content model.
+///
+/// The automaton is built with the Glushkov construction: each occurrence of an element
+/// name in the content model is a state, and state 0 is the initial state.
+struct ContentModel {
+    labels: Vec<String>,
+    follow: Vec<Vec<usize>>,
+    accepting: Vec<bool>
+}
+
+impl ContentModel {
+    fn new(particle: &ContentParticle) -> ContentModel {
+        let mut model = ContentModel {
+            labels: vec![String::new()],
+            follow: vec![Vec::new()],
+            accepting: Vec::new()
+        };
+        let (nullable, first, last) = model.build(particle);
+        model.follow[0] = first;
+        model.accepting = vec![false; model.labels.len()];
+        model.accepting[0] = nullable;
+        for i in last {
+            model.accepting[i] = true;
+        }
+        model
+    }
+
+    /// Adds states for the given particle, returning whether it matches an empty sequence,
+    /// and the states its matches can start and end with.
+    fn build(&mut self, particle: &ContentParticle) -> (bool, Vec<usize>, Vec<usize>) {
+        let (mut nullable, first, last) = match particle.kind {
+            ParticleKind::Name(ref name) => {
+                let state = self.labels.len();
+                self.labels.push(name.clone());
+                self.follow.push(Vec::new());
+                (false, vec![state], vec![state])
+            }
+            ParticleKind::Seq(ref particles) => {
+                let (mut nullable, mut first, mut last) = (true, Vec::new(), Vec::new());
+                for p in particles {
+                    let (n, f, l) = self.build(p);
+                    for &i in &last {
+                        self.add_follow(i, &f);
+                    }
+                    if nullable {
+                        merge(&mut first, &f);
+                    }
+                    if n {
+                        merge(&mut last, &l);
+                    } else {
+                        last = l;
+                    }
+                    nullable = nullable && n;
+                }
+                (nullable, first, last)
+            }
+            ParticleKind::Choice(ref particles) => {
+                let (mut nullable, mut first, mut last) = (false, Vec::new(), Vec::new());
+                for p in particles {
+                    let (n, f, l) = self.build(p);
+                    nullable = nullable || n;
+                    merge(&mut first, &f);
+                    merge(&mut last, &l);
+                }
+                (nullable, first, last)
+            }
+        };
+
+        match particle.occurrence {
+            Occurrence::Once => {}
+            Occurrence::Optional => nullable = true,
+            Occurrence::ZeroOrMore | Occurrence::OneOrMore => {
+                for &i in &last {
+                    self.add_follow(i, &first);
+                }
+                if particle.occurrence == Occurrence::ZeroOrMore {
+                    nullable = true;
+                }
+            }
+        }
+        (nullable, first, last)
+    }
+
+    fn add_follow(&mut self, state: usize, states: &[usize]) {
+        merge(&mut self.follow[state], states);
+    }
+
+    /// Returns the states reached from `states` after reading an element with the given name.
+    fn step(&self, states: &[usize], name: &str) -> Vec<usize> {
+        let mut result = Vec::new();
+        for &s in states {
+            for &t in &self.follow[s] {
+                if self.labels[t] == name && !result.contains(&t) {
+                    result.push(t);
+                }
+            }
+        }
+        result
+    }
+
+    fn accepts(&self, states: &[usize]) -> bool {
+        states.iter().any(|&s| self.accepting[s])
+    }
+}
+
+fn merge(target: &mut Vec<usize>, states: &[usize]) {
+    for &s in states {
+        if !target.contains(&s) {
+            target.push(s);
+        }
+    }
+}
+
+/// An element whose content is being validated.
+struct OpenElement {
+    name: String,
+    /// Current states of the content model automaton, for elements with element content.
+    states: Vec<usize>
+}
+
+/// Checks a stream of events against the DTD of the document.
+pub struct Validator {
+    /// The document type name, once the `Doctype` event is read.
+    root: Option<String>,
+    /// Whether whitespace other than spaces separates tokens of attribute values,
+    /// see `util::normalize_tokenized()`.
+    all_whitespace: bool,
+    models: HashMap<String, ContentModel>,
+    stack: Vec<OpenElement>,
+    ids: HashSet<String>,
+    idrefs: Vec<(String, TextPosition)>
+}
+
+impl Validator {
+    pub fn new(all_whitespace: bool) -> Validator {
+        Validator {
+            root: None,
+            all_whitespace: all_whitespace,
+            models: HashMap::new(),
+            stack: Vec::new(),
+            ids: HashSet::new(),
+            idrefs: Vec::new()
+        }
+    }
+
+    /// Checks the next event of the document, located at `pos`, against `dtd`.
+    pub fn validate(&mut self, event: &XmlEvent, dtd: &Dtd, pos: TextPosition) -> Result<()> {
+        macro_rules! error(($($arg:tt)+) => (Err(error::validity(&pos, format!($($arg)+)))));
+
+        match *event {
+            XmlEvent::Doctype { ref name, .. } => {
+                self.root = Some(name.clone());
+                Ok(())
+            }
+
+            XmlEvent::StartElement { ref name, ref attributes, .. } => {
+                let name = name.borrow().to_repr();
+                match self.root {
+                    Some(ref root) => if self.stack.is_empty() && name != *root {
+                        return error!("Root element {} does not match the document type name {}", name, root);
+                    },
+                    None => return error!("Document has no DTD to validate against")
+                }
+                if dtd.element(&name).is_none() {
+                    return error!("Element type {} is not declared", name);
+                }
+                if let Some(parent) = self.stack.pop() {
+                    let parent = try!(self.check_child(dtd, parent, &name, pos));
+                    self.stack.push(parent);
+                }
+                try!(self.check_attributes(dtd, &name, attributes, pos));
+
+                if let ContentSpec::Children(ref particle) = dtd.element(&name).unwrap().content {
+                    if !self.models.contains_key(&name) {
+                        self.models.insert(name.clone(), ContentModel::new(particle));
+                    }
+                }
+                self.stack.push(OpenElement { name: name, states: vec![0] });
+                Ok(())
+            }
+
+            XmlEvent::EndElement { .. } => {
+                let element = self.stack.pop().unwrap();
+                if let Some(model) = self.models.get(&element.name) {
+                    if !model.accepts(&element.states) {
+                        return error!("Content of element {} is incomplete", element.name);
+                    }
+                }
+                Ok(())
+            }
+
+            XmlEvent::Characters(ref data) if is_whitespace_str(data) => self.check_whitespace(dtd, pos),
+            XmlEvent::Whitespace(_) => self.check_whitespace(dtd, pos),
+
+            XmlEvent::Characters(_) | XmlEvent::CData(_) => match self.content_spec(dtd) {
+                Some(&ContentSpec::Empty) => error!("Element {} is declared EMPTY but has content", self.current()),
+                Some(&ContentSpec::Children(_)) =>
+                    error!("Character data is not allowed in the content of element {}", self.current()),
+                _ => Ok(())
+            },
+
+            XmlEvent::Comment(_) | XmlEvent::ProcessingInstruction { .. } => match self.content_spec(dtd) {
+                Some(&ContentSpec::Empty) => error!("Element {} is declared EMPTY but has content", self.current()),
+                _ => Ok(())
+            },
+
+            XmlEvent::EndDocument => {
+                for &(ref idref, ref pos) in &self.idrefs {
+                    if !self.ids.contains(idref) {
+                        return Err(error::validity(pos, format!("IDREF {} does not match any ID", idref)));
+                    }
+                }
+                Ok(())
+            }
+
+            _ => Ok(())
+        }
+    }
+
+    fn current(&self) -> &str {
+        &self.stack.last().unwrap().name
+    }
+
+    /// Returns the content specification of the current element.
+    fn content_spec<'a>(&self, dtd: &'a Dtd) -> Option<&'a ContentSpec> {
+        self.stack.last().and_then(|element| dtd.element(&element.name)).map(|e| &e.content)
+    }
+
+    /// Checks white space, which is only disallowed in the content of EMPTY elements.
+    fn check_whitespace(&self, dtd: &Dtd, pos: TextPosition) -> Result<()> {
+        match self.content_spec(dtd) {
+            Some(&ContentSpec::Empty) =>
+                Err(error::validity(&pos, format!("Element {} is declared EMPTY but has content", self.current()))),
+            _ => Ok(())
+        }
+    }
+
+    /// Checks that a child element with the given name is allowed in the content of `parent`.
+    fn check_child(&self, dtd: &Dtd, mut parent: OpenElement, name: &str, pos: TextPosition) -> Result<OpenElement> {
+        let allowed = match dtd.element(&parent.name).unwrap().content {
+            ContentSpec::Empty => false,
+            ContentSpec::Any => true,
+            ContentSpec::Mixed(ref names) => names.iter().any(|n| n == name),
+            ContentSpec::Children(_) => {
+                parent.states = self.models[&parent.name].step(&parent.states, name);
+                !parent.states.is_empty()
+            }
+        };
+        if allowed {
+            Ok(parent)
+        } else {
+            Err(error::validity(&pos, format!("Element {} is not allowed in the content of element {}",
+                                              name, parent.name)))
+        }
+    }
+
+    fn check_attributes(&mut self, dtd: &Dtd, element: &str, attributes: &[OwnedAttribute],
+                        pos: TextPosition) -> Result<()> {
+        macro_rules! error(($($arg:tt)+) => (Err(error::validity(&pos, format!($($arg)+)))));
+
+        let no_decls = Vec::new();
+        let decls = dtd.attributes.get(element).unwrap_or(&no_decls);
+
+        for decl in decls {
+            // namespace declarations are not reported as attributes
+            if decl.name == "xmlns" || decl.name.starts_with("xmlns:") {
+                continue;
+            }
+            if decl.default == AttributeDefault::Required &&
+               !attributes.iter().any(|a| a.name.borrow().to_repr() == decl.name) {
+                return error!("Required attribute {} of element {} is missing", decl.name, element);
+            }
+        }
+
+        for attribute in attributes {
+            let name = attribute.name.borrow().to_repr();
+            let decl = match decls.iter().find(|d| d.name == name) {
+                Some(decl) => decl,
+                None => return error!("Attribute {} is not declared for element {}", name, element)
+            };
+            let normalize = |value: &str| if decl.attribute_type == AttributeType::CData {
+                value.to_owned()
+            } else {
+                util::normalize_tokenized(value, self.all_whitespace)
+            };
+            let value = normalize(&attribute.value);
+            if let AttributeDefault::Fixed(ref fixed) = decl.default {
+                if value != normalize(fixed) {
+                    return error!("Attribute {} of element {} must have the fixed value \"{}\"", name, element, fixed);
+                }
+            }
+
+            let tokens: Vec<&str> = value.split(' ').collect();
+            let valid = match decl.attribute_type {
+                AttributeType::CData => true,
+                AttributeType::Id | AttributeType::IdRef | AttributeType::Entity => is_name(&value),
+                AttributeType::IdRefs | AttributeType::Entities => tokens.iter().all(|t| is_name(t)),
+                AttributeType::NmToken => is_nmtoken(&value),
+                AttributeType::NmTokens => tokens.iter().all(|t| is_nmtoken(t)),
+                AttributeType::Notation(ref values) | AttributeType::Enumeration(ref values) =>
+                    values.contains(&value)
+            };
+            if !valid {
+                return error!("Value \"{}\" of attribute {} of element {} does not match its declared type",
+                              value, name, element);
+            }
+
+            match decl.attribute_type {
+                AttributeType::Id => {
+                    let is_unique = self.ids.insert(value.clone());
+                    if !is_unique {
+                        return error!("Duplicate ID value: {}", value);
+                    }
+                }
+                AttributeType::IdRef | AttributeType::IdRefs =>
+                    self.idrefs.extend(tokens.iter().map(|t| (t.to_string(), pos))),
+                AttributeType::Notation(_) if dtd.notation(&value).is_none() =>
+                    return error!("Attribute {} of element {} does not name a declared notation: {}",
+                                  name, element, value),
+                AttributeType::Entity | AttributeType::Entities => for token in &tokens {
+                    match dtd.entity(token).map(|e| &e.definition) {
+                        Some(&EntityDef::External { notation: Some(_), .. }) => {}
+                        _ => return error!("Attribute {} of element {} does not name an unparsed entity: {}",
+                                           name, element, token)
+                    }
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    s.starts_with(is_name_start_char) && s.chars().all(is_name_char)
+}
+
+fn is_nmtoken(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_name_char)
+}
+
+#[cfg(test)]
+mod tests {
+    use dtd::{ContentParticle, ParticleKind, Occurrence};
+
+    use super::ContentModel;
+
+    fn name(n: &str, occurrence: Occurrence) -> ContentParticle {
+        ContentParticle { kind: ParticleKind::Name(n.into()), occurrence: occurrence }
+    }
+
+    fn matches(model: &ContentModel, names: &[&str]) -> bool {
+        let mut states = vec![0];
+        for name in names {
+            states = model.step(&states, name);
+        }
+        model.accepts(&states)
+    }
+
+    #[test]
+    fn content_models() {
+        // (head, (p | list)*, foot?)
+        let model = ContentModel::new(&ContentParticle {
+            kind: ParticleKind::Seq(vec![
+                name("head", Occurrence::Once),
+                ContentParticle {
+                    kind: ParticleKind::Choice(vec![name("p", Occurrence::Once), name("list", Occurrence::Once)]),
+                    occurrence: Occurrence::ZeroOrMore
+                },
+                name("foot", Occurrence::Optional)
+            ]),
+            occurrence: Occurrence::Once
+        });
+
+        assert!(matches(&model, &["head"]));
+        assert!(matches(&model, &["head", "p", "list", "p", "foot"]));
+        assert!(matches(&model, &["head", "foot"]));
+        assert!(!matches(&model, &[]));
+        assert!(!matches(&model, &["head", "foot", "p"]));
+        assert!(!matches(&model, &["p"]));
+
+        // (a?, b*)+
+        let model = ContentModel::new(&ContentParticle {
+            kind: ParticleKind::Seq(vec![name("a", Occurrence::Optional), name("b", Occurrence::ZeroOrMore)]),
+            occurrence: Occurrence::OneOrMore
+        });
+        assert!(matches(&model, &[]));
+        assert!(matches(&model, &["b", "a", "a", "b", "b"]));
+        assert!(!matches(&model, &["c"]));
+    }
+}
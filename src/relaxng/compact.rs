@@ -0,0 +1,743 @@
+//! Contains the parser of the RELAX NG compact syntax.
+//!
+//! A schema in the compact syntax is translated into the same tree of nodes as a schema in
+//! the XML syntax, so both are loaded the same way. Qualified names are resolved by the
+//! parser, and annotations are skipped.
+
+use std::collections::HashMap;
+use std::char;
+use std::io::Read;
+
+use common::{TextPosition, is_name_start_char, is_name_char};
+use namespace::{Namespace, NS_XML_URI};
+use reader;
+use relaxng::loader::{Node, XSD_DATATYPES};
+
+type Result<T> = reader::Result<T>;
+
+static KEYWORDS: &'static [&'static str] = &[
+    "attribute", "default", "datatypes", "div", "element", "empty", "external", "grammar", "include",
+    "inherit", "list", "mixed", "namespace", "notAllowed", "parent", "start", "string", "text", "token"
+];
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    /// A keyword or an identifier; escaped keywords are identifiers.
+    Name(String, bool),
+    /// A prefixed name.
+    CName(String, String),
+    /// A prefix followed by `:*`.
+    NsName(String),
+    Literal(String),
+    Operator(&'static str),
+    Eof
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match *self {
+            Token::Name(ref name, false) => name == keyword,
+            _ => false
+        }
+    }
+
+    fn is(&self, operator: &str) -> bool {
+        match *self {
+            Token::Operator(op) => op == operator,
+            _ => false
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            Token::Name(ref name, _) => format!("\"{}\"", name),
+            Token::CName(ref prefix, ref local) => format!("\"{}:{}\"", prefix, local),
+            Token::NsName(ref prefix) => format!("\"{}:*\"", prefix),
+            Token::Literal(_) => "literal".into(),
+            Token::Operator(op) => format!("\"{}\"", op),
+            Token::Eof => "end of schema".into()
+        }
+    }
+}
+
+static OPERATORS: &'static [&'static str] = &[
+    "|=", "&=", ">>", "=", "{", "}", "(", ")", "[", "]", ",", "&", "|", "?", "*", "+", "-", "~"
+];
+
+/// Splits the schema into tokens, replacing escaped characters.
+fn tokenize(source: &str) -> Result<Vec<(Token, TextPosition)>> {
+    let mut chars = Vec::new();
+    let mut pos = TextPosition::new();
+    let mut iter = source.chars().peekable();
+    while let Some(c) = iter.next() {
+        let start = pos;
+        let c = if c == '\r' {
+            if iter.peek() == Some(&'\n') {
+                iter.next();
+            }
+            '\n'
+        } else {
+            c
+        };
+        if c == '\n' {
+            pos.new_line();
+        } else {
+            pos.advance(1);
+        }
+        if c == '\\' && iter.peek() == Some(&'x') {
+            let rest: String = iter.clone().take_while(|&c| c != '}').collect();
+            let hex = &rest[rest.chars().take_while(|&c| c == 'x').count()..];
+            if hex.starts_with('{') {
+                let value = u32::from_str_radix(&hex[1..], 16).ok().and_then(char::from_u32);
+                match value {
+                    Some(value) if iter.clone().nth(rest.chars().count()) == Some('}') => {
+                        for _ in 0..rest.chars().count() + 1 {
+                            iter.next();
+                            pos.advance(1);
+                        }
+                        chars.push((value, start));
+                        continue;
+                    }
+                    _ => return Err((&start, "Invalid character escape").into())
+                }
+            }
+        }
+        chars.push((c, start));
+    }
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (c, start) = chars[i];
+        if c == ' ' || c == '\t' || c == '\n' {
+            i += 1;
+        } else if c == '#' {
+            while i < chars.len() && chars[i].0 != '\n' {
+                i += 1;
+            }
+        } else if c == '"' || c == '\'' {
+            let triple = i + 2 < chars.len() && chars[i + 1].0 == c && chars[i + 2].0 == c;
+            let delimiter = if triple { 3 } else { 1 };
+            i += delimiter;
+            let mut value = String::new();
+            loop {
+                if i >= chars.len() || (!triple && chars[i].0 == '\n') {
+                    return Err((&start, "Unterminated literal").into());
+                }
+                if chars[i].0 == c && (!triple || (i + 2 < chars.len() && chars[i + 1].0 == c && chars[i + 2].0 == c)) {
+                    i += delimiter;
+                    break;
+                }
+                value.push(chars[i].0);
+                i += 1;
+            }
+            tokens.push((Token::Literal(value), start));
+        } else if c == '\\' || is_name_start_char(c) && c != ':' {
+            let escaped = c == '\\';
+            if escaped {
+                i += 1;
+            }
+            let name = name_at(&chars, &mut i);
+            if name.is_empty() {
+                return Err((&start, "Invalid escaped identifier").into());
+            }
+            let token = if !escaped && i + 1 < chars.len() && chars[i].0 == ':' && chars[i + 1].0 == '*' {
+                i += 2;
+                Token::NsName(name)
+            } else if !escaped && i + 1 < chars.len() && chars[i].0 == ':' && is_name_start_char(chars[i + 1].0) {
+                i += 1;
+                Token::CName(name, name_at(&chars, &mut i))
+            } else {
+                Token::Name(name, escaped)
+            };
+            tokens.push((token, start));
+        } else {
+            let rest: String = chars[i..].iter().take(2).map(|&(c, _)| c).collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    i += op.len();
+                    tokens.push((Token::Operator(op), start));
+                }
+                None => return Err((&start, format!("Unexpected character: {}", c)).into())
+            }
+        }
+    }
+    let end = chars.last().map(|&(_, pos)| pos).unwrap_or_else(TextPosition::new);
+    tokens.push((Token::Eof, end));
+    Ok(tokens)
+}
+
+fn name_at(chars: &[(char, TextPosition)], i: &mut usize) -> String {
+    let mut name = String::new();
+    while *i < chars.len() && is_name_char(chars[*i].0) && chars[*i].0 != ':' &&
+          (!name.is_empty() || is_name_start_char(chars[*i].0)) {
+        name.push(chars[*i].0);
+        *i += 1;
+    }
+    name
+}
+
+/// Parses a schema in the compact syntax into a tree of nodes.
+pub fn parse<R: Read>(mut source: R) -> Result<Node> {
+    let mut text = String::new();
+    try!(source.read_to_string(&mut text));
+    let mut parser = Parser {
+        tokens: try!(tokenize(&text)),
+        index: 0,
+        namespaces: HashMap::new(),
+        datatypes: HashMap::new()
+    };
+    parser.namespaces.insert("xml".to_owned(), Some(NS_XML_URI.to_owned()));
+    parser.datatypes.insert("xsd".to_owned(), XSD_DATATYPES.to_owned());
+    parser.top_level()
+}
+
+struct Parser {
+    tokens: Vec<(Token, TextPosition)>,
+    index: usize,
+    /// Namespace prefixes; `None` stands for the inherited namespace.
+    namespaces: HashMap<String, Option<String>>,
+    datatypes: HashMap<String, String>
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.index].0
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        &self.tokens[(self.index + offset).min(self.tokens.len() - 1)].0
+    }
+
+    fn pos(&self) -> TextPosition {
+        self.tokens[self.index].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.index].0.clone();
+        if self.index + 1 < self.tokens.len() {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn unexpected(&self, expected: &str) -> reader::Error {
+        (&self.pos(), format!("Unexpected {}, expected {}", self.peek().describe(), expected)).into()
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<()> {
+        if self.peek().is(operator) {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("\"{}\"", operator)))
+        }
+    }
+
+    /// Returns a node with the given attributes at the position of the current token.
+    fn node(&self, local: &str, attributes: &[(&str, &str)]) -> Node {
+        let mut node = Node::new(local, self.pos());
+        node.attributes = attributes.iter().map(|&(n, v)| (n.to_owned(), v.to_owned())).collect();
+        node
+    }
+
+    /// Skips annotations in square brackets and following annotations.
+    fn skip_annotations(&mut self) -> Result<()> {
+        loop {
+            if self.peek().is("[") {
+                let mut depth = 0;
+                loop {
+                    match self.next() {
+                        Token::Operator("[") => depth += 1,
+                        Token::Operator("]") => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        Token::Eof => return Err(self.unexpected("\"]\"")),
+                        _ => {}
+                    }
+                }
+            } else if self.peek().is(">>") {
+                self.next();
+                match self.next() {
+                    Token::Name(..) | Token::CName(..) => {}
+                    _ => return Err(self.unexpected("annotation element"))
+                }
+                if !self.peek().is("[") {
+                    return Err(self.unexpected("\"[\""));
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn top_level(&mut self) -> Result<Node> {
+        let mut default_ns = None;
+        loop {
+            try!(self.skip_annotations());
+            let default = self.peek().is_keyword("default");
+            if !default && !self.peek().is_keyword("namespace") && !self.peek().is_keyword("datatypes") {
+                break;
+            }
+            if default {
+                self.next();
+                if !self.peek().is_keyword("namespace") {
+                    return Err(self.unexpected("\"namespace\""));
+                }
+            }
+            let datatypes = self.next().is_keyword("datatypes");
+            let prefix = match *self.peek() {
+                Token::Name(ref name, _) => Some(name.clone()),
+                _ if default => None,
+                _ => return Err(self.unexpected("prefix"))
+            };
+            if prefix.is_some() {
+                self.next();
+            }
+            try!(self.expect("="));
+            let uri = if !datatypes && self.peek().is_keyword("inherit") {
+                self.next();
+                None
+            } else {
+                Some(try!(self.literal()))
+            };
+            match (datatypes, prefix) {
+                (true, Some(prefix)) => {
+                    self.datatypes.insert(prefix, uri.unwrap());
+                }
+                (_, prefix) => {
+                    if default {
+                        default_ns = Some(uri.clone());
+                    }
+                    if let Some(prefix) = prefix {
+                        self.namespaces.insert(prefix, uri);
+                    }
+                }
+            }
+        }
+
+        let pos = self.pos();
+        let mut root = if self.is_grammar_content() {
+            let mut grammar = Node::new("grammar", pos);
+            grammar.children = try!(self.grammar_content());
+            grammar
+        } else {
+            try!(self.pattern())
+        };
+        if *self.peek() != Token::Eof {
+            return Err(self.unexpected("end of schema"));
+        }
+        if let Some(Some(ns)) = default_ns {
+            root.attributes.push(("ns".to_owned(), ns));
+        }
+        Ok(root)
+    }
+
+    fn is_grammar_content(&self) -> bool {
+        match *self.peek() {
+            Token::Eof => true,
+            Token::Name(ref name, escaped) =>
+                (!escaped && (name == "start" || name == "div" || name == "include")) ||
+                    self.peek_at(1).is("=") || self.peek_at(1).is("|=") || self.peek_at(1).is("&="),
+            _ => false
+        }
+    }
+
+    /// Parses components of a grammar up to the closing brace or the end of the schema.
+    fn grammar_content(&mut self) -> Result<Vec<Node>> {
+        let mut components = Vec::new();
+        loop {
+            try!(self.skip_annotations());
+            match self.peek().clone() {
+                Token::Eof | Token::Operator("}") => return Ok(components),
+                Token::Name(ref name, false) if name == "div" => {
+                    let mut div = self.node("div", &[]);
+                    self.next();
+                    try!(self.expect("{"));
+                    div.children = try!(self.grammar_content());
+                    try!(self.expect("}"));
+                    components.push(div);
+                }
+                Token::Name(ref name, false) if name == "include" => {
+                    let mut include = self.node("include", &[]);
+                    self.next();
+                    let href = try!(self.literal());
+                    include.attributes.push(("href".to_owned(), href));
+                    if let Some(ns) = try!(self.inherit()) {
+                        include.attributes.push(("ns".to_owned(), ns));
+                    }
+                    if self.peek().is("{") {
+                        self.next();
+                        include.children = try!(self.grammar_content());
+                        try!(self.expect("}"));
+                    }
+                    components.push(include);
+                }
+                Token::Name(name, escaped) => {
+                    let start = !escaped && name == "start";
+                    let mut node = if start { self.node("start", &[]) } else { self.node("define", &[("name", &name)]) };
+                    self.next();
+                    match self.next() {
+                        Token::Operator("=") => {}
+                        Token::Operator("|=") => node.attributes.push(("combine".to_owned(), "choice".to_owned())),
+                        Token::Operator("&=") => node.attributes.push(("combine".to_owned(), "interleave".to_owned())),
+                        _ => {
+                            self.index -= 1;
+                            return Err(self.unexpected("\"=\", \"|=\" or \"&=\""));
+                        }
+                    }
+                    node.children.push(try!(self.pattern()));
+                    components.push(node);
+                }
+                _ => return Err(self.unexpected("grammar component"))
+            }
+        }
+    }
+
+    /// Parses an optional `inherit = prefix` clause, returning the namespace it specifies.
+    fn inherit(&mut self) -> Result<Option<String>> {
+        if !self.peek().is_keyword("inherit") {
+            return Ok(None);
+        }
+        self.next();
+        try!(self.expect("="));
+        let pos = self.pos();
+        match self.next() {
+            Token::Name(prefix, _) => match self.namespaces.get(&prefix) {
+                Some(&Some(ref ns)) => Ok(Some(ns.clone())),
+                Some(&None) => Ok(None),
+                None => Err((&pos, format!("Undeclared namespace prefix: {}", prefix)).into())
+            },
+            _ => {
+                self.index -= 1;
+                Err(self.unexpected("prefix"))
+            }
+        }
+    }
+
+    fn literal(&mut self) -> Result<String> {
+        let mut value = match self.next() {
+            Token::Literal(value) => value,
+            _ => {
+                self.index -= 1;
+                return Err(self.unexpected("literal"));
+            }
+        };
+        while self.peek().is("~") {
+            self.next();
+            match self.next() {
+                Token::Literal(more) => value.push_str(&more),
+                _ => {
+                    self.index -= 1;
+                    return Err(self.unexpected("literal"));
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    fn pattern(&mut self) -> Result<Node> {
+        let first = try!(self.particle());
+        let operator = match *self.peek() {
+            Token::Operator(op) if op == "," || op == "&" || op == "|" => op,
+            _ => return Ok(first)
+        };
+        let mut node = Node::new(match operator { "," => "group", "&" => "interleave", _ => "choice" }, first.pos);
+        node.children.push(first);
+        while self.peek().is(operator) {
+            self.next();
+            node.children.push(try!(self.particle()));
+        }
+        match *self.peek() {
+            Token::Operator(op) if op == "," || op == "&" || op == "|" =>
+                Err((&self.pos(), "Different operators must not be mixed without parentheses").into()),
+            _ => Ok(node)
+        }
+    }
+
+    fn particle(&mut self) -> Result<Node> {
+        let primary = try!(self.primary());
+        let local = match *self.peek() {
+            Token::Operator("?") => "optional",
+            Token::Operator("*") => "zeroOrMore",
+            Token::Operator("+") => "oneOrMore",
+            _ => return Ok(primary)
+        };
+        self.next();
+        try!(self.skip_annotations());
+        let mut node = Node::new(local, primary.pos);
+        node.children.push(primary);
+        Ok(node)
+    }
+
+    fn primary(&mut self) -> Result<Node> {
+        try!(self.skip_annotations());
+        let pos = self.pos();
+        let node = match self.next() {
+            Token::Name(ref keyword, false) if keyword == "element" || keyword == "attribute" => {
+                let element = keyword == "element";
+                let mut node = Node::new(keyword, pos);
+                node.children.push(try!(self.name_class(element)));
+                try!(self.expect("{"));
+                node.children.push(try!(self.pattern()));
+                try!(self.expect("}"));
+                node
+            }
+            Token::Name(ref keyword, false) if keyword == "list" || keyword == "mixed" => {
+                let mut node = Node::new(keyword, pos);
+                try!(self.expect("{"));
+                node.children.push(try!(self.pattern()));
+                try!(self.expect("}"));
+                node
+            }
+            Token::Name(ref keyword, false) if keyword == "empty" || keyword == "text" || keyword == "notAllowed" =>
+                Node::new(keyword, pos),
+            Token::Name(ref keyword, false) if keyword == "parent" => match self.next() {
+                Token::Name(name, _) => {
+                    let mut node = Node::new("parentRef", pos);
+                    node.attributes.push(("name".to_owned(), name));
+                    node
+                }
+                _ => {
+                    self.index -= 1;
+                    return Err(self.unexpected("identifier"));
+                }
+            },
+            Token::Name(ref keyword, false) if keyword == "external" => {
+                let mut node = Node::new("externalRef", pos);
+                let href = try!(self.literal());
+                node.attributes.push(("href".to_owned(), href));
+                if let Some(ns) = try!(self.inherit()) {
+                    node.attributes.push(("ns".to_owned(), ns));
+                }
+                node
+            }
+            Token::Name(ref keyword, false) if keyword == "grammar" => {
+                let mut node = Node::new("grammar", pos);
+                try!(self.expect("{"));
+                node.children = try!(self.grammar_content());
+                try!(self.expect("}"));
+                node
+            }
+            Token::Name(ref keyword, false) if keyword == "string" || keyword == "token" =>
+                try!(self.datatype(pos, "", keyword)),
+            Token::CName(ref prefix, ref local) => {
+                let library = match self.datatypes.get(prefix) {
+                    Some(library) => library.clone(),
+                    None => return Err((&pos, format!("Undeclared datatype prefix: {}", prefix)).into())
+                };
+                try!(self.datatype(pos, &library, local))
+            }
+            Token::Name(ref name, escaped) if escaped || !KEYWORDS.contains(&&name[..]) => {
+                let mut node = Node::new("ref", pos);
+                node.attributes.push(("name".to_owned(), name.clone()));
+                node
+            }
+            Token::Literal(_) => {
+                self.index -= 1;
+                let mut node = Node::new("value", pos);
+                node.text = try!(self.literal());
+                node.namespace = self.namespace();
+                node
+            }
+            Token::Operator("(") => {
+                let node = try!(self.pattern());
+                try!(self.expect(")"));
+                node
+            }
+            _ => {
+                self.index -= 1;
+                return Err(self.unexpected("pattern"));
+            }
+        };
+        try!(self.skip_annotations());
+        Ok(node)
+    }
+
+    /// Parses a value, or a data pattern with optional parameters and except pattern, of the
+    /// datatype whose name was just read.
+    fn datatype(&mut self, pos: TextPosition, library: &str, name: &str) -> Result<Node> {
+        let attributes = vec![("type".to_owned(), name.to_owned()), ("datatypeLibrary".to_owned(), library.to_owned())];
+        if let Token::Literal(_) = *self.peek() {
+            let mut node = Node::new("value", pos);
+            node.attributes = attributes;
+            node.text = try!(self.literal());
+            node.namespace = self.namespace();
+            return Ok(node);
+        }
+        let mut node = Node::new("data", pos);
+        node.attributes = attributes;
+        if self.peek().is("{") {
+            self.next();
+            loop {
+                try!(self.skip_annotations());
+                let pos = self.pos();
+                match self.next() {
+                    Token::Operator("}") => break,
+                    Token::Name(param, _) => {
+                        try!(self.expect("="));
+                        let mut node_param = Node::new("param", pos);
+                        node_param.attributes.push(("name".to_owned(), param));
+                        node_param.text = try!(self.literal());
+                        node.children.push(node_param);
+                    }
+                    _ => {
+                        self.index -= 1;
+                        return Err(self.unexpected("parameter"));
+                    }
+                }
+            }
+        }
+        if self.peek().is("-") {
+            let mut except = self.node("except", &[]);
+            self.next();
+            except.children.push(try!(self.primary()));
+            node.children.push(except);
+        }
+        Ok(node)
+    }
+
+    /// Returns namespace prefixes declared in the schema, for values of `QName` types.
+    fn namespace(&self) -> Namespace {
+        let mut namespace = Namespace::empty();
+        for (prefix, uri) in &self.namespaces {
+            if let Some(ref uri) = *uri {
+                namespace.put(prefix.clone(), uri.clone());
+            }
+        }
+        namespace
+    }
+
+    /// Parses a name class; unprefixed names of elements are in the default namespace,
+    /// while those of attributes have no namespace.
+    fn name_class(&mut self, element: bool) -> Result<Node> {
+        let first = try!(self.name_class_primary(element));
+        if !self.peek().is("|") {
+            return Ok(first);
+        }
+        let mut choice = Node::new("choice", first.pos);
+        choice.children.push(first);
+        while self.peek().is("|") {
+            self.next();
+            choice.children.push(try!(self.name_class_primary(element)));
+        }
+        Ok(choice)
+    }
+
+    fn name_class_primary(&mut self, element: bool) -> Result<Node> {
+        try!(self.skip_annotations());
+        let pos = self.pos();
+        let mut node = match self.next() {
+            Token::Name(name, _) => {
+                let mut node = Node::new("name", pos);
+                if !element {
+                    node.attributes.push(("ns".to_owned(), String::new()));
+                }
+                node.text = name;
+                node
+            }
+            Token::CName(prefix, local) => {
+                let mut node = Node::new("name", pos);
+                node.attributes.push(("ns".to_owned(), try!(self.prefix(&prefix, pos))));
+                node.text = local;
+                node
+            }
+            Token::NsName(prefix) => {
+                let mut node = Node::new("nsName", pos);
+                node.attributes.push(("ns".to_owned(), try!(self.prefix(&prefix, pos))));
+                node
+            }
+            Token::Operator("*") => Node::new("anyName", pos),
+            Token::Operator("(") => {
+                let node = try!(self.name_class(element));
+                try!(self.expect(")"));
+                return Ok(node);
+            }
+            _ => {
+                self.index -= 1;
+                return Err(self.unexpected("name class"));
+            }
+        };
+        if (node.local == "anyName" || node.local == "nsName") && self.peek().is("-") {
+            let mut except = self.node("except", &[]);
+            self.next();
+            except.children.push(try!(self.name_class_primary(element)));
+            node.children.push(except);
+        }
+        try!(self.skip_annotations());
+        Ok(node)
+    }
+
+    fn prefix(&self, prefix: &str, pos: TextPosition) -> Result<String> {
+        match self.namespaces.get(prefix) {
+            Some(&Some(ref ns)) => Ok(ns.clone()),
+            Some(&None) => Err((&pos, format!("Namespace prefix {} is bound to the inherited namespace, which is not supported", prefix)).into()),
+            None => Err((&pos, format!("Undeclared namespace prefix: {}", prefix)).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use relaxng::loader::Node;
+
+    fn render(node: &Node) -> String {
+        let mut s = format!("({}", node.local);
+        for &(ref name, ref value) in &node.attributes {
+            s.push_str(&format!(" {}={:?}", name, value));
+        }
+        if !node.text.is_empty() {
+            s.push_str(&format!(" {:?}", node.text));
+        }
+        for child in &node.children {
+            s.push(' ');
+            s.push_str(&render(child));
+        }
+        s.push(')');
+        s
+    }
+
+    fn parsed(schema: &str) -> String {
+        render(&parse(schema.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(
+            parsed("element a { attribute b { xsd:int }, (element c { text }* | empty) }"),
+            "(element (name \"a\") (group (attribute (name ns=\"\" \"b\") \
+             (data type=\"int\" datatypeLibrary=\"http://www.w3.org/2001/XMLSchema-datatypes\")) \
+             (choice (zeroOrMore (element (name \"c\") (text))) (empty))))"
+        );
+        assert_eq!(
+            parsed("namespace x = 'urn:x'\nelement x:* - x:a { \"v\" ~ '''w''' | string { minLength = \"1\" } - \"z\" }"),
+            "(element (nsName ns=\"urn:x\" (except (name ns=\"urn:x\" \"a\"))) (choice (value \"vw\") \
+             (data type=\"string\" datatypeLibrary=\"\" (param name=\"minLength\" \"1\") \
+             (except (value \"z\")))))"
+        );
+    }
+
+    #[test]
+    fn grammars() {
+        assert_eq!(
+            parsed("default namespace = \"urn:d\"\n# comment\nstart = a\na = [ doc = \"x\" ] element \\element { empty }\na |= parent b\ninclude \"c.rnc\" { start &= notAllowed }"),
+            "(grammar ns=\"urn:d\" (start (ref name=\"a\")) (define name=\"a\" (element (name \"element\") (empty))) \
+             (define name=\"a\" combine=\"choice\" (parentRef name=\"b\")) \
+             (include href=\"c.rnc\" (start combine=\"interleave\" (notAllowed))))"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |schema: &str| parse(schema.as_bytes()).err().unwrap().to_string();
+        assert_eq!(error("element a { b, c | d }"), "1:18 Different operators must not be mixed without parentheses");
+        assert_eq!(error("element a {\n  p:b\n}"), "2:3 Undeclared datatype prefix: p");
+        assert_eq!(error("element p:a { empty }"), "1:9 Undeclared namespace prefix: p");
+        assert_eq!(error("element a { \"b }"), "1:13 Unterminated literal");
+        assert_eq!(error("start = element a { empty } }"), "1:29 Unexpected \"}\", expected end of schema");
+    }
+}
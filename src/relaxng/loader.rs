@@ -0,0 +1,589 @@
+//! Contains the loader which builds a grammar from RELAX NG schema documents.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Read;
+use std::rc::Rc;
+
+use common::{Position, TextPosition};
+use namespace::Namespace;
+use reader::{self, EventReader, XmlEvent, EntityResolver};
+use relaxng::compact;
+use relaxng::pattern::{Name, NameClass, Datatype, Pattern, Grammar, empty, not_allowed, choice, group,
+                       interleave, one_or_more};
+use schema::Datatypes;
+
+type Result<T> = reader::Result<T>;
+
+pub const RNG_NAMESPACE: &'static str = "http://relaxng.org/ns/structure/1.0";
+pub const XSD_DATATYPES: &'static str = "http://www.w3.org/2001/XMLSchema-datatypes";
+
+/// An element of a schema document in the RELAX NG namespace.
+pub struct Node {
+    pub local: String,
+    /// Attributes without a namespace; other attributes are annotations and are ignored.
+    pub attributes: Vec<(String, String)>,
+    pub namespace: Namespace,
+    /// Child elements in the RELAX NG namespace; other elements are annotations and are ignored.
+    pub children: Vec<Node>,
+    /// Character data directly contained in the element.
+    pub text: String,
+    pub pos: TextPosition
+}
+
+impl Node {
+    pub fn new(local: &str, pos: TextPosition) -> Node {
+        Node {
+            local: local.to_owned(),
+            attributes: Vec::new(),
+            namespace: Namespace::empty(),
+            children: Vec::new(),
+            text: String::new(),
+            pos: pos
+        }
+    }
+
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| &v[..])
+    }
+
+    fn required_attr(&self, name: &str) -> Result<&str> {
+        match self.attr(name) {
+            Some(v) => Ok(v.trim()),
+            None => Err(self.error(format!("Attribute {} is required on {}", name, self.local)))
+        }
+    }
+
+    fn error<M: Into<Cow<'static, str>>>(&self, msg: M) -> reader::Error {
+        (&self.pos, msg).into()
+    }
+}
+
+/// Parses a schema document in the XML syntax into a tree of nodes.
+fn parse_document<R: Read>(source: R) -> Result<Node> {
+    let mut reader = EventReader::new(source);
+    let mut stack: Vec<Node> = Vec::new();
+    // Depth of the annotation element being skipped
+    let mut foreign = 0;
+    loop {
+        match try!(reader.next()) {
+            XmlEvent::StartElement { .. } if foreign > 0 => foreign += 1,
+            XmlEvent::StartElement { ref name, .. } if name.namespace_ref() != Some(RNG_NAMESPACE) => {
+                if stack.is_empty() {
+                    return Err((&reader.position(), format!(
+                        "Root element of a RELAX NG schema must be in the namespace {}", RNG_NAMESPACE
+                    )).into());
+                }
+                foreign = 1;
+            }
+            XmlEvent::StartElement { name, attributes, namespace } => stack.push(Node {
+                local: name.local_name,
+                attributes: attributes.into_iter()
+                    .filter(|a| a.name.namespace.is_none())
+                    .map(|a| (a.name.local_name, a.value))
+                    .collect(),
+                namespace: namespace,
+                children: Vec::new(),
+                text: String::new(),
+                pos: reader.position()
+            }),
+            XmlEvent::EndElement { .. } if foreign > 0 => foreign -= 1,
+            XmlEvent::EndElement { .. } => {
+                let node = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => return Ok(node)
+                }
+            }
+            XmlEvent::Characters(ref data) | XmlEvent::CData(ref data) | XmlEvent::Whitespace(ref data) if foreign == 0 =>
+                if let Some(node) = stack.last_mut() {
+                    node.text.push_str(data);
+                },
+            _ => {}
+        }
+    }
+}
+
+/// The default namespace of names and the datatype library, which are inherited by
+/// descendants of the elements which specify them.
+#[derive(Clone)]
+struct Context {
+    ns: String,
+    library: String
+}
+
+impl Context {
+    fn enter(&self, node: &Node) -> Context {
+        Context {
+            ns: node.attr("ns").unwrap_or(&self.ns).to_owned(),
+            library: node.attr("datatypeLibrary").unwrap_or(&self.library).to_owned()
+        }
+    }
+}
+
+/// A position in one of the loaded schema documents.
+#[derive(Clone)]
+struct Location {
+    pos: TextPosition,
+    /// Included or referenced documents containing the position, with positions of
+    /// the elements which refer to them.
+    documents: Vec<(String, TextPosition)>
+}
+
+/// A named pattern of a grammar; the start pattern has an empty name.
+struct Define {
+    name: String,
+    body: Option<Rc<Pattern>>,
+    /// The combine method of the definitions, if any of them specify it.
+    combine: Option<String>,
+    /// Whether one of the definitions has no combine method.
+    plain: bool,
+    location: Option<Location>
+}
+
+impl Define {
+    fn description(&self) -> String {
+        if self.name.is_empty() { "start".into() } else { format!("define {}", self.name) }
+    }
+}
+
+/// Defines of a grammar being loaded.
+struct Scope {
+    defines: HashMap<String, usize>,
+    /// References to defines of the grammar, which are checked when the grammar is complete.
+    references: Vec<(usize, Location)>
+}
+
+pub struct Loader<'a> {
+    resolver: Option<&'a EntityResolver>,
+    /// Defines of all grammars.
+    defines: Vec<Define>,
+    /// Grammars being loaded, innermost last.
+    scopes: Vec<Scope>,
+    datatypes: Datatypes,
+    /// Documents being loaded, see `Location`.
+    documents: Vec<(String, TextPosition)>
+}
+
+impl<'a> Loader<'a> {
+    /// Creates a loader which loads included and referenced schema documents with the given
+    /// resolver; without a resolver, they cannot be loaded.
+    pub fn new(resolver: Option<&'a EntityResolver>) -> Loader<'a> {
+        Loader {
+            resolver: resolver,
+            defines: Vec::new(),
+            scopes: Vec::new(),
+            datatypes: Datatypes::new(),
+            documents: Vec::new()
+        }
+    }
+
+    /// Loads a schema document in the XML syntax and all documents it refers to.
+    pub fn load<R: Read>(self, source: R) -> Result<Grammar> {
+        let root = try!(parse_document(source));
+        self.load_root(root)
+    }
+
+    /// Loads a schema document in the compact syntax and all documents it refers to.
+    pub fn load_compact<R: Read>(self, source: R) -> Result<Grammar> {
+        let root = try!(compact::parse(source));
+        self.load_root(root)
+    }
+
+    fn load_root(mut self, root: Node) -> Result<Grammar> {
+        let start = try!(self.pattern(&root, &Context { ns: String::new(), library: String::new() }));
+        try!(self.check_cycles());
+        Ok(Grammar {
+            start: start,
+            defines: self.defines.into_iter().map(|d| d.body.unwrap()).collect(),
+            datatypes: self.datatypes
+        })
+    }
+
+    /// Parses an included or referenced document; documents with the `.rnc` extension
+    /// are parsed as the compact syntax.
+    fn load_document(&mut self, node: &Node, href: &str) -> Result<Node> {
+        if self.documents.iter().any(|&(ref d, _)| d == href) {
+            return Err(node.error(format!("Recursive inclusion of schema document {}", href)));
+        }
+        let source = match self.resolver.map(|r| r.resolve(None, href)) {
+            Some(Ok(Some(source))) => source,
+            Some(Err(e)) => return Err(node.error(format!("Cannot read schema document {}: {}", href, e))),
+            _ => return Err(node.error(format!("Cannot resolve schema document: {}", href)))
+        };
+        let root = if href.ends_with(".rnc") { compact::parse(source) } else { parse_document(source) };
+        root.map_err(|e| node.error(format!("Error in schema document {}: {}", href, e)))
+    }
+
+    fn location(&self, node: &Node) -> Location {
+        Location { pos: node.pos, documents: self.documents.clone() }
+    }
+
+    /// Creates an error at the given location, which is reported at the position of the
+    /// outermost element referring to a document which is not being loaded.
+    fn error_at(&self, location: &Location, msg: String) -> reader::Error {
+        let mut error: reader::Error = (&location.pos, msg).into();
+        for &(ref document, pos) in location.documents[self.documents.len()..].iter().rev() {
+            error = (&pos, format!("Error in schema document {}: {}", document, error)).into();
+        }
+        error
+    }
+
+    fn pattern(&mut self, node: &Node, cx: &Context) -> Result<Rc<Pattern>> {
+        let cx = cx.enter(node);
+        Ok(match &node.local[..] {
+            "element" => {
+                let (nc, content) = try!(self.named(node, &cx, true));
+                Rc::new(Pattern::Element(nc, try!(self.group(node, content, &cx))))
+            }
+            "attribute" => {
+                let (nc, content) = try!(self.named(node, &cx, false));
+                let value = if content.is_empty() { Rc::new(Pattern::Text) } else { try!(self.group(node, content, &cx)) };
+                Rc::new(Pattern::Attribute(nc, value))
+            }
+            "group" => try!(self.group(node, &node.children, &cx)),
+            "interleave" | "choice" => {
+                let mut result = None;
+                for child in &node.children {
+                    let p = try!(self.pattern(child, &cx));
+                    result = Some(match result {
+                        None => p,
+                        Some(r) => if node.local == "choice" { choice(r, p) } else { interleave(r, p) }
+                    });
+                }
+                try!(result.ok_or_else(|| node.error(format!("{} must contain a pattern", node.local))))
+            }
+            "optional" => choice(try!(self.group(node, &node.children, &cx)), empty()),
+            "zeroOrMore" => choice(one_or_more(try!(self.group(node, &node.children, &cx))), empty()),
+            "oneOrMore" => one_or_more(try!(self.group(node, &node.children, &cx))),
+            "list" => Rc::new(Pattern::List(try!(self.group(node, &node.children, &cx)))),
+            "mixed" => interleave(try!(self.group(node, &node.children, &cx)), Rc::new(Pattern::Text)),
+            "ref" => Rc::new(Pattern::Ref(try!(self.reference(node, 0)))),
+            "parentRef" => Rc::new(Pattern::Ref(try!(self.reference(node, 1)))),
+            "empty" => empty(),
+            "text" => Rc::new(Pattern::Text),
+            "notAllowed" => not_allowed(),
+            "value" => {
+                let (library, type_name) = match node.attr("type") {
+                    Some(t) => (&cx.library[..], t.trim()),
+                    None => ("", "token")
+                };
+                let datatype = try!(self.datatype(node, library, type_name, &[]));
+                let mut namespace = node.namespace.clone();
+                namespace.0.insert(String::new(), cx.ns.clone());
+                if let Datatype::Xsd(ref t) = datatype {
+                    if let Err(e) = self.datatypes.validate(t, &node.text, &namespace) {
+                        return Err(node.error(format!("Invalid value: {}", e)));
+                    }
+                }
+                Rc::new(Pattern::Value(Rc::new(datatype), node.text.clone(), Rc::new(namespace)))
+            }
+            "data" => {
+                let mut params = Vec::new();
+                let mut except = None;
+                for child in &node.children {
+                    match &child.local[..] {
+                        "param" if except.is_none() =>
+                            params.push((try!(child.required_attr("name")).to_owned(), child.text.clone())),
+                        "except" if except.is_none() => {
+                            let mut result = None;
+                            for p in &child.children {
+                                let p = try!(self.pattern(p, &cx.enter(child)));
+                                result = Some(match result { None => p, Some(r) => choice(r, p) });
+                            }
+                            except = Some(try!(result.ok_or_else(|| child.error("except must contain a pattern"))));
+                        }
+                        _ => return Err(child.error(format!("Unexpected element in data: {}", child.local)))
+                    }
+                }
+                let datatype = try!(self.datatype(node, &cx.library, try!(node.required_attr("type")), &params));
+                Rc::new(Pattern::Data(Rc::new(datatype), except))
+            }
+            "externalRef" => {
+                let href = try!(node.required_attr("href"));
+                let root = try!(self.load_document(node, href));
+                self.documents.push((href.to_owned(), node.pos));
+                let result = self.pattern(&root, &Context { ns: cx.ns.clone(), library: String::new() });
+                self.documents.pop();
+                try!(result.map_err(|e| node.error(format!("Error in schema document {}: {}", href, e))))
+            }
+            "grammar" => try!(self.grammar(node, &cx)),
+            _ => return Err(node.error(format!("Unexpected element in pattern: {}", node.local)))
+        })
+    }
+
+    /// Groups patterns in the content of the node.
+    fn group(&mut self, node: &Node, children: &[Node], cx: &Context) -> Result<Rc<Pattern>> {
+        let mut result = None;
+        for child in children {
+            let p = try!(self.pattern(child, cx));
+            result = Some(match result { None => p, Some(r) => group(r, p) });
+        }
+        result.ok_or_else(|| node.error(format!("{} must contain a pattern", node.local)))
+    }
+
+    /// Returns the name class of an element or an attribute pattern, and the nodes of
+    /// its content.
+    fn named<'n>(&mut self, node: &'n Node, cx: &Context, element: bool) -> Result<(Rc<NameClass>, &'n [Node])> {
+        match node.attr("name") {
+            Some(name) => {
+                // Unprefixed names of attributes are in the namespace given on the pattern itself
+                let ns = if element { &cx.ns[..] } else { node.attr("ns").unwrap_or("") };
+                Ok((Rc::new(NameClass::Name(try!(qname(node, name, ns)))), &node.children[..]))
+            }
+            None => match node.children.split_first() {
+                Some((first, rest)) => Ok((try!(self.name_class(first, cx)), rest)),
+                None => Err(node.error(format!("{} must have a name", node.local)))
+            }
+        }
+    }
+
+    fn name_class(&mut self, node: &Node, cx: &Context) -> Result<Rc<NameClass>> {
+        let cx = cx.enter(node);
+        Ok(Rc::new(match &node.local[..] {
+            "name" => NameClass::Name(try!(qname(node, &node.text, &cx.ns))),
+            "anyName" => NameClass::AnyName(try!(self.except_names(node, &cx))),
+            "nsName" => NameClass::NsName(cx.ns.clone(), try!(self.except_names(node, &cx))),
+            "choice" => return self.choice_names(node, &cx),
+            _ => return Err(node.error(format!("Unexpected element in name class: {}", node.local)))
+        }))
+    }
+
+    fn choice_names(&mut self, node: &Node, cx: &Context) -> Result<Rc<NameClass>> {
+        let mut result = None;
+        for child in &node.children {
+            let nc = try!(self.name_class(child, cx));
+            result = Some(match result { None => nc, Some(r) => Rc::new(NameClass::Choice(r, nc)) });
+        }
+        result.ok_or_else(|| node.error(format!("{} must contain a name class", node.local)))
+    }
+
+    fn except_names(&mut self, node: &Node, cx: &Context) -> Result<Option<Rc<NameClass>>> {
+        match node.children.first() {
+            Some(except) if except.local == "except" && node.children.len() == 1 =>
+                self.choice_names(except, &cx.enter(except)).map(Some),
+            Some(child) => Err(child.error(format!("Unexpected element in {}: {}", node.local, child.local))),
+            None => Ok(None)
+        }
+    }
+
+    fn datatype(&mut self, node: &Node, library: &str, name: &str, params: &[(String, String)]) -> Result<Datatype> {
+        match library {
+            "" => {
+                if !params.is_empty() {
+                    return Err(node.error(format!("Datatype {} has no parameters", name)));
+                }
+                match name {
+                    "string" => Ok(Datatype::String),
+                    "token" => Ok(Datatype::Token),
+                    _ => Err(node.error(format!("Unknown datatype: {}", name)))
+                }
+            }
+            XSD_DATATYPES => self.datatypes.datatype(name, params).map(Datatype::Xsd).map_err(|e| node.error(e)),
+            _ => Err(node.error(format!("Unsupported datatype library: {}", library)))
+        }
+    }
+
+    fn grammar(&mut self, node: &Node, cx: &Context) -> Result<Rc<Pattern>> {
+        self.scopes.push(Scope { defines: HashMap::new(), references: Vec::new() });
+        let start = self.index(self.scopes.len() - 1, "");
+        let result = self.grammar_content(&node.children, cx, &[], &mut Vec::new());
+        let scope = self.scopes.pop().unwrap();
+        try!(result);
+        if self.defines[start].body.is_none() {
+            return Err(node.error("Grammar has no start pattern"));
+        }
+        for &(index, ref location) in &scope.references {
+            if self.defines[index].body.is_none() {
+                return Err(self.error_at(location, format!("Undefined reference to {}", self.defines[index].name)));
+            }
+        }
+        Ok(Rc::new(Pattern::Ref(start)))
+    }
+
+    /// Loads starts and defines of a grammar; those named in `overridden` are skipped,
+    /// and their names are added to `found`.
+    fn grammar_content(&mut self, children: &[Node], cx: &Context, overridden: &[String],
+                       found: &mut Vec<String>) -> Result<()> {
+        for child in children {
+            let cx = cx.enter(child);
+            match &child.local[..] {
+                "start" | "define" => {
+                    let name = if child.local == "start" { "" } else { try!(child.required_attr("name")) };
+                    if overridden.iter().any(|o| o == name) {
+                        found.push(name.to_owned());
+                        continue;
+                    }
+                    let body = try!(self.group(child, &child.children, &cx));
+                    try!(self.define(child, name, body));
+                }
+                "div" => try!(self.grammar_content(&child.children, &cx, overridden, found)),
+                "include" => try!(self.include(child, &cx, overridden, found)),
+                _ => return Err(child.error(format!("Unexpected element in grammar: {}", child.local)))
+            }
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, node: &Node, cx: &Context, overridden: &[String], found: &mut Vec<String>) -> Result<()> {
+        let href = try!(node.required_attr("href"));
+        let mut overrides = Vec::new();
+        collect_overrides(&node.children, &mut overrides);
+        let root = try!(self.load_document(node, href));
+        let nested_error = |e: reader::Error| node.error(format!("Error in schema document {}: {}", href, e));
+        if root.local != "grammar" {
+            return Err(nested_error(root.error("Root element of an included schema document must be grammar")));
+        }
+        let skipped: Vec<String> = overrides.iter().chain(overridden).cloned().collect();
+        let mut included = Vec::new();
+        self.documents.push((href.to_owned(), node.pos));
+        let root_cx = Context { ns: cx.ns.clone(), library: String::new() }.enter(&root);
+        let result = self.grammar_content(&root.children, &root_cx, &skipped, &mut included);
+        self.documents.pop();
+        try!(result.map_err(nested_error));
+        for name in &overrides {
+            if !included.contains(name) {
+                let what = if name.is_empty() { "start".into() } else { format!("define {}", name) };
+                return Err(node.error(format!("Included schema document {} has no {} to override", href, what)));
+            }
+        }
+        found.extend(included.into_iter().filter(|name| overridden.contains(name)));
+        self.grammar_content(&node.children, cx, overridden, found)
+    }
+
+    /// Returns the index of the define with the given name in the given grammar.
+    fn index(&mut self, scope: usize, name: &str) -> usize {
+        if let Some(&index) = self.scopes[scope].defines.get(name) {
+            return index;
+        }
+        let index = self.defines.len();
+        self.defines.push(Define { name: name.to_owned(), body: None, combine: None, plain: false, location: None });
+        self.scopes[scope].defines.insert(name.to_owned(), index);
+        index
+    }
+
+    /// Resolves a reference to a define of the current grammar or, with `depth` 1,
+    /// of its parent grammar.
+    fn reference(&mut self, node: &Node, depth: usize) -> Result<usize> {
+        let name = try!(node.required_attr("name"));
+        if self.scopes.len() <= depth {
+            return Err(node.error(format!("{} is only allowed in a {}grammar", node.local, if depth > 0 { "nested " } else { "" })));
+        }
+        let scope = self.scopes.len() - 1 - depth;
+        let index = self.index(scope, name);
+        let location = self.location(node);
+        self.scopes[scope].references.push((index, location));
+        Ok(index)
+    }
+
+    fn define(&mut self, node: &Node, name: &str, body: Rc<Pattern>) -> Result<()> {
+        let scope = self.scopes.len() - 1;
+        let index = self.index(scope, name);
+        let location = self.location(node);
+        let define = &mut self.defines[index];
+        match node.attr("combine").map(str::trim) {
+            None if define.plain => return Err(node.error(format!("Duplicate definition of {}", define.description()))),
+            None => define.plain = true,
+            Some(method) if method != "choice" && method != "interleave" =>
+                return Err(node.error(format!("Invalid combine method: {}", method))),
+            Some(method) => {
+                if define.combine.as_ref().map(|c| c != method).unwrap_or(false) {
+                    return Err(node.error(format!("Conflicting combine methods of {}", define.description())));
+                }
+                define.combine = Some(method.to_owned());
+            }
+        }
+        if define.location.is_none() {
+            define.location = Some(location);
+        }
+        define.body = Some(match define.body.take() {
+            None => body,
+            Some(b) => match define.combine.as_ref().map(|c| &c[..]) {
+                Some("interleave") => interleave(b, body),
+                Some(_) => choice(b, body),
+                None => return Err(node.error(format!("Duplicate definition of {}", define.description())))
+            }
+        });
+        Ok(())
+    }
+
+    /// Checks that no define refers to itself without an element in between.
+    fn check_cycles(&self) -> Result<()> {
+        let mut edges = Vec::new();
+        for define in &self.defines {
+            let mut refs = Vec::new();
+            references(define.body.as_ref().unwrap(), &mut refs);
+            edges.push(refs);
+        }
+        // 0: not visited, 1: on the current path, 2: checked
+        let mut state = vec![0u8; self.defines.len()];
+        for start in 0..self.defines.len() {
+            if let Some(index) = find_cycle(start, &edges, &mut state) {
+                let define = &self.defines[index];
+                let msg = format!("Circular reference to {}", define.description());
+                return Err(match define.location {
+                    Some(ref location) => self.error_at(location, msg),
+                    None => (&TextPosition::new(), msg).into()
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resolves a qualified name of an element or an attribute; unprefixed names are in the
+/// namespace `ns`.
+fn qname(node: &Node, value: &str, ns: &str) -> Result<Name> {
+    let value = value.trim();
+    match value.find(':') {
+        Some(i) => match node.namespace.get(&value[..i]) {
+            Some(uri) => Ok(Name::new(uri, &value[i+1..])),
+            None => Err(node.error(format!("Undeclared namespace prefix: {}", &value[..i])))
+        },
+        None => Ok(Name::new(ns, value))
+    }
+}
+
+/// Collects names of starts and defines in the content of an include, which override
+/// those of the included grammar.
+fn collect_overrides(children: &[Node], names: &mut Vec<String>) {
+    for child in children {
+        match &child.local[..] {
+            "start" => names.push(String::new()),
+            "define" => names.extend(child.attr("name").map(|n| n.trim().to_owned())),
+            "div" => collect_overrides(&child.children, names),
+            _ => {}
+        }
+    }
+}
+
+/// Collects references which are not in the content of an element.
+fn references(p: &Pattern, refs: &mut Vec<usize>) {
+    match *p {
+        Pattern::Ref(i) => refs.push(i),
+        Pattern::Choice(ref a, ref b) | Pattern::Interleave(ref a, ref b) |
+        Pattern::Group(ref a, ref b) | Pattern::After(ref a, ref b) => {
+            references(a, refs);
+            references(b, refs);
+        }
+        Pattern::OneOrMore(ref p) | Pattern::List(ref p) | Pattern::Attribute(_, ref p) => references(p, refs),
+        Pattern::Data(_, Some(ref p)) => references(p, refs),
+        _ => {}
+    }
+}
+
+fn find_cycle(index: usize, edges: &[Vec<usize>], state: &mut [u8]) -> Option<usize> {
+    match state[index] {
+        1 => return Some(index),
+        2 => return None,
+        _ => {}
+    }
+    state[index] = 1;
+    for &next in &edges[index] {
+        if let Some(i) = find_cycle(next, edges, state) {
+            return Some(i);
+        }
+    }
+    state[index] = 2;
+    None
+}
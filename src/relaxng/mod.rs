@@ -0,0 +1,149 @@
+//! Contains RELAX NG validation of documents read with `EventReader`.
+//!
+//! A `Schema` is loaded from a RELAX NG schema in the XML syntax or in the compact syntax;
+//! the documents it includes or refers to with `externalRef` are loaded through an
+//! `EntityResolver`, which receives their `href` exactly as it is written. Documents whose
+//! `href` ends with `.rnc` are parsed as the compact syntax.
+//!
+//! A loaded schema checks a stream of events produced by `EventReader`. Events are matched
+//! with pattern derivatives, so validation only keeps the state of open elements and works
+//! on documents of any length:
+//!
+//! ```rust
+//! use xml::EventReader;
+//! use xml::relaxng::Schema;
+//!
+//! let schema = Schema::from_compact_reader(r#"
+//!     element order {
+//!       attribute id { xsd:ID },
+//!       element item { xsd:positiveInteger }+
+//!     }
+//! "#.as_bytes()).unwrap();
+//!
+//! assert!(schema.validate(EventReader::from_str(r#"<order id="a1"><item>1</item></order>"#)).is_ok());
+//!
+//! let e = schema.validate(EventReader::from_str(r#"<order id="a1"/>"#)).unwrap_err();
+//! assert_eq!(e.msg(), "Content of element order is incomplete; expected item");
+//! ```
+//!
+//! Errors in the schema itself are reported as syntax errors at the position of the offending
+//! schema element, and violations of the schema in the document are reported with
+//! `ErrorKind::Validity` at the position of the event which violates it. Validation stops
+//! at the first violation.
+//!
+//! The built-in datatype library and the XML Schema datatype library are supported; the
+//! restrictions of section 7 of the RELAX NG specification are not checked, except that
+//! definitions must not refer to themselves outside of an element, and the ID/IDREF
+//! compatibility checks are not performed.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use common::Position;
+use reader::{self, EventReader, XmlEvent, EntityResolver, DirectoryResolver};
+
+mod pattern;
+mod loader;
+mod compact;
+mod validator;
+
+/// A loaded RELAX NG schema.
+pub struct Schema {
+    grammar: pattern::Grammar
+}
+
+impl Schema {
+    /// Loads a schema from a single document in the XML syntax; the document cannot
+    /// include or refer to other documents.
+    pub fn from_reader<R: Read>(source: R) -> reader::Result<Schema> {
+        loader::Loader::new(None).load(source).map(|g| Schema { grammar: g })
+    }
+
+    /// Loads a schema from a document in the XML syntax, loading the documents it includes
+    /// or refers to with the given resolver.
+    pub fn from_reader_with_resolver<R: Read, E: EntityResolver>(source: R, resolver: &E) -> reader::Result<Schema> {
+        loader::Loader::new(Some(resolver)).load(source).map(|g| Schema { grammar: g })
+    }
+
+    /// Loads a schema from a single document in the compact syntax; the document cannot
+    /// include or refer to other documents.
+    pub fn from_compact_reader<R: Read>(source: R) -> reader::Result<Schema> {
+        loader::Loader::new(None).load_compact(source).map(|g| Schema { grammar: g })
+    }
+
+    /// Loads a schema from a document in the compact syntax, loading the documents it
+    /// includes or refers to with the given resolver.
+    pub fn from_compact_reader_with_resolver<R: Read, E: EntityResolver>(source: R, resolver: &E) -> reader::Result<Schema> {
+        loader::Loader::new(Some(resolver)).load_compact(source).map(|g| Schema { grammar: g })
+    }
+
+    /// Loads a schema from a file, which is parsed as the compact syntax if its extension
+    /// is `.rnc`; included and referenced documents are loaded from the directory containing
+    /// the file, see `DirectoryResolver`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> reader::Result<Schema> {
+        let path = path.as_ref();
+        let file = try!(File::open(path));
+        let resolver = DirectoryResolver::new(path.parent().unwrap_or_else(|| Path::new(".")));
+        if path.extension().map(|e| e == "rnc").unwrap_or(false) {
+            Schema::from_compact_reader_with_resolver(file, &resolver)
+        } else {
+            Schema::from_reader_with_resolver(file, &resolver)
+        }
+    }
+
+    /// Returns a validator which checks events of a single document.
+    ///
+    /// This is useful when events are processed as they are read; otherwise `validate()`
+    /// is simpler.
+    pub fn validator<'a>(&'a self) -> Validator<'a> {
+        Validator(validator::Validator::new(&self.grammar))
+    }
+
+    /// Reads the whole document and checks it against the schema.
+    ///
+    /// Returns the first well-formedness or validity error in the document.
+    pub fn validate<R: Read>(&self, mut reader: EventReader<R>) -> reader::Result<()> {
+        let mut validator = self.validator();
+        loop {
+            let event = try!(reader.next());
+            try!(validator.validate(&event, &reader));
+            if let XmlEvent::EndDocument = event {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Checks events of a document against a RELAX NG `Schema`.
+///
+/// ```rust
+/// use xml::EventReader;
+/// use xml::reader::XmlEvent;
+/// use xml::relaxng::Schema;
+///
+/// let schema = Schema::from_reader(r#"
+///     <element name="flag" xmlns="http://relaxng.org/ns/structure/1.0">
+///       <choice><value>on</value><value>off</value></choice>
+///     </element>
+/// "#.as_bytes()).unwrap();
+///
+/// let mut reader = EventReader::from_str("<flag>on</flag>");
+/// let mut validator = schema.validator();
+/// loop {
+///     let event = reader.next().unwrap();
+///     validator.validate(&event, &reader).unwrap();
+///     if event == XmlEvent::EndDocument {
+///         break;
+///     }
+/// }
+/// ```
+pub struct Validator<'a>(validator::Validator<'a>);
+
+impl<'a> Validator<'a> {
+    /// Checks the next event of the document; `pos` is used as the position of errors,
+    /// and it is usually the reader which produced the event.
+    pub fn validate<P: Position>(&mut self, event: &XmlEvent, pos: &P) -> reader::Result<()> {
+        self.0.validate(event, pos)
+    }
+}
@@ -0,0 +1,747 @@
+//! Contains patterns of a loaded RELAX NG grammar and their derivatives.
+//!
+//! Documents are matched with derivatives as described by James Clark in "An algorithm
+//! for RELAX NG validation": the pattern describing what is still allowed in the document
+//! is replaced with its derivative with respect to each start tag, attribute, text and end
+//! tag. Open elements are represented by `After` patterns, so the memory needed does not
+//! depend on the length of the document. As in the algorithm, the patterns of derivatives
+//! are interned and derivatives are memoized by `Derivatives`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use common::{is_whitespace_char, is_whitespace_str};
+use namespace::Namespace;
+use schema::{self, Datatypes};
+
+/// An expanded name of an element or an attribute; a name without a namespace has an
+/// empty namespace name.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Name {
+    pub ns: String,
+    pub local: String
+}
+
+impl Name {
+    pub fn new<N: Into<String>, L: Into<String>>(ns: N, local: L) -> Name {
+        Name { ns: ns.into(), local: local.into() }
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ns.is_empty() {
+            write!(f, "{}", self.local)
+        } else {
+            write!(f, "{{{}}}{}", self.ns, self.local)
+        }
+    }
+}
+
+/// A set of names allowed for an element or an attribute.
+#[derive(Debug)]
+pub enum NameClass {
+    Name(Name),
+    /// Any name except names in the given class.
+    AnyName(Option<Rc<NameClass>>),
+    /// Any name in the namespace except names in the given class.
+    NsName(String, Option<Rc<NameClass>>),
+    Choice(Rc<NameClass>, Rc<NameClass>)
+}
+
+impl NameClass {
+    pub fn contains(&self, name: &Name) -> bool {
+        match *self {
+            NameClass::Name(ref n) => n == name,
+            NameClass::AnyName(ref except) => except.as_ref().map(|e| !e.contains(name)).unwrap_or(true),
+            NameClass::NsName(ref ns, ref except) =>
+                *ns == name.ns && except.as_ref().map(|e| !e.contains(name)).unwrap_or(true),
+            NameClass::Choice(ref a, ref b) => a.contains(name) || b.contains(name)
+        }
+    }
+
+    fn describe(&self, names: &mut Vec<String>) {
+        match *self {
+            NameClass::Name(ref n) => names.push(n.to_string()),
+            NameClass::AnyName(_) => names.push("any name".into()),
+            NameClass::NsName(ref ns, _) if ns.is_empty() => names.push("any name without a namespace".into()),
+            NameClass::NsName(ref ns, _) => names.push(format!("any name in namespace {}", ns)),
+            NameClass::Choice(ref a, ref b) => {
+                a.describe(names);
+                b.describe(names);
+            }
+        }
+    }
+}
+
+/// A datatype of `data` and `value` patterns.
+#[derive(Debug)]
+pub enum Datatype {
+    /// `string` of the built-in datatype library.
+    String,
+    /// `token` of the built-in datatype library.
+    Token,
+    /// A type of the XML Schema datatype library.
+    Xsd(schema::Datatype)
+}
+
+#[derive(Debug)]
+pub enum Pattern {
+    Empty,
+    NotAllowed,
+    Text,
+    Choice(Rc<Pattern>, Rc<Pattern>),
+    Interleave(Rc<Pattern>, Rc<Pattern>),
+    Group(Rc<Pattern>, Rc<Pattern>),
+    OneOrMore(Rc<Pattern>),
+    List(Rc<Pattern>),
+    /// A value of the datatype which does not match the optional except pattern.
+    Data(Rc<Datatype>, Option<Rc<Pattern>>),
+    /// A value equal to the given one, which is resolved in the given namespace context.
+    Value(Rc<Datatype>, String, Rc<Namespace>),
+    Attribute(Rc<NameClass>, Rc<Pattern>),
+    Element(Rc<NameClass>, Rc<Pattern>),
+    /// The content of an open element followed by what is allowed after the element.
+    After(Rc<Pattern>, Rc<Pattern>),
+    /// A reference to a define of the grammar.
+    Ref(usize)
+}
+
+fn same(a: &Rc<Pattern>, b: &Rc<Pattern>) -> bool {
+    if Rc::ptr_eq(a, b) {
+        return true;
+    }
+    match (&**a, &**b) {
+        (&Pattern::Empty, &Pattern::Empty) | (&Pattern::NotAllowed, &Pattern::NotAllowed) |
+        (&Pattern::Text, &Pattern::Text) => true,
+        (&Pattern::Choice(ref a1, ref a2), &Pattern::Choice(ref b1, ref b2)) |
+        (&Pattern::Interleave(ref a1, ref a2), &Pattern::Interleave(ref b1, ref b2)) |
+        (&Pattern::Group(ref a1, ref a2), &Pattern::Group(ref b1, ref b2)) |
+        (&Pattern::After(ref a1, ref a2), &Pattern::After(ref b1, ref b2)) =>
+            same(a1, b1) && same(a2, b2),
+        (&Pattern::OneOrMore(ref a), &Pattern::OneOrMore(ref b)) => same(a, b),
+        (&Pattern::Ref(a), &Pattern::Ref(b)) => a == b,
+        _ => false
+    }
+}
+
+pub fn empty() -> Rc<Pattern> {
+    Rc::new(Pattern::Empty)
+}
+
+pub fn not_allowed() -> Rc<Pattern> {
+    Rc::new(Pattern::NotAllowed)
+}
+
+pub fn choice(a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+    match (&*a, &*b) {
+        (&Pattern::NotAllowed, _) => return b.clone(),
+        (_, &Pattern::NotAllowed) => return a.clone(),
+        _ if same(&a, &b) => return a.clone(),
+        _ => {}
+    }
+    Rc::new(Pattern::Choice(a, b))
+}
+
+pub fn group(a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+    match (&*a, &*b) {
+        (&Pattern::NotAllowed, _) | (_, &Pattern::Empty) => return a.clone(),
+        (_, &Pattern::NotAllowed) | (&Pattern::Empty, _) => return b.clone(),
+        _ => {}
+    }
+    Rc::new(Pattern::Group(a, b))
+}
+
+pub fn interleave(a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+    match (&*a, &*b) {
+        (&Pattern::NotAllowed, _) | (_, &Pattern::Empty) => return a.clone(),
+        (_, &Pattern::NotAllowed) | (&Pattern::Empty, _) => return b.clone(),
+        _ => {}
+    }
+    Rc::new(Pattern::Interleave(a, b))
+}
+
+pub fn one_or_more(p: Rc<Pattern>) -> Rc<Pattern> {
+    match *p {
+        Pattern::NotAllowed | Pattern::Empty => p.clone(),
+        _ => Rc::new(Pattern::OneOrMore(p))
+    }
+}
+
+/// A loaded grammar.
+pub struct Grammar {
+    pub start: Rc<Pattern>,
+    /// Bodies of all defines, including defines of nested grammars.
+    pub defines: Vec<Rc<Pattern>>,
+    pub datatypes: Datatypes
+}
+
+impl Grammar {
+    pub fn nullable(&self, p: &Pattern) -> bool {
+        match *p {
+            Pattern::Empty | Pattern::Text => true,
+            Pattern::Choice(ref a, ref b) => self.nullable(a) || self.nullable(b),
+            Pattern::Interleave(ref a, ref b) | Pattern::Group(ref a, ref b) => self.nullable(a) && self.nullable(b),
+            Pattern::OneOrMore(ref p) => self.nullable(p),
+            Pattern::Ref(i) => self.nullable(&self.defines[i]),
+            _ => false
+        }
+    }
+
+    /// Checks that a value is valid for the datatype, returning a description of the
+    /// problem otherwise.
+    pub fn allows(&self, datatype: &Datatype, value: &str, namespace: &Namespace) -> Result<(), String> {
+        match *datatype {
+            Datatype::String | Datatype::Token => Ok(()),
+            Datatype::Xsd(ref t) => self.datatypes.validate(t, value, namespace)
+        }
+    }
+
+    fn equal(&self, datatype: &Datatype, a: &str, a_namespace: &Namespace, b: &str, b_namespace: &Namespace) -> bool {
+        match *datatype {
+            Datatype::String => a == b,
+            Datatype::Token => tokens(a).eq(tokens(b)),
+            Datatype::Xsd(ref t) =>
+                self.datatypes.validate(t, a, a_namespace).is_ok() &&
+                    self.datatypes.same_value(t, a, a_namespace, b, b_namespace)
+        }
+    }
+
+
+    /// Describes the elements allowed next in the content of the innermost open element.
+    pub fn expected_elements(&self, p: &Pattern) -> String {
+        let mut names = Vec::new();
+        self.expected(p, &mut names);
+        names.dedup();
+        if names.is_empty() { "no more elements".into() } else { names.join(", ") }
+    }
+
+    fn expected(&self, p: &Pattern, names: &mut Vec<String>) {
+        match *p {
+            Pattern::Element(ref nc, _) => nc.describe(names),
+            Pattern::After(ref a, _) | Pattern::OneOrMore(ref a) => self.expected(a, names),
+            Pattern::Choice(ref a, ref b) | Pattern::Interleave(ref a, ref b) => {
+                self.expected(a, names);
+                self.expected(b, names);
+            }
+            Pattern::Group(ref a, ref b) => {
+                self.expected(a, names);
+                if self.nullable(a) {
+                    self.expected(b, names);
+                }
+            }
+            Pattern::Ref(i) => self.expected(&self.defines[i], names),
+            _ => {}
+        }
+    }
+
+    /// Describes the attributes which are still required in a start tag.
+    pub fn required_attributes(&self, p: &Pattern) -> Vec<String> {
+        match *p {
+            Pattern::Attribute(ref nc, _) => {
+                let mut names = Vec::new();
+                nc.describe(&mut names);
+                vec![names.join(" or ")]
+            }
+            Pattern::After(ref a, _) | Pattern::OneOrMore(ref a) => self.required_attributes(a),
+            Pattern::Group(ref a, ref b) | Pattern::Interleave(ref a, ref b) => {
+                let mut names = self.required_attributes(a);
+                names.extend(self.required_attributes(b));
+                names
+            }
+            Pattern::Choice(ref a, ref b) => {
+                let (a, b) = (self.required_attributes(a), self.required_attributes(b));
+                if a.is_empty() || b.is_empty() { Vec::new() } else { vec![format!("{} or {}", a.join(", "), b.join(", "))] }
+            }
+            Pattern::Ref(i) => self.required_attributes(&self.defines[i]),
+            _ => Vec::new()
+        }
+    }
+
+    /// Checks whether text is allowed next in the content of the innermost open element
+    /// or in an attribute value pattern.
+    pub fn allows_text(&self, p: &Pattern) -> bool {
+        match *p {
+            Pattern::Text | Pattern::Data(..) | Pattern::Value(..) | Pattern::List(_) => true,
+            Pattern::After(ref a, _) | Pattern::OneOrMore(ref a) => self.allows_text(a),
+            Pattern::Choice(ref a, ref b) | Pattern::Interleave(ref a, ref b) => self.allows_text(a) || self.allows_text(b),
+            Pattern::Group(ref a, ref b) => self.allows_text(a) || (self.nullable(a) && self.allows_text(b)),
+            Pattern::Ref(i) => self.allows_text(&self.defines[i]),
+            _ => false
+        }
+    }
+
+    fn find_attribute(&self, p: &Pattern, name: &Name) -> Option<Rc<Pattern>> {
+        match *p {
+            Pattern::Attribute(ref nc, ref q) if nc.contains(name) => Some(q.clone()),
+            Pattern::After(ref a, _) | Pattern::OneOrMore(ref a) => self.find_attribute(a, name),
+            Pattern::Choice(ref a, ref b) | Pattern::Interleave(ref a, ref b) | Pattern::Group(ref a, ref b) =>
+                self.find_attribute(a, name).or_else(|| self.find_attribute(b, name)),
+            Pattern::Ref(i) => self.find_attribute(&self.defines[i], name),
+            _ => None
+        }
+    }
+}
+
+/// The most patterns `Derivatives` keeps before it forgets them, so that the memory needed
+/// does not grow with the length of the document.
+const MAX_PATTERNS: usize = 100000;
+
+/// A pattern built by `Derivatives` from the patterns with the given ids.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Choice(usize, usize),
+    Interleave(usize, usize),
+    Group(usize, usize),
+    OneOrMore(usize),
+    After(usize, usize)
+}
+
+#[derive(Default)]
+struct Tables {
+    /// Ids of the patterns seen so far by their addresses; the patterns are kept, so that
+    /// their addresses are not reused.
+    ids: HashMap<*const Pattern, (usize, Rc<Pattern>)>,
+    interned: HashMap<Key, Rc<Pattern>>,
+    nullable: HashMap<usize, bool>,
+    start_tag_open: HashMap<(usize, Name), Rc<Pattern>>,
+    start_tag_close: HashMap<usize, Rc<Pattern>>,
+    end_tag: HashMap<usize, Rc<Pattern>>
+}
+
+/// Computes derivatives of the patterns of a grammar.
+///
+/// Patterns built for the derivatives are interned, so that equal patterns share an id;
+/// choices are flattened and their alternatives are sorted by id without duplicates.
+/// Derivatives with respect to tags are memoized, and derivatives with respect to text
+/// are memoized while the same text is matched, so ambiguous patterns do not make the
+/// derivatives grow.
+pub struct Derivatives<'a> {
+    pub grammar: &'a Grammar,
+    empty: Rc<Pattern>,
+    not_allowed: Rc<Pattern>,
+    tables: RefCell<Tables>
+}
+
+impl<'a> Derivatives<'a> {
+    pub fn new(grammar: &'a Grammar) -> Derivatives<'a> {
+        Derivatives {
+            grammar: grammar,
+            empty: empty(),
+            not_allowed: not_allowed(),
+            tables: RefCell::new(Tables::default())
+        }
+    }
+
+    fn id(&self, p: &Rc<Pattern>) -> usize {
+        let mut tables = self.tables.borrow_mut();
+        let next = tables.ids.len();
+        tables.ids.entry(&**p as *const Pattern).or_insert_with(|| (next, p.clone())).0
+    }
+
+    fn intern<F: FnOnce() -> Pattern>(&self, key: Key, make: F) -> Rc<Pattern> {
+        if let Some(p) = self.tables.borrow().interned.get(&key) {
+            return p.clone();
+        }
+        let p = Rc::new(make());
+        self.id(&p);
+        self.tables.borrow_mut().interned.insert(key, p.clone());
+        p
+    }
+
+    /// Forgets the patterns and the derivatives when there are too many of them; this only
+    /// makes the following derivatives slower.
+    fn limit(&self) {
+        let mut tables = self.tables.borrow_mut();
+        if tables.ids.len() > MAX_PATTERNS {
+            *tables = Tables::default();
+        }
+    }
+
+    pub fn choice(&self, a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+        let mut alternatives = Vec::new();
+        self.alternatives(a, &mut alternatives);
+        self.alternatives(b, &mut alternatives);
+        alternatives.sort_by_key(|&(id, _)| id);
+        alternatives.dedup_by_key(|&mut (id, _)| id);
+        let mut alternatives = alternatives.into_iter().rev();
+        let last = match alternatives.next() {
+            Some((_, p)) => p,
+            None => return self.not_allowed.clone()
+        };
+        alternatives.fold(last, |rest, (id, p)| {
+            let key = Key::Choice(id, self.id(&rest));
+            self.intern(key, || Pattern::Choice(p, rest))
+        })
+    }
+
+    /// Collects the alternatives of a choice with their ids, leaving out `NotAllowed`.
+    fn alternatives(&self, p: Rc<Pattern>, alternatives: &mut Vec<(usize, Rc<Pattern>)>) {
+        match *p {
+            Pattern::Choice(ref a, ref b) => {
+                self.alternatives(a.clone(), alternatives);
+                self.alternatives(b.clone(), alternatives);
+            }
+            Pattern::NotAllowed => {}
+            Pattern::Empty => alternatives.push((self.id(&self.empty), self.empty.clone())),
+            _ => alternatives.push((self.id(&p), p.clone()))
+        }
+    }
+
+    fn group(&self, a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+        match (&*a, &*b) {
+            (&Pattern::NotAllowed, _) | (_, &Pattern::Empty) => return a.clone(),
+            (_, &Pattern::NotAllowed) | (&Pattern::Empty, _) => return b.clone(),
+            _ => {}
+        }
+        let key = Key::Group(self.id(&a), self.id(&b));
+        self.intern(key, || Pattern::Group(a, b))
+    }
+
+    fn interleave(&self, a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+        match (&*a, &*b) {
+            (&Pattern::NotAllowed, _) | (_, &Pattern::Empty) => return a.clone(),
+            (_, &Pattern::NotAllowed) | (&Pattern::Empty, _) => return b.clone(),
+            _ => {}
+        }
+        let key = Key::Interleave(self.id(&a), self.id(&b));
+        self.intern(key, || Pattern::Interleave(a, b))
+    }
+
+    fn one_or_more(&self, p: Rc<Pattern>) -> Rc<Pattern> {
+        match *p {
+            Pattern::NotAllowed | Pattern::Empty => return p.clone(),
+            _ => {}
+        }
+        let key = Key::OneOrMore(self.id(&p));
+        self.intern(key, || Pattern::OneOrMore(p))
+    }
+
+    fn after(&self, a: Rc<Pattern>, b: Rc<Pattern>) -> Rc<Pattern> {
+        match (&*a, &*b) {
+            (&Pattern::NotAllowed, _) => return a.clone(),
+            (_, &Pattern::NotAllowed) => return b.clone(),
+            _ => {}
+        }
+        let key = Key::After(self.id(&a), self.id(&b));
+        self.intern(key, || Pattern::After(a, b))
+    }
+
+    /// Replaces the pattern following each open element with the result of the function.
+    fn apply_after<F: Fn(Rc<Pattern>) -> Rc<Pattern>>(&self, p: &Rc<Pattern>, f: &F) -> Rc<Pattern> {
+        match **p {
+            Pattern::After(ref a, ref b) => self.after(a.clone(), f(b.clone())),
+            Pattern::Choice(ref a, ref b) => self.choice(self.apply_after(a, f), self.apply_after(b, f)),
+            _ => self.not_allowed.clone()
+        }
+    }
+
+    pub fn nullable(&self, p: &Rc<Pattern>) -> bool {
+        let id = self.id(p);
+        if let Some(&nullable) = self.tables.borrow().nullable.get(&id) {
+            return nullable;
+        }
+        let nullable = match **p {
+            Pattern::Empty | Pattern::Text => true,
+            Pattern::Choice(ref a, ref b) => self.nullable(a) || self.nullable(b),
+            Pattern::Interleave(ref a, ref b) | Pattern::Group(ref a, ref b) => self.nullable(a) && self.nullable(b),
+            Pattern::OneOrMore(ref p) => self.nullable(p),
+            Pattern::Ref(i) => self.nullable(&self.grammar.defines[i]),
+            _ => false
+        };
+        self.tables.borrow_mut().nullable.insert(id, nullable);
+        nullable
+    }
+
+    /// Returns the derivative with respect to a text node.
+    pub fn text_deriv(&self, p: &Rc<Pattern>, s: &str, namespace: &Namespace) -> Rc<Pattern> {
+        self.limit();
+        self.text_deriv_in(p, s, namespace, &mut HashMap::new())
+    }
+
+    /// Returns the derivative with respect to a text node, using the derivatives of the
+    /// patterns with respect to the same text computed so far.
+    fn text_deriv_in(&self, p: &Rc<Pattern>, s: &str, namespace: &Namespace,
+                     memo: &mut HashMap<usize, Rc<Pattern>>) -> Rc<Pattern> {
+        let id = self.id(p);
+        if let Some(d) = memo.get(&id) {
+            return d.clone();
+        }
+        let grammar = self.grammar;
+        let d = match **p {
+            Pattern::Choice(ref a, ref b) => {
+                let a = self.text_deriv_in(a, s, namespace, memo);
+                self.choice(a, self.text_deriv_in(b, s, namespace, memo))
+            }
+            Pattern::Interleave(ref a, ref b) => {
+                let first = self.interleave(self.text_deriv_in(a, s, namespace, memo), b.clone());
+                self.choice(first, self.interleave(a.clone(), self.text_deriv_in(b, s, namespace, memo)))
+            }
+            Pattern::Group(ref a, ref b) => {
+                let first = self.group(self.text_deriv_in(a, s, namespace, memo), b.clone());
+                if self.nullable(a) { self.choice(first, self.text_deriv_in(b, s, namespace, memo)) } else { first }
+            }
+            Pattern::After(ref a, ref b) => self.after(self.text_deriv_in(a, s, namespace, memo), b.clone()),
+            Pattern::OneOrMore(ref q) => {
+                let rest = self.choice(p.clone(), self.empty.clone());
+                self.group(self.text_deriv_in(q, s, namespace, memo), rest)
+            }
+            Pattern::Text => p.clone(),
+            Pattern::Value(ref datatype, ref value, ref value_namespace) =>
+                if grammar.equal(datatype, s, namespace, value, value_namespace) { self.empty.clone() } else { self.not_allowed.clone() },
+            Pattern::Data(ref datatype, ref except) => {
+                let excluded = except.as_ref().map(|e| self.nullable(&self.text_deriv_in(e, s, namespace, &mut HashMap::new()))).unwrap_or(false);
+                if grammar.allows(datatype, s, namespace).is_ok() && !excluded { self.empty.clone() } else { self.not_allowed.clone() }
+            }
+            Pattern::List(ref q) => {
+                let mut rest = q.clone();
+                for token in tokens(s) {
+                    rest = self.text_deriv_in(&rest, token, namespace, &mut HashMap::new());
+                }
+                if self.nullable(&rest) { self.empty.clone() } else { self.not_allowed.clone() }
+            }
+            Pattern::Ref(i) => self.text_deriv_in(&grammar.defines[i], s, namespace, memo),
+            _ => self.not_allowed.clone()
+        };
+        memo.insert(id, d.clone());
+        d
+    }
+
+    /// Returns the derivative with respect to an attribute; when `check_value` is false,
+    /// only the name of the attribute is matched.
+    pub fn att_deriv(&self, p: &Rc<Pattern>, name: &Name, value: &str, namespace: &Namespace,
+                     check_value: bool) -> Rc<Pattern> {
+        self.limit();
+        self.att_deriv_in(p, name, value, namespace, check_value, &mut HashMap::new())
+    }
+
+    fn att_deriv_in(&self, p: &Rc<Pattern>, name: &Name, value: &str, namespace: &Namespace,
+                    check_value: bool, memo: &mut HashMap<usize, Rc<Pattern>>) -> Rc<Pattern> {
+        let id = self.id(p);
+        if let Some(d) = memo.get(&id) {
+            return d.clone();
+        }
+        let d = match **p {
+            Pattern::After(ref a, ref b) =>
+                self.after(self.att_deriv_in(a, name, value, namespace, check_value, memo), b.clone()),
+            Pattern::Choice(ref a, ref b) => {
+                let a = self.att_deriv_in(a, name, value, namespace, check_value, memo);
+                self.choice(a, self.att_deriv_in(b, name, value, namespace, check_value, memo))
+            }
+            Pattern::Group(ref a, ref b) => {
+                let first = self.group(self.att_deriv_in(a, name, value, namespace, check_value, memo), b.clone());
+                self.choice(first, self.group(a.clone(), self.att_deriv_in(b, name, value, namespace, check_value, memo)))
+            }
+            Pattern::Interleave(ref a, ref b) => {
+                let first = self.interleave(self.att_deriv_in(a, name, value, namespace, check_value, memo), b.clone());
+                self.choice(first, self.interleave(a.clone(), self.att_deriv_in(b, name, value, namespace, check_value, memo)))
+            }
+            Pattern::OneOrMore(ref q) => {
+                let rest = self.choice(p.clone(), self.empty.clone());
+                self.group(self.att_deriv_in(q, name, value, namespace, check_value, memo), rest)
+            }
+            Pattern::Attribute(ref nc, ref q) if nc.contains(name) && (!check_value || self.value_match(q, value, namespace)) =>
+                self.empty.clone(),
+            Pattern::Ref(i) => self.att_deriv_in(&self.grammar.defines[i], name, value, namespace, check_value, memo),
+            _ => self.not_allowed.clone()
+        };
+        memo.insert(id, d.clone());
+        d
+    }
+
+    fn value_match(&self, p: &Rc<Pattern>, value: &str, namespace: &Namespace) -> bool {
+        (self.nullable(p) && is_whitespace_str(value)) ||
+            self.nullable(&self.text_deriv_in(p, value, namespace, &mut HashMap::new()))
+    }
+
+    /// Returns the derivative with respect to the start of a start tag.
+    pub fn start_tag_open_deriv(&self, p: &Rc<Pattern>, name: &Name) -> Rc<Pattern> {
+        self.limit();
+        self.start_tag_open_deriv_in(p, name)
+    }
+
+    fn start_tag_open_deriv_in(&self, p: &Rc<Pattern>, name: &Name) -> Rc<Pattern> {
+        let key = (self.id(p), name.clone());
+        if let Some(d) = self.tables.borrow().start_tag_open.get(&key) {
+            return d.clone();
+        }
+        let d = match **p {
+            Pattern::Choice(ref a, ref b) => {
+                let a = self.start_tag_open_deriv_in(a, name);
+                self.choice(a, self.start_tag_open_deriv_in(b, name))
+            }
+            Pattern::Element(ref nc, ref content) if nc.contains(name) => self.after(content.clone(), self.empty.clone()),
+            Pattern::Interleave(ref a, ref b) => {
+                let first = self.apply_after(&self.start_tag_open_deriv_in(a, name), &|x| self.interleave(x, b.clone()));
+                self.choice(first, self.apply_after(&self.start_tag_open_deriv_in(b, name), &|x| self.interleave(a.clone(), x)))
+            }
+            Pattern::OneOrMore(ref q) => {
+                let rest = self.choice(p.clone(), self.empty.clone());
+                self.apply_after(&self.start_tag_open_deriv_in(q, name), &|x| self.group(x, rest.clone()))
+            }
+            Pattern::Group(ref a, ref b) => {
+                let first = self.apply_after(&self.start_tag_open_deriv_in(a, name), &|x| self.group(x, b.clone()));
+                if self.nullable(a) { self.choice(first, self.start_tag_open_deriv_in(b, name)) } else { first }
+            }
+            Pattern::After(ref a, ref b) =>
+                self.apply_after(&self.start_tag_open_deriv_in(a, name), &|x| self.after(x, b.clone())),
+            Pattern::Ref(i) => self.start_tag_open_deriv_in(&self.grammar.defines[i], name),
+            _ => self.not_allowed.clone()
+        };
+        self.tables.borrow_mut().start_tag_open.insert(key, d.clone());
+        d
+    }
+
+    /// Returns the derivative with respect to the end of a start tag, after which
+    /// no more attributes are allowed.
+    pub fn start_tag_close_deriv(&self, p: &Rc<Pattern>) -> Rc<Pattern> {
+        self.limit();
+        self.start_tag_close_deriv_in(p)
+    }
+
+    fn start_tag_close_deriv_in(&self, p: &Rc<Pattern>) -> Rc<Pattern> {
+        let id = self.id(p);
+        if let Some(d) = self.tables.borrow().start_tag_close.get(&id) {
+            return d.clone();
+        }
+        let d = match **p {
+            Pattern::After(ref a, ref b) => self.after(self.start_tag_close_deriv_in(a), b.clone()),
+            Pattern::Choice(ref a, ref b) => {
+                let a = self.start_tag_close_deriv_in(a);
+                self.choice(a, self.start_tag_close_deriv_in(b))
+            }
+            Pattern::Group(ref a, ref b) => self.group(self.start_tag_close_deriv_in(a), self.start_tag_close_deriv_in(b)),
+            Pattern::Interleave(ref a, ref b) =>
+                self.interleave(self.start_tag_close_deriv_in(a), self.start_tag_close_deriv_in(b)),
+            Pattern::OneOrMore(ref q) => self.one_or_more(self.start_tag_close_deriv_in(q)),
+            Pattern::Attribute(..) => self.not_allowed.clone(),
+            Pattern::Ref(i) => self.start_tag_close_deriv_in(&self.grammar.defines[i]),
+            _ => p.clone()
+        };
+        self.tables.borrow_mut().start_tag_close.insert(id, d.clone());
+        d
+    }
+
+    /// Returns the derivative with respect to an end tag.
+    pub fn end_tag_deriv(&self, p: &Rc<Pattern>) -> Rc<Pattern> {
+        self.limit();
+        self.end_tag_deriv_in(p)
+    }
+
+    fn end_tag_deriv_in(&self, p: &Rc<Pattern>) -> Rc<Pattern> {
+        let id = self.id(p);
+        if let Some(d) = self.tables.borrow().end_tag.get(&id) {
+            return d.clone();
+        }
+        let d = match **p {
+            Pattern::Choice(ref a, ref b) => {
+                let a = self.end_tag_deriv_in(a);
+                self.choice(a, self.end_tag_deriv_in(b))
+            }
+            Pattern::After(ref a, ref b) if self.nullable(a) => b.clone(),
+            _ => self.not_allowed.clone()
+        };
+        self.tables.borrow_mut().end_tag.insert(id, d.clone());
+        d
+    }
+
+    /// Describes why the text does not match the pattern, using the first datatype or values
+    /// which could match text.
+    pub fn text_error(&self, p: &Pattern, s: &str, namespace: &Namespace) -> String {
+        let mut values = Vec::new();
+        if let Some(e) = self.data_error(p, s, namespace, &mut values) {
+            return e;
+        }
+        if values.is_empty() {
+            format!("\"{}\" is not allowed", s)
+        } else {
+            format!("\"{}\" is not one of {}", s, values.join(", "))
+        }
+    }
+
+    /// Describes why the value does not match the value patterns of attributes with the name.
+    pub fn attribute_error(&self, p: &Pattern, name: &Name, value: &str, namespace: &Namespace) -> String {
+        match self.grammar.find_attribute(p, name) {
+            Some(q) => self.text_error(&q, value, namespace),
+            None => format!("\"{}\" is not allowed", value)
+        }
+    }
+
+    fn data_error(&self, p: &Pattern, s: &str, namespace: &Namespace, values: &mut Vec<String>) -> Option<String> {
+        match *p {
+            Pattern::Data(ref datatype, _) => self.grammar.allows(datatype, s, namespace).err(),
+            Pattern::Value(_, ref value, _) => {
+                let value = format!("\"{}\"", value);
+                if !values.contains(&value) {
+                    values.push(value);
+                }
+                None
+            }
+            Pattern::List(ref q) => {
+                // Reports the first token which is not allowed in the list
+                let mut rest = q.clone();
+                for token in tokens(s) {
+                    let next = self.text_deriv(&rest, token, namespace);
+                    if let Pattern::NotAllowed = *next {
+                        return Some(self.text_error(&rest, token, namespace));
+                    }
+                    rest = next;
+                }
+                None
+            }
+            Pattern::After(ref a, _) | Pattern::OneOrMore(ref a) => self.data_error(a, s, namespace, values),
+            Pattern::Choice(ref a, ref b) | Pattern::Interleave(ref a, ref b) | Pattern::Group(ref a, ref b) =>
+                self.data_error(a, s, namespace, values).or_else(|| self.data_error(b, s, namespace, values)),
+            Pattern::Ref(i) => self.data_error(&self.grammar.defines[i], s, namespace, values),
+            _ => None
+        }
+    }
+}
+
+fn tokens<'a>(s: &'a str) -> Box<Iterator<Item=&'a str> + 'a> {
+    Box::new(s.split(is_whitespace_char).filter(|t| !t.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use relaxng::Schema;
+
+    use super::{Pattern, Derivatives, Name};
+
+    fn size(p: &Pattern) -> usize {
+        match *p {
+            Pattern::Choice(ref a, ref b) | Pattern::Interleave(ref a, ref b) |
+            Pattern::Group(ref a, ref b) | Pattern::After(ref a, ref b) => 1 + size(a) + size(b),
+            Pattern::OneOrMore(ref a) => 1 + size(a),
+            _ => 1
+        }
+    }
+
+    #[test]
+    fn ambiguous_patterns_do_not_grow() {
+        let cases = [
+            ("element r { element a { empty }*, element a { empty }*, element a { empty }* }", "a"),
+            ("element r { (element a { empty } & element b { empty }?)+ }", "ab"),
+            ("element r { (element a { empty } & element b { empty }?)+ }", "a"),
+            ("element r { (element a { empty }?, element a { empty }?)* }", "a"),
+        ];
+        for &(schema, children) in cases.iter() {
+            let schema = Schema::from_compact_reader(schema.as_bytes()).unwrap();
+            let derivs = Derivatives::new(&schema.grammar);
+            let p = derivs.start_tag_open_deriv(&schema.grammar.start, &Name::new("", "r"));
+            let mut p = derivs.start_tag_close_deriv(&p);
+            let mut sizes = Vec::new();
+            for i in 0..200 {
+                let name = Name::new("", &children[i % children.len()..][..1]);
+                p = derivs.start_tag_open_deriv(&p, &name);
+                p = derivs.start_tag_close_deriv(&p);
+                p = derivs.end_tag_deriv(&p);
+                sizes.push(size(&p));
+            }
+            let max = *sizes[..20].iter().max().unwrap();
+            assert!(sizes[20..].iter().all(|&s| s <= max), "{}: {:?}", children, sizes);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Contains the validator which checks a stream of events against a grammar.
+
+use std::mem;
+use std::rc::Rc;
+
+use attribute::OwnedAttribute;
+use common::{Position, TextPosition, is_whitespace_str};
+use namespace::Namespace;
+use reader::{self, XmlEvent};
+use relaxng::pattern::{Name, Pattern, Grammar, Derivatives};
+
+/// An open element.
+struct Open {
+    name: Name,
+    namespace: Namespace,
+    /// Whether the element has child elements, in which case whitespace between them
+    /// is ignored.
+    has_children: bool
+}
+
+/// Validates a stream of events against a grammar.
+pub struct Validator<'a> {
+    derivs: Derivatives<'a>,
+    /// What is still allowed in the document.
+    pattern: Rc<Pattern>,
+    open: Vec<Open>,
+    /// Character data since the last tag, with the position where it starts.
+    text: String,
+    text_pos: TextPosition,
+    /// Set after an error; the rest of the document is not checked.
+    failed: bool
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(grammar: &'a Grammar) -> Validator<'a> {
+        Validator {
+            derivs: Derivatives::new(grammar),
+            pattern: grammar.start.clone(),
+            open: Vec::new(),
+            text: String::new(),
+            text_pos: TextPosition::new(),
+            failed: false
+        }
+    }
+
+    /// Checks the next event of the document.
+    pub fn validate<P: Position>(&mut self, event: &XmlEvent, pos: &P) -> reader::Result<()> {
+        if self.failed {
+            return Ok(());
+        }
+        let pos = pos.position();
+        let result = match *event {
+            XmlEvent::StartElement { ref name, ref attributes, ref namespace } => {
+                let name = Name::new(name.namespace_ref().unwrap_or(""), &name.local_name[..]);
+                self.start_element(name, attributes, namespace, pos)
+            }
+            XmlEvent::EndElement { .. } => self.end_element(pos),
+            XmlEvent::Characters(ref data) | XmlEvent::CData(ref data) | XmlEvent::Whitespace(ref data) => {
+                if self.text.is_empty() {
+                    self.text_pos = pos;
+                }
+                self.text.push_str(data);
+                Ok(())
+            }
+            _ => Ok(())
+        };
+        if result.is_err() {
+            self.failed = true;
+        }
+        result
+    }
+
+    fn start_element(&mut self, name: Name, attributes: &[OwnedAttribute], namespace: &Namespace,
+                     pos: TextPosition) -> reader::Result<()> {
+        try!(self.flush_text(false));
+        let (derivs, grammar) = (&self.derivs, self.derivs.grammar);
+        let mut p = derivs.start_tag_open_deriv(&self.pattern, &name);
+        if let Pattern::NotAllowed = *p {
+            let expected = grammar.expected_elements(&self.pattern);
+            return Err(reader::validity(&pos, match self.open.last_mut() {
+                Some(parent) => format!("Element {} is not allowed here in the content of element {}; expected {}",
+                                        name, parent.name, expected),
+                None => format!("Element {} is not allowed as the root element; expected {}", name, expected)
+            }));
+        }
+        if let Some(parent) = self.open.last_mut() {
+            parent.has_children = true;
+        }
+        for attribute in attributes {
+            let attribute_name = Name::new(attribute.name.namespace_ref().unwrap_or(""), &attribute.name.local_name[..]);
+            let next = derivs.att_deriv(&p, &attribute_name, &attribute.value, namespace, true);
+            if let Pattern::NotAllowed = *next {
+                let named = derivs.att_deriv(&p, &attribute_name, &attribute.value, namespace, false);
+                return Err(reader::validity(&pos, match *named {
+                    Pattern::NotAllowed => format!("Attribute {} is not allowed on element {}", attribute_name, name),
+                    _ => format!("Invalid value of attribute {} of element {}: {}", attribute_name, name,
+                                 derivs.attribute_error(&p, &attribute_name, &attribute.value, namespace))
+                }));
+            }
+            p = next;
+        }
+        let closed = derivs.start_tag_close_deriv(&p);
+        if let Pattern::NotAllowed = *closed {
+            let missing = grammar.required_attributes(&p);
+            return Err(reader::validity(&pos, if missing.is_empty() {
+                format!("Attributes of element {} are incomplete", name)
+            } else {
+                format!("Required attribute {} of element {} is missing", missing.join(", "), name)
+            }));
+        }
+        self.pattern = closed;
+        self.open.push(Open { name: name, namespace: namespace.clone(), has_children: false });
+        Ok(())
+    }
+
+    fn end_element(&mut self, pos: TextPosition) -> reader::Result<()> {
+        try!(self.flush_text(true));
+        let grammar = self.derivs.grammar;
+        let element = self.open.pop().unwrap();
+        let p = self.derivs.end_tag_deriv(&self.pattern);
+        if let Pattern::NotAllowed = *p {
+            return Err(reader::validity(&pos, format!(
+                "Content of element {} is incomplete; expected {}", element.name, grammar.expected_elements(&self.pattern)
+            )));
+        }
+        self.pattern = p;
+        Ok(())
+    }
+
+    /// Matches character data collected since the last tag; `end` is true when the next
+    /// tag is an end tag.
+    fn flush_text(&mut self, end: bool) -> reader::Result<()> {
+        let text = mem::replace(&mut self.text, String::new());
+        let (name, namespace, has_children) = match self.open.last() {
+            Some(element) => (&element.name, &element.namespace, element.has_children),
+            None => return Ok(())
+        };
+        let whitespace = is_whitespace_str(&text);
+        // Whitespace between child elements is insignificant, while the whole content of an
+        // element without children, even if it is empty, is matched as a value
+        if whitespace && (has_children || !end) {
+            return Ok(());
+        }
+        let (derivs, grammar) = (&self.derivs, self.derivs.grammar);
+        let p = derivs.text_deriv(&self.pattern, &text, namespace);
+        self.pattern = match *p {
+            Pattern::NotAllowed if whitespace => return Ok(()),
+            Pattern::NotAllowed if grammar.allows_text(&self.pattern) => return Err(reader::validity(&self.text_pos, format!(
+                "Invalid content of element {}: {}", name, derivs.text_error(&self.pattern, &text, namespace)
+            ))),
+            Pattern::NotAllowed => return Err(reader::validity(&self.text_pos, format!(
+                "Character data is not allowed in the content of element {}", name
+            ))),
+            _ if whitespace => derivs.choice(self.pattern.clone(), p),
+            _ => p
+        };
+        Ok(())
+    }
+}
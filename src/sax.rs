@@ -0,0 +1,174 @@
+//! Contains a callback-based interface to the parser in the style of SAX.
+//!
+//! A `ContentHandler` is a set of callbacks which are called by `parse()` for events read
+//! by an `EventReader`. Each callback tells the driver whether to continue parsing, and a
+//! `Locator` given to the handler before the first event reports the position of the event
+//! being handled:
+//!
+//! ```rust
+//! use xml::EventReader;
+//! use xml::common::Position;
+//! use xml::name::OwnedName;
+//! use xml::attribute::OwnedAttribute;
+//! use xml::namespace::Namespace;
+//! use xml::sax::{self, ContentHandler, Locator, Flow};
+//!
+//! #[derive(Default)]
+//! struct FindTitle {
+//!     locator: Option<Locator>,
+//!     found: Option<String>
+//! }
+//!
+//! impl ContentHandler for FindTitle {
+//!     fn set_document_locator(&mut self, locator: Locator) {
+//!         self.locator = Some(locator);
+//!     }
+//!
+//!     fn start_element(&mut self, name: &OwnedName, _: &[OwnedAttribute], _: &Namespace) -> Flow {
+//!         if name.local_name == "title" {
+//!             let position = self.locator.as_ref().unwrap().position();
+//!             self.found = Some(position.to_string());
+//!             return Flow::Stop;
+//!         }
+//!         Flow::Continue
+//!     }
+//! }
+//!
+//! let mut handler = FindTitle::default();
+//! let mut reader = EventReader::from_str("<book>\n  <title>Dune</title>\n</book>");
+//! assert_eq!(sax::parse(&mut reader, &mut handler).unwrap(), Flow::Stop);
+//! assert_eq!(handler.found, Some("2:3".into()));
+//! ```
+
+use std::cell::Cell;
+use std::io::Read;
+use std::rc::Rc;
+
+use attribute::OwnedAttribute;
+use common::{Position, TextPosition, XmlVersion};
+use dtd::Dtd;
+use name::OwnedName;
+use namespace::Namespace;
+use reader::{EventReader, XmlEvent, Result};
+
+/// Tells the driver whether to continue parsing after a callback returns.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Flow {
+    /// Continue parsing with the next event.
+    Continue,
+    /// Stop parsing; `parse()` returns immediately.
+    Stop
+}
+
+/// Reports the position of the event which is being handled.
+///
+/// A locator is given to `ContentHandler::set_document_locator()` before any other callback
+/// is called; its position is updated by the driver before each callback.
+#[derive(Clone, Debug)]
+pub struct Locator(Rc<Cell<TextPosition>>);
+
+impl Position for Locator {
+    /// Returns the position of the current event.
+    #[inline]
+    fn position(&self) -> TextPosition {
+        self.0.get()
+    }
+}
+
+/// Callbacks for the events of a document.
+///
+/// Each callback corresponds to a variant of `reader::XmlEvent`; see its documentation for
+/// the details. All callbacks do nothing by default.
+pub trait ContentHandler {
+    /// Receives the locator which reports the position of events.
+    fn set_document_locator(&mut self, locator: Locator) {}
+
+    /// Called for the document declaration, which is always reported before other events.
+    fn start_document(&mut self, version: XmlVersion, encoding: &str, standalone: Option<bool>) -> Flow {
+        Flow::Continue
+    }
+
+    /// Called at the end of the document; this is the last callback.
+    fn end_document(&mut self) {}
+
+    /// Called for a processing instruction.
+    fn processing_instruction(&mut self, name: &str, data: Option<&str>) -> Flow {
+        Flow::Continue
+    }
+
+    /// Called for the document type declaration.
+    fn doctype(&mut self, name: &str, public_id: Option<&str>, system_id: Option<&str>, dtd: &Dtd) -> Flow {
+        Flow::Continue
+    }
+
+    /// Called for the opening tag of an element, and for an empty element before
+    /// `end_element()`.
+    fn start_element(&mut self, name: &OwnedName, attributes: &[OwnedAttribute], namespace: &Namespace) -> Flow {
+        Flow::Continue
+    }
+
+    /// Called for the closing tag of an element.
+    fn end_element(&mut self, name: &OwnedName) -> Flow {
+        Flow::Continue
+    }
+
+    /// Called for character data.
+    fn characters(&mut self, data: &str) -> Flow {
+        Flow::Continue
+    }
+
+    /// Called for white space outside of the root element and, unless the parser is
+    /// configured otherwise, for white space between tags.
+    fn whitespace(&mut self, data: &str) -> Flow {
+        Flow::Continue
+    }
+
+    /// Called for a CDATA section; calls `characters()` by default.
+    fn cdata(&mut self, data: &str) -> Flow {
+        self.characters(data)
+    }
+
+    /// Called for a comment, unless the parser is configured to ignore them.
+    fn comment(&mut self, data: &str) -> Flow {
+        Flow::Continue
+    }
+}
+
+/// Reads events from `reader` and calls the callbacks of `handler` for them until the end
+/// of the document or until a callback returns `Flow::Stop`.
+///
+/// Returns `Flow::Stop` if parsing was stopped by a callback, so that it can be continued
+/// by calling this function again, and `Flow::Continue` if the whole document was read.
+/// Parsing errors are returned as they are.
+pub fn parse<R: Read, H: ContentHandler>(reader: &mut EventReader<R>, handler: &mut H) -> Result<Flow> {
+    let locator = Locator(Rc::new(Cell::new(reader.position())));
+    handler.set_document_locator(locator.clone());
+    loop {
+        let event = try!(reader.next());
+        locator.0.set(reader.position());
+        let flow = match event {
+            XmlEvent::StartDocument { version, ref encoding, standalone } =>
+                handler.start_document(version, encoding, standalone),
+            XmlEvent::EndDocument => {
+                handler.end_document();
+                return Ok(Flow::Continue);
+            }
+            XmlEvent::ProcessingInstruction { ref name, ref data } =>
+                handler.processing_instruction(name, data.as_ref().map(|d| &d[..])),
+            XmlEvent::Doctype { ref name, ref public_id, ref system_id, ref dtd } =>
+                handler.doctype(name, public_id.as_ref().map(|id| &id[..]),
+                                system_id.as_ref().map(|id| &id[..]), dtd),
+            XmlEvent::StartElement { ref name, ref attributes, ref namespace } =>
+                handler.start_element(name, attributes, namespace),
+            XmlEvent::EndElement { ref name } =>
+                handler.end_element(name),
+            XmlEvent::CData(ref data) => handler.cdata(data),
+            XmlEvent::Comment(ref data) => handler.comment(data),
+            XmlEvent::Characters(ref data) => handler.characters(data),
+            XmlEvent::Whitespace(ref data) => handler.whitespace(data)
+        };
+        if flow == Flow::Stop {
+            return Ok(Flow::Stop);
+        }
+    }
+}
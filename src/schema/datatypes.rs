@@ -0,0 +1,94 @@
+//! Contains XML Schema datatypes for use by other schema languages.
+//!
+//! RELAX NG schemas refer to XML Schema built-in types by their local names and restrict
+//! them with parameters, which correspond to constraining facets.
+
+use std::rc::Rc;
+
+use namespace::Namespace;
+use schema::model::{QName, SimpleType, Variety, Facet, TypeDef, Components};
+use schema::regex::Regex;
+use schema::types::{self, Builtin, BUILTINS};
+
+/// A built-in type, possibly restricted by parameters, registered in `Datatypes`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Datatype(QName);
+
+/// A set of datatypes sharing the built-in types they are derived from.
+pub struct Datatypes {
+    components: Components,
+    restrictions: usize
+}
+
+impl Datatypes {
+    pub fn new() -> Datatypes {
+        let mut components = Components::default();
+        types::add_builtins(&mut components);
+        Datatypes { components: components, restrictions: 0 }
+    }
+
+    /// Returns the built-in type with the given local name restricted by the given
+    /// parameters, or a description of the problem.
+    pub fn datatype(&mut self, name: &str, params: &[(String, String)]) -> Result<Datatype, String> {
+        let base = QName::xs(name);
+        if !BUILTINS.iter().any(|&(n, b)| n == name && b != Builtin::AnySimpleType) {
+            return Err(format!("Unknown datatype: {}", name));
+        }
+        if params.is_empty() {
+            return Ok(Datatype(base));
+        }
+        let mut facets = Vec::new();
+        for &(ref param, ref value) in params {
+            let number = || value.trim().parse().map_err(|_| format!("Invalid value of parameter {}: {}", param, value));
+            facets.push(match &param[..] {
+                "length" => Facet::Length(try!(number())),
+                "minLength" => Facet::MinLength(try!(number())),
+                "maxLength" => Facet::MaxLength(try!(number())),
+                "totalDigits" => Facet::TotalDigits(try!(number())),
+                "fractionDigits" => Facet::FractionDigits(try!(number())),
+                "maxInclusive" => Facet::MaxInclusive(value.trim().to_owned()),
+                "maxExclusive" => Facet::MaxExclusive(value.trim().to_owned()),
+                "minInclusive" => Facet::MinInclusive(value.trim().to_owned()),
+                "minExclusive" => Facet::MinExclusive(value.trim().to_owned()),
+                // Unlike pattern facets in a schema, all pattern parameters must match
+                "pattern" => Facet::Pattern(vec![try!(Regex::new(value))]),
+                _ => return Err(format!("Unsupported parameter of datatype {}: {}", name, param))
+            });
+        }
+        self.restrictions += 1;
+        let restricted = QName::new(None, format!("#restriction{}", self.restrictions));
+        self.components.types.insert(restricted.clone(), TypeDef::Simple(Rc::new(SimpleType {
+            name: restricted.clone(),
+            variety: Variety::Restriction(base),
+            facets: facets
+        })));
+        Ok(Datatype(restricted))
+    }
+
+    /// Checks that the value is valid for the datatype; `namespace` is used to resolve
+    /// prefixes in values of `QName` types.
+    pub fn validate(&self, datatype: &Datatype, value: &str, namespace: &Namespace) -> Result<(), String> {
+        types::validate_simple(&self.components, &datatype.0, value, namespace)
+    }
+
+    /// Checks whether two values of the datatype are equal, each of them in its own
+    /// namespace context.
+    pub fn same_value(&self, datatype: &Datatype, a: &str, a_namespace: &Namespace,
+                      b: &str, b_namespace: &Namespace) -> bool {
+        match types::builtin_base(&self.components, &datatype.0) {
+            Some(Builtin::QName) => {
+                let a = types::normalize_value(&self.components, &datatype.0, a);
+                let b = types::normalize_value(&self.components, &datatype.0, b);
+                resolve(&a, a_namespace).is_some() && resolve(&a, a_namespace) == resolve(&b, b_namespace)
+            }
+            _ => types::same_value(&self.components, &datatype.0, a, b)
+        }
+    }
+}
+
+fn resolve<'a>(value: &'a str, namespace: &'a Namespace) -> Option<(Option<&'a str>, &'a str)> {
+    match value.find(':') {
+        Some(i) => namespace.get(&value[..i]).map(|ns| (Some(ns), &value[i+1..])),
+        None => Some((namespace.get("").and_then(|ns| if ns.is_empty() { None } else { Some(ns) }), value))
+    }
+}
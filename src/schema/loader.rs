@@ -0,0 +1,908 @@
+//! Contains the loader which builds schema components from schema documents.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::rc::Rc;
+
+use common::{Position, TextPosition, is_whitespace_char};
+use namespace::Namespace;
+use reader::{self, EventReader, XmlEvent};
+use reader::EntityResolver;
+use schema::model::{XS_NAMESPACE, QName, ElementDecl, AttributeUse, NamespaceConstraint, ProcessContents,
+                    Wildcard, Compositor, Term, Particle, Content, Derivation, ComplexType, WhiteSpace,
+                    Facet, Variety, SimpleType, TypeDef, Components};
+use schema::regex::Regex;
+use schema::types;
+
+type Result<T> = reader::Result<T>;
+
+/// Names of elements specifying constraining facets.
+static FACETS: &'static [&'static str] = &[
+    "length", "minLength", "maxLength", "pattern", "enumeration", "whiteSpace",
+    "maxInclusive", "maxExclusive", "minInclusive", "minExclusive", "totalDigits", "fractionDigits"
+];
+
+/// An element of a schema document.
+struct Node {
+    name: QName,
+    /// Attributes without a namespace; other attributes are annotations and are ignored.
+    attributes: Vec<(String, String)>,
+    namespace: Namespace,
+    children: Vec<Node>,
+    pos: TextPosition
+}
+
+impl Node {
+    /// Returns the local name of the node if it is in the XML Schema namespace,
+    /// and an empty string otherwise.
+    fn local(&self) -> &str {
+        if self.name.namespace.as_ref().map(|s| &s[..]) == Some(XS_NAMESPACE) { &self.name.local } else { "" }
+    }
+
+    fn is(&self, local: &str) -> bool {
+        self.local() == local
+    }
+
+    /// Returns the children of the node except annotations.
+    fn content(&self) -> Vec<&Node> {
+        self.children.iter().filter(|c| !c.is("annotation")).collect()
+    }
+
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| &v[..])
+    }
+
+    fn owned_attr(&self, name: &str) -> Option<String> {
+        self.attr(name).map(|v| v.to_owned())
+    }
+
+    fn required_attr(&self, name: &str) -> Result<&str> {
+        match self.attr(name) {
+            Some(v) => Ok(v),
+            None => Err(self.error(format!("Attribute {} is required on xs:{}", name, self.name.local)))
+        }
+    }
+
+    fn flag(&self, name: &str) -> Result<bool> {
+        match self.attr(name).map(str::trim) {
+            None | Some("false") | Some("0") => Ok(false),
+            Some("true") | Some("1") => Ok(true),
+            Some(v) => Err(self.error(format!("Invalid boolean value of attribute {}: {}", name, v)))
+        }
+    }
+
+    /// Resolves a qualified name used in an attribute value.
+    fn resolve(&self, value: &str) -> Result<QName> {
+        let value = value.trim();
+        let (prefix, local) = match value.find(':') {
+            Some(i) => (&value[..i], &value[i+1..]),
+            None => ("", value)
+        };
+        match self.namespace.get(prefix) {
+            Some(ns) => Ok(QName::new(Some(ns), local)),
+            None if prefix.is_empty() => Ok(QName::new(None, local)),
+            None => Err(self.error(format!("Undeclared namespace prefix: {}", prefix)))
+        }
+    }
+
+    fn error<M: Into<Cow<'static, str>>>(&self, msg: M) -> reader::Error {
+        (&self.pos, msg).into()
+    }
+}
+
+/// Parses a schema document into a tree of nodes.
+fn parse_document<R: Read>(source: R) -> Result<Node> {
+    let mut reader = EventReader::new(source);
+    let mut stack: Vec<Node> = Vec::new();
+    loop {
+        match try!(reader.next()) {
+            XmlEvent::StartElement { name, attributes, namespace } => stack.push(Node {
+                name: QName::new(name.namespace.as_ref().map(|s| &s[..]), &name.local_name[..]),
+                attributes: attributes.into_iter()
+                    .filter(|a| a.name.namespace.is_none())
+                    .map(|a| (a.name.local_name, a.value))
+                    .collect(),
+                namespace: namespace,
+                children: Vec::new(),
+                pos: reader.position()
+            }),
+            XmlEvent::EndElement { .. } => {
+                let node = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => return Ok(node)
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Kinds of named components.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+enum Kind {
+    Type,
+    Element,
+    Attribute,
+    Group,
+    AttributeGroup
+}
+
+impl Kind {
+    fn description(self) -> &'static str {
+        match self {
+            Kind::Type => "type",
+            Kind::Element => "element",
+            Kind::Attribute => "attribute",
+            Kind::Group => "model group",
+            Kind::AttributeGroup => "attribute group"
+        }
+    }
+}
+
+/// A position in one of the loaded schema documents.
+#[derive(Clone)]
+struct Location {
+    pos: TextPosition,
+    /// Included or imported documents containing the position, with positions of
+    /// the elements which include them.
+    documents: Vec<(String, TextPosition)>
+}
+
+/// Properties of the schema document being loaded.
+struct Document {
+    target: Option<String>,
+    /// Whether the document has no target namespace and takes the one of the including document.
+    chameleon: bool,
+    qualified_elements: bool,
+    qualified_attributes: bool
+}
+
+pub struct Loader<'a> {
+    resolver: Option<&'a EntityResolver>,
+    components: Components,
+    /// Locations of included and imported documents.
+    loaded: HashSet<String>,
+    /// References to named components, which are checked after all documents are loaded.
+    references: Vec<(Kind, QName, Location)>,
+    /// Locations of named component definitions.
+    positions: HashMap<(Kind, QName), Location>,
+    /// Component definitions in document order, so that errors found after loading
+    /// do not depend on the order of hash maps.
+    definitions: Vec<(Kind, QName)>,
+    /// Documents being loaded, see `Location`.
+    documents: Vec<(String, TextPosition)>,
+    anonymous: usize
+}
+
+impl<'a> Loader<'a> {
+    /// Creates a loader which loads included and imported schema documents with the given
+    /// resolver; without a resolver, they cannot be loaded.
+    pub fn new(resolver: Option<&'a EntityResolver>) -> Loader<'a> {
+        Loader {
+            resolver: resolver,
+            components: Components::default(),
+            loaded: HashSet::new(),
+            references: Vec::new(),
+            positions: HashMap::new(),
+            definitions: Vec::new(),
+            documents: Vec::new(),
+            anonymous: 0
+        }
+    }
+
+    /// Loads the schema document and all documents it includes or imports.
+    pub fn load<R: Read>(mut self, source: R) -> Result<Components> {
+        self.add_builtins();
+        let root = try!(parse_document(source));
+        try!(self.load_document(&root, None));
+        try!(self.check_references());
+        try!(self.complete());
+        Ok(self.components)
+    }
+
+    fn add_builtins(&mut self) {
+        types::add_builtins(&mut self.components);
+        let any = Wildcard { namespaces: NamespaceConstraint::Any, process_contents: ProcessContents::Lax };
+        self.components.types.insert(QName::xs("anyType"), TypeDef::Complex(Rc::new(ComplexType {
+            name: QName::xs("anyType"),
+            base: None,
+            is_abstract: false,
+            mixed: true,
+            content: Content::Elements(Particle {
+                term: Term::Wildcard(Rc::new(any.clone())),
+                min_occurs: 0,
+                max_occurs: None
+            }),
+            attributes: Vec::new(),
+            attribute_groups: Vec::new(),
+            any_attribute: Some(any)
+        })));
+    }
+
+    /// Loads components from a schema document, returning its target namespace.
+    ///
+    /// `includer` is the target namespace of the including document, which is used
+    /// by included documents without a target namespace.
+    fn load_document(&mut self, root: &Node, includer: Option<&Option<String>>) -> Result<Option<String>> {
+        if !root.is("schema") {
+            return Err(root.error("Root element of a schema document must be xs:schema"));
+        }
+        let (target, chameleon) = match (root.owned_attr("targetNamespace"), includer) {
+            (None, Some(target)) => (target.clone(), target.is_some()),
+            (target, _) => (target, false)
+        };
+        let doc = Document {
+            target: target,
+            chameleon: chameleon,
+            qualified_elements: root.attr("elementFormDefault") == Some("qualified"),
+            qualified_attributes: root.attr("attributeFormDefault") == Some("qualified")
+        };
+
+        for node in root.content() {
+            match node.local() {
+                "include" => {
+                    let location = try!(node.required_attr("schemaLocation"));
+                    if let Some(target) = try!(self.load_location(node, location, Some(&doc.target))) {
+                        if target != doc.target {
+                            return Err(node.error(format!("Included schema document {} has a different target namespace", location)));
+                        }
+                    }
+                }
+                "import" => {
+                    let namespace = node.owned_attr("namespace");
+                    if namespace == doc.target {
+                        return Err(node.error("Imported namespace must differ from the target namespace"));
+                    }
+                    if let Some(location) = node.attr("schemaLocation") {
+                        if let Some(target) = try!(self.load_location(node, location, None)) {
+                            if target != namespace {
+                                return Err(node.error(format!("Imported schema document {} has a different target namespace", location)));
+                            }
+                        }
+                    }
+                }
+                "redefine" => return Err(node.error("xs:redefine is not supported")),
+                "notation" => {}
+                "simpleType" | "complexType" => {
+                    let name = QName::new(doc.target.as_ref().map(|s| &s[..]), try!(node.required_attr("name")));
+                    let t = try!(self.type_definition(node, &doc, name.clone()));
+                    try!(self.define(Kind::Type, &name, node));
+                    self.components.types.insert(name, t);
+                }
+                "element" => {
+                    let decl = try!(self.element(node, &doc, true));
+                    try!(self.define(Kind::Element, &decl.name, node));
+                    self.components.elements.insert(decl.name.clone(), Rc::new(decl));
+                }
+                "attribute" => {
+                    let attribute = try!(self.attribute(node, &doc, true));
+                    try!(self.define(Kind::Attribute, &attribute.name, node));
+                    self.components.attributes.insert(attribute.name.clone(), attribute);
+                }
+                "group" => {
+                    let name = QName::new(doc.target.as_ref().map(|s| &s[..]), try!(node.required_attr("name")));
+                    let particle = match node.content().into_iter().next() {
+                        Some(child) if child.is("sequence") || child.is("choice") || child.is("all") => Particle {
+                            term: try!(self.model_group(child, &doc)),
+                            min_occurs: 1,
+                            max_occurs: Some(1)
+                        },
+                        _ => return Err(node.error("Model group definition requires xs:sequence, xs:choice or xs:all"))
+                    };
+                    try!(self.define(Kind::Group, &name, node));
+                    self.components.groups.insert(name, particle);
+                }
+                "attributeGroup" => {
+                    let name = QName::new(doc.target.as_ref().map(|s| &s[..]), try!(node.required_attr("name")));
+                    let mut group = (Vec::new(), Vec::new(), None);
+                    for child in node.content() {
+                        try!(self.attribute_use(child, &doc, &mut group.0, &mut group.1, &mut group.2));
+                    }
+                    try!(self.define(Kind::AttributeGroup, &name, node));
+                    self.components.attribute_groups.insert(name, group);
+                }
+                _ => return Err(node.error(format!("Unexpected element in schema: {}", node.name)))
+            }
+        }
+        Ok(doc.target)
+    }
+
+    /// Loads an included or imported document, returning its target namespace,
+    /// or `None` if the document has already been loaded.
+    fn load_location(&mut self, node: &Node, location: &str,
+                     includer: Option<&Option<String>>) -> Result<Option<Option<String>>> {
+        if !self.loaded.insert(location.to_owned()) {
+            return Ok(None);
+        }
+        let source = match self.resolver.map(|r| r.resolve(None, location)) {
+            Some(Ok(Some(source))) => source,
+            Some(Err(e)) => return Err(node.error(format!("Cannot read schema document {}: {}", location, e))),
+            _ => return Err(node.error(format!("Cannot resolve schema document: {}", location)))
+        };
+        let nested_error = |e: reader::Error| node.error(format!("Error in schema document {}: {}", location, e));
+        let root = try!(parse_document(source).map_err(&nested_error));
+        self.documents.push((location.to_owned(), node.pos));
+        let target = try!(self.load_document(&root, includer).map_err(nested_error));
+        self.documents.pop();
+        Ok(Some(target))
+    }
+
+    fn location(&self, node: &Node) -> Location {
+        Location { pos: node.pos, documents: self.documents.clone() }
+    }
+
+    /// Creates an error at the given location, which is reported at the position of the
+    /// outermost include element.
+    fn error_at(&self, location: &Location, msg: String) -> reader::Error {
+        let mut error: reader::Error = (&location.pos, msg).into();
+        for &(ref document, pos) in location.documents.iter().rev() {
+            error = (&pos, format!("Error in schema document {}: {}", document, error)).into();
+        }
+        error
+    }
+
+    /// Records the position of a named component, checking that it is not defined twice.
+    fn define(&mut self, kind: Kind, name: &QName, node: &Node) -> Result<()> {
+        let location = self.location(node);
+        if self.positions.insert((kind, name.clone()), location).is_some() {
+            return Err(node.error(format!("Duplicate definition of {} {}", kind.description(), name)));
+        }
+        self.definitions.push((kind, name.clone()));
+        Ok(())
+    }
+
+    /// Resolves a reference to a named component, which is checked after loading.
+    fn reference(&mut self, kind: Kind, doc: &Document, node: &Node, value: &str) -> Result<QName> {
+        let mut name = try!(node.resolve(value));
+        if doc.chameleon && name.namespace.is_none() {
+            name.namespace = doc.target.clone();
+        }
+        let location = self.location(node);
+        self.references.push((kind, name.clone(), location));
+        Ok(name)
+    }
+
+    fn anonymous_type(&mut self, node: &Node, doc: &Document) -> Result<QName> {
+        self.anonymous += 1;
+        let name = QName::new(None, format!("#anonymous{}", self.anonymous));
+        let t = try!(self.type_definition(node, doc, name.clone()));
+        let location = self.location(node);
+        self.positions.insert((Kind::Type, name.clone()), location);
+        self.definitions.push((Kind::Type, name.clone()));
+        self.components.types.insert(name.clone(), t);
+        Ok(name)
+    }
+
+    fn type_definition(&mut self, node: &Node, doc: &Document, name: QName) -> Result<TypeDef> {
+        if node.is("simpleType") {
+            self.simple_type(node, doc, name).map(|t| TypeDef::Simple(Rc::new(t)))
+        } else {
+            self.complex_type(node, doc, name).map(|t| TypeDef::Complex(Rc::new(t)))
+        }
+    }
+
+    /// Returns the type named in the `type` attribute or defined by a child of the node.
+    fn type_of(&mut self, node: &Node, doc: &Document) -> Result<Option<QName>> {
+        if let Some(name) = node.attr("type") {
+            return self.reference(Kind::Type, doc, node, name).map(Some);
+        }
+        for child in node.content() {
+            if child.is("simpleType") || child.is("complexType") {
+                return self.anonymous_type(child, doc).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the simple type defined by a child of the node.
+    fn inline_simple_type(&mut self, node: &Node, doc: &Document) -> Result<QName> {
+        match node.content().into_iter().find(|c| c.is("simpleType")) {
+            Some(child) => self.anonymous_type(child, doc),
+            None => Err(node.error(format!("xs:{} requires a simple type", node.name.local)))
+        }
+    }
+
+    fn element(&mut self, node: &Node, doc: &Document, global: bool) -> Result<ElementDecl> {
+        let local = try!(node.required_attr("name"));
+        let qualified = match node.attr("form") {
+            Some("qualified") => true,
+            Some("unqualified") => false,
+            Some(v) => return Err(node.error(format!("Invalid value of attribute form: {}", v))),
+            None => doc.qualified_elements
+        };
+        let namespace = if global || qualified { doc.target.as_ref().map(|s| &s[..]) } else { None };
+        let substitution_group = match node.attr("substitutionGroup") {
+            Some(head) if global => Some(try!(self.reference(Kind::Element, doc, node, head))),
+            _ => None
+        };
+        Ok(ElementDecl {
+            name: QName::new(namespace, local),
+            type_name: try!(self.type_of(node, doc)),
+            nillable: try!(node.flag("nillable")),
+            is_abstract: try!(node.flag("abstract")),
+            default: node.owned_attr("default"),
+            fixed: node.owned_attr("fixed"),
+            substitution_group: substitution_group
+        })
+    }
+
+    fn attribute(&mut self, node: &Node, doc: &Document, global: bool) -> Result<AttributeUse> {
+        let (required, prohibited) = match node.attr("use") {
+            None | Some("optional") => (false, false),
+            Some("required") => (true, false),
+            Some("prohibited") => (false, true),
+            Some(v) => return Err(node.error(format!("Invalid value of attribute use: {}", v)))
+        };
+        if let Some(name) = node.attr("ref") {
+            return Ok(AttributeUse {
+                name: try!(self.reference(Kind::Attribute, doc, node, name)),
+                type_name: QName::xs("anySimpleType"),
+                required: required,
+                prohibited: prohibited,
+                default: node.owned_attr("default"),
+                fixed: node.owned_attr("fixed"),
+                reference: true
+            });
+        }
+        let local = try!(node.required_attr("name"));
+        let qualified = match node.attr("form") {
+            Some("qualified") => true,
+            Some("unqualified") => false,
+            Some(v) => return Err(node.error(format!("Invalid value of attribute form: {}", v))),
+            None => doc.qualified_attributes
+        };
+        let namespace = if global || qualified { doc.target.as_ref().map(|s| &s[..]) } else { None };
+        Ok(AttributeUse {
+            name: QName::new(namespace, local),
+            type_name: try!(self.type_of(node, doc)).unwrap_or_else(|| QName::xs("anySimpleType")),
+            required: required,
+            prohibited: prohibited,
+            default: node.owned_attr("default"),
+            fixed: node.owned_attr("fixed"),
+            reference: false
+        })
+    }
+
+    /// Adds an attribute use, an attribute group reference or an attribute wildcard
+    /// to the given lists.
+    fn attribute_use(&mut self, node: &Node, doc: &Document, uses: &mut Vec<AttributeUse>,
+                     groups: &mut Vec<QName>, any: &mut Option<Wildcard>) -> Result<()> {
+        match node.local() {
+            "attribute" => uses.push(try!(self.attribute(node, doc, false))),
+            "attributeGroup" => {
+                let name = try!(node.required_attr("ref"));
+                groups.push(try!(self.reference(Kind::AttributeGroup, doc, node, name)));
+            }
+            "anyAttribute" => *any = Some(try!(wildcard(node, doc))),
+            _ => return Err(node.error(format!("Unexpected element in attribute declarations: {}", node.name)))
+        }
+        Ok(())
+    }
+
+    fn particle(&mut self, node: &Node, doc: &Document) -> Result<Particle> {
+        let (min_occurs, max_occurs) = try!(occurs(node));
+        let term = match node.local() {
+            "element" => match node.attr("ref") {
+                Some(name) => Term::ElementRef(try!(self.reference(Kind::Element, doc, node, name))),
+                None => Term::Element(Rc::new(try!(self.element(node, doc, false))))
+            },
+            "group" => {
+                let name = try!(node.required_attr("ref"));
+                Term::GroupRef(try!(self.reference(Kind::Group, doc, node, name)))
+            }
+            "any" => Term::Wildcard(Rc::new(try!(wildcard(node, doc)))),
+            "sequence" | "choice" | "all" => try!(self.model_group(node, doc)),
+            _ => return Err(node.error(format!("Unexpected element in content model: {}", node.name)))
+        };
+        Ok(Particle { term: term, min_occurs: min_occurs, max_occurs: max_occurs })
+    }
+
+    fn model_group(&mut self, node: &Node, doc: &Document) -> Result<Term> {
+        let compositor = match node.local() {
+            "sequence" => Compositor::Sequence,
+            "choice" => Compositor::Choice,
+            _ => Compositor::All
+        };
+        let mut particles = Vec::new();
+        for child in node.content() {
+            particles.push(try!(self.particle(child, doc)));
+        }
+        Ok(Term::Group(compositor, particles))
+    }
+
+    fn complex_type(&mut self, node: &Node, doc: &Document, name: QName) -> Result<ComplexType> {
+        let mut t = ComplexType {
+            name: name,
+            base: Some((QName::xs("anyType"), Derivation::Restriction)),
+            is_abstract: try!(node.flag("abstract")),
+            mixed: try!(node.flag("mixed")),
+            content: Content::Empty,
+            attributes: Vec::new(),
+            attribute_groups: Vec::new(),
+            any_attribute: None
+        };
+        let children = node.content();
+        let mut simple_content = false;
+        let body = match children.first() {
+            Some(&content) if content.is("simpleContent") || content.is("complexContent") => {
+                let derivation = match content.content().into_iter().next() {
+                    Some(d) if d.is("extension") || d.is("restriction") => d,
+                    _ => return Err(content.error(format!("xs:{} requires xs:extension or xs:restriction", content.name.local)))
+                };
+                let base = try!(self.reference(Kind::Type, doc, derivation, try!(derivation.required_attr("base"))));
+                let method = if derivation.is("extension") { Derivation::Extension } else { Derivation::Restriction };
+                t.base = Some((base.clone(), method));
+                if content.is("simpleContent") {
+                    simple_content = true;
+                    t.content = Content::Simple(if method == Derivation::Extension {
+                        base
+                    } else {
+                        // Facets of the restriction constrain the simple content of the base type
+                        self.anonymous += 1;
+                        let name = QName::new(None, format!("#anonymous{}", self.anonymous));
+                        let facets = try!(facets(&derivation.content()));
+                        self.components.types.insert(name.clone(), TypeDef::Simple(Rc::new(SimpleType {
+                            name: name.clone(),
+                            variety: Variety::Restriction(base),
+                            facets: facets
+                        })));
+                        name
+                    });
+                } else if content.attr("mixed").is_some() {
+                    t.mixed = try!(content.flag("mixed"));
+                }
+                derivation.content()
+            }
+            _ => children
+        };
+
+        let mut particle = None;
+        for child in body {
+            match child.local() {
+                "sequence" | "choice" | "all" | "group" if !simple_content && particle.is_none() =>
+                    particle = Some(try!(self.particle(child, doc))),
+                "attribute" | "attributeGroup" | "anyAttribute" =>
+                    try!(self.attribute_use(child, doc, &mut t.attributes, &mut t.attribute_groups, &mut t.any_attribute)),
+                local if simple_content && (local == "simpleType" || FACETS.contains(&local)) => {}
+                _ => return Err(child.error(format!("Unexpected element in complex type definition: {}", child.name)))
+            }
+        }
+        if let Some(particle) = particle {
+            t.content = Content::Elements(particle);
+        } else if t.mixed && !simple_content {
+            t.content = Content::Elements(Particle {
+                term: Term::Group(Compositor::Sequence, Vec::new()),
+                min_occurs: 1,
+                max_occurs: Some(1)
+            });
+        }
+        Ok(t)
+    }
+
+    fn simple_type(&mut self, node: &Node, doc: &Document, name: QName) -> Result<SimpleType> {
+        let derivation = match node.content().into_iter().next() {
+            Some(d) => d,
+            None => return Err(node.error("Simple type definition requires xs:restriction, xs:list or xs:union"))
+        };
+        let (variety, facets) = match derivation.local() {
+            "restriction" => {
+                let base = match derivation.attr("base") {
+                    Some(base) => try!(self.reference(Kind::Type, doc, derivation, base)),
+                    None => try!(self.inline_simple_type(derivation, doc))
+                };
+                let content = derivation.content();
+                for child in &content {
+                    if !child.is("simpleType") && !FACETS.contains(&child.local()) {
+                        return Err(child.error(format!("Unexpected element in restriction: {}", child.name)));
+                    }
+                }
+                (Variety::Restriction(base), try!(facets(&content)))
+            }
+            "list" => (Variety::List(match derivation.attr("itemType") {
+                Some(item) => try!(self.reference(Kind::Type, doc, derivation, item)),
+                None => try!(self.inline_simple_type(derivation, doc))
+            }), Vec::new()),
+            "union" => {
+                let mut members = Vec::new();
+                if let Some(types) = derivation.attr("memberTypes") {
+                    for member in types.split(is_whitespace_char).filter(|s| !s.is_empty()) {
+                        members.push(try!(self.reference(Kind::Type, doc, derivation, member)));
+                    }
+                }
+                for child in derivation.content() {
+                    if child.is("simpleType") {
+                        members.push(try!(self.anonymous_type(child, doc)));
+                    }
+                }
+                if members.is_empty() {
+                    return Err(derivation.error("xs:union requires member types"));
+                }
+                (Variety::Union(members), Vec::new())
+            }
+            _ => return Err(derivation.error(format!("Unexpected element in simple type definition: {}", derivation.name)))
+        };
+        Ok(SimpleType { name: name, variety: variety, facets: facets })
+    }
+
+    fn check_references(&self) -> Result<()> {
+        let c = &self.components;
+        for &(kind, ref name, ref location) in &self.references {
+            let defined = match kind {
+                Kind::Type => c.types.contains_key(name),
+                Kind::Element => c.elements.contains_key(name),
+                Kind::Attribute => c.attributes.contains_key(name),
+                Kind::Group => c.groups.contains_key(name),
+                Kind::AttributeGroup => c.attribute_groups.contains_key(name)
+            };
+            if !defined {
+                return Err(self.error_at(location, format!("Undefined {}: {}", kind.description(), name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the names of components of the given kind defined in the schema documents.
+    fn defined(&self, kind: Kind) -> Vec<QName> {
+        self.definitions.iter().filter(|&&(k, _)| k == kind).map(|&(_, ref name)| name.clone()).collect()
+    }
+
+    fn circular(&self, kind: Kind, name: &QName) -> reader::Error {
+        let msg = format!("Circular definition of {} {}", kind.description(), name);
+        match self.positions.get(&(kind, name.clone())) {
+            Some(location) => self.error_at(location, msg),
+            None => (&TextPosition::new(), msg).into()
+        }
+    }
+
+    /// Checks definitions for cycles and computes the effective attributes and content
+    /// of complex types.
+    fn complete(&mut self) -> Result<()> {
+        for name in self.defined(Kind::Group) {
+            try!(self.check_group(&self.components.groups[&name], &mut vec![name.clone()]));
+        }
+        for name in self.defined(Kind::Element) {
+            let decl = &self.components.elements[&name];
+            let mut seen = vec![name.clone()];
+            let mut head = decl.substitution_group.as_ref();
+            while let Some(h) = head {
+                if seen.contains(h) {
+                    return Err(self.circular(Kind::Element, &name));
+                }
+                seen.push(h.clone());
+                head = self.components.elements.get(h).and_then(|d| d.substitution_group.as_ref());
+            }
+        }
+        for name in self.defined(Kind::Type) {
+            let mut seen = vec![name.clone()];
+            let mut current = self.components.types.get(&name);
+            loop {
+                let base = match current {
+                    Some(&TypeDef::Simple(ref t)) => match t.variety {
+                        Variety::Restriction(ref base) => base,
+                        _ => break
+                    },
+                    Some(&TypeDef::Complex(ref t)) => match t.content {
+                        Content::Simple(ref base) => base,
+                        _ => break
+                    },
+                    None => break
+                };
+                if seen.contains(base) {
+                    return Err(self.circular(Kind::Type, &name));
+                }
+                seen.push(base.clone());
+                current = self.components.types.get(base);
+            }
+        }
+
+        let globals = self.components.attributes.clone();
+        for group in self.components.attribute_groups.values_mut() {
+            resolve_attribute_references(&mut group.0, &globals);
+        }
+        let mut done = HashSet::new();
+        for name in self.defined(Kind::Type) {
+            try!(self.complete_type(&name, &mut Vec::new(), &mut done));
+        }
+        Ok(())
+    }
+
+    fn check_group(&self, particle: &Particle, open: &mut Vec<QName>) -> Result<()> {
+        match particle.term {
+            Term::GroupRef(ref name) => {
+                if open.contains(name) {
+                    return Err(self.circular(Kind::Group, name));
+                }
+                open.push(name.clone());
+                try!(self.check_group(&self.components.groups[name], open));
+                open.pop();
+            }
+            Term::Group(_, ref particles) => for p in particles {
+                try!(self.check_group(p, open));
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn complete_type(&mut self, name: &QName, open: &mut Vec<QName>, done: &mut HashSet<QName>) -> Result<()> {
+        if done.contains(name) {
+            return Ok(());
+        }
+        if open.contains(name) {
+            return Err(self.circular(Kind::Type, name));
+        }
+        let t = match self.components.types.get(name) {
+            Some(&TypeDef::Complex(ref t)) => t.clone(),
+            _ => return Ok(())
+        };
+        open.push(name.clone());
+
+        let mut completed = (*t).clone();
+        let mut attributes = Vec::new();
+        let mut any = completed.any_attribute.take();
+        try!(self.expand_attribute_groups(&completed.attribute_groups, &mut attributes, &mut any, &mut Vec::new()));
+        attributes.extend(completed.attributes.drain(..));
+        resolve_attribute_references(&mut attributes, &self.components.attributes);
+
+        if let Some((ref base, method)) = t.base {
+            try!(self.complete_type(base, open, done));
+            if let Some(&TypeDef::Complex(ref b)) = self.components.types.get(base) {
+                let mut inherited: Vec<AttributeUse> = b.attributes.iter()
+                    .filter(|a| !attributes.iter().any(|own| own.name == a.name))
+                    .cloned()
+                    .collect();
+                inherited.extend(attributes);
+                attributes = inherited;
+                if method == Derivation::Extension {
+                    if any.is_none() {
+                        any = b.any_attribute.clone();
+                    }
+                    completed.content = match (b.content.clone(), completed.content) {
+                        (Content::Elements(base), Content::Elements(own)) => Content::Elements(Particle {
+                            term: Term::Group(Compositor::Sequence, vec![base, own]),
+                            min_occurs: 1,
+                            max_occurs: Some(1)
+                        }),
+                        (Content::Elements(base), Content::Empty) => Content::Elements(base),
+                        (_, own) => own
+                    };
+                    completed.mixed = completed.mixed || b.mixed;
+                }
+            }
+        }
+
+        attributes.retain(|a| !a.prohibited);
+        completed.attributes = attributes;
+        completed.attribute_groups = Vec::new();
+        completed.any_attribute = any;
+        self.components.types.insert(name.clone(), TypeDef::Complex(Rc::new(completed)));
+        open.pop();
+        done.insert(name.clone());
+        Ok(())
+    }
+
+    fn expand_attribute_groups(&self, groups: &[QName], uses: &mut Vec<AttributeUse>,
+                               any: &mut Option<Wildcard>, open: &mut Vec<QName>) -> Result<()> {
+        for name in groups {
+            if open.contains(name) {
+                return Err(self.circular(Kind::AttributeGroup, name));
+            }
+            let &(ref group_uses, ref nested, ref wildcard) = &self.components.attribute_groups[name];
+            open.push(name.clone());
+            try!(self.expand_attribute_groups(nested, uses, any, open));
+            open.pop();
+            uses.extend(group_uses.iter().cloned());
+            if any.is_none() {
+                *any = wildcard.clone();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copies types and value constraints of global attribute declarations into
+/// the uses referring to them.
+fn resolve_attribute_references(uses: &mut [AttributeUse], globals: &HashMap<QName, AttributeUse>) {
+    for u in uses.iter_mut().filter(|u| u.reference) {
+        if let Some(global) = globals.get(&u.name) {
+            u.type_name = global.type_name.clone();
+            if global.fixed.is_some() || u.default.is_none() && u.fixed.is_none() {
+                u.default = global.default.clone();
+                u.fixed = global.fixed.clone();
+            }
+        }
+        u.reference = false;
+    }
+}
+
+fn occurs(node: &Node) -> Result<(u32, Option<u32>)> {
+    let parse = |name: &str, value: &str| value.trim().parse().map_err(|_| {
+        node.error(format!("Invalid value of attribute {}: {}", name, value))
+    });
+    let min = match node.attr("minOccurs") {
+        Some(v) => try!(parse("minOccurs", v)),
+        None => 1
+    };
+    let max = match node.attr("maxOccurs") {
+        Some(v) if v.trim() == "unbounded" => None,
+        Some(v) => Some(try!(parse("maxOccurs", v))),
+        None => Some(1)
+    };
+    if max.map(|max| max < min).unwrap_or(false) {
+        return Err(node.error("maxOccurs must not be less than minOccurs"));
+    }
+    Ok((min, max))
+}
+
+fn wildcard(node: &Node, doc: &Document) -> Result<Wildcard> {
+    let namespaces = match node.attr("namespace").map(str::trim) {
+        None | Some("##any") => NamespaceConstraint::Any,
+        Some("##other") => NamespaceConstraint::Not(doc.target.clone()),
+        Some(list) => NamespaceConstraint::List(list.split(is_whitespace_char)
+            .filter(|s| !s.is_empty())
+            .map(|ns| match ns {
+                "##targetNamespace" => doc.target.clone(),
+                "##local" => None,
+                ns => Some(ns.to_owned())
+            })
+            .collect())
+    };
+    let process_contents = match node.attr("processContents") {
+        None | Some("strict") => ProcessContents::Strict,
+        Some("lax") => ProcessContents::Lax,
+        Some("skip") => ProcessContents::Skip,
+        Some(v) => return Err(node.error(format!("Invalid value of attribute processContents: {}", v)))
+    };
+    Ok(Wildcard { namespaces: namespaces, process_contents: process_contents })
+}
+
+/// Collects constraining facets from the given nodes, ignoring other nodes.
+fn facets(nodes: &[&Node]) -> Result<Vec<Facet>> {
+    let mut facets = Vec::new();
+    let mut patterns = Vec::new();
+    let mut values = Vec::new();
+    for node in nodes {
+        let local = node.local();
+        if !FACETS.contains(&local) {
+            continue;
+        }
+        let value = try!(node.required_attr("value"));
+        let number = || value.trim().parse().map_err(|_| {
+            node.error(format!("Invalid value of facet {}: {}", local, value))
+        });
+        facets.push(match local {
+            "length" => Facet::Length(try!(number())),
+            "minLength" => Facet::MinLength(try!(number())),
+            "maxLength" => Facet::MaxLength(try!(number())),
+            "totalDigits" => Facet::TotalDigits(try!(number())),
+            "fractionDigits" => Facet::FractionDigits(try!(number())),
+            "maxInclusive" => Facet::MaxInclusive(value.trim().to_owned()),
+            "maxExclusive" => Facet::MaxExclusive(value.trim().to_owned()),
+            "minInclusive" => Facet::MinInclusive(value.trim().to_owned()),
+            "minExclusive" => Facet::MinExclusive(value.trim().to_owned()),
+            "whiteSpace" => Facet::WhiteSpace(match value.trim() {
+                "preserve" => WhiteSpace::Preserve,
+                "replace" => WhiteSpace::Replace,
+                "collapse" => WhiteSpace::Collapse,
+                _ => return Err(node.error(format!("Invalid value of facet whiteSpace: {}", value)))
+            }),
+            "pattern" => {
+                patterns.push(try!(Regex::new(value).map_err(|e| node.error(e))));
+                continue;
+            }
+            _ => {
+                values.push(value.to_owned());
+                continue;
+            }
+        });
+    }
+    if !patterns.is_empty() {
+        facets.push(Facet::Pattern(patterns));
+    }
+    if !values.is_empty() {
+        facets.push(Facet::Enumeration(values));
+    }
+    Ok(facets)
+}
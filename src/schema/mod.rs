@@ -0,0 +1,138 @@
+//! Contains XML Schema validation of documents read with `EventReader`.
+//!
+//! A `Schema` is loaded from an XML Schema 1.0 document; the documents it includes or imports
+//! are loaded through an `EntityResolver`, which receives their `schemaLocation` exactly as it
+//! is written. A loaded schema checks a stream of events produced by `EventReader`:
+//!
+//! ```rust
+//! use xml::EventReader;
+//! use xml::schema::Schema;
+//!
+//! let schema = Schema::from_reader(r#"
+//!     <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+//!       <xs:element name="quantity">
+//!         <xs:simpleType>
+//!           <xs:restriction base="xs:positiveInteger">
+//!             <xs:maxExclusive value="100"/>
+//!           </xs:restriction>
+//!         </xs:simpleType>
+//!       </xs:element>
+//!     </xs:schema>
+//! "#.as_bytes()).unwrap();
+//!
+//! assert!(schema.validate(EventReader::from_str("<quantity>12</quantity>")).is_ok());
+//!
+//! let e = schema.validate(EventReader::from_str("<quantity>120</quantity>")).unwrap_err();
+//! assert_eq!(e.msg(), "Invalid content of element quantity: \"120\" does not satisfy the maxExclusive facet");
+//! ```
+//!
+//! Errors in the schema itself are reported as syntax errors at the position of the offending
+//! schema element, and violations of the schema in the document are reported with
+//! `ErrorKind::Validity` at the position of the event which violates it.
+//!
+//! Element and attribute declarations, complex types with simple, element-only, mixed and empty
+//! content, derivation by extension and restriction, model groups, wildcards, substitution
+//! groups, simple types with facets, lists and unions, `xsi:type` and `xsi:nil` are supported.
+//! Identity constraints and `xs:redefine` are not supported, restrictions are not checked
+//! against their base types, Unicode character categories in patterns are approximated with
+//! the classification available in the standard library, and dates and times are compared
+//! as strings.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use common::Position;
+use reader::{self, EventReader, XmlEvent, EntityResolver, DirectoryResolver};
+
+mod model;
+mod loader;
+mod types;
+mod regex;
+mod validator;
+mod datatypes;
+
+pub(crate) use self::datatypes::{Datatype, Datatypes};
+
+/// A loaded XML Schema.
+pub struct Schema {
+    components: model::Components
+}
+
+impl Schema {
+    /// Loads a schema from a single document; the document cannot include or import
+    /// other documents.
+    pub fn from_reader<R: Read>(source: R) -> reader::Result<Schema> {
+        loader::Loader::new(None).load(source).map(|c| Schema { components: c })
+    }
+
+    /// Loads a schema from a document, loading the documents it includes or imports
+    /// with the given resolver.
+    pub fn from_reader_with_resolver<R: Read, E: EntityResolver>(source: R, resolver: &E) -> reader::Result<Schema> {
+        loader::Loader::new(Some(resolver)).load(source).map(|c| Schema { components: c })
+    }
+
+    /// Loads a schema from a file; included and imported documents are loaded from
+    /// the directory containing the file, see `DirectoryResolver`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> reader::Result<Schema> {
+        let path = path.as_ref();
+        let file = try!(File::open(path));
+        let resolver = DirectoryResolver::new(path.parent().unwrap_or_else(|| Path::new(".")));
+        Schema::from_reader_with_resolver(file, &resolver)
+    }
+
+    /// Returns a validator which checks events of a single document.
+    ///
+    /// This is useful when events are processed as they are read; otherwise `validate()`
+    /// is simpler.
+    pub fn validator<'a>(&'a self) -> Validator<'a> {
+        Validator(validator::Validator::new(&self.components))
+    }
+
+    /// Reads the whole document and checks it against the schema.
+    ///
+    /// Returns the first well-formedness or validity error in the document.
+    pub fn validate<R: Read>(&self, mut reader: EventReader<R>) -> reader::Result<()> {
+        let mut validator = self.validator();
+        loop {
+            let event = try!(reader.next());
+            try!(validator.validate(&event, &reader));
+            if let XmlEvent::EndDocument = event {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Checks events of a document against a `Schema`.
+///
+/// ```rust
+/// use xml::EventReader;
+/// use xml::reader::XmlEvent;
+/// use xml::schema::Schema;
+///
+/// let schema = Schema::from_reader(r#"
+///     <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+///       <xs:element name="flag" type="xs:boolean"/>
+///     </xs:schema>
+/// "#.as_bytes()).unwrap();
+///
+/// let mut reader = EventReader::from_str("<flag>true</flag>");
+/// let mut validator = schema.validator();
+/// loop {
+///     let event = reader.next().unwrap();
+///     validator.validate(&event, &reader).unwrap();
+///     if event == XmlEvent::EndDocument {
+///         break;
+///     }
+/// }
+/// ```
+pub struct Validator<'a>(validator::Validator<'a>);
+
+impl<'a> Validator<'a> {
+    /// Checks the next event of the document; `pos` is used as the position of errors,
+    /// and it is usually the reader which produced the event.
+    pub fn validate<P: Position>(&mut self, event: &XmlEvent, pos: &P) -> reader::Result<()> {
+        self.0.validate(event, pos)
+    }
+}
@@ -0,0 +1,242 @@
+//! Contains the in-memory model of schema components.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use schema::regex::Regex;
+use schema::types::Builtin;
+
+/// The namespace of XML Schema components.
+pub const XS_NAMESPACE: &'static str = "http://www.w3.org/2001/XMLSchema";
+
+/// The namespace of attributes controlling validation of instance documents.
+pub const XSI_NAMESPACE: &'static str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// An expanded name of a schema component or an instance element.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct QName {
+    pub namespace: Option<String>,
+    pub local: String
+}
+
+impl QName {
+    pub fn new<S: Into<String>>(namespace: Option<&str>, local: S) -> QName {
+        QName {
+            namespace: namespace.and_then(|ns| if ns.is_empty() { None } else { Some(ns.into()) }),
+            local: local.into()
+        }
+    }
+
+    /// Creates a name in the XML Schema namespace.
+    pub fn xs(local: &str) -> QName {
+        QName::new(Some(XS_NAMESPACE), local)
+    }
+}
+
+impl fmt::Display for QName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.namespace {
+            Some(ref ns) => write!(f, "{{{}}}{}", ns, self.local),
+            None => write!(f, "{}", self.local)
+        }
+    }
+}
+
+/// An element declaration.
+#[derive(Debug)]
+pub struct ElementDecl {
+    pub name: QName,
+    /// The type of the element; `None` means the type of the substitution group head,
+    /// or `xs:anyType` if there is no substitution group.
+    pub type_name: Option<QName>,
+    pub nillable: bool,
+    pub is_abstract: bool,
+    pub default: Option<String>,
+    pub fixed: Option<String>,
+    pub substitution_group: Option<QName>
+}
+
+/// An attribute declaration together with the way it is used by a complex type.
+#[derive(Clone, Debug)]
+pub struct AttributeUse {
+    pub name: QName,
+    pub type_name: QName,
+    pub required: bool,
+    pub prohibited: bool,
+    pub default: Option<String>,
+    pub fixed: Option<String>,
+    /// Whether this use refers to a global attribute declaration, whose type and value
+    /// constraints are copied when the schema is loaded.
+    pub reference: bool
+}
+
+/// A namespace constraint of a wildcard.
+#[derive(Clone, PartialEq, Debug)]
+pub enum NamespaceConstraint {
+    Any,
+    /// Any namespace except the given one and no namespace, i.e. `##other`.
+    Not(Option<String>),
+    List(Vec<Option<String>>)
+}
+
+/// How elements and attributes matched by a wildcard are validated.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ProcessContents {
+    Strict,
+    Lax,
+    Skip
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Wildcard {
+    pub namespaces: NamespaceConstraint,
+    pub process_contents: ProcessContents
+}
+
+impl Wildcard {
+    pub fn allows(&self, namespace: Option<&str>) -> bool {
+        match self.namespaces {
+            NamespaceConstraint::Any => true,
+            NamespaceConstraint::Not(ref ns) => namespace.is_some() && namespace != ns.as_ref().map(|s| &s[..]),
+            NamespaceConstraint::List(ref list) => list.iter().any(|ns| ns.as_ref().map(|s| &s[..]) == namespace)
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Compositor {
+    Sequence,
+    Choice,
+    All
+}
+
+#[derive(Clone, Debug)]
+pub enum Term {
+    Element(Rc<ElementDecl>),
+    /// A reference to a global element declaration.
+    ElementRef(QName),
+    Wildcard(Rc<Wildcard>),
+    Group(Compositor, Vec<Particle>),
+    /// A reference to a named model group.
+    GroupRef(QName)
+}
+
+#[derive(Clone, Debug)]
+pub struct Particle {
+    pub term: Term,
+    pub min_occurs: u32,
+    /// `None` means `unbounded`.
+    pub max_occurs: Option<u32>
+}
+
+#[derive(Clone, Debug)]
+pub enum Content {
+    Empty,
+    /// Simple content of the given simple type.
+    Simple(QName),
+    Elements(Particle)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Derivation {
+    Extension,
+    Restriction
+}
+
+#[derive(Clone, Debug)]
+pub struct ComplexType {
+    pub name: QName,
+    pub base: Option<(QName, Derivation)>,
+    pub is_abstract: bool,
+    pub mixed: bool,
+    /// The content type; after the schema is loaded, it includes the content inherited
+    /// from the base type.
+    pub content: Content,
+    /// Attribute uses; after the schema is loaded, they include the attributes inherited
+    /// from the base type and attribute groups.
+    pub attributes: Vec<AttributeUse>,
+    pub attribute_groups: Vec<QName>,
+    pub any_attribute: Option<Wildcard>
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum WhiteSpace {
+    Preserve,
+    Replace,
+    Collapse
+}
+
+#[derive(Clone, Debug)]
+pub enum Facet {
+    Length(usize),
+    MinLength(usize),
+    MaxLength(usize),
+    /// Patterns specified in the same derivation step, any of which must match.
+    Pattern(Vec<Regex>),
+    Enumeration(Vec<String>),
+    WhiteSpace(WhiteSpace),
+    MaxInclusive(String),
+    MaxExclusive(String),
+    MinInclusive(String),
+    MinExclusive(String),
+    TotalDigits(usize),
+    FractionDigits(usize)
+}
+
+#[derive(Clone, Debug)]
+pub enum Variety {
+    Builtin(Builtin),
+    Restriction(QName),
+    List(QName),
+    Union(Vec<QName>)
+}
+
+#[derive(Clone, Debug)]
+pub struct SimpleType {
+    pub name: QName,
+    pub variety: Variety,
+    pub facets: Vec<Facet>
+}
+
+#[derive(Clone, Debug)]
+pub enum TypeDef {
+    Simple(Rc<SimpleType>),
+    Complex(Rc<ComplexType>)
+}
+
+impl TypeDef {
+    pub fn name(&self) -> &QName {
+        match *self {
+            TypeDef::Simple(ref t) => &t.name,
+            TypeDef::Complex(ref t) => &t.name
+        }
+    }
+
+    /// Returns the name of the type this one is derived from, or `None` for `xs:anyType`.
+    pub fn base(&self) -> Option<QName> {
+        match *self {
+            TypeDef::Simple(ref t) => match t.variety {
+                Variety::Restriction(ref base) => Some(base.clone()),
+                Variety::Builtin(Builtin::AnySimpleType) => Some(QName::xs("anyType")),
+                Variety::Builtin(b) => b.base().map(|b| QName::xs(b.name())),
+                _ => Some(QName::xs("anySimpleType"))
+            },
+            TypeDef::Complex(ref t) => t.base.as_ref().map(|&(ref base, _)| base.clone())
+        }
+    }
+}
+
+/// All components of a schema, keyed by their names.
+#[derive(Default)]
+pub struct Components {
+    pub elements: HashMap<QName, Rc<ElementDecl>>,
+    pub attributes: HashMap<QName, AttributeUse>,
+    pub types: HashMap<QName, TypeDef>,
+    pub groups: HashMap<QName, Particle>,
+    pub attribute_groups: HashMap<QName, AttributeGroup>
+}
+
+/// Attribute uses, references to other attribute groups and the attribute wildcard
+/// of an attribute group.
+pub type AttributeGroup = (Vec<AttributeUse>, Vec<QName>, Option<Wildcard>);
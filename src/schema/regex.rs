@@ -0,0 +1,538 @@
+//! Contains an implementation of regular expressions used by `pattern` facets.
+//!
+//! XML Schema regular expressions are always matched against the whole value, and they
+//! have no anchors, backreferences or non-greedy quantifiers, so they are compiled to
+//! a program which is run on all alternatives at once, as a Pike VM does. Matching takes
+//! time linear in the length of the value and does not recurse.
+
+use std::fmt;
+use std::mem;
+
+use common::{is_whitespace_char, is_name_start_char, is_name_char};
+
+/// A compiled XML Schema regular expression.
+#[derive(Clone)]
+pub struct Regex {
+    source: String,
+    program: Vec<Inst>
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Regex({:?})", self.source)
+    }
+}
+
+type Alternation = Vec<Vec<Piece>>;
+
+#[derive(Clone)]
+struct Piece {
+    atom: Atom,
+    min: u32,
+    max: Option<u32>
+}
+
+#[derive(Clone)]
+enum Atom {
+    Class(CharClass),
+    Group(Alternation)
+}
+
+#[derive(Clone)]
+struct CharClass {
+    negated: bool,
+    items: Vec<ClassItem>,
+    subtracted: Option<Box<CharClass>>
+}
+
+#[derive(Clone)]
+enum ClassItem {
+    Range(char, char),
+    /// A character property and whether it is negated.
+    Property(fn(char) -> bool, bool)
+}
+
+impl CharClass {
+    fn single(item: ClassItem) -> CharClass {
+        CharClass { negated: false, items: vec![item], subtracted: None }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        let matched = self.items.iter().any(|item| match *item {
+            ClassItem::Range(from, to) => from <= c && c <= to,
+            ClassItem::Property(p, negated) => p(c) != negated
+        });
+        matched != self.negated && !self.subtracted.as_ref().map(|s| s.matches(c)).unwrap_or(false)
+    }
+}
+
+/// A part of a character class expression.
+enum Escape {
+    Char(char),
+    Item(ClassItem)
+}
+
+impl Regex {
+    /// Compiles the given regular expression.
+    pub fn new(source: &str) -> Result<Regex, String> {
+        let mut parser = Parser { chars: source.chars().collect(), pos: 0 };
+        let root = try!(parser.parse_alternation());
+        if let Some(c) = parser.peek() {
+            return Err(format!("Unexpected character in pattern {}: {}", source, c));
+        }
+        let mut program = Vec::new();
+        if compile_alternation(&root, &mut program).is_err() {
+            return Err(format!("Pattern is too large: {}", source));
+        }
+        program.push(Inst::Match);
+        Ok(Regex { source: source.into(), program: program })
+    }
+
+    /// Returns true if the regular expression matches the whole string.
+    pub fn is_match(&self, s: &str) -> bool {
+        // `seen[pc]` is the step at which the instruction was last added, so the sets of
+        // threads do not need to be cleared
+        let mut seen = vec![0; self.program.len()];
+        let mut threads = Vec::new();
+        let mut next = Vec::new();
+        let mut stack = Vec::new();
+        self.add_thread(&mut threads, &mut seen, &mut stack, 1, 0);
+        for (step, c) in s.chars().enumerate() {
+            if threads.is_empty() {
+                return false;
+            }
+            for &pc in &threads {
+                if let Inst::Class(ref class) = self.program[pc] {
+                    if class.matches(c) {
+                        self.add_thread(&mut next, &mut seen, &mut stack, step + 2, pc + 1);
+                    }
+                }
+            }
+            mem::swap(&mut threads, &mut next);
+            next.clear();
+        }
+        threads.iter().any(|&pc| match self.program[pc] {
+            Inst::Match => true,
+            _ => false
+        })
+    }
+
+    /// Adds the instructions matching a character or the end of the string which are
+    /// reachable from `pc` to the threads of the step.
+    fn add_thread(&self, threads: &mut Vec<usize>, seen: &mut [usize], stack: &mut Vec<usize>,
+                  step: usize, pc: usize) {
+        stack.push(pc);
+        while let Some(pc) = stack.pop() {
+            if seen[pc] == step {
+                continue;
+            }
+            seen[pc] = step;
+            match self.program[pc] {
+                Inst::Split(a, b) => {
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Inst::Jump(a) => stack.push(a),
+                Inst::Class(_) | Inst::Match => threads.push(pc)
+            }
+        }
+    }
+}
+
+/// An instruction of a compiled regular expression.
+#[derive(Clone)]
+enum Inst {
+    /// Matches a character of the class and continues with the next instruction.
+    Class(CharClass),
+    /// Continues with both instructions.
+    Split(usize, usize),
+    Jump(usize),
+    Match
+}
+
+/// The most instructions of a compiled regular expression; counted repetitions are
+/// expanded, so they could make the program very large.
+const MAX_PROGRAM_LEN: usize = 100000;
+
+fn compile_alternation(alternation: &Alternation, program: &mut Vec<Inst>) -> Result<(), ()> {
+    let mut jumps = Vec::new();
+    for (i, branch) in alternation.iter().enumerate() {
+        if i + 1 == alternation.len() {
+            try!(compile_branch(branch, program));
+            break;
+        }
+        let split = program.len();
+        program.push(Inst::Split(split + 1, 0));
+        try!(compile_branch(branch, program));
+        jumps.push(program.len());
+        program.push(Inst::Jump(0));
+        program[split] = Inst::Split(split + 1, program.len());
+    }
+    let end = program.len();
+    for jump in jumps {
+        program[jump] = Inst::Jump(end);
+    }
+    Ok(())
+}
+
+fn compile_branch(pieces: &[Piece], program: &mut Vec<Inst>) -> Result<(), ()> {
+    for piece in pieces {
+        for _ in 0..piece.min {
+            try!(compile_atom(&piece.atom, program));
+        }
+        match piece.max {
+            None => {
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                try!(compile_atom(&piece.atom, program));
+                program.push(Inst::Jump(split));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            Some(max) => {
+                let mut splits = Vec::new();
+                for _ in piece.min..max {
+                    splits.push(program.len());
+                    program.push(Inst::Split(0, 0));
+                    try!(compile_atom(&piece.atom, program));
+                }
+                let end = program.len();
+                for split in splits {
+                    program[split] = Inst::Split(split + 1, end);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn compile_atom(atom: &Atom, program: &mut Vec<Inst>) -> Result<(), ()> {
+    if program.len() > MAX_PROGRAM_LEN {
+        return Err(());
+    }
+    match *atom {
+        Atom::Class(ref class) => program.push(Inst::Class(class.clone())),
+        Atom::Group(ref alternation) => try!(compile_alternation(alternation, program))
+    }
+    Ok(())
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn looking_at(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        match self.peek() {
+            Some(c) => {
+                self.pos += 1;
+                Ok(c)
+            }
+            None => Err(self.error("Unexpected end of pattern"))
+        }
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("{}: {}", msg, self.chars.iter().cloned().collect::<String>())
+    }
+
+    fn parse_alternation(&mut self) -> Result<Alternation, String> {
+        let mut branches = vec![try!(self.parse_branch())];
+        while self.eat('|') {
+            branches.push(try!(self.parse_branch()));
+        }
+        Ok(branches)
+    }
+
+    fn parse_branch(&mut self) -> Result<Vec<Piece>, String> {
+        let mut pieces = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = try!(self.parse_atom());
+            let (min, max) = try!(self.parse_quantifier());
+            pieces.push(Piece { atom: atom, min: min, max: max });
+        }
+        Ok(pieces)
+    }
+
+    fn parse_quantifier(&mut self) -> Result<(u32, Option<u32>), String> {
+        if self.eat('?') {
+            Ok((0, Some(1)))
+        } else if self.eat('*') {
+            Ok((0, None))
+        } else if self.eat('+') {
+            Ok((1, None))
+        } else if self.eat('{') {
+            let min = try!(self.parse_number());
+            let max = if self.eat(',') {
+                if self.peek() == Some('}') { None } else { Some(try!(self.parse_number())) }
+            } else {
+                Some(min)
+            };
+            if !self.eat('}') || max.map(|max| max < min).unwrap_or(false) {
+                return Err(self.error("Invalid quantifier in pattern"));
+            }
+            Ok((min, max))
+        } else {
+            Ok((1, Some(1)))
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<u32, String> {
+        let start = self.pos;
+        while self.peek().map(|c| c.is_digit(10)).unwrap_or(false) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().cloned().collect();
+        digits.parse().map_err(|_| self.error("Invalid quantifier in pattern"))
+    }
+
+    fn parse_atom(&mut self) -> Result<Atom, String> {
+        match try!(self.next()) {
+            '(' => {
+                let alternation = try!(self.parse_alternation());
+                if !self.eat(')') {
+                    return Err(self.error("Unclosed group in pattern"));
+                }
+                Ok(Atom::Group(alternation))
+            }
+            '[' => Ok(Atom::Class(try!(self.parse_class_expression()))),
+            '.' => Ok(Atom::Class(CharClass {
+                negated: true,
+                items: vec![ClassItem::Range('\n', '\n'), ClassItem::Range('\r', '\r')],
+                subtracted: None
+            })),
+            '\\' => Ok(Atom::Class(CharClass::single(match try!(self.parse_escape()) {
+                Escape::Char(c) => ClassItem::Range(c, c),
+                Escape::Item(item) => item
+            }))),
+            c @ '?' | c @ '*' | c @ '+' | c @ '{' | c @ '}' | c @ ']' =>
+                Err(self.error(&format!("Unexpected character {} in pattern", c))),
+            c => Ok(Atom::Class(CharClass::single(ClassItem::Range(c, c))))
+        }
+    }
+
+    /// Parses a character class expression after the opening bracket.
+    fn parse_class_expression(&mut self) -> Result<CharClass, String> {
+        let negated = self.eat('^');
+        let mut class = CharClass { negated: negated, items: Vec::new(), subtracted: None };
+        loop {
+            if self.peek().is_none() {
+                return Err(self.error("Unclosed character class in pattern"));
+            }
+            if self.looking_at("-[") {
+                self.pos += 2;
+                class.subtracted = Some(Box::new(try!(self.parse_class_expression())));
+                if !self.eat(']') {
+                    return Err(self.error("Unclosed character class in pattern"));
+                }
+                break;
+            }
+            if self.eat(']') {
+                if class.items.is_empty() {
+                    return Err(self.error("Empty character class in pattern"));
+                }
+                break;
+            }
+            let from = match try!(self.next()) {
+                '\\' => match try!(self.parse_escape()) {
+                    Escape::Char(c) => c,
+                    Escape::Item(item) => {
+                        class.items.push(item);
+                        continue;
+                    }
+                },
+                '[' => return Err(self.error("Unexpected [ in character class in pattern")),
+                c => c
+            };
+            if self.peek() == Some('-') && !self.looking_at("-[") && !self.looking_at("-]") {
+                self.pos += 1;
+                let to = match try!(self.next()) {
+                    '\\' => match try!(self.parse_escape()) {
+                        Escape::Char(c) => c,
+                        Escape::Item(_) => return Err(self.error("Invalid character range in pattern"))
+                    },
+                    c => c
+                };
+                if to < from {
+                    return Err(self.error("Invalid character range in pattern"));
+                }
+                class.items.push(ClassItem::Range(from, to));
+            } else {
+                class.items.push(ClassItem::Range(from, from));
+            }
+        }
+        Ok(class)
+    }
+
+    /// Parses an escape after the backslash.
+    fn parse_escape(&mut self) -> Result<Escape, String> {
+        let c = try!(self.next());
+        let (property, negated): (fn(char) -> bool, bool) = match c {
+            'n' => return Ok(Escape::Char('\n')),
+            'r' => return Ok(Escape::Char('\r')),
+            't' => return Ok(Escape::Char('\t')),
+            '\\' | '|' | '.' | '-' | '^' | '?' | '*' | '+' | '{' | '}' | '(' | ')' | '[' | ']' =>
+                return Ok(Escape::Char(c)),
+            's' | 'S' => (is_whitespace_char, c == 'S'),
+            'i' | 'I' => (is_name_start_char, c == 'I'),
+            'c' | 'C' => (is_name_char, c == 'C'),
+            'd' | 'D' => (is_decimal_digit, c == 'D'),
+            'w' | 'W' => (is_word_char, c == 'W'),
+            'p' | 'P' => {
+                if !self.eat('{') {
+                    return Err(self.error("Invalid character property in pattern"));
+                }
+                let mut name = String::new();
+                loop {
+                    match try!(self.next()) {
+                        '}' => break,
+                        c => name.push(c)
+                    }
+                }
+                match category(&name) {
+                    Some(property) => (property, c == 'P'),
+                    None => return Err(self.error(&format!("Unsupported character property {} in pattern", name)))
+                }
+            }
+            _ => return Err(self.error(&format!("Invalid escape \\{} in pattern", c)))
+        };
+        Ok(Escape::Item(ClassItem::Property(property, negated)))
+    }
+}
+
+/// Returns a predicate for a Unicode general category or block name.
+///
+/// Only the commonly used categories are supported, and some of them are approximated
+/// with the character classification available in the standard library.
+fn category(name: &str) -> Option<fn(char) -> bool> {
+    Some(match name {
+        "L" | "Lo" | "Lm" => char::is_alphabetic,
+        "Lu" | "Lt" => char::is_uppercase,
+        "Ll" => char::is_lowercase,
+        "N" | "No" | "Nl" => char::is_numeric,
+        "Nd" => is_decimal_digit,
+        "P" | "Pc" | "Pd" | "Ps" | "Pe" | "Pi" | "Pf" | "Po" => is_punctuation,
+        "S" | "Sm" | "Sc" | "Sk" | "So" => is_symbol,
+        "Z" | "Zs" | "Zl" | "Zp" => is_separator,
+        "C" | "Cc" => char::is_control,
+        "IsBasicLatin" => is_basic_latin,
+        "IsLatin-1Supplement" => is_latin1_supplement,
+        _ => return None
+    })
+}
+
+fn is_decimal_digit(c: char) -> bool {
+    c.is_digit(10) || !c.is_ascii() && c.is_numeric()
+}
+
+fn is_punctuation(c: char) -> bool {
+    match c {
+        '!' | '"' | '#' | '%' | '&' | '\'' | '(' | ')' | '*' | ',' | '-' | '.' | '/' | ':' | ';' |
+        '?' | '@' | '[' | '\\' | ']' | '_' | '{' | '}' => true,
+        '\u{A1}' | '\u{A7}' | '\u{AB}' | '\u{B6}' | '\u{B7}' | '\u{BB}' | '\u{BF}' => true,
+        '\u{2010}'...'\u{2027}' | '\u{2030}'...'\u{205E}' | '\u{3001}'...'\u{3003}' => true,
+        _ => false
+    }
+}
+
+fn is_symbol(c: char) -> bool {
+    match c {
+        '$' | '+' | '<' | '=' | '>' | '^' | '`' | '|' | '~' => true,
+        _ => !c.is_ascii() && !c.is_alphanumeric() && !c.is_whitespace() && !c.is_control() && !is_punctuation(c)
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() && !c.is_control()
+}
+
+fn is_word_char(c: char) -> bool {
+    !(is_punctuation(c) || is_separator(c) || c.is_control())
+}
+
+fn is_basic_latin(c: char) -> bool {
+    c <= '\u{7F}'
+}
+
+fn is_latin1_supplement(c: char) -> bool {
+    match c {
+        '\u{80}'...'\u{FF}' => true,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+
+    fn matches(pattern: &str, s: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(s)
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(matches("[A-Z]{2}-\\d{4}", "AB-1234"));
+        assert!(!matches("[A-Z]{2}-\\d{4}", "AB-12345"));
+        assert!(!matches("[A-Z]{2}-\\d{4}", "xAB-1234"));
+        assert!(matches("(ab|cd)*e?", ""));
+        assert!(matches("(ab|cd)*e?", "abcdabe"));
+        assert!(!matches("(ab|cd)*e?", "abc"));
+        assert!(matches("a{2,}b{0,1}", "aaab"));
+        assert!(!matches("a{2,}b{0,1}", "ab"));
+        assert!(matches("[^\\s]+", "no-spaces"));
+        assert!(!matches("[^\\s]+", "a space"));
+        assert!(matches("[a-z-[aeiou]]+", "xyz"));
+        assert!(!matches("[a-z-[aeiou]]+", "xaz"));
+        assert!(matches("\\i\\c*", "xs:name-1"));
+        assert!(matches("\\p{Lu}\\p{Ll}+", "Éclair"));
+        assert!(matches(".+\\.xml", "a.xml"));
+        assert!(!matches(".+\\.xml", "axml"));
+        assert!(matches("(a?){3}", ""));
+        assert!(matches("[+\\-]?\\d+", "-12"));
+    }
+
+    #[test]
+    fn long_values() {
+        let value: String = (0..100000).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        assert!(matches("[a-z]*", &value));
+        assert!(!matches("[a-z]*", &(value.clone() + "0")));
+        assert!(matches("([a-z]|\\d)+", &value));
+    }
+
+    #[test]
+    fn ambiguous_patterns() {
+        let value = "a".repeat(30);
+        assert!(!matches("(a*)*b", &value));
+        assert!(matches("(a*)*b", &(value.clone() + "b")));
+        assert!(!matches("(a|aa)+(a|aa)+c", &value));
+        assert!(!matches("(a?){30}a{30}", &value[1..]));
+        assert!(matches("(a?){30}a{30}", &(value.clone() + &value)));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in &["(a", "a{2,1}", "[]", "*a", "\\q", "[b-a]", "\\p{Foo}", "((a{1000}){1000}){1000}"] {
+            assert!(Regex::new(pattern).is_err(), "{}", pattern);
+        }
+    }
+}
@@ -0,0 +1,685 @@
+//! Contains built-in simple types and validation of values of simple types.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use common::{is_whitespace_char, is_name_start_char, is_name_char};
+use namespace::Namespace;
+use schema::model::{Components, QName, TypeDef, Content, SimpleType, Variety, Facet, WhiteSpace};
+
+/// A built-in simple type.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Builtin {
+    AnySimpleType,
+    String,
+    NormalizedString,
+    Token,
+    Language,
+    Name,
+    NcName,
+    Id,
+    IdRef,
+    IdRefs,
+    Entity,
+    Entities,
+    NmToken,
+    NmTokens,
+    Boolean,
+    Decimal,
+    Integer,
+    NonPositiveInteger,
+    NegativeInteger,
+    Long,
+    Int,
+    Short,
+    Byte,
+    NonNegativeInteger,
+    UnsignedLong,
+    UnsignedInt,
+    UnsignedShort,
+    UnsignedByte,
+    PositiveInteger,
+    Float,
+    Double,
+    Duration,
+    DateTime,
+    Time,
+    Date,
+    GYearMonth,
+    GYear,
+    GMonthDay,
+    GDay,
+    GMonth,
+    HexBinary,
+    Base64Binary,
+    AnyUri,
+    QName,
+    Notation
+}
+
+/// All built-in simple types with their names in the XML Schema namespace.
+pub static BUILTINS: &'static [(&'static str, Builtin)] = &[
+    ("anySimpleType", Builtin::AnySimpleType),
+    ("string", Builtin::String),
+    ("normalizedString", Builtin::NormalizedString),
+    ("token", Builtin::Token),
+    ("language", Builtin::Language),
+    ("Name", Builtin::Name),
+    ("NCName", Builtin::NcName),
+    ("ID", Builtin::Id),
+    ("IDREF", Builtin::IdRef),
+    ("IDREFS", Builtin::IdRefs),
+    ("ENTITY", Builtin::Entity),
+    ("ENTITIES", Builtin::Entities),
+    ("NMTOKEN", Builtin::NmToken),
+    ("NMTOKENS", Builtin::NmTokens),
+    ("boolean", Builtin::Boolean),
+    ("decimal", Builtin::Decimal),
+    ("integer", Builtin::Integer),
+    ("nonPositiveInteger", Builtin::NonPositiveInteger),
+    ("negativeInteger", Builtin::NegativeInteger),
+    ("long", Builtin::Long),
+    ("int", Builtin::Int),
+    ("short", Builtin::Short),
+    ("byte", Builtin::Byte),
+    ("nonNegativeInteger", Builtin::NonNegativeInteger),
+    ("unsignedLong", Builtin::UnsignedLong),
+    ("unsignedInt", Builtin::UnsignedInt),
+    ("unsignedShort", Builtin::UnsignedShort),
+    ("unsignedByte", Builtin::UnsignedByte),
+    ("positiveInteger", Builtin::PositiveInteger),
+    ("float", Builtin::Float),
+    ("double", Builtin::Double),
+    ("duration", Builtin::Duration),
+    ("dateTime", Builtin::DateTime),
+    ("time", Builtin::Time),
+    ("date", Builtin::Date),
+    ("gYearMonth", Builtin::GYearMonth),
+    ("gYear", Builtin::GYear),
+    ("gMonthDay", Builtin::GMonthDay),
+    ("gDay", Builtin::GDay),
+    ("gMonth", Builtin::GMonth),
+    ("hexBinary", Builtin::HexBinary),
+    ("base64Binary", Builtin::Base64Binary),
+    ("anyURI", Builtin::AnyUri),
+    ("QName", Builtin::QName),
+    ("NOTATION", Builtin::Notation),
+];
+
+impl Builtin {
+    /// Returns the local name of the type.
+    pub fn name(self) -> &'static str {
+        BUILTINS.iter().find(|&&(_, b)| b == self).map(|&(name, _)| name).unwrap()
+    }
+
+    /// Returns the type this one is derived from, or `None` for `anySimpleType`.
+    pub fn base(self) -> Option<Builtin> {
+        use self::Builtin::*;
+        Some(match self {
+            AnySimpleType => return None,
+            NormalizedString => String,
+            Token => NormalizedString,
+            Language | Name | NmToken => Token,
+            NcName => Name,
+            Id | IdRef | Entity => NcName,
+            Integer => Decimal,
+            NonPositiveInteger | Long | NonNegativeInteger => Integer,
+            NegativeInteger => NonPositiveInteger,
+            Int => Long,
+            Short => Int,
+            Byte => Short,
+            UnsignedLong | PositiveInteger => NonNegativeInteger,
+            UnsignedInt => UnsignedLong,
+            UnsignedShort => UnsignedInt,
+            UnsignedByte => UnsignedShort,
+            _ => AnySimpleType
+        })
+    }
+
+    /// Returns the primitive type this type is derived from.
+    fn primitive(self) -> Builtin {
+        let mut t = self;
+        while let Some(base) = t.base() {
+            if base == Builtin::AnySimpleType {
+                break;
+            }
+            t = base;
+        }
+        t
+    }
+
+    fn is_list(self) -> bool {
+        match self {
+            Builtin::IdRefs | Builtin::Entities | Builtin::NmTokens => true,
+            _ => false
+        }
+    }
+
+    fn white_space(self) -> WhiteSpace {
+        match self {
+            Builtin::AnySimpleType | Builtin::String => WhiteSpace::Preserve,
+            Builtin::NormalizedString => WhiteSpace::Replace,
+            _ => WhiteSpace::Collapse
+        }
+    }
+
+    /// Returns the bounds of integer types.
+    fn integer_range(self) -> (Option<&'static str>, Option<&'static str>) {
+        use self::Builtin::*;
+        match self {
+            NonPositiveInteger => (None, Some("0")),
+            NegativeInteger => (None, Some("-1")),
+            Long => (Some("-9223372036854775808"), Some("9223372036854775807")),
+            Int => (Some("-2147483648"), Some("2147483647")),
+            Short => (Some("-32768"), Some("32767")),
+            Byte => (Some("-128"), Some("127")),
+            NonNegativeInteger => (Some("0"), None),
+            UnsignedLong => (Some("0"), Some("18446744073709551615")),
+            UnsignedInt => (Some("0"), Some("4294967295")),
+            UnsignedShort => (Some("0"), Some("65535")),
+            UnsignedByte => (Some("0"), Some("255")),
+            PositiveInteger => (Some("1"), None),
+            _ => (None, None)
+        }
+    }
+
+    /// Checks that a whitespace-normalized value belongs to the lexical space of the type.
+    fn check(self, value: &str, namespace: &Namespace) -> bool {
+        use self::Builtin::*;
+        match self {
+            AnySimpleType | String | NormalizedString | Token | AnyUri => true,
+            Language => value.split('-').enumerate().all(|(i, part)| {
+                !part.is_empty() && part.len() <= 8 &&
+                    part.chars().all(|c| c.is_ascii_alphabetic() || i > 0 && c.is_ascii_digit())
+            }),
+            Name => is_name(value),
+            NcName | Id | IdRef | Entity => is_ncname(value),
+            NmToken => !value.is_empty() && value.chars().all(is_name_char),
+            IdRefs | Entities | NmTokens => {
+                let item = if self == NmTokens { NmToken } else { NcName };
+                !value.is_empty() && value.split(' ').all(|v| item.check(v, namespace))
+            }
+            Boolean => value == "true" || value == "false" || value == "1" || value == "0",
+            Decimal => parse_decimal(value).is_some(),
+            Float | Double => parse_float(value).is_some(),
+            Duration => is_duration(value),
+            DateTime => split_timezone(value)
+                .and_then(date)
+                .and_then(|s| expect(s, "T"))
+                .and_then(time)
+                .map(str::is_empty).unwrap_or(false),
+            Time => split_timezone(value).and_then(time).map(str::is_empty).unwrap_or(false),
+            Date => split_timezone(value).and_then(date).map(str::is_empty).unwrap_or(false),
+            GYearMonth => split_timezone(value)
+                .and_then(year)
+                .and_then(|(_, s)| expect(s, "-"))
+                .and_then(|s| number(s, 1, 12))
+                .map(|(_, s)| s.is_empty()).unwrap_or(false),
+            GYear => split_timezone(value).and_then(year).map(|(_, s)| s.is_empty()).unwrap_or(false),
+            GMonthDay => split_timezone(value)
+                .and_then(|s| expect(s, "--"))
+                .and_then(|s| number(s, 1, 12))
+                .and_then(|(m, s)| expect(s, "-").and_then(|s| number(s, 1, days_in_month(2000, m))))
+                .map(|(_, s)| s.is_empty()).unwrap_or(false),
+            GDay => split_timezone(value)
+                .and_then(|s| expect(s, "---"))
+                .and_then(|s| number(s, 1, 31))
+                .map(|(_, s)| s.is_empty()).unwrap_or(false),
+            GMonth => split_timezone(value)
+                .and_then(|s| expect(s, "--"))
+                .and_then(|s| number(s, 1, 12))
+                .map(|(_, s)| s.is_empty()).unwrap_or(false),
+            HexBinary => value.len() & 1 == 0 && value.chars().all(|c| c.is_digit(16)),
+            Base64Binary => base64_length(value).is_some(),
+            QName | Notation => {
+                let mut parts = value.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(local), None) => is_ncname(local),
+                    (Some(prefix), Some(local)) =>
+                        is_ncname(prefix) && is_ncname(local) && namespace.get(prefix).is_some(),
+                    _ => false
+                }
+            }
+            _ => match parse_decimal(value) {
+                Some(d) if !value.contains('.') => {
+                    let (min, max) = self.integer_range();
+                    min.map(|min| cmp_decimal(&d, &parse_decimal(min).unwrap()) != Ordering::Less).unwrap_or(true) &&
+                        max.map(|max| cmp_decimal(&d, &parse_decimal(max).unwrap()) != Ordering::Greater).unwrap_or(true)
+                }
+                _ => false
+            }
+        }
+    }
+}
+
+/// Adds definitions of all built-in simple types to the components.
+pub fn add_builtins(components: &mut Components) {
+    for &(name, builtin) in BUILTINS {
+        let name = QName::xs(name);
+        components.types.insert(name.clone(), TypeDef::Simple(Rc::new(SimpleType {
+            name: name,
+            variety: Variety::Builtin(builtin),
+            facets: Vec::new()
+        })));
+    }
+}
+
+/// Validates a value against the given simple type, returning a description of the problem
+/// if the value is invalid.
+///
+/// `namespace` is used to resolve prefixes in values of `QName` types.
+pub fn validate_simple(components: &Components, type_name: &QName, value: &str,
+                       namespace: &Namespace) -> Result<(), String> {
+    let t = match simple_type(components, type_name) {
+        Some(t) => t,
+        None => return Err(format!("Type {} is not a simple type", type_name))
+    };
+    let value = match white_space(components, t) {
+        Some(ws) => normalize(value, ws),
+        None => Cow::Borrowed(value)
+    };
+    check(components, t, &value, namespace)
+}
+
+/// Normalizes whitespace in a value of the given simple type.
+pub fn normalize_value<'a>(components: &Components, type_name: &QName, value: &'a str) -> Cow<'a, str> {
+    match simple_type(components, type_name).and_then(|t| white_space(components, t)) {
+        Some(ws) => normalize(value, ws),
+        None => Cow::Borrowed(value)
+    }
+}
+
+/// Checks whether two values of the given simple type are equal.
+pub fn same_value(components: &Components, type_name: &QName, a: &str, b: &str) -> bool {
+    let kind = match simple_type(components, type_name) {
+        Some(t) => kind(components, t),
+        None => return a == b
+    };
+    let a = normalize_value(components, type_name, a);
+    let b = normalize_value(components, type_name, b);
+    compare(kind, &a, &b) == Some(Ordering::Equal)
+}
+
+/// Returns the built-in type the given simple type is ultimately derived from
+/// by restriction.
+pub fn builtin_base(components: &Components, type_name: &QName) -> Option<Builtin> {
+    simple_type(components, type_name).and_then(|t| match t.variety {
+        Variety::Builtin(b) => Some(b),
+        Variety::Restriction(ref base) => builtin_base(components, base),
+        _ => None
+    })
+}
+
+/// Normalizes whitespace in a value according to the `whiteSpace` facet.
+pub fn normalize(value: &str, ws: WhiteSpace) -> Cow<str> {
+    match ws {
+        WhiteSpace::Preserve => Cow::Borrowed(value),
+        WhiteSpace::Replace => Cow::Owned(value.chars().map(|c| if is_whitespace_char(c) { ' ' } else { c }).collect()),
+        WhiteSpace::Collapse => {
+            let parts: Vec<&str> = value.split(is_whitespace_char).filter(|s| !s.is_empty()).collect();
+            Cow::Owned(parts.join(" "))
+        }
+    }
+}
+
+fn simple_type<'a>(components: &'a Components, name: &QName) -> Option<&'a SimpleType> {
+    match components.types.get(name) {
+        Some(&TypeDef::Simple(ref t)) => Some(t),
+        // A complex type with simple content stands for the type of its content
+        Some(&TypeDef::Complex(ref t)) => match t.content {
+            Content::Simple(ref content) => simple_type(components, content),
+            _ => None
+        },
+        None => None
+    }
+}
+
+/// Returns how whitespace in values of the type is normalized, or `None` for unions,
+/// whose member types normalize values on their own.
+fn white_space(components: &Components, t: &SimpleType) -> Option<WhiteSpace> {
+    for facet in &t.facets {
+        if let Facet::WhiteSpace(ws) = *facet {
+            return Some(ws);
+        }
+    }
+    match t.variety {
+        Variety::Builtin(b) => Some(b.white_space()),
+        Variety::Restriction(ref base) => simple_type(components, base).and_then(|t| white_space(components, t)),
+        Variety::List(_) => Some(WhiteSpace::Collapse),
+        Variety::Union(_) => None
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Kind {
+    Atomic(Builtin),
+    List,
+    Union
+}
+
+fn kind(components: &Components, t: &SimpleType) -> Kind {
+    match t.variety {
+        Variety::Builtin(b) if b.is_list() => Kind::List,
+        Variety::Builtin(b) => Kind::Atomic(b.primitive()),
+        Variety::Restriction(ref base) => match simple_type(components, base) {
+            Some(t) => kind(components, t),
+            None => Kind::Atomic(Builtin::AnySimpleType)
+        },
+        Variety::List(_) => Kind::List,
+        Variety::Union(_) => Kind::Union
+    }
+}
+
+fn check(components: &Components, t: &SimpleType, value: &str, namespace: &Namespace) -> Result<(), String> {
+    match t.variety {
+        Variety::Builtin(b) => if !b.check(value, namespace) {
+            return Err(format!("\"{}\" is not a valid {}", value, b.name()));
+        },
+        Variety::Restriction(ref base) => match simple_type(components, base) {
+            Some(base) => try!(check(components, base, value, namespace)),
+            None => return Err(format!("Type {} is not a simple type", base))
+        },
+        Variety::List(ref item) => for v in value.split(' ').filter(|v| !v.is_empty()) {
+            try!(validate_simple(components, item, v, namespace));
+        },
+        Variety::Union(ref members) => if !members.iter().any(|m| validate_simple(components, m, value, namespace).is_ok()) {
+            return Err(format!("\"{}\" does not match any member type of the union", value));
+        }
+    }
+    let kind = kind(components, t);
+    for facet in &t.facets {
+        if !check_facet(facet, kind, value) {
+            return Err(format!("\"{}\" does not satisfy the {} facet", value, facet_name(facet)));
+        }
+    }
+    Ok(())
+}
+
+fn check_facet(facet: &Facet, kind: Kind, value: &str) -> bool {
+    match *facet {
+        Facet::Length(n) => length(kind, value).map(|len| len == n).unwrap_or(true),
+        Facet::MinLength(n) => length(kind, value).map(|len| len >= n).unwrap_or(true),
+        Facet::MaxLength(n) => length(kind, value).map(|len| len <= n).unwrap_or(true),
+        Facet::Pattern(ref patterns) => patterns.iter().any(|p| p.is_match(value)),
+        Facet::Enumeration(ref values) => values.iter().any(|v| compare(kind, value, v) == Some(Ordering::Equal)),
+        Facet::WhiteSpace(_) => true,
+        Facet::MaxInclusive(ref bound) => compare(kind, value, bound).map(|o| o != Ordering::Greater).unwrap_or(true),
+        Facet::MaxExclusive(ref bound) => compare(kind, value, bound).map(|o| o == Ordering::Less).unwrap_or(true),
+        Facet::MinInclusive(ref bound) => compare(kind, value, bound).map(|o| o != Ordering::Less).unwrap_or(true),
+        Facet::MinExclusive(ref bound) => compare(kind, value, bound).map(|o| o == Ordering::Greater).unwrap_or(true),
+        Facet::TotalDigits(n) => parse_decimal(value).map(|d| d.int.len() + d.frac.len() <= n).unwrap_or(true),
+        Facet::FractionDigits(n) => parse_decimal(value).map(|d| d.frac.len() <= n).unwrap_or(true)
+    }
+}
+
+fn facet_name(facet: &Facet) -> &'static str {
+    match *facet {
+        Facet::Length(_) => "length",
+        Facet::MinLength(_) => "minLength",
+        Facet::MaxLength(_) => "maxLength",
+        Facet::Pattern(_) => "pattern",
+        Facet::Enumeration(_) => "enumeration",
+        Facet::WhiteSpace(_) => "whiteSpace",
+        Facet::MaxInclusive(_) => "maxInclusive",
+        Facet::MaxExclusive(_) => "maxExclusive",
+        Facet::MinInclusive(_) => "minInclusive",
+        Facet::MinExclusive(_) => "minExclusive",
+        Facet::TotalDigits(_) => "totalDigits",
+        Facet::FractionDigits(_) => "fractionDigits"
+    }
+}
+
+/// Returns the length of a value as defined for the length facets, or `None` if
+/// the length facets do not apply to it.
+fn length(kind: Kind, value: &str) -> Option<usize> {
+    match kind {
+        Kind::List => Some(value.split(' ').filter(|v| !v.is_empty()).count()),
+        Kind::Atomic(Builtin::HexBinary) => Some(value.len() / 2),
+        Kind::Atomic(Builtin::Base64Binary) => base64_length(value),
+        Kind::Atomic(Builtin::QName) | Kind::Atomic(Builtin::Notation) | Kind::Union => None,
+        Kind::Atomic(_) => Some(value.chars().count())
+    }
+}
+
+/// Compares two values of the same type.
+///
+/// Dates and times are compared as strings, which is only correct for values with
+/// the same timezone; durations are not ordered at all.
+fn compare(kind: Kind, a: &str, b: &str) -> Option<Ordering> {
+    match kind {
+        Kind::Atomic(Builtin::Decimal) => match (parse_decimal(a), parse_decimal(b)) {
+            (Some(a), Some(b)) => Some(cmp_decimal(&a, &b)),
+            _ => None
+        },
+        Kind::Atomic(Builtin::Float) | Kind::Atomic(Builtin::Double) => match (parse_float(a), parse_float(b)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => None
+        },
+        Kind::Atomic(Builtin::Boolean) => {
+            let value = |v| match v { "1" => "true", "0" => "false", v => v };
+            if value(a) == value(b) { Some(Ordering::Equal) } else { None }
+        }
+        Kind::Atomic(Builtin::Duration) => if a == b { Some(Ordering::Equal) } else { None },
+        _ => Some(a.cmp(b))
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().map(is_name_start_char).unwrap_or(false) && chars.all(is_name_char)
+}
+
+fn is_ncname(s: &str) -> bool {
+    is_name(s) && !s.contains(':')
+}
+
+/// A decimal number split into its sign, integer digits without leading zeros
+/// and fraction digits without trailing zeros.
+struct Decimal<'a> {
+    negative: bool,
+    int: &'a str,
+    frac: &'a str
+}
+
+fn parse_decimal(s: &str) -> Option<Decimal> {
+    let (negative, s) = if s.starts_with('-') {
+        (true, &s[1..])
+    } else if s.starts_with('+') {
+        (false, &s[1..])
+    } else {
+        (false, s)
+    };
+    let (int, frac) = match s.find('.') {
+        Some(i) => (&s[..i], &s[i+1..]),
+        None => (s, "")
+    };
+    if int.is_empty() && frac.is_empty() || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let int = int.trim_start_matches('0');
+    let frac = frac.trim_end_matches('0');
+    Some(Decimal { negative: negative && !(int.is_empty() && frac.is_empty()), int: int, frac: frac })
+}
+
+fn cmp_decimal(a: &Decimal, b: &Decimal) -> Ordering {
+    let magnitude = |a: &Decimal, b: &Decimal| a.int.len().cmp(&b.int.len())
+        .then_with(|| a.int.cmp(b.int))
+        .then_with(|| a.frac.cmp(b.frac));
+    match (a.negative, b.negative) {
+        (false, true) => Ordering::Greater,
+        (true, false) => Ordering::Less,
+        (false, false) => magnitude(a, b),
+        (true, true) => magnitude(b, a)
+    }
+}
+
+fn parse_float(s: &str) -> Option<f64> {
+    match s {
+        "INF" => Some(f64::INFINITY),
+        "-INF" => Some(f64::NEG_INFINITY),
+        "NaN" => Some(f64::NAN),
+        _ if s.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) => s.parse().ok(),
+        _ => None
+    }
+}
+
+/// Returns the number of bytes encoded in a base64 value.
+fn base64_length(s: &str) -> Option<usize> {
+    let data: Vec<u8> = s.bytes().filter(|&b| b != b' ').collect();
+    let padding = data.iter().rev().take_while(|&&b| b == b'=').count();
+    let valid = data.len() & 3 == 0 && padding <= 2 &&
+        data[..data.len() - padding].iter().all(|&b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/');
+    if valid { Some(data.len() / 4 * 3 - padding) } else { None }
+}
+
+fn is_duration(s: &str) -> bool {
+    let s = if s.starts_with('-') { &s[1..] } else { s };
+    if !s.starts_with('P') {
+        return false;
+    }
+    let (date, time) = match s[1..].find('T') {
+        Some(i) => (&s[1..i+1], Some(&s[i+2..])),
+        None => (&s[1..], None)
+    };
+    let components = |s: &str, designators: &str| -> Option<usize> {
+        let mut rest = s;
+        let mut count = 0;
+        for d in designators.chars() {
+            let digits = rest.bytes().take_while(|b| b.is_ascii_digit() || *b == b'.').count();
+            if digits > 0 && rest[digits..].starts_with(d) {
+                let number = &rest[..digits];
+                if number.starts_with('.') || number.ends_with('.') ||
+                    number.contains('.') && (d != 'S' || number.matches('.').count() > 1) {
+                    return None;
+                }
+                rest = &rest[digits + 1..];
+                count += 1;
+            }
+        }
+        if rest.is_empty() { Some(count) } else { None }
+    };
+    match (components(date, "YMD"), time.map(|t| components(t, "HMS"))) {
+        (Some(n), None) => n > 0,
+        (Some(_), Some(Some(n))) => n > 0,
+        _ => false
+    }
+}
+
+fn expect<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.starts_with(prefix) { Some(&s[prefix.len()..]) } else { None }
+}
+
+/// Parses a two-digit number in the given range.
+fn number(s: &str, min: u32, max: u32) -> Option<(u32, &str)> {
+    if s.len() >= 2 && s.as_bytes()[..2].iter().all(|b| b.is_ascii_digit()) {
+        let n = s[..2].parse().unwrap();
+        if min <= n && n <= max {
+            return Some((n, &s[2..]));
+        }
+    }
+    None
+}
+
+fn year(s: &str) -> Option<(i64, &str)> {
+    let (negative, s) = if s.starts_with('-') { (true, &s[1..]) } else { (false, s) };
+    let digits = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if digits < 4 || digits > 4 && s.starts_with('0') || digits > 18 {
+        return None;
+    }
+    let year: i64 = s[..digits].parse().unwrap();
+    if year == 0 {
+        return None;
+    }
+    Some((if negative { -year } else { year }, &s[digits..]))
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+fn date(s: &str) -> Option<&str> {
+    year(s)
+        .and_then(|(y, s)| expect(s, "-").map(|s| (y, s)))
+        .and_then(|(y, s)| number(s, 1, 12).map(|(m, s)| (y, m, s)))
+        .and_then(|(y, m, s)| expect(s, "-").and_then(|s| number(s, 1, days_in_month(y, m))))
+        .map(|(_, s)| s)
+}
+
+fn time(s: &str) -> Option<&str> {
+    let (h, s) = match number(s, 0, 24) { Some(r) => r, None => return None };
+    let (m, s) = match expect(s, ":").and_then(|s| number(s, 0, 59)) { Some(r) => r, None => return None };
+    let (sec, s) = match expect(s, ":").and_then(|s| number(s, 0, 59)) { Some(r) => r, None => return None };
+    let (fraction, s) = match expect(s, ".") {
+        Some(s) => {
+            let digits = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+            if digits == 0 {
+                return None;
+            }
+            (&s[..digits], &s[digits..])
+        }
+        None => ("", s)
+    };
+    if h == 24 && (m != 0 || sec != 0 || fraction.bytes().any(|b| b != b'0')) {
+        return None;
+    }
+    Some(s)
+}
+
+/// Strips a valid timezone from the end of a date or time value.
+fn split_timezone(s: &str) -> Option<&str> {
+    if s.ends_with('Z') {
+        return Some(&s[..s.len() - 1]);
+    }
+    if s.len() >= 6 && s.is_char_boundary(s.len() - 6) {
+        let (head, tz) = s.split_at(s.len() - 6);
+        if (tz.starts_with('+') || tz.starts_with('-')) && tz.as_bytes()[3] == b':' {
+            return match (number(&tz[1..], 0, 14), number(&tz[4..], 0, 59)) {
+                (Some((h, _)), Some((m, _))) if h < 14 || m == 0 => Some(head),
+                _ => None
+            };
+        }
+    }
+    Some(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Builtin;
+    use namespace::Namespace;
+
+    #[test]
+    fn lexical_spaces() {
+        let mut ns = Namespace::empty();
+        ns.put("p", "urn:p");
+        let valid = [
+            (Builtin::Int, "-2147483648"), (Builtin::UnsignedByte, "+255"), (Builtin::Decimal, "-.5"),
+            (Builtin::Decimal, "12."), (Builtin::Double, "-1.5E10"), (Builtin::Float, "INF"),
+            (Builtin::Boolean, "1"), (Builtin::Language, "en-US"), (Builtin::NcName, "_a.b-c"),
+            (Builtin::NmTokens, "a 1 -"), (Builtin::Date, "2004-02-29"), (Builtin::DateTime, "2001-10-26T21:32:52.12679+02:00"),
+            (Builtin::Time, "24:00:00Z"), (Builtin::GMonthDay, "--02-29"), (Builtin::GYear, "-0044"),
+            (Builtin::Duration, "P1Y2M3DT10H30M1.5S"), (Builtin::Duration, "-PT1M"), (Builtin::HexBinary, "0FB7"),
+            (Builtin::Base64Binary, "aGVsbG8="), (Builtin::QName, "p:local"), (Builtin::QName, "local"),
+        ];
+        for &(t, v) in &valid {
+            assert!(t.check(v, &ns), "{:?} {}", t, v);
+        }
+        let invalid = [
+            (Builtin::Int, "2147483648"), (Builtin::UnsignedByte, "-1"), (Builtin::Integer, "1.0"),
+            (Builtin::Decimal, "."), (Builtin::Double, "inf"), (Builtin::Boolean, "yes"),
+            (Builtin::Language, "toolongtag"), (Builtin::NcName, "a:b"), (Builtin::NmTokens, ""),
+            (Builtin::Date, "2003-02-29"), (Builtin::DateTime, "2001-10-26"), (Builtin::Time, "24:00:01"),
+            (Builtin::Date, "2001-10-26+15:00"), (Builtin::GYear, "0000"), (Builtin::Duration, "P"),
+            (Builtin::Duration, "P1YT"), (Builtin::Duration, "P1.5Y"), (Builtin::HexBinary, "0FB"),
+            (Builtin::Base64Binary, "aGVsbG8"), (Builtin::QName, "q:local"), (Builtin::PositiveInteger, "0"),
+        ];
+        for &(t, v) in &invalid {
+            assert!(!t.check(v, &ns), "{:?} {}", t, v);
+        }
+    }
+}
@@ -0,0 +1,545 @@
+//! Contains the validator which checks a stream of events against a schema.
+//!
+//! Content models are matched with derivatives: the expression describing the content
+//! which is still allowed in an element is replaced with its derivative with respect to
+//! each child element, and the element is complete when the remaining expression
+//! matches the empty sequence.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use attribute::OwnedAttribute;
+use common::{Position, TextPosition, is_whitespace_str};
+use namespace::Namespace;
+use reader::{self, XmlEvent};
+use schema::model::{XSI_NAMESPACE, QName, ElementDecl, AttributeUse, ProcessContents, Wildcard,
+                    Compositor, Term, Particle, Content, TypeDef, Components};
+use schema::types::{self, Builtin};
+
+/// A content model expression.
+enum Expr {
+    Empty,
+    NotAllowed,
+    Element(Rc<ElementDecl>),
+    Any(Rc<Wildcard>),
+    Seq(Rc<Expr>, Rc<Expr>),
+    Choice(Rc<Expr>, Rc<Expr>),
+    Interleave(Rc<Expr>, Rc<Expr>),
+    Repeat(Rc<Expr>, u32, Option<u32>)
+}
+
+fn same(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (&Expr::Empty, &Expr::Empty) | (&Expr::NotAllowed, &Expr::NotAllowed) => true,
+        (&Expr::Element(ref a), &Expr::Element(ref b)) => Rc::ptr_eq(a, b),
+        (&Expr::Any(ref a), &Expr::Any(ref b)) => Rc::ptr_eq(a, b),
+        (&Expr::Seq(ref a1, ref a2), &Expr::Seq(ref b1, ref b2)) |
+        (&Expr::Choice(ref a1, ref a2), &Expr::Choice(ref b1, ref b2)) |
+        (&Expr::Interleave(ref a1, ref a2), &Expr::Interleave(ref b1, ref b2)) =>
+            same(a1, b1) && same(a2, b2),
+        (&Expr::Repeat(ref a, a_min, a_max), &Expr::Repeat(ref b, b_min, b_max)) =>
+            a_min == b_min && a_max == b_max && same(a, b),
+        _ => false
+    }
+}
+
+fn seq(a: Rc<Expr>, b: Rc<Expr>) -> Rc<Expr> {
+    match (&*a, &*b) {
+        (&Expr::NotAllowed, _) | (_, &Expr::Empty) => return a.clone(),
+        (_, &Expr::NotAllowed) | (&Expr::Empty, _) => return b.clone(),
+        _ => {}
+    }
+    Rc::new(Expr::Seq(a, b))
+}
+
+fn choice(a: Rc<Expr>, b: Rc<Expr>) -> Rc<Expr> {
+    match (&*a, &*b) {
+        (&Expr::NotAllowed, _) => return b.clone(),
+        (_, &Expr::NotAllowed) => return a.clone(),
+        _ if same(&a, &b) => return a.clone(),
+        _ => {}
+    }
+    Rc::new(Expr::Choice(a, b))
+}
+
+fn interleave(a: Rc<Expr>, b: Rc<Expr>) -> Rc<Expr> {
+    match (&*a, &*b) {
+        (&Expr::NotAllowed, _) | (_, &Expr::Empty) => return a.clone(),
+        (_, &Expr::NotAllowed) | (&Expr::Empty, _) => return b.clone(),
+        _ => {}
+    }
+    Rc::new(Expr::Interleave(a, b))
+}
+
+fn repeat(e: Rc<Expr>, min: u32, max: Option<u32>) -> Rc<Expr> {
+    match *e {
+        _ if max == Some(0) => Rc::new(Expr::Empty),
+        Expr::Empty => e.clone(),
+        Expr::NotAllowed if min == 0 => Rc::new(Expr::Empty),
+        Expr::NotAllowed => e.clone(),
+        _ if min == 1 && max == Some(1) => e.clone(),
+        _ => Rc::new(Expr::Repeat(e, min, max))
+    }
+}
+
+fn nullable(e: &Expr) -> bool {
+    match *e {
+        Expr::Empty => true,
+        Expr::NotAllowed | Expr::Element(_) | Expr::Any(_) => false,
+        Expr::Seq(ref a, ref b) | Expr::Interleave(ref a, ref b) => nullable(a) && nullable(b),
+        Expr::Choice(ref a, ref b) => nullable(a) || nullable(b),
+        Expr::Repeat(ref e, min, _) => min == 0 || nullable(e)
+    }
+}
+
+/// The particle a child element was matched with.
+enum Match {
+    Element(Rc<ElementDecl>),
+    Wildcard(Rc<Wildcard>)
+}
+
+/// Collects names of the elements allowed at the start of the expression.
+fn expected(e: &Expr, names: &mut Vec<String>) {
+    match *e {
+        Expr::Element(ref decl) => names.push(decl.name.to_string()),
+        Expr::Any(_) => names.push("any element".into()),
+        Expr::Seq(ref a, ref b) => {
+            expected(a, names);
+            if nullable(a) {
+                expected(b, names);
+            }
+        }
+        Expr::Choice(ref a, ref b) | Expr::Interleave(ref a, ref b) => {
+            expected(a, names);
+            expected(b, names);
+        }
+        Expr::Repeat(ref e, _, _) => expected(e, names),
+        Expr::Empty | Expr::NotAllowed => {}
+    }
+}
+
+fn describe_expected(e: &Expr) -> String {
+    let mut names = Vec::new();
+    expected(e, &mut names);
+    names.dedup();
+    if names.is_empty() { "no more elements".into() } else { names.join(", ") }
+}
+
+/// The state of an open element.
+enum Frame {
+    /// An element validated against its type.
+    Typed {
+        name: QName,
+        decl: Option<Rc<ElementDecl>>,
+        type_def: TypeDef,
+        /// The content which is still allowed in the element.
+        content: Rc<Expr>,
+        /// Character data of elements with simple content.
+        text: String,
+        namespace: Namespace,
+        nil: bool,
+        /// The position of the start tag, where invalid simple content is reported.
+        pos: TextPosition
+    },
+    /// An element matched by a lax wildcard without a declaration; its children are
+    /// validated only if they are declared.
+    Lax,
+    /// An element matched by a skip wildcard, or a child of one.
+    Skip
+}
+
+/// Validates a stream of events against schema components.
+pub struct Validator<'a> {
+    schema: &'a Components,
+    stack: Vec<Frame>,
+    /// Content model expressions of complex types.
+    models: HashMap<QName, Rc<Expr>>,
+    ids: HashSet<String>,
+    idrefs: Vec<(String, TextPosition)>
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(schema: &'a Components) -> Validator<'a> {
+        Validator {
+            schema: schema,
+            stack: Vec::new(),
+            models: HashMap::new(),
+            ids: HashSet::new(),
+            idrefs: Vec::new()
+        }
+    }
+
+    /// Checks the next event of the document.
+    pub fn validate<P: Position>(&mut self, event: &XmlEvent, pos: &P) -> reader::Result<()> {
+        let pos = pos.position();
+        let result = match *event {
+            XmlEvent::StartElement { ref name, ref attributes, ref namespace } =>
+                self.start_element(QName::new(name.namespace_ref(), &name.local_name[..]), attributes, namespace, pos),
+            XmlEvent::EndElement { .. } => return self.end_element(pos),
+            XmlEvent::Characters(ref data) | XmlEvent::CData(ref data) => self.characters(data, false),
+            XmlEvent::Whitespace(ref data) => self.characters(data, true),
+            XmlEvent::EndDocument => match self.idrefs.iter().find(|&&(ref id, _)| !self.ids.contains(id)) {
+                Some(&(ref id, pos)) => return Err(reader::validity(&pos, format!("IDREF {} does not match any ID", id))),
+                None => Ok(())
+            },
+            _ => Ok(())
+        };
+        result.map_err(|msg| reader::validity(&pos, msg))
+    }
+
+    fn start_element(&mut self, name: QName, attributes: &[OwnedAttribute], namespace: &Namespace,
+                     pos: TextPosition) -> Result<(), String> {
+        let schema = self.schema;
+        let decl = match self.stack.last_mut() {
+            None => match schema.elements.get(&name) {
+                Some(decl) => decl.clone(),
+                None => return Err(format!("Element {} is not declared in the schema", name))
+            },
+            Some(&mut Frame::Skip) => {
+                self.stack.push(Frame::Skip);
+                return Ok(());
+            }
+            Some(&mut Frame::Lax) => match schema.elements.get(&name) {
+                Some(decl) => decl.clone(),
+                None => return self.start_lax(attributes, namespace, pos)
+            },
+            Some(&mut Frame::Typed { name: ref parent, ref mut content, nil, .. }) => {
+                if nil {
+                    return Err(format!("Element {} is nil but has content", parent));
+                }
+                let mut matched = None;
+                let next = derive(schema, content, &name, &mut matched);
+                if let Expr::NotAllowed = *next {
+                    return Err(format!("Element {} is not allowed here in the content of element {}; expected {}",
+                                       name, parent, describe_expected(content)));
+                }
+                *content = next;
+                match matched {
+                    Some(Match::Element(decl)) => decl,
+                    Some(Match::Wildcard(ref w)) => match (w.process_contents, schema.elements.get(&name)) {
+                        (ProcessContents::Skip, _) => {
+                            self.stack.push(Frame::Skip);
+                            return Ok(());
+                        }
+                        (_, Some(decl)) => decl.clone(),
+                        (ProcessContents::Lax, None) => return self.start_lax(attributes, namespace, pos),
+                        (ProcessContents::Strict, None) =>
+                            return Err(format!("Element {} matched by a wildcard is not declared", name))
+                    },
+                    None => unreachable!()
+                }
+            }
+        };
+
+        if decl.is_abstract {
+            return Err(format!("Element {} is abstract", name));
+        }
+        let declared = element_type(schema, &decl);
+        let type_name = match xsi_attribute(attributes, "type") {
+            Some(value) => {
+                let t = try!(resolve_qname(value, namespace));
+                if !schema.types.contains_key(&t) {
+                    return Err(format!("Type {} given in xsi:type is not defined", t));
+                }
+                if !derives(schema, &t, &declared) {
+                    return Err(format!("Type {} given in xsi:type is not derived from {}", t, declared));
+                }
+                t
+            }
+            None => declared
+        };
+        let type_def = schema.types[&type_name].clone();
+        if let TypeDef::Complex(ref t) = type_def {
+            if t.is_abstract {
+                return Err(format!("Type {} of element {} is abstract", type_name, name));
+            }
+        }
+        let nil = match xsi_attribute(attributes, "nil").map(str::trim) {
+            Some("true") | Some("1") if decl.nillable => true,
+            Some("true") | Some("1") => return Err(format!("Element {} is not nillable", name)),
+            _ => false
+        };
+
+        try!(self.validate_attributes(&name, &type_def, attributes, namespace, pos));
+        let content = match type_def {
+            TypeDef::Complex(ref t) => match t.content {
+                Content::Elements(ref particle) => self.content_model(&t.name, particle),
+                _ => Rc::new(Expr::Empty)
+            },
+            TypeDef::Simple(_) => Rc::new(Expr::Empty)
+        };
+        self.stack.push(Frame::Typed {
+            name: name,
+            decl: Some(decl),
+            type_def: type_def,
+            content: content,
+            text: String::new(),
+            namespace: namespace.clone(),
+            nil: nil,
+            pos: pos
+        });
+        Ok(())
+    }
+
+    fn start_lax(&mut self, attributes: &[OwnedAttribute], namespace: &Namespace,
+                 pos: TextPosition) -> Result<(), String> {
+        for attr in attributes {
+            let name = QName::new(attr.name.namespace_ref(), &attr.name.local_name[..]);
+            if let Some(global) = self.schema.attributes.get(&name) {
+                try!(self.check_attribute("", global, &attr.value, namespace, pos));
+            }
+        }
+        self.stack.push(Frame::Lax);
+        Ok(())
+    }
+
+    fn end_element(&mut self, pos: TextPosition) -> reader::Result<()> {
+        let (name, decl, type_def, content, text, namespace, nil, start) = match self.stack.pop() {
+            Some(Frame::Typed { name, decl, type_def, content, text, namespace, nil, pos }) =>
+                (name, decl, type_def, content, text, namespace, nil, pos),
+            _ => return Ok(())
+        };
+        if nil {
+            return if is_whitespace_str(&text) { Ok(()) }
+                   else { Err(reader::validity(&start, format!("Element {} is nil but has content", name))) };
+        }
+        let simple = match type_def {
+            TypeDef::Simple(ref t) => Some(t.name.clone()),
+            TypeDef::Complex(ref t) => match t.content {
+                Content::Simple(ref content) => Some(content.clone()),
+                _ => None
+            }
+        };
+        match simple {
+            Some(simple) => {
+                let value = match decl {
+                    Some(ref decl) if text.is_empty() => decl.fixed.clone().or_else(|| decl.default.clone()).unwrap_or(text),
+                    _ => text
+                };
+                if let Err(e) = types::validate_simple(self.schema, &simple, &value, &namespace) {
+                    return Err(reader::validity(&start, format!("Invalid content of element {}: {}", name, e)));
+                }
+                if let Some(fixed) = decl.as_ref().and_then(|d| d.fixed.as_ref()) {
+                    if !types::same_value(self.schema, &simple, &value, fixed) {
+                        return Err(reader::validity(&start, format!("Element {} must have the fixed value \"{}\"", name, fixed)));
+                    }
+                }
+                Ok(())
+            }
+            None if !nullable(&content) => Err(reader::validity(&pos, format!(
+                "Content of element {} is incomplete; expected {}", name, describe_expected(&content)
+            ))),
+            None => Ok(())
+        }
+    }
+
+    fn characters(&mut self, data: &str, whitespace: bool) -> Result<(), String> {
+        match self.stack.last_mut() {
+            Some(&mut Frame::Typed { ref name, ref type_def, ref mut text, nil, .. }) => {
+                let (simple, mixed) = match *type_def {
+                    TypeDef::Simple(_) => (true, false),
+                    TypeDef::Complex(ref t) => match t.content {
+                        Content::Simple(_) => (true, false),
+                        _ => (false, t.mixed)
+                    }
+                };
+                if simple || nil {
+                    text.push_str(data);
+                } else if !mixed && !whitespace && !is_whitespace_str(data) {
+                    return Err(format!("Character data is not allowed in the content of element {}", name));
+                }
+                Ok(())
+            }
+            _ => Ok(())
+        }
+    }
+
+    fn validate_attributes(&mut self, element: &QName, type_def: &TypeDef, attributes: &[OwnedAttribute],
+                           namespace: &Namespace, pos: TextPosition) -> Result<(), String> {
+        let schema = self.schema;
+        let (uses, any): (&[AttributeUse], Option<&Wildcard>) = match *type_def {
+            TypeDef::Complex(ref t) => (&t.attributes, t.any_attribute.as_ref()),
+            TypeDef::Simple(_) => (&[], None)
+        };
+        for attr in attributes {
+            let name = QName::new(attr.name.namespace_ref(), &attr.name.local_name[..]);
+            if name.namespace.as_ref().map(|s| &s[..]) == Some(XSI_NAMESPACE) {
+                continue;
+            }
+            match uses.iter().find(|u| u.name == name) {
+                Some(u) => try!(self.check_attribute(&element.to_string(), u, &attr.value, namespace, pos)),
+                None => match any {
+                    Some(w) if w.allows(name.namespace.as_ref().map(|s| &s[..])) => {
+                        match (w.process_contents, schema.attributes.get(&name)) {
+                            (ProcessContents::Skip, _) | (ProcessContents::Lax, None) => {}
+                            (_, Some(global)) => try!(self.check_attribute(&element.to_string(), global, &attr.value, namespace, pos)),
+                            (ProcessContents::Strict, None) =>
+                                return Err(format!("Attribute {} matched by a wildcard is not declared", name))
+                        }
+                    }
+                    _ => return Err(format!("Attribute {} is not allowed on element {}", name, element))
+                }
+            }
+        }
+        for u in uses.iter().filter(|u| u.required) {
+            if !attributes.iter().any(|a| a.name.namespace_ref() == u.name.namespace.as_ref().map(|s| &s[..]) &&
+                                          a.name.local_name == u.name.local) {
+                return Err(format!("Required attribute {} of element {} is missing", u.name, element));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_attribute(&mut self, element: &str, u: &AttributeUse, value: &str, namespace: &Namespace,
+                       pos: TextPosition) -> Result<(), String> {
+        let schema = self.schema;
+        let context = if element.is_empty() { format!("attribute {}", u.name) }
+                      else { format!("attribute {} of element {}", u.name, element) };
+        if let Err(e) = types::validate_simple(schema, &u.type_name, value, namespace) {
+            return Err(format!("Invalid value of {}: {}", context, e));
+        }
+        if let Some(ref fixed) = u.fixed {
+            if !types::same_value(schema, &u.type_name, value, fixed) {
+                return Err(format!("The {} must have the fixed value \"{}\"", context, fixed));
+            }
+        }
+        let value = types::normalize_value(schema, &u.type_name, value);
+        match types::builtin_base(schema, &u.type_name) {
+            Some(Builtin::Id) if self.ids.contains(&*value) =>
+                return Err(format!("Duplicate ID value of {}", context)),
+            Some(Builtin::Id) => {
+                self.ids.insert(value.into_owned());
+            }
+            Some(Builtin::IdRef) | Some(Builtin::IdRefs) => for id in value.split(' ') {
+                self.idrefs.push((id.to_owned(), pos));
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn content_model(&mut self, type_name: &QName, particle: &Particle) -> Rc<Expr> {
+        let schema = self.schema;
+        self.models.entry(type_name.clone()).or_insert_with(|| particle_expr(schema, particle)).clone()
+    }
+}
+
+fn particle_expr(schema: &Components, particle: &Particle) -> Rc<Expr> {
+    let term = match particle.term {
+        Term::Element(ref decl) => Rc::new(Expr::Element(decl.clone())),
+        Term::ElementRef(ref name) => Rc::new(Expr::Element(schema.elements[name].clone())),
+        Term::Wildcard(ref w) => Rc::new(Expr::Any(w.clone())),
+        Term::GroupRef(ref name) => particle_expr(schema, &schema.groups[name]),
+        Term::Group(Compositor::Sequence, ref particles) => particles.iter().rev()
+            .fold(Rc::new(Expr::Empty), |e, p| seq(particle_expr(schema, p), e)),
+        Term::Group(Compositor::Choice, ref particles) => particles.iter()
+            .fold(Rc::new(Expr::NotAllowed), |e, p| choice(e, particle_expr(schema, p))),
+        Term::Group(Compositor::All, ref particles) => particles.iter()
+            .fold(Rc::new(Expr::Empty), |e, p| interleave(e, particle_expr(schema, p)))
+    };
+    repeat(term, particle.min_occurs, particle.max_occurs)
+}
+
+/// Returns the derivative of the expression with respect to an element with the given
+/// name, recording the particle it was matched with.
+fn derive(schema: &Components, e: &Rc<Expr>, name: &QName, matched: &mut Option<Match>) -> Rc<Expr> {
+    match **e {
+        Expr::Empty | Expr::NotAllowed => Rc::new(Expr::NotAllowed),
+        Expr::Element(ref decl) => match substitute(schema, decl, name) {
+            Some(decl) => {
+                if matched.is_none() {
+                    *matched = Some(Match::Element(decl));
+                }
+                Rc::new(Expr::Empty)
+            }
+            None => Rc::new(Expr::NotAllowed)
+        },
+        Expr::Any(ref w) => if w.allows(name.namespace.as_ref().map(|s| &s[..])) {
+            if matched.is_none() {
+                *matched = Some(Match::Wildcard(w.clone()));
+            }
+            Rc::new(Expr::Empty)
+        } else {
+            Rc::new(Expr::NotAllowed)
+        },
+        Expr::Seq(ref a, ref b) => {
+            let first = seq(derive(schema, a, name, matched), b.clone());
+            if nullable(a) { choice(first, derive(schema, b, name, matched)) } else { first }
+        }
+        Expr::Choice(ref a, ref b) => choice(derive(schema, a, name, matched), derive(schema, b, name, matched)),
+        Expr::Interleave(ref a, ref b) => choice(
+            interleave(derive(schema, a, name, matched), b.clone()),
+            interleave(a.clone(), derive(schema, b, name, matched))
+        ),
+        Expr::Repeat(ref inner, min, max) => seq(
+            derive(schema, inner, name, matched),
+            repeat(inner.clone(), min.saturating_sub(1), max.map(|m| m - 1))
+        )
+    }
+}
+
+/// Returns the declaration of an element with the given name if it may appear in place
+/// of the given declaration, either directly or as a member of its substitution group.
+fn substitute(schema: &Components, decl: &Rc<ElementDecl>, name: &QName) -> Option<Rc<ElementDecl>> {
+    if decl.name == *name {
+        return Some(decl.clone());
+    }
+    let member = match schema.elements.get(name) {
+        Some(member) => member,
+        None => return None
+    };
+    let mut head = member.substitution_group.as_ref();
+    while let Some(h) = head {
+        if *h == decl.name {
+            return Some(member.clone());
+        }
+        head = schema.elements.get(h).and_then(|d| d.substitution_group.as_ref());
+    }
+    None
+}
+
+/// Returns the type of an element, which is inherited from the head of its substitution
+/// group when it is not specified.
+fn element_type(schema: &Components, decl: &ElementDecl) -> QName {
+    let mut decl = decl;
+    loop {
+        if let Some(ref t) = decl.type_name {
+            return t.clone();
+        }
+        match decl.substitution_group.as_ref().and_then(|h| schema.elements.get(h)) {
+            Some(head) => decl = head,
+            None => return QName::xs("anyType")
+        }
+    }
+}
+
+/// Checks whether a type is derived from another one, or is the same type.
+fn derives(schema: &Components, t: &QName, base: &QName) -> bool {
+    let mut current = Some(t.clone());
+    while let Some(name) = current {
+        if name == *base {
+            return true;
+        }
+        current = schema.types.get(&name).and_then(|t| t.base());
+    }
+    false
+}
+
+fn xsi_attribute<'a>(attributes: &'a [OwnedAttribute], local: &str) -> Option<&'a str> {
+    attributes.iter()
+        .find(|a| a.name.namespace_ref() == Some(XSI_NAMESPACE) && a.name.local_name == local)
+        .map(|a| &a.value[..])
+}
+
+fn resolve_qname(value: &str, namespace: &Namespace) -> Result<QName, String> {
+    let value = value.trim();
+    let (prefix, local) = match value.find(':') {
+        Some(i) => (&value[..i], &value[i+1..]),
+        None => ("", value)
+    };
+    match namespace.get(prefix) {
+        Some(ns) => Ok(QName::new(Some(ns), local)),
+        None if prefix.is_empty() => Ok(QName::new(None, local)),
+        None => Err(format!("Undeclared namespace prefix: {}", prefix))
+    }
+}
@@ -1,11 +1,20 @@
 use std::io::{self, Read};
 use std::str;
 use std::fmt;
+use std::char;
+use std::mem;
+use std::cmp;
+use std::borrow::Cow;
+
+use common::is_whitespace_char;
+use encoding::SingleByteEncoding;
 
 #[derive(Debug)]
 pub enum CharReadError {
     UnexpectedEof,
     Utf8(str::Utf8Error),
+    /// Invalid input in an encoding other than UTF-8.
+    Encoding(Cow<'static, str>),
     Io(io::Error)
 }
 
@@ -27,68 +36,449 @@ impl fmt::Display for CharReadError {
         match *self {
             UnexpectedEof => write!(f, "unexpected end of stream"),
             Utf8(ref e) => write!(f, "UTF-8 decoding error: {}", e),
+            Encoding(ref msg) => write!(f, "{}", msg),
             Io(ref e) => write!(f, "I/O error: {}", e)
         }
     }
 }
 
-pub fn next_char_from<R: Read>(source: &mut R) -> Result<Option<char>, CharReadError> {
-    const MAX_CODEPOINT_LEN: usize = 4;
+/// The size of the buffer of `CharReader`.
+const BUFFER_SIZE: usize = 8192;
+
+/// An encoding of the input stream which can be decoded by `CharReader`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf16Be,
+    Utf16Le
+}
+
+impl Encoding {
+    /// Returns the name of the encoding as it is written in XML declarations; the byte
+    /// order of UTF-16 is only specified when there is no byte order mark.
+    pub fn name(&self, bom: bool) -> &'static str {
+        match *self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Be | Encoding::Utf16Le if bom => "UTF-16",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Utf16Le => "UTF-16LE"
+        }
+    }
+
+    /// Checks whether the encoding declared in an XML or text declaration agrees with
+    /// this encoding, which was detected from the first bytes of the stream.
+    ///
+    /// Names of encodings which cannot be detected this way are accepted when no byte
+    /// order mark was found.
+    pub fn allows_declared(&self, bom: bool, declared: &str) -> bool {
+        let declared = declared.to_ascii_uppercase();
+        match (*self, &declared[..]) {
+            (Encoding::Utf8, "UTF-8") => true,
+            (Encoding::Utf8, "UTF-16") | (Encoding::Utf8, "UTF-16BE") | (Encoding::Utf8, "UTF-16LE") => false,
+            (Encoding::Utf8, _) => !bom,
+            (Encoding::Utf16Be, "UTF-16") | (Encoding::Utf16Le, "UTF-16") => true,
+            (Encoding::Utf16Be, "UTF-16BE") | (Encoding::Utf16Le, "UTF-16LE") => !bom,
+            _ => false
+        }
+    }
+}
+
+/// Decodes characters of a byte stream in the encoding detected from its first bytes,
+/// as described in Appendix F of the XML specification.
+///
+/// A byte order mark is consumed and not returned as a character. Streams which
+/// start neither with a byte order mark nor with `<?` in UTF-16 are decoded as UTF-8.
+/// Line breaks are normalized as described in section 2.11 of the specification.
+///
+/// The stream is read in chunks of `BUFFER_SIZE` bytes, so reading it does not need to be
+/// buffered, and bytes after the last returned character may have been read from it.
+/// Bytes are decoded one character at a time, since the encoding may change after
+/// the XML declaration.
+pub struct CharReader {
+    encoding: Option<Encoding>,
+    bom: bool,
+    /// Bytes read from the stream; those before `buf_pos` are already decoded.
+    buf: Vec<u8>,
+    buf_pos: usize,
+    /// Number of bytes dropped from the beginning of `buf`.
+    consumed: u64,
+    /// Offset of the last returned character or run.
+    char_start: u64,
+    /// Length in bytes of the last run, which ends at `buf_pos`.
+    run_len: usize,
+    /// A single-byte encoding declared in the XML declaration, which is used instead
+    /// of UTF-8 for the rest of the stream.
+    single_byte: Option<SingleByteEncoding>,
+    /// Whether the last character was `'\r'`, so that a following `'\n'` is skipped.
+    after_cr: bool,
+    xml11: bool
+}
+
+impl CharReader {
+    pub fn new() -> CharReader {
+        CharReader {
+            encoding: None,
+            bom: false,
+            buf: Vec::new(),
+            buf_pos: 0,
+            consumed: 0,
+            char_start: 0,
+            run_len: 0,
+            single_byte: None,
+            after_cr: false,
+            xml11: false
+        }
+    }
+
+    /// Returns the detected encoding; UTF-8 is returned until the first character is read.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding.unwrap_or(Encoding::Utf8)
+    }
+
+    /// Returns true if the stream starts with a byte order mark.
+    pub fn has_bom(&self) -> bool { self.bom }
+
+    /// Returns the offset of the byte following the last decoded character.
+    pub fn offset(&self) -> u64 { self.consumed + self.buf_pos as u64 }
 
-    let mut bytes = source.bytes();
-    let mut buf = [0u8; MAX_CODEPOINT_LEN];
-    let mut pos = 0;
+    /// Returns the offset of the first byte of the last character returned by
+    /// `next_char_from()` or of the last run returned by `read_run()`.
+    ///
+    /// A line feed skipped after a carriage return is not a part of the next character.
+    pub fn char_start(&self) -> u64 { self.char_start }
 
-    loop {
-        let next = match bytes.next() {
-            Some(Ok(b)) => b,
-            Some(Err(e)) => return Err(e.into()),
-            None if pos == 0 => return Ok(None),
-            None => return Err(CharReadError::UnexpectedEof)
+    /// Checks the encoding declared in an XML or text declaration against the detected
+    /// encoding, and switches to the declared encoding if it is a single-byte one, which
+    /// is looked up with `find`.
+    pub fn declare_encoding<F>(&mut self, name: &str, find: F) -> Result<(), String>
+        where F: FnOnce(&str) -> Option<SingleByteEncoding>
+    {
+        let encoding = self.encoding();
+        if !encoding.allows_declared(self.bom, name) {
+            return Err(format!("Declared encoding {} does not match the detected encoding {}",
+                               name, encoding.name(self.bom)));
+        }
+        if encoding == Encoding::Utf8 && !name.eq_ignore_ascii_case("UTF-8") {
+            match find(name) {
+                Some(single_byte) => self.single_byte = Some(single_byte),
+                None => return Err(format!("Unsupported encoding: {}", name))
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes NEL (U+0085) and LINE SEPARATOR (U+2028) characters line breaks, as they are
+    /// in XML 1.1 documents.
+    pub fn set_xml11_line_breaks(&mut self) {
+        self.xml11 = true;
+    }
+
+    /// Reads the next character from the stream, reporting all line breaks as `'\n'`.
+    ///
+    /// `"\r\n"` and a lone `'\r'` are line breaks; so are `"\r\u{85}"`, `'\u{85}'` and
+    /// `'\u{2028}'` after `set_xml11_line_breaks()` is called.
+    pub fn next_char_from<R: Read>(&mut self, source: &mut R) -> Result<Option<char>, CharReadError> {
+        loop {
+            // `after_cr` is kept if the stream fails, so reading can be resumed
+            let c = try!(self.decode_char(source));
+            let after_cr = mem::replace(&mut self.after_cr, false);
+            match c {
+                Some('\n') if after_cr => {}
+                Some('\u{85}') if after_cr && self.xml11 => {}
+                Some('\r') => {
+                    self.after_cr = true;
+                    return Ok(Some('\n'));
+                }
+                Some('\u{85}') | Some('\u{2028}') if self.xml11 => return Ok(Some('\n')),
+                c => return Ok(c)
+            }
+        }
+    }
+
+    /// Reads a run of characters without special meaning in markup from the buffer;
+    /// returns `None` if there is no such run at the current position, which happens,
+    /// among other cases, in encodings other than UTF-8.
+    ///
+    /// The run contains no markup delimiters (see `is_plain_byte()`) and no carriage
+    /// returns, so its characters need no normalization. If it starts with white space,
+    /// it contains only white space.
+    pub fn read_run<R: Read>(&mut self, source: &mut R) -> Result<Option<&str>, CharReadError> {
+        if self.encoding != Some(Encoding::Utf8) || self.single_byte.is_some() {
+            return Ok(None);
+        }
+        if !try!(self.fill_buf(source, 1)) {
+            return Ok(None);
+        }
+        if self.after_cr {
+            match self.buf[self.buf_pos] {
+                b'\n' => {
+                    self.after_cr = false;
+                    self.buf_pos += 1;
+                    if !try!(self.fill_buf(source, 1)) {
+                        return Ok(None);
+                    }
+                }
+                0xC2 if self.xml11 => return Ok(None),
+                _ => self.after_cr = false
+            }
+        }
+        let bytes = &self.buf[self.buf_pos..];
+        let whitespace = is_whitespace_byte(bytes[0]);
+        let len = bytes.iter().position(|&b| {
+            !is_plain_byte(b) || whitespace && !is_whitespace_byte(b) ||
+                self.xml11 && (b == 0xC2 || b == 0xE2)  // may start NEL or LINE SEPARATOR
+        }).unwrap_or(bytes.len());
+        let run = match str::from_utf8(&bytes[..len]) {
+            Ok(run) => run,
+            // an invalid or incomplete sequence is left for `decode_char()`
+            Err(e) => str::from_utf8(&bytes[..e.valid_up_to()]).unwrap()
         };
-        buf[pos] = next;
-        pos += 1;
+        if run.is_empty() {
+            return Ok(None);
+        }
+        self.char_start = self.consumed + self.buf_pos as u64;
+        self.buf_pos += run.len();
+        self.run_len = run.len();
+        Ok(Some(run))
+    }
+
+    /// Returns the run returned by the last call to `read_run()`, which is still in the buffer
+    /// until the next character or run is read.
+    pub fn last_run(&self) -> &str {
+        // the run was checked when it was read, so this cannot fail
+        str::from_utf8(&self.buf[self.buf_pos - self.run_len..self.buf_pos]).unwrap_or("")
+    }
 
-        match str::from_utf8(&buf[..pos]) {
-            Ok(s) => return Ok(s.chars().next()),  // always Some(..)
-            Err(_) if pos < MAX_CODEPOINT_LEN => {},
-            Err(e) => return Err(e.into())
+    fn decode_char<R: Read>(&mut self, source: &mut R) -> Result<Option<char>, CharReadError> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => try!(self.detect(source))
+        };
+        self.char_start = self.offset();
+        if !try!(self.fill_buf(source, 1)) {
+            return Ok(None);
+        }
+        match encoding {
+            Encoding::Utf8 => {
+                let b = self.buf[self.buf_pos];
+                if let Some(ref single_byte) = self.single_byte {
+                    self.buf_pos += 1;
+                    return match single_byte.decode(b) {
+                        Some(c) => Ok(Some(c)),
+                        None => Err(CharReadError::Encoding(
+                            format!("Byte 0x{:02X} is not used by encoding {}", b, single_byte.name()).into()
+                        ))
+                    };
+                }
+                if b < 0x80 {
+                    self.buf_pos += 1;
+                    return Ok(Some(b as char));
+                }
+                let len = match b {
+                    0xC0...0xDF => 2,
+                    0xE0...0xEF => 3,
+                    0xF0...0xF7 => 4,
+                    _ => 1  // invalid in any position, reported below
+                };
+                if !try!(self.fill_buf(source, len)) {
+                    self.buf_pos = self.buf.len();
+                    return Err(CharReadError::UnexpectedEof);
+                }
+                let bytes = &self.buf[self.buf_pos..self.buf_pos + len];
+                self.buf_pos += len;
+                match str::from_utf8(bytes) {
+                    Ok(s) => Ok(s.chars().next()),  // always Some(..)
+                    Err(e) => Err(e.into())
+                }
+            }
+            Encoding::Utf16Be | Encoding::Utf16Le => {
+                let big_endian = encoding == Encoding::Utf16Be;
+                // a surrogate pair is only consumed once both of its units are buffered
+                if try!(self.fill_buf(source, 2)) {
+                    let high = if big_endian { self.buf[self.buf_pos] } else { self.buf[self.buf_pos + 1] };
+                    if high & 0xFC == 0xD8 {
+                        try!(self.fill_buf(source, 4));
+                    }
+                }
+                let unit = match try!(self.next_unit(source, big_endian)) {
+                    Some(unit) => unit,
+                    None => return Ok(None)
+                };
+                let c = match unit {
+                    0xD800...0xDBFF => match try!(self.next_unit(source, big_endian)) {
+                        Some(low @ 0xDC00...0xDFFF) =>
+                            char::from_u32(0x10000 + ((unit as u32 - 0xD800) << 10) + (low as u32 - 0xDC00)),
+                        Some(_) => None,
+                        None => return Err(CharReadError::UnexpectedEof)
+                    },
+                    _ => char::from_u32(unit as u32)
+                };
+                match c {
+                    Some(c) => Ok(Some(c)),
+                    None => Err(CharReadError::Encoding(
+                        format!("UTF-16 decoding error: unpaired surrogate 0x{:04X}", unit).into()
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Reads the next UTF-16 code unit; returns `None` at the end of the stream.
+    fn next_unit<R: Read>(&mut self, source: &mut R, big_endian: bool) -> Result<Option<u16>, CharReadError> {
+        if !try!(self.fill_buf(source, 2)) {
+            return if self.buf_pos == self.buf.len() {
+                Ok(None)
+            } else {
+                self.buf_pos = self.buf.len();
+                Err(CharReadError::UnexpectedEof)
+            };
+        }
+        let (a, b) = (self.buf[self.buf_pos] as u16, self.buf[self.buf_pos + 1] as u16);
+        self.buf_pos += 2;
+        Ok(Some(if big_endian { a << 8 | b } else { b << 8 | a }))
+    }
+
+    /// Reads the stream until at least `len` bytes are buffered; returns false if the
+    /// stream ends before that.
+    fn fill_buf<R: Read>(&mut self, source: &mut R, len: usize) -> io::Result<bool> {
+        while self.buf.len() - self.buf_pos < len {
+            self.buf.drain(..self.buf_pos);
+            self.consumed += self.buf_pos as u64;
+            self.buf_pos = 0;
+            let start = self.buf.len();
+            self.buf.resize(BUFFER_SIZE, 0);
+            match source.read(&mut self.buf[start..]) {
+                Ok(0) => {
+                    self.buf.truncate(start);
+                    return Ok(false);
+                }
+                Ok(n) => self.buf.truncate(start + n),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => self.buf.truncate(start),
+                Err(e) => {
+                    self.buf.truncate(start);
+                    return Err(e);
+                }
+            }
         }
+        Ok(true)
+    }
+
+    fn detect<R: Read>(&mut self, source: &mut R) -> Result<Encoding, CharReadError> {
+        try!(self.fill_buf(source, 4));
+        let (encoding, bom_len) = {
+            let head = &self.buf[..cmp::min(self.buf.len(), 4)];
+            if head.starts_with(&[0xEF, 0xBB, 0xBF]) {
+                (Encoding::Utf8, 3)
+            } else if head.starts_with(&[0x00, 0x00]) || head == [0xFF, 0xFE, 0x00, 0x00] ||
+                      head == [0x3C, 0x00, 0x00, 0x00] {
+                return Err(CharReadError::Encoding("UCS-4 encoding is not supported".into()));
+            } else if head.starts_with(&[0xFE, 0xFF]) {
+                (Encoding::Utf16Be, 2)
+            } else if head.starts_with(&[0xFF, 0xFE]) {
+                (Encoding::Utf16Le, 2)
+            } else if head == [0x00, 0x3C, 0x00, 0x3F] {
+                (Encoding::Utf16Be, 0)
+            } else if head == [0x3C, 0x00, 0x3F, 0x00] {
+                (Encoding::Utf16Le, 0)
+            } else {
+                (Encoding::Utf8, 0)
+            }
+        };
+        self.encoding = Some(encoding);
+        self.bom = bom_len > 0;
+        self.buf_pos = bom_len;
+        Ok(encoding)
     }
 }
 
+/// Returns true if the byte is not a markup delimiter recognized by the lexer nor
+/// a carriage return.
+#[inline]
+fn is_plain_byte(b: u8) -> bool {
+    match b {
+        b'<' | b'>' | b'/' | b'=' | b'"' | b'\'' | b'?' | b'-' | b']' | b'&' | b';' | b'\r' => false,
+        _ => true
+    }
+}
+
+#[inline]
+fn is_whitespace_byte(b: u8) -> bool {
+    b == b' ' || b == b'\t' || b == b'\n'
+}
+
+/// Resolves a reference to one of the predefined entities or a character reference.
+///
+/// `name` is the text between `&` and `;`. Returns `None` if the reference is neither
+/// a predefined entity reference nor a character reference.
+pub fn resolve_predefined_reference(name: &str) -> Option<Result<char, Cow<'static, str>>> {
+    match name {
+        "lt"   => Some(Ok('<')),
+        "gt"   => Some(Ok('>')),
+        "amp"  => Some(Ok('&')),
+        "apos" => Some(Ok('\'')),
+        "quot" => Some(Ok('"')),
+        _ if name.starts_with('#') => Some(parse_char_reference(&name[1..])),
+        _ => None
+    }
+}
+
+/// Parses the number of a character reference, that is, the text between `&#` and `;`.
+pub fn parse_char_reference(num: &str) -> Result<char, Cow<'static, str>> {
+    let (num_str, radix) = if num.len() > 1 && num.starts_with('x') {
+        (&num[1..], 16)
+    } else {
+        (num, 10)
+    };
+    if num_str == "0" {
+        return Err("Null character entity is not allowed".into());
+    }
+    match u32::from_str_radix(num_str, radix).ok().and_then(char::from_u32) {
+        Some(c) => Ok(c),
+        None if radix == 16 => Err(format!("Invalid hexadecimal character number in an entity: #{}", num).into()),
+        None => Err(format!("Invalid decimal character number in an entity: #{}", num).into())
+    }
+}
+
+/// Normalizes a value of a tokenized attribute type by removing leading and trailing
+/// spaces and replacing sequences of spaces with a single space.
+///
+/// If `all_whitespace` is true, tabs and line breaks are treated as spaces; this is used
+/// when values are not normalized as CDATA beforehand.
+pub fn normalize_tokenized(value: &str, all_whitespace: bool) -> String {
+    let parts: Vec<_> = if all_whitespace {
+        value.split(is_whitespace_char).filter(|s| !s.is_empty()).collect()
+    } else {
+        value.split(' ').filter(|s| !s.is_empty()).collect()
+    };
+    parts.join(" ")
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
     fn test_next_char_from() {
         use std::io;
-        use std::error::Error;
-
-        let mut bytes: &[u8] = "correct".as_bytes();    // correct ASCII
-        assert_eq!(super::next_char_from(&mut bytes).unwrap(), Some('c'));
-
-        let mut bytes: &[u8] = "правильно".as_bytes();  // correct BMP
-        assert_eq!(super::next_char_from(&mut bytes).unwrap(), Some('п'));
+        use super::{CharReader, CharReadError};
 
-        let mut bytes: &[u8] = "😊".as_bytes();          // correct non-BMP
-        assert_eq!(super::next_char_from(&mut bytes).unwrap(), Some('😊'));
+        fn next_char(mut bytes: &[u8]) -> Result<Option<char>, CharReadError> {
+            CharReader::new().next_char_from(&mut bytes)
+        }
 
-        let mut bytes: &[u8] = b"";                     // empty
-        assert_eq!(super::next_char_from(&mut bytes).unwrap(), None);
+        assert_eq!(next_char("correct".as_bytes()).unwrap(), Some('c'));      // correct ASCII
+        assert_eq!(next_char("правильно".as_bytes()).unwrap(), Some('п'));    // correct BMP
+        assert_eq!(next_char("😊".as_bytes()).unwrap(), Some('😊'));            // correct non-BMP
+        assert_eq!(next_char(b"").unwrap(), None);                            // empty
 
-        let mut bytes: &[u8] = b"\xf0\x9f\x98";         // incomplete code point
-        match super::next_char_from(&mut bytes).unwrap_err() {
-            super::CharReadError::UnexpectedEof => {},
+        match next_char(b"\xf0\x9f\x98").unwrap_err() {                    // incomplete code point
+            CharReadError::UnexpectedEof => {},
             e => panic!("Unexpected result: {:?}", e)
         };
 
-        let mut bytes: &[u8] = b"\xff\x9f\x98\x32";     // invalid code point
-        match super::next_char_from(&mut bytes).unwrap_err() {
-            super::CharReadError::Utf8(_) => {},
+        match next_char(b"\xff\x9f\x98\x32").unwrap_err() {               // invalid code point
+            CharReadError::Utf8(_) => {},
             e => panic!("Unexpected result: {:?}", e)
         };
 
-
         // error during read
         struct ErrorReader;
         impl io::Read for ErrorReader {
@@ -98,10 +488,94 @@ mod tests {
         }
 
         let mut r = ErrorReader;
-        match super::next_char_from(&mut r).unwrap_err() {
-            super::CharReadError::Io(ref e) if e.kind() == io::ErrorKind::Other &&
-                                               e.description() == "test error" => {},
+        match CharReader::new().next_char_from(&mut r).unwrap_err() {
+            CharReadError::Io(ref e) if e.kind() == io::ErrorKind::Other &&
+                                        e.to_string() == "test error" => {},
+            e => panic!("Unexpected result: {:?}", e)
+        }
+    }
+
+    #[test]
+    fn test_char_reader_buffering() {
+        use std::io::{self, Read};
+        use super::{CharReader, BUFFER_SIZE};
+
+        // yields at most `n` bytes per read and is interrupted before each of them
+        struct Trickle<'a> { data: &'a [u8], n: usize, interrupted: bool }
+        impl<'a> Read for Trickle<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.interrupted = !self.interrupted;
+                if self.interrupted {
+                    return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+                }
+                let n = self.n.min(buf.len()).min(self.data.len());
+                buf[..n].copy_from_slice(&self.data[..n]);
+                self.data = &self.data[n..];
+                Ok(n)
+            }
+        }
+
+        let text: String = "a\u{444}\u{1F600}\u{2028}".chars().cycle().take(BUFFER_SIZE).collect();
+        for &n in [1, 3, BUFFER_SIZE].iter() {
+            let mut source = Trickle { data: text.as_bytes(), n: n, interrupted: false };
+            let mut reader = CharReader::new();
+            let mut result = String::new();
+            while let Some(c) = reader.next_char_from(&mut source).unwrap() {
+                result.push(c);
+            }
+            assert_eq!(result, text);
+        }
+    }
+
+    #[test]
+    fn test_char_reader() {
+        use super::{CharReader, Encoding, CharReadError};
+
+        fn read_all(bytes: &[u8]) -> Result<(String, Encoding, bool), CharReadError> {
+            let mut reader = CharReader::new();
+            let mut source = bytes;
+            let mut text = String::new();
+            while let Some(c) = try!(reader.next_char_from(&mut source)) {
+                text.push(c);
+            }
+            Ok((text, reader.encoding(), reader.has_bom()))
+        }
+
+        assert_eq!(read_all(b"").unwrap(), ("".into(), Encoding::Utf8, false));
+        assert_eq!(read_all(b"a").unwrap(), ("a".into(), Encoding::Utf8, false));
+        assert_eq!(read_all(b"\xef\xbb\xbf<\xd0\xbf").unwrap(), ("<п".into(), Encoding::Utf8, true));
+        assert_eq!(read_all(b"\xfe\xff\x00<\xd8\x3d\xde\x0a").unwrap(), ("<😊".into(), Encoding::Utf16Be, true));
+        assert_eq!(read_all(b"\xff\xfe<\x00").unwrap(), ("<".into(), Encoding::Utf16Le, true));
+        assert_eq!(read_all(b"<\x00?\x00x\x00").unwrap(), ("<?x".into(), Encoding::Utf16Le, false));
+        assert_eq!(read_all(b"\x00<\x00?").unwrap(), ("<?".into(), Encoding::Utf16Be, false));
+        assert_eq!(read_all(b"a\r\nb\rc\r\r\n\xc2\x85").unwrap(), ("a\nb\nc\n\n\u{85}".into(), Encoding::Utf8, false));
+
+        match read_all(b"\xfe\xff\x00<\x00").unwrap_err() {
+            CharReadError::UnexpectedEof => {},
+            e => panic!("Unexpected result: {:?}", e)
+        }
+        match read_all(b"\xff\xfe\x00\xdc").unwrap_err() {
+            CharReadError::Encoding(_) => {},
+            e => panic!("Unexpected result: {:?}", e)
+        }
+        match read_all(b"\x00\x00\x00<").unwrap_err() {
+            CharReadError::Encoding(_) => {},
             e => panic!("Unexpected result: {:?}", e)
         }
     }
+
+    #[test]
+    fn test_char_reader_offsets() {
+        use super::CharReader;
+
+        let mut reader = CharReader::new();
+        let mut source: &[u8] = b"\xef\xbb\xbfa\xd0\xbf\r\nb";
+        let mut offsets = Vec::new();
+        while let Some(c) = reader.next_char_from(&mut source).unwrap() {
+            offsets.push((c, reader.char_start(), reader.offset()));
+        }
+        // the line feed is skipped when the next character is read
+        assert_eq!(offsets, vec![('a', 3, 4), ('\u{43f}', 4, 6), ('\n', 6, 7), ('b', 8, 9)]);
+        assert_eq!(reader.offset(), 9);
+    }
 }
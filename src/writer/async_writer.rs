@@ -0,0 +1,164 @@
+//! Contains `AsyncEventWriter`, an events-based XML emitter writing to an asynchronous stream.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_io::AsyncWrite;
+
+use writer::{EmitterConfig, XmlEvent, Result};
+use writer::emitter::Emitter;
+
+const BUFFER_SIZE: usize = 8192;
+
+/// A wrapper around a `futures_io::AsyncWrite` instance which emits XML document according
+/// to provided events without blocking.
+///
+/// This type is only available with the `async` feature. The output is produced by the same
+/// emitter as the output of `EventWriter` and collected in a buffer of several kilobytes,
+/// which is written to the stream when it is full. Futures returned by `write()` do not
+/// resolve until then, so a slow stream slows down the producer of events. The rest of the
+/// buffer is written by `flush()` or `close()`.
+///
+/// ```rust,ignore
+/// let mut writer = AsyncEventWriter::new(socket);
+/// writer.write(XmlEvent::start_element("greeting")).await?;
+/// writer.write("Hello").await?;
+/// writer.write(XmlEvent::end_element()).await?;
+/// writer.close().await?;
+/// ```
+pub struct AsyncEventWriter<W> {
+    sink: W,
+    emitter: Emitter,
+    buf: Vec<u8>,
+    written: usize
+}
+
+impl<W: AsyncWrite + Unpin> AsyncEventWriter<W> {
+    /// Creates a new `AsyncEventWriter` out of an `AsyncWrite` instance using the default
+    /// configuration.
+    #[inline]
+    pub fn new(sink: W) -> AsyncEventWriter<W> {
+        AsyncEventWriter::new_with_config(sink, EmitterConfig::new())
+    }
+
+    /// Creates a new `AsyncEventWriter` out of an `AsyncWrite` instance using the provided
+    /// configuration.
+    pub fn new_with_config(sink: W, config: EmitterConfig) -> AsyncEventWriter<W> {
+        AsyncEventWriter {
+            sink: sink,
+            emitter: Emitter::new(config),
+            buf: Vec::with_capacity(BUFFER_SIZE),
+            written: 0
+        }
+    }
+
+    /// Returns a future which writes the next piece of XML document according to the
+    /// provided event.
+    ///
+    /// The output is the same as the output of `EventWriter::write()` for this event.
+    pub fn write<'w, 'a, E>(&'w mut self, event: E) -> WriteEvent<'w, 'a, W> where E: Into<XmlEvent<'a>> {
+        WriteEvent { writer: self, event: Some(event.into()) }
+    }
+
+    /// Returns a future which writes all buffered output and flushes the stream.
+    #[inline]
+    pub fn flush(&mut self) -> Flush<W> {
+        Flush { writer: self }
+    }
+
+    /// Returns a future which writes all buffered output and closes the stream.
+    #[inline]
+    pub fn close(&mut self) -> Close<W> {
+        Close { writer: self }
+    }
+
+    /// Unwraps this `AsyncEventWriter`, returning the underlying stream.
+    ///
+    /// The output which has not been written by `flush()` or `close()` yet is lost.
+    pub fn into_inner(self) -> W {
+        self.sink
+    }
+
+    /// Writes the buffered output to the stream.
+    fn poll_write_buffer(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        while self.written < self.buf.len() {
+            match Pin::new(&mut self.sink).poll_write(cx, &self.buf[self.written..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(
+                    io::Error::new(io::ErrorKind::WriteZero, "failed to write the buffered data").into()
+                )),
+                Poll::Ready(Ok(n)) => self.written += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending
+            }
+        }
+        self.buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A future which writes an event to an `AsyncEventWriter`.
+///
+/// It is returned by `AsyncEventWriter::write()`.
+pub struct WriteEvent<'w, 'a, W: 'w> {
+    writer: &'w mut AsyncEventWriter<W>,
+    event: Option<XmlEvent<'a>>
+}
+
+impl<'w, 'a, W: AsyncWrite + Unpin> Future for WriteEvent<'w, 'a, W> {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        let this = &mut *self;
+        if let Some(event) = this.event.take() {
+            let writer = &mut *this.writer;
+            if let Err(e) = writer.emitter.emit_event(&mut writer.buf, event) {
+                return Poll::Ready(Err(e));
+            }
+        }
+        if this.writer.buf.len() < BUFFER_SIZE {
+            return Poll::Ready(Ok(()));
+        }
+        this.writer.poll_write_buffer(cx)
+    }
+}
+
+/// A future which flushes an `AsyncEventWriter`.
+///
+/// It is returned by `AsyncEventWriter::flush()`.
+pub struct Flush<'w, W: 'w> {
+    writer: &'w mut AsyncEventWriter<W>
+}
+
+impl<'w, W: AsyncWrite + Unpin> Future for Flush<'w, W> {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        match self.writer.poll_write_buffer(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other
+        }
+        Pin::new(&mut self.writer.sink).poll_flush(cx).map(|r| r.map_err(From::from))
+    }
+}
+
+/// A future which closes an `AsyncEventWriter`.
+///
+/// It is returned by `AsyncEventWriter::close()`.
+pub struct Close<'w, W: 'w> {
+    writer: &'w mut AsyncEventWriter<W>
+}
+
+impl<'w, W: AsyncWrite + Unpin> Future for Close<'w, W> {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        match self.writer.poll_write_buffer(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other
+        }
+        Pin::new(&mut self.writer.sink).poll_close(cx).map(|r| r.map_err(From::from))
+    }
+}
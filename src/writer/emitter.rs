@@ -13,6 +13,8 @@ use common::XmlVersion;
 use namespace::{NamespaceStack, NS_NO_PREFIX, NS_EMPTY_URI, NS_XMLNS_PREFIX, NS_XML_PREFIX};
 
 use writer::config::EmitterConfig;
+use writer::encoder::{Encoder, EncodingWriter};
+use writer::events::XmlEvent;
 
 /// An error which may be returned by `XmlWriter` when writing XML events.
 #[derive(Debug)]
@@ -32,7 +34,14 @@ pub enum EmitterError {
 
     /// End element name is not specified when it is needed, for example, when automatic
     /// closing is not enabled in configuration.
-    EndElementNameIsNotSpecified
+    EndElementNameIsNotSpecified,
+
+    /// The encoding given in the document declaration is not supported.
+    UnsupportedEncoding(String),
+
+    /// A character cannot be represented in the encoding of the document in a place where
+    /// character references are not allowed, for example, in a name or a comment.
+    UnrepresentableCharacter(char)
 }
 
 impl From<io::Error> for EmitterError {
@@ -49,6 +58,10 @@ impl fmt::Display for EmitterError {
         match *self {
             EmitterError::Io(ref e) =>
                 write!(f, "I/O error: {}", e),
+            EmitterError::UnsupportedEncoding(ref name) =>
+                write!(f, "encoding {} is not supported", name),
+            EmitterError::UnrepresentableCharacter(c) =>
+                write!(f, "character {:?} cannot be represented in the encoding of the document", c),
             ref other =>
                 write!(f, "{}", other.description()),
         }
@@ -68,6 +81,10 @@ impl Error for EmitterError {
                 "end element name is not equal to last start element name",
             EmitterError::EndElementNameIsNotSpecified =>
                 "end element name is not specified and can't be inferred",
+            EmitterError::UnsupportedEncoding(_) =>
+                "encoding is not supported",
+            EmitterError::UnrepresentableCharacter(_) =>
+                "character cannot be represented in the encoding of the document",
         }
     }
 }
@@ -87,6 +104,8 @@ pub struct Emitter {
 
     element_names: Vec<OwnedName>,
 
+    encoder: Encoder,
+
     start_document_emitted: bool,
     just_wrote_start_element: bool
 }
@@ -103,6 +122,8 @@ impl Emitter {
 
             element_names: Vec::new(),
 
+            encoder: Encoder::Utf8,
+
             start_document_emitted: false,
             just_wrote_start_element: false
         }
@@ -147,6 +168,60 @@ impl Emitter {
         &mut self.nst
     }
 
+    /// Returns the encoding of the document.
+    #[inline]
+    pub fn encoder(&self) -> &Encoder {
+        &self.encoder
+    }
+
+    /// Sets the encoding of the document, which can only be done before the document
+    /// declaration is written.
+    pub fn set_encoding(&mut self, encoding: &str) -> Result<()> {
+        if self.start_document_emitted {
+            return Err(EmitterError::DocumentStartAlreadyEmitted);
+        }
+        match Encoder::for_name(encoding) {
+            Some(encoder) => {
+                self.encoder = encoder;
+                Ok(())
+            }
+            None => Err(EmitterError::UnsupportedEncoding(encoding.into()))
+        }
+    }
+
+    /// Writes the given event to `target`, encoding it in the encoding of the document.
+    pub fn emit_event<W: Write>(&mut self, target: &mut W, event: XmlEvent) -> Result<()> {
+        if let XmlEvent::StartDocument { encoding, .. } = event {
+            try!(self.set_encoding(encoding.unwrap_or("UTF-8")));
+        }
+        let mut sink = EncodingWriter::new(target, self.encoder.clone());
+        let result = match event {
+            XmlEvent::StartDocument { version, encoding, standalone } =>
+                self.emit_start_document(&mut sink, version, encoding.unwrap_or("UTF-8"), standalone),
+            XmlEvent::ProcessingInstruction { name, data } =>
+                self.emit_processing_instruction(&mut sink, name, data),
+            XmlEvent::StartElement { name, attributes, namespace } => {
+                self.nst.push_empty().checked_target().extend(namespace.as_ref());
+                self.emit_start_element(&mut sink, name, &attributes)
+            }
+            XmlEvent::EndElement { name } => {
+                let r = self.emit_end_element(&mut sink, name);
+                self.nst.try_pop();
+                r
+            }
+            XmlEvent::Comment(content) =>
+                self.emit_comment(&mut sink, content),
+            XmlEvent::CData(content) =>
+                self.emit_cdata(&mut sink, content),
+            XmlEvent::Characters(content) =>
+                self.emit_characters(&mut sink, content)
+        };
+        match sink.unencodable() {
+            Some(c) if result.is_err() => Err(EmitterError::UnrepresentableCharacter(c)),
+            _ => result
+        }
+    }
+
     #[inline]
     fn wrote_text(&self) -> bool {
         self.indent_stack.last().unwrap().contains(WROTE_TEXT)
@@ -238,6 +313,10 @@ impl Emitter {
         }
         self.start_document_emitted = true;
 
+        if self.encoder.needs_bom() {
+            try!(target.write_all("\u{feff}".as_bytes()));
+        }
+
         wrapped_with!(self; before_markup(target) and after_markup,
             try_chain! {
                 write!(target, "<?xml version=\"{}\" encoding=\"{}\"", version, encoding),
@@ -342,11 +421,12 @@ impl Emitter {
     pub fn emit_attributes<W: Write>(&mut self, target: &mut W,
                                       attributes: &[Attribute]) -> Result<()> {
         for attr in attributes.iter() {
+            let value = if self.config.perform_escaping { escape_str_attribute(attr.value) }
+                        else { Cow::Borrowed(attr.value) };
             try!(write!(
                 target, " {}=\"{}\"",
                 attr.name.repr_display(),
-                if self.config.perform_escaping { escape_str_attribute(attr.value) }
-                else { Cow::Borrowed(attr.value) }
+                self.encoder.escape_unencodable(value)
             ))
         }
         Ok(())
@@ -393,11 +473,20 @@ impl Emitter {
             self.emit_characters(target, content)
         } else {
             // TODO: escape ']]>' characters in CDATA as two adjacent CDATA blocks
-            try_chain! {
-                target.write(b"<![CDATA["),
-                target.write(content.as_bytes()),
-                ignore target.write(b"]]>")
-            };
+            // Characters which cannot be encoded are written as character references
+            // between CDATA sections, omitting the empty ones
+            let mut rest = content;
+            while let Some((i, c)) = rest.char_indices().find(|&(_, c)| !self.encoder.can_encode(c)) {
+                if i > 0 {
+                    try!(write!(target, "<![CDATA[{}]]>", &rest[..i]));
+                }
+                try!(write!(target, "&#x{:X};", c as u32));
+                rest = &rest[i + c.len_utf8()..];
+            }
+            // an empty CDATA section is written only for empty content
+            if !rest.is_empty() || rest.len() == content.len() {
+                try!(write!(target, "<![CDATA[{}]]>", rest));
+            }
             self.after_text();
             Ok(())
         }
@@ -406,10 +495,9 @@ impl Emitter {
     pub fn emit_characters<W: Write>(&mut self, target: &mut W,
                                       content: &str) -> Result<()> {
         try!(self.fix_non_empty_element(target));
-        try!(target.write(
-            (if self.config.perform_escaping { escape_str_pcdata(content) }
-            else { Cow::Borrowed(content) }).as_bytes()
-        ));
+        let content = if self.config.perform_escaping { escape_str_pcdata(content) }
+                      else { Cow::Borrowed(content) };
+        try!(target.write(self.encoder.escape_unencodable(content).as_bytes()));
         self.after_text();
         Ok(())
     }
@@ -0,0 +1,149 @@
+//! Contains encoding of the output stream.
+
+use std::borrow::Cow;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+use std::str;
+
+use encoding::SingleByteEncoding;
+
+/// An encoding in which the emitter writes the document.
+#[derive(Clone, Debug)]
+pub enum Encoder {
+    Utf8,
+    Utf16 { big_endian: bool, bom: bool },
+    SingleByte(SingleByteEncoding)
+}
+
+impl Encoder {
+    /// Returns the encoder for the encoding name given in `StartDocument`, ignoring case.
+    ///
+    /// `UTF-16` is written in little-endian byte order with a byte order mark, while
+    /// `UTF-16BE` and `UTF-16LE` are written without it.
+    pub fn for_name(name: &str) -> Option<Encoder> {
+        match &name.to_ascii_uppercase()[..] {
+            "UTF-8" => Some(Encoder::Utf8),
+            "UTF-16" => Some(Encoder::Utf16 { big_endian: false, bom: true }),
+            "UTF-16BE" => Some(Encoder::Utf16 { big_endian: true, bom: false }),
+            "UTF-16LE" => Some(Encoder::Utf16 { big_endian: false, bom: false }),
+            _ => SingleByteEncoding::for_label(name).map(Encoder::SingleByte)
+        }
+    }
+
+    /// Returns true if the document must start with a byte order mark.
+    #[inline]
+    pub fn needs_bom(&self) -> bool {
+        match *self {
+            Encoder::Utf16 { bom, .. } => bom,
+            _ => false
+        }
+    }
+
+    /// Returns true if the character can be represented in the encoding.
+    #[inline]
+    pub fn can_encode(&self, c: char) -> bool {
+        match *self {
+            Encoder::SingleByte(ref e) => e.encode(c).is_some(),
+            _ => true
+        }
+    }
+
+    /// Replaces characters which cannot be represented in the encoding with character
+    /// references; only valid in character data and attribute values.
+    pub fn escape_unencodable<'a>(&self, s: Cow<'a, str>) -> Cow<'a, str> {
+        if s.chars().all(|c| self.can_encode(c)) {
+            return s;
+        }
+        let mut result = String::with_capacity(s.len() + 8);
+        for c in s.chars() {
+            if self.can_encode(c) {
+                result.push(c);
+            } else {
+                write!(result, "&#x{:X};", c as u32).unwrap();
+            }
+        }
+        result.into()
+    }
+
+    /// Appends the encoded character to `out`; returns false if the character cannot be
+    /// represented in the encoding.
+    fn encode(&self, c: char, out: &mut Vec<u8>) -> bool {
+        match *self {
+            Encoder::Utf8 => {
+                let mut buf = [0; 4];
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            Encoder::Utf16 { big_endian, .. } => {
+                let mut buf = [0; 2];
+                for &unit in c.encode_utf16(&mut buf).iter() {
+                    if big_endian {
+                        out.push((unit >> 8) as u8);
+                        out.push(unit as u8);
+                    } else {
+                        out.push(unit as u8);
+                        out.push((unit >> 8) as u8);
+                    }
+                }
+            }
+            Encoder::SingleByte(ref e) => match e.encode(c) {
+                Some(b) => out.push(b),
+                None => return false
+            }
+        }
+        true
+    }
+}
+
+/// A writer which encodes the UTF-8 text written by the emitter into the sink.
+///
+/// The emitter always writes whole strings, so UTF-8 sequences are never split
+/// between calls to `write()`.
+pub struct EncodingWriter<'a, W: 'a> {
+    sink: &'a mut W,
+    encoder: Encoder,
+    buf: Vec<u8>,
+    /// The character which could not be encoded, which fails the write.
+    unencodable: Option<char>
+}
+
+impl<'a, W: Write> EncodingWriter<'a, W> {
+    pub fn new(sink: &'a mut W, encoder: Encoder) -> EncodingWriter<'a, W> {
+        EncodingWriter {
+            sink: sink,
+            encoder: encoder,
+            buf: Vec::new(),
+            unencodable: None
+        }
+    }
+
+    /// Returns the character which failed the last write, if any.
+    #[inline]
+    pub fn unencodable(&self) -> Option<char> { self.unencodable }
+}
+
+impl<'a, W: Write> Write for EncodingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Encoder::Utf8 = self.encoder {
+            try!(self.sink.write_all(buf));
+            return Ok(buf.len());
+        }
+        let s = match str::from_utf8(buf) {
+            Ok(s) => s,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e))
+        };
+        self.buf.clear();
+        for c in s.chars() {
+            if !self.encoder.encode(c, &mut self.buf) {
+                self.unencodable = Some(c);
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "character cannot be represented in the output encoding"));
+            }
+        }
+        try!(self.sink.write_all(&self.buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
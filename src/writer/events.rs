@@ -26,7 +26,11 @@ pub enum XmlEvent<'a> {
 
         /// XML document encoding.
         ///
-        /// Defaults to `Some("UTF-8")`.
+        /// Defaults to `Some("UTF-8")`. The document is written in this encoding, which can
+        /// be UTF-8, UTF-16 (with a byte order mark), UTF-16BE, UTF-16LE or one of the
+        /// single-byte encodings known to `SingleByteEncoding::for_label()`. Characters which
+        /// cannot be represented in a single-byte encoding are written as character
+        /// references in character data and attribute values, and are an error elsewhere.
         encoding: Option<&'a str>,
 
         /// XML standalone declaration.
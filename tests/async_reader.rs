@@ -0,0 +1,146 @@
+#![cfg(feature = "async")]
+
+extern crate futures_io;
+extern crate xml;
+
+use std::cmp;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::ptr;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use futures_io::AsyncRead;
+
+use xml::common::Position;
+use xml::reader::{ParserConfig, EventReader, AsyncEventReader, XmlEvent, ErrorKind};
+
+/// An in-memory stream which returns at most `chunk` bytes at a time and is not ready
+/// on every other poll.
+struct Trickle<'a> {
+    data: &'a [u8],
+    chunk: usize,
+    ready: bool
+}
+
+impl<'a> Trickle<'a> {
+    fn new(data: &'a [u8], chunk: usize) -> Trickle<'a> {
+        Trickle { data: data, chunk: chunk, ready: false }
+    }
+}
+
+impl<'a> AsyncRead for Trickle<'a> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if !self.ready {
+            self.ready = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        self.ready = false;
+        let n = cmp::min(cmp::min(buf.len(), self.chunk), self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Poll::Ready(Ok(n))
+    }
+}
+
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker { RawWaker::new(ptr::null(), &VTABLE) }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    unsafe { Waker::from_raw(clone(ptr::null())) }
+}
+
+/// Polls the future until it is ready.
+fn block_on<F: Future>(f: F) -> F::Output {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut f = Box::pin(f);
+    loop {
+        if let Poll::Ready(result) = f.as_mut().poll(&mut cx) {
+            return result;
+        }
+    }
+}
+
+/// Checks that `AsyncEventReader` reading `chunk` bytes at a time produces the same events
+/// at the same positions as `EventReader`.
+fn compare(input: &[u8], config: ParserConfig, chunk: usize) {
+    let mut expected = EventReader::new_with_config(input, config.clone());
+    let mut actual = AsyncEventReader::new_with_config(Trickle::new(input, chunk), config);
+    loop {
+        let e = expected.next();
+        let a = block_on(actual.next());
+        assert_eq!(e, a);
+        match a {
+            Ok(_) => assert_eq!(expected.position(), actual.position(), "position of {:?}", a),
+            Err(ref a) => assert_eq!(e.unwrap_err().position(), a.position())
+        }
+        match a {
+            Ok(XmlEvent::EndDocument) | Err(_) => break,
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn samples() {
+    let samples: [&[u8]; 5] = [
+        include_bytes!("documents/sample_1.xml"),
+        include_bytes!("documents/sample_2.xml"),
+        include_bytes!("documents/sample_3.xml"),
+        include_bytes!("documents/sample_4.xml"),
+        include_bytes!("documents/sample_5.xml"),
+    ];
+    for sample in &samples {
+        for &chunk in &[1, 5, 4096] {
+            compare(sample, ParserConfig::new(), chunk);
+            compare(sample, ParserConfig::new().trim_whitespace(true).coalesce_characters(true), chunk);
+        }
+    }
+}
+
+#[test]
+fn suspended_inside_characters() {
+    // line breaks and characters split between reads
+    compare(b"<a>x\r\ny\r\r\nz\r</a>", ParserConfig::new(), 1);
+    compare("<?xml version='1.1'?><a>x\r\u{85}y\u{2028}\u{444}</a>".as_bytes(), ParserConfig::new(), 1);
+
+    let mut utf16 = vec![0xFF, 0xFE];
+    for unit in "<a b='\u{1F600}'>\u{1F600}\r\n</a>".encode_utf16() {
+        utf16.push(unit as u8);
+        utf16.push((unit >> 8) as u8);
+    }
+    for &chunk in &[1, 3] {
+        compare(&utf16, ParserConfig::new(), chunk);
+    }
+}
+
+#[test]
+fn errors() {
+    compare(b"<a><b></a>", ParserConfig::new(), 1);
+    compare(b"<a>text", ParserConfig::new(), 2);
+    compare(b"<a>\xC3</a>", ParserConfig::new(), 1);
+}
+
+#[test]
+fn stream_error() {
+    struct Failing;
+
+    impl AsyncRead for Failing {
+        fn poll_read(self: Pin<&mut Self>, _: &mut Context, _: &mut [u8]) -> Poll<io::Result<usize>> {
+            Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, "connection reset")))
+        }
+    }
+
+    let mut reader = AsyncEventReader::new(Failing);
+    for _ in 0..2 {
+        match block_on(reader.next()) {
+            Err(ref e) => match *e.kind() {
+                ErrorKind::Io(ref e) => assert_eq!(e.kind(), io::ErrorKind::Other),
+                ref kind => panic!("Unexpected error: {:?}", kind)
+            },
+            Ok(e) => panic!("Unexpected event: {:?}", e)
+        }
+    }
+}
@@ -0,0 +1,169 @@
+#![cfg(feature = "async")]
+
+extern crate futures_io;
+extern crate xml;
+
+use std::cmp;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::ptr;
+use std::str;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use futures_io::AsyncWrite;
+
+use xml::reader::EventReader;
+use xml::writer::{EmitterConfig, EventWriter, AsyncEventWriter, XmlEvent};
+
+/// An in-memory stream which accepts at most `chunk` bytes at a time and is not ready
+/// on every other poll, or never if `chunk` is zero.
+struct Slow {
+    data: Vec<u8>,
+    chunk: usize,
+    ready: bool,
+    closed: bool
+}
+
+impl Slow {
+    fn new(chunk: usize) -> Slow {
+        Slow { data: Vec::new(), chunk: chunk, ready: false, closed: false }
+    }
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<()> {
+        if self.chunk == 0 || !self.ready {
+            self.ready = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        self.ready = false;
+        Poll::Ready(())
+    }
+}
+
+impl AsyncWrite for Slow {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.poll_ready(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let n = cmp::min(buf.len(), self.chunk);
+        self.data.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_ready(cx).map(Ok)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        if self.poll_ready(cx).is_pending() {
+            return Poll::Pending;
+        }
+        self.closed = true;
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker { RawWaker::new(ptr::null(), &VTABLE) }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    unsafe { Waker::from_raw(clone(ptr::null())) }
+}
+
+fn poll_once<F: Future + Unpin>(f: &mut F) -> Poll<F::Output> {
+    let waker = noop_waker();
+    Pin::new(f).poll(&mut Context::from_waker(&waker))
+}
+
+/// Polls the future until it is ready.
+fn block_on<F: Future + Unpin>(mut f: F) -> F::Output {
+    loop {
+        if let Poll::Ready(result) = poll_once(&mut f) {
+            return result;
+        }
+    }
+}
+
+#[test]
+fn same_output_as_event_writer() {
+    let input = include_bytes!("documents/sample_2.xml");
+    let config = EmitterConfig::new().perform_indent(true);
+
+    let mut expected = Vec::new();
+    {
+        let mut w = EventWriter::new_with_config(&mut expected, config.clone());
+        for e in EventReader::new(&input[..]) {
+            if let Some(e) = e.unwrap().as_writer_event() {
+                w.write(e).unwrap();
+            }
+        }
+    }
+
+    let mut w = AsyncEventWriter::new_with_config(Slow::new(3), config);
+    for e in EventReader::new(&input[..]) {
+        if let Some(e) = e.unwrap().as_writer_event() {
+            block_on(w.write(e)).unwrap();
+        }
+    }
+    block_on(w.close()).unwrap();
+
+    let sink = w.into_inner();
+    assert!(sink.closed);
+    assert_eq!(str::from_utf8(&sink.data).unwrap(), str::from_utf8(&expected).unwrap());
+}
+
+#[test]
+fn writes_wait_for_the_stream() {
+    let text: String = (0..1000).map(|_| 'x').collect();
+    let mut w = AsyncEventWriter::new_with_config(
+        Slow::new(0), EmitterConfig::new().write_document_declaration(false)
+    );
+    assert!(poll_once(&mut w.write(XmlEvent::start_element("a"))).is_ready());
+
+    // the output is buffered until there are several kilobytes of it
+    let mut written = 0;
+    loop {
+        match poll_once(&mut w.write(&text[..])) {
+            Poll::Ready(result) => {
+                result.unwrap();
+                written += 1;
+            }
+            Poll::Pending => break
+        }
+        assert!(written < 100, "the writer does not wait for the stream");
+    }
+    assert!(written > 1);
+    assert!(poll_once(&mut w.flush()).is_pending());
+
+    assert!(w.into_inner().data.is_empty());
+}
+
+#[test]
+fn flushing_writes_the_buffer() {
+    let mut w = AsyncEventWriter::new_with_config(
+        Slow::new(1), EmitterConfig::new().write_document_declaration(false)
+    );
+    block_on(w.write(XmlEvent::start_element("a"))).unwrap();
+    block_on(w.write("text")).unwrap();
+    block_on(w.write(XmlEvent::end_element())).unwrap();
+    assert!(w.into_inner().data.is_empty());
+
+    let mut w = AsyncEventWriter::new_with_config(
+        Slow::new(1), EmitterConfig::new().write_document_declaration(false)
+    );
+    block_on(w.write(XmlEvent::start_element("a"))).unwrap();
+    block_on(w.write("text")).unwrap();
+    block_on(w.write(XmlEvent::end_element())).unwrap();
+    block_on(w.flush()).unwrap();
+    let sink = w.into_inner();
+    assert!(!sink.closed);
+    assert_eq!(str::from_utf8(&sink.data).unwrap(), "<a>text</a>");
+}
+
+#[test]
+fn emitter_errors() {
+    let mut w = AsyncEventWriter::new(Slow::new(1));
+    block_on(w.write(XmlEvent::start_element("a"))).unwrap();
+    assert!(block_on(w.write(XmlEvent::end_element().name("b"))).is_err());
+}
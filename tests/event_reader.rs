@@ -3,13 +3,14 @@ extern crate xml;
 use std::env;
 use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Write, stderr};
+use std::io::{self, BufRead, BufReader, Read, Write, stderr};
 use std::path::Path;
 use std::sync::{Once, ONCE_INIT};
 
 use xml::name::OwnedName;
-use xml::common::Position;
-use xml::reader::{Result, XmlEvent, ParserConfig, EventReader};
+use xml::encoding::SingleByteEncoding;
+use xml::common::{Position, XmlVersion};
+use xml::reader::{Result, XmlEvent, ParserConfig, EventReader, EntityResolver, MapResolver, ErrorKind};
 
 /// Dummy function that opens a file, parses it, and returns a `Result`.
 /// There can be IO errors (from `File::open`) and XML errors (from the parser).
@@ -337,6 +338,1137 @@ fn issue_attribues_have_no_default_namespace () {
     );
 }
 
+#[test]
+fn doctype_with_internal_subset() {
+    test(
+        br#"<?xml version="1.0"?>
+<!DOCTYPE doc PUBLIC "-//Acme//Doc//EN" "doc.dtd" [
+    <!ELEMENT doc (#PCDATA)>
+    <!-- a comment with > inside -->
+    <!ATTLIST doc x CDATA "a > b">
+    <!ENTITY e "<x/>">
+]>
+<doc/>"#,
+        br#"
+            |1:1 StartDocument(1.0, UTF-8)
+            |2:1 Doctype(doc, Some("-//Acme//Doc//EN"), Some("doc.dtd"))
+            |8:1 StartElement(doc [x="a > b" (defaulted)])
+            |8:1 EndElement(doc)
+            |8:7 EndDocument
+        "#,
+        ParserConfig::new()
+            .trim_whitespace(true),
+        true
+    );
+}
+
+#[test]
+fn attribute_defaults() {
+    test(
+        br#"<!DOCTYPE doc [
+    <!ATTLIST doc
+        xmlns CDATA #FIXED "urn:doc"
+        xmlns:x CDATA "urn:x"
+        xml:space (default | preserve) "preserve"
+        x:kind NMTOKEN " a "
+        refs IDREFS #IMPLIED
+        tokens NMTOKENS "  a  b "
+        title CDATA "  a  b ">
+]>
+<doc refs=" a  b c " title="  c ">
+    <doc xmlns:x="urn:y" tokens="c"/>
+</doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement({urn:doc}doc [refs="a b c", title="  c ", {http://www.w3.org/XML/1998/namespace}xml:space="preserve" (defaulted), {urn:x}x:kind="a" (defaulted), tokens="a b" (defaulted)])
+            |StartElement({urn:doc}doc [tokens="c", {http://www.w3.org/XML/1998/namespace}xml:space="preserve" (defaulted), {urn:y}x:kind="a" (defaulted), title="  a  b " (defaulted)])
+            |EndElement({urn:doc}doc)
+            |EndElement({urn:doc}doc)
+            |EndDocument
+        "#,
+        ParserConfig::new()
+            .trim_whitespace(true),
+        false
+    );
+}
+
+#[test]
+fn entity_references_in_attribute_defaults() {
+    test(
+        br#"<!DOCTYPE doc [<!ENTITY c "Acme"><!ATTLIST doc a CDATA "&c;" b CDATA "&c; &amp; Co">]><doc b="&c;"/>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc [b="Acme", a="Acme" (defaulted)])
+            |EndElement(doc)
+            |EndDocument
+        "#,
+        ParserConfig::new(),
+        false
+    );
+}
+
+#[test]
+fn tokenized_attributes_without_normalization() {
+    test(
+        b"<!DOCTYPE doc [<!ATTLIST doc tokens NMTOKENS #IMPLIED title CDATA #IMPLIED>]>\
+          <doc tokens='\ta\n\tb\r\n c\t' title='\ta\nb'/>",
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc [tokens="a b c", title="\ta\nb"])
+            |EndElement(doc)
+            |EndDocument
+        "#,
+        ParserConfig::new()
+            .normalize_attribute_values(false),
+        false
+    );
+}
+
+#[test]
+fn doctype_without_declaration() {
+    test(
+        br#"<!DOCTYPE doc><doc/>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc)
+            |EndElement(doc)
+            |EndDocument
+        "#,
+        ParserConfig::new(),
+        false
+    );
+}
+
+#[test]
+fn doctype_errors() {
+    test(
+        br#"<!DOCTYPE doc [<!ELEMENT doc (a, b | c)>]><doc/>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |1:36 Cannot mix ',' and '|' in the same content particle
+        "#,
+        ParserConfig::new(),
+        false
+    );
+    test(
+        br#"<doc/><!DOCTYPE doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |StartElement(doc)
+            |EndElement(doc)
+            |1:7 Unexpected token: <!DOCTYPE
+        "#,
+        ParserConfig::new(),
+        false
+    );
+}
+
+#[test]
+fn internal_entities() {
+    test(
+        br#"<!DOCTYPE doc [
+    <!ENTITY company "Acme &amp; Co.">
+    <!ENTITY sig "<p:sig xmlns:p='urn:sig'>&company;</p:sig>">
+    <!ENTITY rows "<x:row/>&#38;#60;&#x20;<x:row/>">
+]>
+<doc xmlns:x="urn:x" attr="&company; &#38;">&company;: &sig;&rows;</doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc [attr="Acme & Co. &"])
+            |Characters("Acme & Co.: ")
+            |StartElement({urn:sig}p:sig)
+            |Characters("Acme & Co.")
+            |EndElement({urn:sig}p:sig)
+            |StartElement({urn:x}x:row)
+            |EndElement({urn:x}x:row)
+            |Characters("< ")
+            |StartElement({urn:x}x:row)
+            |EndElement({urn:x}x:row)
+            |EndElement(doc)
+            |EndDocument
+        "#,
+        ParserConfig::new(),
+        false
+    );
+}
+
+#[test]
+fn internal_entities_in_attribute_values() {
+    test(
+        br#"<!DOCTYPE doc [
+    <!ENTITY a "&b;-&b;">
+    <!ENTITY b "&#38;#60;">
+    <!ENTITY lt2 "&#60;">
+]>
+<doc x="[&a;]"><e y="&lt2;"/></doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc [x="[<-<]"])
+            |6:26 Unexpected token inside attribute value: <
+        "#,
+        ParserConfig::new(),
+        false
+    );
+}
+
+#[test]
+fn malformed_internal_entities() {
+    test(
+        br#"<!DOCTYPE doc [<!ENTITY a "<a>">]><doc>&a;</a></doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc)
+            |StartElement(a)
+            |1:43 Unexpected end of entity: a
+        "#,
+        ParserConfig::new(),
+        false
+    );
+    test(
+        br#"<!DOCTYPE doc [<!ENTITY a "</doc>">]><doc>&a;"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc)
+            |1:46 Unexpected closing tag inside entity a: doc
+        "#,
+        ParserConfig::new(),
+        false
+    );
+    test(
+        br#"<!DOCTYPE doc [<!ENTITY a "x&b;"><!ENTITY b "&a;">]><doc>&a;</doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc)
+            |1:61 Recursive entity reference: a
+        "#,
+        ParserConfig::new(),
+        false
+    );
+    test(
+        br#"<!DOCTYPE doc [<!ENTITY a "x&b;"><!ENTITY b "&a;">]><doc x="&a;"/>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |1:63 Recursive entity reference: a
+        "#,
+        ParserConfig::new(),
+        false
+    );
+}
+
+#[test]
+fn external_entities() {
+    let resolver = MapResolver::new()
+        .add("doc.dtd", "<?xml version='1.0' encoding='UTF-8'?>\n\
+                         <!ENTITY chapter SYSTEM 'chapter.xml'>\n\
+                         <!ENTITY title 'External'>")
+        .add("chapter.xml", "<?xml encoding='UTF-8'?><chapter>&title;</chapter>")
+        .add("-//Acme//Chapter//EN", "<chapter>&title;</chapter>");
+
+    test(
+        br#"<!DOCTYPE doc SYSTEM "doc.dtd" [<!ENTITY title "Internal">]><doc>&chapter;</doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, Some("doc.dtd"))
+            |StartElement(doc)
+            |StartElement(chapter)
+            |Characters("Internal")
+            |EndElement(chapter)
+            |EndElement(doc)
+            |EndDocument
+        "#,
+        ParserConfig::new()
+            .entity_resolver(resolver.clone()),
+        false
+    );
+    test(
+        br#"<!DOCTYPE doc [<!ENTITY c PUBLIC "-//Acme//Chapter//EN" "c.xml">]><doc>&c;</doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc)
+            |StartElement(chapter)
+            |1:75 Unexpected entity: title
+        "#,
+        ParserConfig::new()
+            .entity_resolver(resolver.clone()),
+        false
+    );
+    test(
+        br#"<!DOCTYPE doc [<!ENTITY a SYSTEM "doc.dtd">]><doc x="&a;"/>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |1:56 Unexpected external entity reference inside attribute value: a
+        "#,
+        ParserConfig::new()
+            .entity_resolver(resolver),
+        false
+    );
+}
+
+#[test]
+fn parameter_entities() {
+    let resolver = MapResolver::new()
+        .add("doc.dtd", "<!ENTITY % content SYSTEM 'content.ent'>\n\
+                         <![%draft;[ <!ENTITY status 'draft'> ]]>\n\
+                         <![IGNORE[ <!ENTITY status 'ignored'> ]]>\n\
+                         <!ENTITY status 'final'>\n\
+                         %content;")
+        .add("content.ent", "<!ENTITY % text '&#60;p>%title;&#60;/p>'>\n\
+                             <!ENTITY body '%text;'>");
+
+    test(
+        br#"<!DOCTYPE doc SYSTEM "doc.dtd" [
+            <!ENTITY % draft "INCLUDE">
+            <!ENTITY % title "&#38;status;">
+        ]><doc>&body;</doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, Some("doc.dtd"))
+            |StartElement(doc)
+            |StartElement(p)
+            |Characters("draft")
+            |EndElement(p)
+            |EndElement(doc)
+            |EndDocument
+        "#,
+        ParserConfig::new()
+            .entity_resolver(resolver.clone()),
+        false
+    );
+    test(
+        br#"<!DOCTYPE doc SYSTEM "doc.dtd" [<!ENTITY % draft "IGNORE"><!ENTITY % title "&#38;status;">]><doc>&body;</doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, Some("doc.dtd"))
+            |StartElement(doc)
+            |StartElement(p)
+            |Characters("final")
+            |EndElement(p)
+            |EndElement(doc)
+            |EndDocument
+        "#,
+        ParserConfig::new()
+            .entity_resolver(resolver),
+        false
+    );
+}
+
+#[test]
+fn external_entities_are_refused_by_default() {
+    test(
+        br#"<!DOCTYPE doc SYSTEM "/etc/passwd" [<!ENTITY a SYSTEM "a.xml">]><doc>&a;</doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, Some("/etc/passwd"))
+            |StartElement(doc)
+            |1:72 Cannot resolve external entity: a
+        "#,
+        ParserConfig::new(),
+        false
+    );
+}
+
+fn utf16(s: &str, big_endian: bool, bom: bool) -> Vec<u8> {
+    let units = if bom { Some(0xFEFF) } else { None }.into_iter().chain(s.encode_utf16());
+    units.flat_map(|u| if big_endian { vec![(u >> 8) as u8, u as u8] } else { vec![u as u8, (u >> 8) as u8] })
+        .collect()
+}
+
+#[test]
+fn encoding_detection() {
+    let events = r#"
+        |1:1 StartDocument(1.0, UTF-16)
+        |1:1 StartElement(doc)
+        |1:6 Characters("фx")
+        |1:8 EndElement(doc)
+        |1:14 EndDocument
+    "#.as_bytes();
+    test(&utf16("<doc>фx</doc>", true, true), events, ParserConfig::new(), true);
+    test(&utf16("<doc>фx</doc>", false, true), events, ParserConfig::new(), true);
+    test(
+        &utf16("<?xml version='1.0' encoding='utf-16le'?><doc>\u{1f60a}</doc>", false, false),
+        r#"
+            |StartDocument(1.0, utf-16le)
+            |StartElement(doc)
+            |Characters("😊")
+            |EndElement(doc)
+            |EndDocument
+        "#.as_bytes(),
+        ParserConfig::new(),
+        false
+    );
+    test(
+        b"\xEF\xBB\xBF<doc/>",
+        br#"
+            |1:1 StartDocument(1.0, UTF-8)
+            |1:1 StartElement(doc)
+            |1:1 EndElement(doc)
+            |1:7 EndDocument
+        "#,
+        ParserConfig::new(),
+        true
+    );
+}
+
+#[test]
+fn encoding_errors() {
+    test(
+        &utf16("<?xml version='1.0' encoding='UTF-8'?><doc/>", true, true),
+        br#"
+            |1:1 1:36 Declared encoding UTF-8 does not match the detected encoding UTF-16
+        "#,
+        ParserConfig::new(),
+        true
+    );
+    test(
+        &utf16("<?xml version='1.0' encoding='UTF-16BE'?><doc/>", false, false),
+        br#"
+            |1:1 1:39 Declared encoding UTF-16BE does not match the detected encoding UTF-16LE
+        "#,
+        ParserConfig::new(),
+        true
+    );
+    test(
+        b"<?xml version='1.0' encoding='UTF-16'?><doc/>",
+        br#"
+            |1:1 1:37 Declared encoding UTF-16 does not match the detected encoding UTF-8
+        "#,
+        ParserConfig::new(),
+        true
+    );
+    test(
+        b"\xEF\xBB\xBF<?xml version='1.0' encoding='ISO-8859-1'?><doc/>",
+        br#"
+            |1:1 1:41 Declared encoding ISO-8859-1 does not match the detected encoding UTF-8
+        "#,
+        ParserConfig::new(),
+        true
+    );
+    test(
+        b"\xFE\xFF\x00<\xD8\x00\x00>",
+        br#"
+            |1:1 1:1 UTF-16 decoding error: unpaired surrogate 0xD800
+        "#,
+        ParserConfig::new(),
+        true
+    );
+}
+
+#[test]
+fn single_byte_encodings() {
+    test(
+        b"<?xml version='1.0' encoding='ISO-8859-1'?><doc a='\xE9'>caf\xE9</doc>",
+        r#"
+            |StartDocument(1.0, ISO-8859-1)
+            |StartElement(doc [a="é"])
+            |Characters("café")
+            |EndElement(doc)
+            |EndDocument
+        "#.as_bytes(),
+        ParserConfig::new(),
+        false
+    );
+    test(
+        b"<?xml version='1.0' encoding='windows-1252'?><doc>\x80 \x93q\x94</doc>",
+        r#"
+            |StartDocument(1.0, windows-1252)
+            |StartElement(doc)
+            |Characters("€ “q”")
+            |EndElement(doc)
+            |EndDocument
+        "#.as_bytes(),
+        ParserConfig::new(),
+        false
+    );
+
+    let mut table = vec![0; 128];
+    table[0] = 'ж' as u16;
+    test(
+        b"<?xml version='1.0' encoding='X-Custom'?><doc>\x80</doc>",
+        r#"
+            |StartDocument(1.0, X-Custom)
+            |StartElement(doc)
+            |Characters("ж")
+            |EndElement(doc)
+            |EndDocument
+        "#.as_bytes(),
+        ParserConfig::new()
+            .add_encoding("x-custom", SingleByteEncoding::new("x-custom", table)),
+        false
+    );
+
+    let resolver = MapResolver::new()
+        .add("title.ent", &b"<?xml encoding='latin1'?>\xC0 la carte"[..])
+        .add("bad.ent", &b"<?xml version='1.0' encoding='Shift_JIS'?>title"[..]);
+    test(
+        br#"<!DOCTYPE doc [<!ENTITY t SYSTEM "title.ent">]><doc>&t;</doc>"#,
+        r#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc)
+            |Characters("À la carte")
+            |EndElement(doc)
+            |EndDocument
+        "#.as_bytes(),
+        ParserConfig::new()
+            .entity_resolver(resolver.clone()),
+        false
+    );
+    test(
+        br#"<!DOCTYPE doc [<!ENTITY t SYSTEM "bad.ent">]><doc>&t;</doc>"#,
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc)
+            |1:53 Cannot read external entity bad.ent: Unsupported encoding: Shift_JIS
+        "#,
+        ParserConfig::new()
+            .entity_resolver(resolver),
+        false
+    );
+}
+
+#[test]
+fn single_byte_encoding_errors() {
+    test(
+        b"<?xml version='1.0' encoding='Shift_JIS'?><doc/>",
+        br#"
+            |1:1 1:40 Unsupported encoding: Shift_JIS
+        "#,
+        ParserConfig::new(),
+        true
+    );
+    test(
+        b"<?xml version='1.0' encoding='windows-1252'?><doc>\x81</doc>",
+        br#"
+            |1:1 StartDocument(1.0, windows-1252)
+            |1:46 StartElement(doc)
+            |1:46 1:1 Byte 0x81 is not used by encoding windows-1252
+        "#,
+        ParserConfig::new(),
+        true
+    );
+}
+
+#[test]
+fn end_of_line_normalization() {
+    test(
+        b"<?xml version='1.0'?>\r\n<doc a='x\r\ny'>\r\n  a\rb\r\r\nc<![CDATA[\r\n]]>\xC2\x85\xE2\x80\xA8<!--\r-->\r\n</doc>",
+        r#"
+            |1:1 StartDocument(1.0, UTF-8)
+            |2:1 StartElement(doc [a="x y"])
+            |3:4 Characters("\n  a\nb\n\nc")
+            |7:2 CData("\n")
+            |8:4 Characters("\u{85}\u{2028}")
+            |8:6 Comment("\n")
+            |9:4 Whitespace("\n")
+            |10:1 EndElement(doc)
+            |10:7 EndDocument
+        "#.as_bytes(),
+        ParserConfig::new()
+            .ignore_comments(false)
+            .coalesce_characters(false),
+        true
+    );
+    test(
+        b"<?xml version='1.1'?>\r\xC2\x85<doc>a\xC2\x85b\r\xC2\x85c\xE2\x80\xA8&#13;&#x85;</doc>",
+        r#"
+            |1:1 StartDocument(1.1, UTF-8)
+            |2:1 StartElement(doc)
+            |2:6 Characters("a\nb\nc\n\r\u{85}")
+            |5:12 EndElement(doc)
+            |5:18 EndDocument
+        "#.as_bytes(),
+        ParserConfig::new(),
+        true
+    );
+}
+
+#[test]
+fn attribute_value_normalization() {
+    let doc = "<!DOCTYPE doc [
+    <!ENTITY tab \"a&#9;b\">
+    <!ENTITY nl \"&#38;#10;\">
+    <!ATTLIST doc d CDATA 'x\ty'>
+]>
+<doc a='\t1\n2 &#9;3&#10;4&#13;' b='&tab;&nl;' c=\"  x\r\n\"/>";
+    test(
+        doc.as_bytes(),
+        r#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc [a=" 1 2 \t3\n4\r", b="a b\n", c="  x ", d="x y" (defaulted)])
+            |EndElement(doc)
+            |EndDocument
+        "#.as_bytes(),
+        ParserConfig::new(),
+        false
+    );
+    test(
+        doc.as_bytes(),
+        r#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc [a="\t1\n2 \t3\n4\r", b="a\tb\n", c="  x\n", d="x\ty" (defaulted)])
+            |EndElement(doc)
+            |EndDocument
+        "#.as_bytes(),
+        ParserConfig::new()
+            .normalize_attribute_values(false),
+        false
+    );
+}
+
+#[test]
+fn long_text() {
+    // text and attribute values longer than the input buffer, with characters split
+    // between reads
+    let line = "x\u{444}\u{1F600} y\tz\r\n";
+    let text: String = (0..2000).map(|_| line).collect();
+    let doc = format!("<a v='{}'>{}<b/></a>", text, text);
+
+    let mut reader = EventReader::new(doc.as_bytes());
+    reader.next().unwrap();
+    let normalized = text.replace("\r\n", "\n");
+    match reader.next().unwrap() {
+        XmlEvent::StartElement { ref attributes, .. } =>
+            assert_eq!(attributes[0].value, normalized.replace(&['\t', '\n'][..], " ")),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    match reader.next().unwrap() {
+        XmlEvent::Characters(ref data) => assert_eq!(*data, normalized),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    assert_eq!("2001:3", reader.position().to_string());
+    reader.next().unwrap();
+    assert_eq!("4001:1", reader.position().to_string());
+}
+
+#[test]
+fn non_blocking_stream() {
+    // a stream which has no data available before each byte
+    struct NonBlocking<'a> {
+        data: &'a [u8],
+        ready: bool
+    }
+
+    impl<'a> Read for NonBlocking<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.ready {
+                self.ready = true;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.ready = false;
+            (&mut self.data).take(1).read(buf)
+        }
+    }
+
+    let doc = "<?xml version='1.0'?>\r\n<a x='1\r\n2'>\u{444}\r\n&amp;<b/><!-- c --></a>";
+    let mut reader = EventReader::new(NonBlocking { data: doc.as_bytes(), ready: false });
+    let mut expected = EventReader::from_str(doc);
+    let mut blocked = 0;
+    loop {
+        match reader.next() {
+            Err(ref e) if e.is_would_block() => blocked += 1,
+            e => {
+                assert_eq!(e, expected.next());
+                assert_eq!(reader.position(), expected.position());
+                if e == Ok(XmlEvent::EndDocument) {
+                    break;
+                }
+            }
+        }
+    }
+    assert!(blocked >= doc.len());
+}
+
+#[test]
+fn peeking() {
+    let doc = "<a>\n  <b x='1'/>text</a>";
+    let mut reader = EventReader::new_with_config(doc.as_bytes(), ParserConfig::new().trim_whitespace(true));
+    let mut expected = EventReader::new_with_config(doc.as_bytes(), ParserConfig::new().trim_whitespace(true));
+
+    let start = reader.position();
+    match reader.peek_nth(2) {
+        Ok(&XmlEvent::StartElement { ref name, .. }) => assert_eq!(name.local_name, "b"),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    assert_eq!(reader.position(), start);
+
+    // events are returned by next() with their positions
+    loop {
+        let e = expected.next();
+        assert_eq!(reader.peek().cloned(), e);
+        assert_eq!(reader.next(), e);
+        assert_eq!(reader.position(), expected.position());
+        if e == Ok(XmlEvent::EndDocument) {
+            break;
+        }
+    }
+
+    // peeking past the end returns the last event
+    let mut reader = EventReader::from_str("<a/>");
+    assert_eq!(reader.peek_nth(10).unwrap(), &XmlEvent::EndDocument);
+    assert_eq!(reader.next().unwrap(), XmlEvent::StartDocument {
+        version: XmlVersion::Version10,
+        encoding: "UTF-8".into(),
+        standalone: None
+    });
+
+    let mut reader = EventReader::from_str("<a></b>");
+    assert_eq!(reader.peek_nth(10).unwrap_err().msg(), "Unexpected closing tag: b, expected a");
+    assert!(reader.next().is_ok());
+    assert!(reader.next().is_ok());
+    assert_eq!(reader.next().unwrap_err().position().to_string(), "1:7");
+
+    // lookahead is bounded
+    let mut reader = EventReader::new_with_config("<a><b/></a>".as_bytes(), ParserConfig::new().max_lookahead(2));
+    let e = reader.peek_nth(2).unwrap_err();
+    assert_eq!(e.kind(), &ErrorKind::LookaheadLimit("Lookahead limit of 2 events exceeded".into()));
+    match reader.peek_nth(1) {
+        Ok(&XmlEvent::StartElement { ref name, .. }) => assert_eq!(name.local_name, "a"),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    assert!(reader.next().is_ok());
+    assert!(reader.next().is_ok());
+    assert!(reader.peek_nth(1).is_ok());
+}
+
+#[test]
+fn skipping_and_reading_text() {
+    let doc = "<root xmlns:x='urn:x'>\
+                 <skip a='1'><!-- c --><inner>lots of text<?pi data?></inner><![CDATA[raw]]></skip>\
+                 <x:t>one <b>two</b><![CDATA[ three]]></x:t>\
+                 <deep><x:outer><inner/>tail</x:outer></deep>\
+                 <last/>\
+               </root>";
+    let mut reader = EventReader::from_str(doc);
+    fn start<R: Read>(reader: &mut EventReader<R>, expected: &str) {
+        loop {
+            match reader.next().unwrap() {
+                XmlEvent::StartElement { ref name, .. } if name.local_name == expected => return,
+                XmlEvent::StartElement { name, .. } => panic!("Unexpected element: {}", name),
+                _ => {}
+            }
+        }
+    }
+
+    start(&mut reader, "root");
+    start(&mut reader, "skip");
+    reader.skip_element().unwrap();
+    start(&mut reader, "t");
+    assert_eq!(reader.read_text().unwrap(), "one two three");
+    start(&mut reader, "deep");
+    start(&mut reader, "outer");
+    start(&mut reader, "inner");
+    reader.read_to_end("x:outer").unwrap();
+    // text is collected again after skipping
+    match reader.next().unwrap() {
+        XmlEvent::EndElement { ref name } => assert_eq!(name.local_name, "deep"),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    match reader.next().unwrap() {
+        XmlEvent::StartElement { ref name, ref namespace, .. } => {
+            assert_eq!(name.local_name, "last");
+            assert_eq!(namespace.get("x"), Some("urn:x"));
+        }
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    reader.skip_element().unwrap();
+    match reader.next().unwrap() {
+        XmlEvent::EndElement { ref name } => assert_eq!(name.local_name, "root"),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+
+    // errors inside skipped content are reported
+    let mut reader = EventReader::from_str("<a><b><c></b></a>");
+    start(&mut reader, "a");
+    start(&mut reader, "b");
+    assert_eq!(reader.skip_element().unwrap_err().msg(), "Unexpected closing tag: b, expected c");
+
+    let mut reader = EventReader::from_str("<a/>");
+    assert_eq!(reader.skip_element().unwrap_err().msg(), "Unexpected end of document while skipping an element");
+}
+
+#[test]
+fn element_context() {
+    let doc = "<root xmlns='urn:default' xmlns:ns='urn:ns'>\
+                 <ns:item xmlns:xsi='http://www.w3.org/2001/XMLSchema-instance' xsi:type='ns:Foo'/>\
+                 <other>text</other>\
+               </root>";
+    let mut reader = EventReader::from_str(doc);
+    assert_eq!(reader.depth(), 0);
+    reader.next().unwrap();  // StartDocument
+    reader.next().unwrap();  // root
+    assert_eq!(reader.element_path(), &[OwnedName::qualified("root", "urn:default", None::<&str>)]);
+
+    match reader.next().unwrap() {
+        XmlEvent::StartElement { ref attributes, .. } => {
+            let path: Vec<_> = reader.element_path().iter().map(|n| n.to_string()).collect();
+            assert_eq!(path, ["{urn:default}root", "{urn:ns}ns:item"]);
+            assert_eq!(reader.namespace().get("xsi"), Some("http://www.w3.org/2001/XMLSchema-instance"));
+            assert_eq!(reader.resolve_qname(&attributes[0].value),
+                       Some(OwnedName::qualified("Foo", "urn:ns", Some("ns"))));
+            assert_eq!(reader.resolve_qname("Bar"), Some(OwnedName::qualified("Bar", "urn:default", None::<&str>)));
+            assert_eq!(reader.resolve_qname("unbound:Bar"), None);
+            assert_eq!(reader.resolve_qname("not:a:name"), None);
+        }
+        e => panic!("Unexpected event: {:?}", e)
+    }
+
+    // the element is still in scope at its closing tag
+    assert!(match reader.next().unwrap() { XmlEvent::EndElement { .. } => true, _ => false });
+    assert_eq!(reader.depth(), 2);
+    assert!(reader.namespace().get("xsi").is_some());
+
+    reader.next().unwrap();  // other
+    assert_eq!(reader.depth(), 2);
+    assert!(reader.namespace().get("xsi").is_none());
+    assert_eq!(reader.next().unwrap(), XmlEvent::Characters("text".into()));
+    assert_eq!(reader.element_path()[1].local_name, "other");
+    reader.next().unwrap();
+    assert_eq!(reader.next().unwrap(), XmlEvent::EndElement { name: OwnedName::qualified("root", "urn:default", None::<&str>) });
+    assert_eq!(reader.depth(), 1);
+    assert_eq!(reader.next().unwrap(), XmlEvent::EndDocument);
+    assert_eq!(reader.depth(), 0);
+}
+
+#[test]
+fn element_context_with_peek() {
+    let mut reader = EventReader::from_str("<a xmlns:p='urn:p'><p:b xmlns:q='urn:q'><c/></p:b></a>");
+    reader.next().unwrap();  // StartDocument
+    reader.next().unwrap();  // a
+    assert_eq!(reader.peek_nth(2).unwrap(), &XmlEvent::EndElement { name: OwnedName::local("c") });
+    assert_eq!(reader.element_path(), &[OwnedName::local("a")]);
+    assert_eq!(reader.depth(), 1);
+    assert!(reader.namespace().get("q").is_none());
+
+    reader.next().unwrap();  // p:b
+    assert_eq!(reader.depth(), 2);
+    assert_eq!(reader.namespace().get("q"), Some("urn:q"));
+    reader.next().unwrap();  // c
+    let path: Vec<_> = reader.element_path().iter().map(|n| n.to_string()).collect();
+    assert_eq!(path, ["a", "{urn:p}p:b", "c"]);
+
+    // the last peeked event has the live context of the parser
+    reader.next().unwrap();  // /c
+    assert_eq!(reader.depth(), 3);
+    assert_eq!(reader.peek().unwrap(), &XmlEvent::EndElement { name: OwnedName::qualified("b", "urn:p", Some("p")) });
+    assert_eq!(reader.depth(), 3);
+    reader.next().unwrap();  // /p:b
+    assert_eq!(reader.depth(), 2);
+    reader.next().unwrap();  // /a
+    assert_eq!(reader.depth(), 1);
+    assert!(reader.namespace().get("q").is_none());
+    assert_eq!(reader.namespace().get("p"), Some("urn:p"));
+}
+
+#[test]
+fn spans() {
+    fn slices(doc: &str, config: ParserConfig) -> Vec<String> {
+        let mut reader = EventReader::new_with_config(doc.as_bytes(), config);
+        let mut result = Vec::new();
+        loop {
+            let e = reader.next().unwrap();
+            let span = reader.span();
+            result.push(format!("{:?}", &doc[span.start as usize..span.end as usize]));
+            for a in reader.attribute_spans() {
+                result.push(format!("  {:?}={:?}", &doc[a.name.start as usize..a.name.end as usize],
+                                    &doc[a.value.start as usize..a.value.end as usize]));
+            }
+            if e == XmlEvent::EndDocument {
+                return result;
+            }
+        }
+    }
+
+    let doc = "<?xml version='1.0'?>\r\n<a x:y='1' xmlns:x='urn:x' b = \"t&amp;\u{444}\">\r\n\
+               text&amp;\u{444} <![CDATA[c]]><!--c--><?pi d?><e/></a>\n";
+    assert_eq!(slices(doc, ParserConfig::new().ignore_comments(false)), vec![
+        r#""<?xml version='1.0'?>""#,
+        r#""<a x:y='1' xmlns:x='urn:x' b = \"t&amp;ф\">""#,
+        r#"  "x:y"="1""#,
+        r#"  "b"="t&amp;ф""#,
+        r#""\r\ntext&amp;ф ""#,
+        r#""<![CDATA[c]]>""#,
+        r#""<!--c-->""#,
+        r#""<?pi d?>""#,
+        r#""<e/>""#,
+        r#""<e/>""#,
+        r#""</a>""#,
+        r#""""#,
+    ]);
+
+    // coalesced character data spans all of its parts, and the document declaration
+    // is empty if it is missing
+    let doc = "\u{feff}<!DOCTYPE a [<!ENTITY e '<b/>'>]><a>x<![CDATA[y]]>&e;z</a>";
+    assert_eq!(slices(doc, ParserConfig::new().cdata_to_characters(true)), vec![
+        r#""""#,
+        r#""<!DOCTYPE a [<!ENTITY e '<b/>'>]>""#,
+        r#""<a>""#,
+        r#""x<![CDATA[y]]>&e;""#,
+        r#""""#,
+        r#""""#,
+        r#""z""#,
+        r#""</a>""#,
+        r#""""#,
+    ]);
+
+    // character data starting with a reference
+    let doc = "<a>&amp;q<!--c-->&#x41;<?pi?>&#65;r</a>";
+    assert_eq!(slices(doc, ParserConfig::new().ignore_comments(false)), vec![
+        r#""""#,
+        r#""<a>""#,
+        r#""&amp;q""#,
+        r#""<!--c-->""#,
+        r#""&#x41;""#,
+        r#""<?pi?>""#,
+        r#""&#65;r""#,
+        r#""</a>""#,
+        r#""""#,
+    ]);
+
+    // an entity reference producing only markup
+    let doc = "<!DOCTYPE a [<!ENTITY e '<b/>'>]><a>&e;&amp;</a>";
+    assert_eq!(slices(doc, ParserConfig::new()), vec![
+        r#""""#,
+        r#""<!DOCTYPE a [<!ENTITY e '<b/>'>]>""#,
+        r#""<a>""#,
+        r#""""#,
+        r#""""#,
+        r#""&amp;""#,
+        r#""</a>""#,
+        r#""""#,
+    ]);
+
+    // offsets count bytes of the input
+    let bytes: Vec<u8> = "\u{feff}<a b='\u{444}'/>".encode_utf16().flat_map(|u| vec![u as u8, (u >> 8) as u8]).collect();
+    let mut reader = EventReader::new(&bytes[..]);
+    reader.next().unwrap();
+    reader.next().unwrap();
+    assert_eq!(reader.span().to_string(), "2..22");
+    assert_eq!(reader.attribute_spans()[0].value.to_string(), "14..16");
+}
+
+static BILLION_LAUGHS: &'static str = r#"<?xml version="1.0"?>
+<!DOCTYPE lolz [
+    <!ENTITY lol "lol">
+    <!ENTITY lol1 "&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;">
+    <!ENTITY lol2 "&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;">
+    <!ENTITY lol3 "&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;">
+    <!ENTITY lol4 "&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;">
+    <!ENTITY lol5 "&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;">
+    <!ENTITY lol6 "&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;">
+    <!ENTITY lol7 "&lol6;&lol6;&lol6;&lol6;&lol6;&lol6;&lol6;&lol6;&lol6;&lol6;">
+    <!ENTITY lol8 "&lol7;&lol7;&lol7;&lol7;&lol7;&lol7;&lol7;&lol7;&lol7;&lol7;">
+    <!ENTITY lol9 "&lol8;&lol8;&lol8;&lol8;&lol8;&lol8;&lol8;&lol8;&lol8;&lol8;">
+]>
+<lolz>&lol9;</lolz>"#;
+
+fn expect_entity_expansion_limit(doc: &str, config: ParserConfig, msg: &str) {
+    let mut reader = EventReader::new_with_config(doc.as_bytes(), config);
+    loop {
+        match reader.next() {
+            Ok(XmlEvent::EndDocument) => panic!("Unexpected end of document"),
+            Ok(_) => {}
+            Err(e) => {
+                match *e.kind() {
+                    ErrorKind::EntityExpansionLimit(_) => {}
+                    ref kind => panic!("Unexpected error kind: {:?}", kind)
+                }
+                assert_eq!(e.msg(), msg);
+                return;
+            }
+        }
+    }
+}
+
+#[test]
+fn entity_expansion_limits() {
+    expect_entity_expansion_limit(
+        BILLION_LAUGHS, ParserConfig::new(),
+        "Entity expansion ratio limit of 100 exceeded"
+    );
+    expect_entity_expansion_limit(
+        &BILLION_LAUGHS.replace("<lolz>&lol9;</lolz>", "<lolz a='&lol9;'/>"), ParserConfig::new(),
+        "Entity expansion ratio limit of 100 exceeded"
+    );
+    expect_entity_expansion_limit(
+        BILLION_LAUGHS, ParserConfig::new().max_entity_expansion_size(50000),
+        "Entity expansion size limit of 50000 characters exceeded"
+    );
+    expect_entity_expansion_limit(
+        BILLION_LAUGHS, ParserConfig::new().max_entity_expansion_depth(5),
+        "Entity expansion depth limit of 5 exceeded"
+    );
+
+    test(
+        BILLION_LAUGHS.replace("&lol9;", "&lol1;").as_bytes(),
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(lolz, None, None)
+            |StartElement(lolz)
+            |Characters("lollollollollollollollollollol")
+            |EndElement(lolz)
+            |EndDocument
+        "#,
+        ParserConfig::new()
+            .max_entity_expansion_size(80),
+        false
+    );
+    test(
+        BILLION_LAUGHS.replace("&lol9;", "&lol1;").as_bytes(),
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(lolz, None, None)
+            |StartElement(lolz)
+            |14:13 Entity expansion size limit of 79 characters exceeded
+        "#,
+        ParserConfig::new()
+            .max_entity_expansion_size(79),
+        false
+    );
+
+    // external entities and subsets are not read beyond the limit
+    struct EndlessResolver;
+
+    impl EntityResolver for EndlessResolver {
+        fn resolve(&self, _: Option<&str>, _: &str) -> io::Result<Option<Box<Read>>> {
+            Ok(Some(Box::new(io::repeat(b'a'))))
+        }
+    }
+
+    for doc in &[r#"<!DOCTYPE doc [<!ENTITY e SYSTEM "e.xml">]><doc>&e;</doc>"#,
+                 r#"<!DOCTYPE doc SYSTEM "doc.dtd"><doc/>"#,
+                 r#"<!DOCTYPE doc [<!ENTITY % e SYSTEM "e.dtd">%e;]><doc/>"#] {
+        expect_entity_expansion_limit(
+            doc, ParserConfig::new().entity_resolver(EndlessResolver).max_entity_expansion_size(100),
+            "Entity expansion size limit of 100 characters exceeded"
+        );
+    }
+}
+
+
+static VALIDATED_DTD: &'static str = r#"<!DOCTYPE doc [
+<!ELEMENT doc (head, (p | list)*)>
+<!ELEMENT head EMPTY>
+<!ELEMENT p (#PCDATA | em)*>
+<!ELEMENT em (#PCDATA)>
+<!ELEMENT list ANY>
+<!ATTLIST doc version CDATA #FIXED "1.0">
+<!ATTLIST head title CDATA #REQUIRED>
+<!ATTLIST p id ID #IMPLIED align (left | right) "left" refs IDREFS #IMPLIED>
+]>"#;
+
+fn validity_error(doc: &str) -> String {
+    let mut reader = EventReader::new_with_config(doc.as_bytes(), ParserConfig::new().validate_dtd(true));
+    loop {
+        match reader.next() {
+            Ok(XmlEvent::EndDocument) => panic!("Unexpected end of document"),
+            Ok(_) => {}
+            Err(e) => {
+                match *e.kind() {
+                    ErrorKind::Validity(_) => {}
+                    ref kind => panic!("Unexpected error kind: {:?}", kind)
+                }
+                return e.to_string();
+            }
+        }
+    }
+}
+
+#[test]
+fn dtd_validation() {
+    test(
+        format!("{}{}", VALIDATED_DTD, r#"<doc>
+            <head title="Report"/>
+            <p id="a" align=" right ">Text <em>with</em> markup</p>
+            <list><p refs="a b"/><p id="b"/></list>
+        </doc>"#).as_bytes(),
+        br#"
+            |StartDocument(1.0, UTF-8)
+            |Doctype(doc, None, None)
+            |StartElement(doc [version="1.0" (defaulted)])
+            |StartElement(head [title="Report"])
+            |EndElement(head)
+            |StartElement(p [id="a", align="right"])
+            |Characters("Text")
+            |StartElement(em)
+            |Characters("with")
+            |EndElement(em)
+            |Characters("markup")
+            |EndElement(p)
+            |StartElement(list)
+            |StartElement(p [refs="a b", align="left" (defaulted)])
+            |EndElement(p)
+            |StartElement(p [id="b", align="left" (defaulted)])
+            |EndElement(p)
+            |EndElement(list)
+            |EndElement(doc)
+            |EndDocument
+        "#,
+        ParserConfig::new()
+            .trim_whitespace(true)
+            .validate_dtd(true),
+        false
+    );
+
+    let invalid = |body: &str| validity_error(&format!("{}\n{}", VALIDATED_DTD, body));
+    assert_eq!(validity_error("<doc/>"), "1:1 Document has no DTD to validate against");
+    assert_eq!(invalid("<head/>"), "11:1 Root element head does not match the document type name doc");
+    assert_eq!(invalid("<doc><foo/></doc>"), "11:6 Element type foo is not declared");
+    assert_eq!(invalid("<doc><p/></doc>"), "11:6 Element p is not allowed in the content of element doc");
+    assert_eq!(invalid("<doc>\n\n</doc>"), "13:1 Content of element doc is incomplete");
+    assert_eq!(invalid("<doc><head title=''/>text</doc>"),
+               "11:22 Character data is not allowed in the content of element doc");
+    assert_eq!(invalid("<doc><head title=''> </head></doc>"), "11:21 Element head is declared EMPTY but has content");
+    assert_eq!(invalid("<doc><head title=''/><p><p/></p></doc>"),
+               "11:25 Element p is not allowed in the content of element p");
+    assert_eq!(invalid("<doc><head/></doc>"), "11:6 Required attribute title of element head is missing");
+    assert_eq!(invalid("<doc version='2.0'/>"), "11:1 Attribute version of element doc must have the fixed value \"1.0\"");
+    assert_eq!(invalid("<doc><head title='' lang='en'/></doc>"), "11:6 Attribute lang is not declared for element head");
+    assert_eq!(invalid("<doc><head title=''/><p align='center'/></doc>"),
+               "11:22 Value \"center\" of attribute align of element p does not match its declared type");
+    assert_eq!(invalid("<doc><head title=''/><p id='1'/></doc>"),
+               "11:22 Value \"1\" of attribute id of element p does not match its declared type");
+    assert_eq!(invalid("<doc><head title=''/><p id='a'/><p id='a'/></doc>"), "11:33 Duplicate ID value: a");
+    assert_eq!(invalid("<doc><head title=''/><p id='a'/>\n<p refs='a b'/></doc>"), "12:1 IDREF b does not match any ID");
+}
+
+#[test]
+fn dtd_validation_of_tokenized_attributes() {
+    let dtd = r#"<!DOCTYPE doc [
+<!ELEMENT doc EMPTY>
+<!NOTATION gif SYSTEM "image/gif">
+<!ATTLIST doc kind NMTOKEN #FIXED " a " tokens NMTOKENS #IMPLIED format NOTATION (gif | png) #IMPLIED>
+]>"#;
+    for body in &["<doc/>", "<doc kind='a'/>", "<doc kind=' a '/>", "<doc tokens=' a  b '/>", "<doc format='gif'/>"] {
+        let doc = format!("{}{}", dtd, body);
+        let reader = EventReader::new_with_config(doc.as_bytes(), ParserConfig::new().validate_dtd(true));
+        for e in reader {
+            assert!(e.is_ok(), "{}: {}", body, e.unwrap_err());
+        }
+    }
+
+    let invalid = |body: &str| validity_error(&format!("{}{}", dtd, body));
+    assert_eq!(invalid("<doc kind='b'/>"), "5:3 Attribute kind of element doc must have the fixed value \" a \"");
+    // references to white space characters are not normalized
+    assert_eq!(invalid("<doc tokens='a&#9;b'/>"),
+               "5:3 Value \"a\tb\" of attribute tokens of element doc does not match its declared type");
+    assert_eq!(invalid("<doc format='png'/>"), "5:3 Attribute format of element doc does not name a declared notation: png");
+}
+
 
 static START: Once = ONCE_INIT;
 static mut PRINT: bool = false;
@@ -430,6 +1562,8 @@ impl<'a> fmt::Display for Event<'a> {
                     write!(f, "StartDocument({}, {})", version, encoding),
                 XmlEvent::EndDocument =>
                     write!(f, "EndDocument"),
+                XmlEvent::Doctype { ref name, ref public_id, ref system_id, .. } =>
+                    write!(f, "Doctype({}, {:?}, {:?})", name, public_id, system_id),
                 XmlEvent::ProcessingInstruction { ref name, ref data } =>
                     write!(f, "ProcessingInstruction({}={:?})", name,
                         data.as_ref().unwrap_or(&empty)),
@@ -439,7 +1573,9 @@ impl<'a> fmt::Display for Event<'a> {
                     }
                     else {
                         let attrs: Vec<_> = attributes.iter()
-                            .map(|a| format!("{}={:?}", Name(&a.name), a.value)) .collect();
+                            .map(|a| format!("{}={:?}{}", Name(&a.name), a.value,
+                                             if a.defaulted { " (defaulted)" } else { "" }))
+                            .collect();
                         write!(f, "StartElement({} [{}])", Name(name), attrs.join(", "))
                     }
                 },
@@ -5,7 +5,8 @@ use std::io::prelude::*;
 use std::fs::File;
 use std::str;
 
-use xml::reader::EventReader;
+use xml::common::XmlVersion;
+use xml::reader::{EventReader, ParserConfig, XmlEvent as ReaderEvent};
 use xml::writer::EmitterConfig;
 
 macro_rules! unwrap_all {
@@ -219,4 +220,98 @@ fn attribute_escaping() {
 <hello testNl=\"&#xA;\" testCr=\"&#xD;\" />
 <hello testNl=\"\\n\" testCr=\"\\r\" />"
     );
-}
\ No newline at end of file
+}
+#[test]
+fn writing_in_different_encodings() {
+    use xml::writer::XmlEvent;
+
+    fn write_document(encoding: &str) -> Vec<u8> {
+        let mut b = Vec::new();
+        {
+            let mut w = EmitterConfig::new().create_writer(&mut b);
+            unwrap_all! {
+                w.write(XmlEvent::StartDocument { version: XmlVersion::Version10, encoding: Some(encoding), standalone: None });
+                w.write(XmlEvent::start_element("café").attr("price", "5 €"));
+                w.write("déjà vu ☺");
+                w.write(XmlEvent::cdata("a☺b"));
+                w.write(XmlEvent::end_element())
+            }
+        }
+        b
+    }
+
+    let utf16 = write_document("UTF-16");
+    assert_eq!(&utf16[..4], b"\xFF\xFE<\x00");
+    let units: Vec<u16> = utf16.chunks(2).map(|c| c[0] as u16 | (c[1] as u16) << 8).collect();
+    assert_eq!(
+        String::from_utf16(&units).unwrap(),
+        "\u{feff}<?xml version=\"1.0\" encoding=\"UTF-16\"?><café price=\"5 €\">déjà vu ☺<![CDATA[a☺b]]></café>"
+    );
+    assert_eq!(&write_document("UTF-16BE")[..4], b"\x00<\x00?");
+
+    assert_eq!(
+        write_document("ISO-8859-1"),
+        &b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><caf\xE9 price=\"5 &#x20AC;\">d\xE9j\xE0 vu &#x263A;\
+           <![CDATA[a]]>&#x263A;<![CDATA[b]]></caf\xE9>"[..]
+    );
+    assert_eq!(
+        write_document("windows-1252"),
+        &b"<?xml version=\"1.0\" encoding=\"windows-1252\"?><caf\xE9 price=\"5 \x80\">d\xE9j\xE0 vu &#x263A;\
+           <![CDATA[a]]>&#x263A;<![CDATA[b]]></caf\xE9>"[..]
+    );
+
+    // The characters read back are the same in all encodings
+    for &encoding in &["UTF-8", "UTF-16", "UTF-16LE", "ISO-8859-1", "windows-1252"] {
+        let document = write_document(encoding);
+        let reader = ParserConfig::new().cdata_to_characters(true).create_reader(&document[..]);
+        let events: Vec<_> = reader.into_iter().map(|e| e.unwrap()).collect();
+        assert_eq!(events[2], ReaderEvent::Characters("déjà vu ☺a☺b".into()), "{}", encoding);
+    }
+}
+
+#[test]
+fn unencodable_cdata() {
+    use xml::writer::XmlEvent;
+
+    let mut b = Vec::new();
+    {
+        let mut w = EmitterConfig::new().create_writer(&mut b);
+        unwrap_all! {
+            w.write(XmlEvent::StartDocument { version: XmlVersion::Version10, encoding: Some("ISO-8859-1"), standalone: None });
+            w.write(XmlEvent::start_element("a"));
+            w.write(XmlEvent::cdata("\u{263A}\u{444}"));
+            w.write(XmlEvent::cdata("\u{263A}b\u{444}"));
+            w.write(XmlEvent::cdata(""));
+            w.write(XmlEvent::end_element())
+        }
+    }
+    assert_eq!(
+        b,
+        &b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a>&#x263A;&#x444;&#x263A;<![CDATA[b]]>&#x444;\
+           <![CDATA[]]></a>"[..]
+    );
+}
+
+#[test]
+fn encoding_errors() {
+    use xml::writer::{XmlEvent, Error};
+
+    let mut b = Vec::new();
+    let mut w = EmitterConfig::new().create_writer(&mut b);
+    match w.write(XmlEvent::StartDocument { version: XmlVersion::Version10, encoding: Some("KOI8-R"), standalone: None }) {
+        Err(Error::UnsupportedEncoding(ref name)) if name == "KOI8-R" => {}
+        r => panic!("Unexpected result: {:?}", r)
+    }
+    unwrap_all! {
+        w.write(XmlEvent::StartDocument { version: XmlVersion::Version10, encoding: Some("US-ASCII"), standalone: None });
+        w.write(XmlEvent::start_element("a"))
+    }
+    match w.write(XmlEvent::start_element("é")) {
+        Err(Error::UnrepresentableCharacter('é')) => {}
+        r => panic!("Unexpected result: {:?}", r)
+    }
+    match w.write(XmlEvent::comment("☺")) {
+        Err(ref e) => assert_eq!(e.to_string(), "emitter error: character '☺' cannot be represented in the encoding of the document"),
+        r => panic!("Unexpected result: {:?}", r)
+    }
+}
@@ -0,0 +1,134 @@
+extern crate xml;
+
+use xml::common::{Position, XmlVersion};
+use xml::reader::{ParserConfig, EventReader, PushReader, XmlEvent};
+
+/// Checks that `PushReader` fed with `chunk` bytes at a time produces the same events
+/// at the same positions and spans as `EventReader`.
+fn compare(input: &[u8], config: ParserConfig, chunk: usize) {
+    let mut expected = EventReader::new_with_config(input, config.clone());
+    let mut actual = PushReader::new_with_config(config);
+    let mut chunks = input.chunks(chunk);
+    loop {
+        let a = match actual.next() {
+            Some(a) => a,
+            None => {
+                match chunks.next() {
+                    Some(chunk) => actual.feed(chunk),
+                    None => actual.finish()
+                }
+                continue;
+            }
+        };
+        let e = expected.next();
+        assert_eq!(e, a);
+        match a {
+            Ok(_) => {
+                assert_eq!(expected.position(), actual.position(), "position of {:?}", a);
+                assert_eq!(expected.span(), actual.span(), "span of {:?}", a);
+                assert_eq!(expected.attribute_spans(), actual.attribute_spans(), "attribute spans of {:?}", a);
+            }
+            Err(ref a) => assert_eq!(e.unwrap_err().position(), a.position())
+        }
+        match a {
+            Ok(XmlEvent::EndDocument) | Err(_) => break,
+            _ => {}
+        }
+    }
+    assert!(actual.next().is_none());
+}
+
+#[test]
+fn samples() {
+    let samples: [&[u8]; 5] = [
+        include_bytes!("documents/sample_1.xml"),
+        include_bytes!("documents/sample_2.xml"),
+        include_bytes!("documents/sample_3.xml"),
+        include_bytes!("documents/sample_4.xml"),
+        include_bytes!("documents/sample_5.xml"),
+    ];
+    for sample in &samples {
+        for &chunk in &[1, 7, 100000] {
+            compare(sample, ParserConfig::new(), chunk);
+            compare(sample, ParserConfig::new().trim_whitespace(true).coalesce_characters(false), chunk);
+        }
+    }
+
+    let crlf = "<?xml version='1.0'?>\r\n<a b='1'\r\n   c='2'>\r\n  text\r\n\r\n  <d/>\r\n</a>\r\n";
+    compare(crlf.as_bytes(), ParserConfig::new(), 1);
+}
+
+#[test]
+fn errors() {
+    compare(b"<a><b></a>", ParserConfig::new(), 1);
+    compare(b"<a>text", ParserConfig::new(), 3);
+    compare(b"", ParserConfig::new(), 1);
+}
+
+#[test]
+fn partial_tokens_are_kept() {
+    let mut reader = PushReader::new_with_config(ParserConfig::new().coalesce_characters(false));
+    reader.feed(b"<a><b attr='x");
+    assert_eq!(reader.next(), Some(Ok(XmlEvent::StartDocument {
+        version: XmlVersion::Version10,
+        encoding: "UTF-8".into(),
+        standalone: None
+    })));
+    match reader.next() {
+        Some(Ok(XmlEvent::StartElement { ref name, .. })) => assert_eq!(name.local_name, "a"),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    assert_eq!(reader.next(), None);
+
+    // a character split between chunks
+    reader.feed(b"y'>\xD1");
+    match reader.next() {
+        Some(Ok(XmlEvent::StartElement { ref attributes, .. })) => assert_eq!(attributes[0].value, "xy"),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    assert_eq!(reader.next(), None);
+    reader.feed(b"\x84</");
+    assert_eq!(reader.next(), Some(Ok(XmlEvent::Characters("\u{444}".into()))));
+    assert_eq!(reader.next(), None);
+    reader.feed(b"b>");
+    assert!(reader.next().unwrap().is_ok());
+    assert_eq!(reader.next(), None);
+}
+
+#[test]
+fn long_lived_stream() {
+    // events are produced as soon as they are complete, without waiting for the end
+    // of the root element
+    let mut reader = PushReader::new();
+    reader.feed(b"<stream:stream xmlns:stream='http://etherx.jabber.org/streams'>");
+    let mut events = 0;
+    while let Some(e) = reader.next() {
+        e.unwrap();
+        events += 1;
+    }
+    assert_eq!(events, 2);
+
+    for _ in 0..3 {
+        reader.feed(b"<message><body>Hi");
+        reader.feed(b"!</body></message>");
+        let mut names = Vec::new();
+        while let Some(e) = reader.next() {
+            match e.unwrap() {
+                XmlEvent::StartElement { name, .. } => names.push(name.local_name),
+                XmlEvent::Characters(data) => assert_eq!(data, "Hi!"),
+                XmlEvent::EndElement { .. } => {}
+                e => panic!("Unexpected event: {:?}", e)
+            }
+        }
+        assert_eq!(names, ["message", "body"]);
+    }
+
+    reader.feed(b"</stream:stream>");
+    reader.finish();
+    match reader.next() {
+        Some(Ok(XmlEvent::EndElement { ref name })) => assert_eq!(name.local_name, "stream"),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    assert_eq!(reader.next(), Some(Ok(XmlEvent::EndDocument)));
+    assert_eq!(reader.next(), None);
+}
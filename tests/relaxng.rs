@@ -0,0 +1,328 @@
+extern crate xml;
+
+use std::io::{self, Read};
+
+use xml::EventReader;
+use xml::reader::{ErrorKind, MapResolver};
+use xml::relaxng::Schema;
+
+static ADDRESS_BOOK: &'static str = r#"<grammar xmlns="http://relaxng.org/ns/structure/1.0"
+         ns="urn:book" datatypeLibrary="http://www.w3.org/2001/XMLSchema-datatypes">
+  <start>
+    <element name="book">
+      <optional><attribute name="version"><value>1.0</value></attribute></optional>
+      <zeroOrMore><ref name="card"/></zeroOrMore>
+    </element>
+  </start>
+  <define name="card">
+    <element name="card">
+      <attribute name="id"><data type="ID"/></attribute>
+      <interleave>
+        <element name="name"><text/></element>
+        <oneOrMore>
+          <element name="email">
+            <data type="string"><param name="pattern">[^@]+@[^@]+</param></data>
+          </element>
+        </oneOrMore>
+        <optional><element name="age"><data type="nonNegativeInteger"><param name="maxInclusive">150</param></data></element></optional>
+      </interleave>
+      <optional><element name="note"><mixed><zeroOrMore><element name="b"><text/></element></zeroOrMore></mixed></element></optional>
+      <optional><element name="tags"><list><oneOrMore><choice><value type="token">home</value><value>work</value></choice></oneOrMore></list></element></optional>
+    </element>
+  </define>
+</grammar>"#;
+
+static ADDRESS_BOOK_COMPACT: &'static str = r#"
+default namespace = "urn:book"
+datatypes d = "http://www.w3.org/2001/XMLSchema-datatypes"
+
+start = element book { attribute version { "1.0" }?, card* }
+card = element card {
+  attribute id { d:ID },
+  (element name { text }
+   & element email { d:string { pattern = "[^@]+@[^@]+" } }+
+   & element age { xsd:nonNegativeInteger { maxInclusive = "150" } }?),
+  element note { mixed { element b { text }* } }?,
+  element tags { list { ("home" | "work")+ } }?
+}
+"#;
+
+fn validate(schema: &Schema, doc: &str) -> Result<(), String> {
+    schema.validate(EventReader::from_str(doc)).map_err(|e| {
+        match *e.kind() {
+            ErrorKind::Validity(_) => {}
+            ref kind => panic!("Unexpected error kind: {:?}", kind)
+        }
+        e.to_string()
+    })
+}
+
+fn book(body: &str) -> String {
+    format!("<book xmlns=\"urn:book\">\n{}\n</book>", body)
+}
+
+fn check_address_book(schema: &Schema) {
+    let valid = [
+        "",
+        "<card id='a'><name>Jane</name><email>jane@example.com</email></card>",
+        "<card id='a'>\n  <email>a@b</email>\n  <age> 42 </age>\n  <email>c@d</email>\n  <name/>\n</card>\
+         <card id='b'><name>Joe</name><email>joe@example.com</email><note>Met <b>twice</b>.</note>\
+         <tags> work  home </tags></card>",
+    ];
+    for body in &valid {
+        assert_eq!(validate(schema, &book(body)), Ok(()), "{}", body);
+    }
+    assert_eq!(validate(schema, "<book xmlns='urn:book' version=' 1.0 '/>"), Ok(()));
+
+    let invalid = [
+        ("<card id='a'><name>Jane</name></card>",
+         "2:31 Content of element {urn:book}card is incomplete; expected {urn:book}email, {urn:book}age"),
+        ("<card id='a'><name>Jane</name><name>Joe</name></card>",
+         "2:31 Element {urn:book}name is not allowed here in the content of element {urn:book}card; \
+          expected {urn:book}email, {urn:book}age"),
+        ("<card><name>Jane</name><email>a@b</email></card>",
+         "2:1 Required attribute id of element {urn:book}card is missing"),
+        ("<card id='1'><name>Jane</name><email>a@b</email></card>",
+         "2:1 Invalid value of attribute id of element {urn:book}card: \"1\" is not a valid ID"),
+        ("<card id='a' x='1'><name>Jane</name><email>a@b</email></card>",
+         "2:1 Attribute x is not allowed on element {urn:book}card"),
+        ("<card id='a'><name>Jane</name><email>jane</email></card>",
+         "2:38 Invalid content of element {urn:book}email: \"jane\" does not satisfy the pattern facet"),
+        ("<card id='a'><name>Jane</name><email>a@b</email><age>151</age></card>",
+         "2:54 Invalid content of element {urn:book}age: \"151\" does not satisfy the maxInclusive facet"),
+        ("<card id='a'><name>Jane</name><email>a@b</email><tags>home car</tags></card>",
+         "2:55 Invalid content of element {urn:book}tags: \"car\" is not one of \"home\", \"work\""),
+        ("<card id='a'>Jane<name/><email>a@b</email></card>",
+         "2:14 Character data is not allowed in the content of element {urn:book}card"),
+        ("<card id='a'><name>Jane</name><email>a@b</email><note><i/></note></card>",
+         "2:55 Element {urn:book}i is not allowed here in the content of element {urn:book}note; \
+          expected {urn:book}b"),
+    ];
+    for &(body, error) in &invalid {
+        assert_eq!(validate(schema, &book(body)), Err(error.into()), "{}", body);
+    }
+
+    assert_eq!(
+        validate(schema, "<book version='2.0' xmlns='urn:book'/>"),
+        Err("1:1 Invalid value of attribute version of element {urn:book}book: \"2.0\" is not one of \"1.0\"".into())
+    );
+    assert_eq!(
+        validate(schema, "<book/>"),
+        Err("1:1 Element book is not allowed as the root element; expected {urn:book}book".into())
+    );
+}
+
+#[test]
+fn xml_syntax() {
+    let schema = Schema::from_reader(ADDRESS_BOOK.as_bytes()).unwrap();
+    check_address_book(&schema);
+}
+
+#[test]
+fn compact_syntax() {
+    let schema = Schema::from_compact_reader(ADDRESS_BOOK_COMPACT.as_bytes()).unwrap();
+    check_address_book(&schema);
+}
+
+#[test]
+fn name_classes() {
+    let schema = Schema::from_compact_reader(r#"
+        namespace x = "urn:x"
+        start = element root { any* }
+        any = element * - (x:* - x:allowed | local) {
+          attribute * - id { text }*,
+          (text | any)*
+        }
+    "#.as_bytes()).unwrap();
+    assert_eq!(validate(&schema, "<root><a b='1'><c xmlns:x='urn:x' x:d='2'/>t</a><allowed xmlns='urn:x'/></root>"), Ok(()));
+    assert_eq!(
+        validate(&schema, "<root><a><b xmlns='urn:x'/></a></root>"),
+        Err("1:10 Element {urn:x}b is not allowed here in the content of element a; expected any name".into())
+    );
+    assert_eq!(
+        validate(&schema, "<root><local/></root>"),
+        Err("1:7 Element local is not allowed here in the content of element root; expected any name".into())
+    );
+    assert_eq!(
+        validate(&schema, "<root><a id='1'/></root>"),
+        Err("1:7 Attribute id is not allowed on element a".into())
+    );
+}
+
+#[test]
+fn datatypes() {
+    let schema = Schema::from_compact_reader(r#"
+        namespace p = "urn:p"
+        element values {
+          element qname { xsd:QName "p:a" }*,
+          element except { xsd:integer - ("0" | xsd:negativeInteger) }*,
+          element string { string " a " }*,
+          element token { token " a  b " }*,
+          element sizes { list { xsd:decimal+, ("px" | "em") } }*,
+          element empty { empty }*
+        }
+    "#.as_bytes()).unwrap();
+    let valid = [
+        "<qname xmlns:q='urn:p'>q:a</qname>",
+        "<except>5</except>",
+        "<string> a </string>",
+        "<token>a b</token>",
+        "<sizes>1 2.5 em</sizes>",
+        "<empty>  </empty><empty/>",
+    ];
+    for body in &valid {
+        assert_eq!(validate(&schema, &format!("<values>{}</values>", body)), Ok(()), "{}", body);
+    }
+    let invalid = [
+        ("<qname xmlns:q='urn:q'>q:a</qname>", "1:32 Invalid content of element qname: \"q:a\" is not one of \"p:a\""),
+        ("<except>-3</except>", "1:17 Invalid content of element except: \"-3\" is not allowed"),
+        ("<except>0</except>", "1:17 Invalid content of element except: \"0\" is not allowed"),
+        ("<string>a</string>", "1:17 Invalid content of element string: \"a\" is not one of \" a \""),
+        ("<sizes>1 2 pt</sizes>", "1:16 Invalid content of element sizes: \"pt\" is not a valid decimal"),
+        ("<empty>x</empty>", "1:16 Character data is not allowed in the content of element empty"),
+    ];
+    for &(body, error) in &invalid {
+        assert_eq!(validate(&schema, &format!("<values>{}</values>", body)), Err(error.into()), "{}", body);
+    }
+}
+
+#[test]
+fn includes() {
+    let resolver = MapResolver::new()
+        .add("common.rng", r#"<grammar xmlns="http://relaxng.org/ns/structure/1.0">
+                                <start><ref name="doc"/></start>
+                                <define name="doc"><element name="doc"><ref name="body"/></element></define>
+                                <define name="body"><text/></define>
+                              </grammar>"#)
+        .add("para.rnc", "para = element para { text }")
+        .add("broken.rng", r#"<grammar xmlns="http://relaxng.org/ns/structure/1.0">
+                                <start><ref name="missing"/></start>
+                              </grammar>"#);
+    let main = r#"<grammar xmlns="http://relaxng.org/ns/structure/1.0">
+                    <include href="common.rng">
+                      <define name="body"><oneOrMore><externalRef href="para.rnc"/></oneOrMore></define>
+                    </include>
+                  </grammar>"#;
+    let e = Schema::from_reader_with_resolver(main.as_bytes(), &resolver).err().unwrap();
+    assert_eq!(e.to_string(), "3:54 Error in schema document para.rnc: 1:1 Grammar has no start pattern");
+
+    let resolver = resolver.add("para.rnc", "start = para\npara = element para { text }");
+    let schema = Schema::from_reader_with_resolver(main.as_bytes(), &resolver).unwrap();
+    assert_eq!(validate(&schema, "<doc><para>a</para><para/></doc>"), Ok(()));
+    assert_eq!(
+        validate(&schema, "<doc>text</doc>"),
+        Err("1:6 Character data is not allowed in the content of element doc".into())
+    );
+
+    let schema = Schema::from_compact_reader_with_resolver(
+        "include \"common.rng\" { body = element b { empty } }".as_bytes(), &resolver
+    ).unwrap();
+    assert_eq!(validate(&schema, "<doc><b/></doc>"), Ok(()));
+
+    let e = Schema::from_reader(main.as_bytes()).err().unwrap();
+    assert_eq!(e.to_string(), "2:21 Cannot resolve schema document: common.rng");
+
+    let e = Schema::from_reader_with_resolver(main.replace("common.rng", "broken.rng").as_bytes(), &resolver).err().unwrap();
+    assert_eq!(e.to_string(), "2:21 Included schema document broken.rng has no define body to override");
+
+    let e = Schema::from_compact_reader_with_resolver("include \"broken.rng\"".as_bytes(), &resolver).err().unwrap();
+    assert_eq!(e.to_string(), "1:1 Error in schema document broken.rng: 2:40 Undefined reference to missing");
+}
+
+#[test]
+fn schema_errors() {
+    let errors = [
+        ("<element xmlns='urn:x'/>",
+         "1:1 Root element of a RELAX NG schema must be in the namespace http://relaxng.org/ns/structure/1.0"),
+        ("<element xmlns='http://relaxng.org/ns/structure/1.0'/>", "1:1 element must have a name"),
+        ("<element name='a' xmlns='http://relaxng.org/ns/structure/1.0'/>", "1:1 element must contain a pattern"),
+        ("<element xmlns='http://relaxng.org/ns/structure/1.0'><name>p:a</name><empty/></element>",
+         "1:54 Undeclared namespace prefix: p"),
+        ("<element name='a' xmlns='http://relaxng.org/ns/structure/1.0'><foo/></element>",
+         "1:63 Unexpected element in pattern: foo"),
+    ];
+    for &(doc, error) in &errors {
+        match Schema::from_reader(doc.as_bytes()) {
+            Ok(_) => panic!("Schema error expected: {}", doc),
+            Err(e) => assert_eq!(e.to_string(), error, "{}", doc)
+        }
+    }
+
+    let errors = [
+        ("a = empty", "1:1 Grammar has no start pattern"),
+        ("start = a", "1:9 Undefined reference to a"),
+        ("start = a\na = empty\na = text", "3:1 Duplicate definition of define a"),
+        ("start |= empty\nstart &= text", "2:1 Conflicting combine methods of start"),
+        ("start = a\na = text | a", "2:1 Circular reference to define a"),
+        ("element a { b }", "1:13 ref is only allowed in a grammar"),
+        ("start = element a { parent b }", "1:21 parentRef is only allowed in a nested grammar"),
+        ("element a { xsd:foo }", "1:13 Unknown datatype: foo"),
+        ("datatypes d = 'urn:dt'\nelement a { d:x }", "2:13 Unsupported datatype library: urn:dt"),
+        ("element a { xsd:date { x = '1' } }", "1:13 Unsupported parameter of datatype date: x"),
+        ("element a { xsd:int 'one' }", "1:13 Invalid value: \"one\" is not a valid int"),
+    ];
+    for &(schema, error) in &errors {
+        match Schema::from_compact_reader(schema.as_bytes()) {
+            Ok(_) => panic!("Schema error expected: {}", schema),
+            Err(e) => assert_eq!(e.to_string(), error, "{}", schema)
+        }
+    }
+}
+
+/// Produces a document with the given number of items without keeping it in memory.
+struct Items {
+    count: usize,
+    buf: Vec<u8>
+}
+
+impl Read for Items {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            self.buf = match self.count {
+                0 => return Ok(0),
+                1 => b"<item>1</item></items>".to_vec(),
+                _ => format!("<item>{}</item><other/>", self.count).into_bytes()
+            };
+            self.count -= 1;
+        }
+        let n = out.len().min(self.buf.len());
+        out[..n].copy_from_slice(&self.buf[..n]);
+        self.buf.drain(..n);
+        Ok(n)
+    }
+}
+
+#[test]
+fn long_documents() {
+    let schema = Schema::from_compact_reader(
+        "element items { (element item { xsd:positiveInteger } & element other { empty }*)+ }".as_bytes()
+    ).unwrap();
+    let items = Items { count: 100000, buf: b"<items>".to_vec() };
+    assert!(schema.validate(EventReader::new(items)).is_ok());
+}
+
+#[test]
+fn ambiguous_patterns() {
+    let cases = [
+        ("element r { element a { empty }*, element a { empty }*, element a { empty }* }", "<a/>"),
+        ("element r { (element a { empty } & element b { empty }?)+ }", "<a/><b/>"),
+        ("element r { (element a { empty } & element b { empty }?)+ }", "<a/>"),
+        ("element r { (element a { empty }?, element a { empty }?)* }", "<a/>"),
+        ("element r { mixed { (element a { text }?, element a { text }?)+ } }", "<a>x</a>y"),
+    ];
+    for &(schema, children) in cases.iter() {
+        let schema = Schema::from_compact_reader(schema.as_bytes()).unwrap();
+        let doc = format!("<r>{}</r>", children.repeat(5000));
+        assert_eq!(validate(&schema, &doc), Ok(()));
+    }
+}
+
+#[test]
+fn patterns_of_long_values() {
+    let schema = Schema::from_compact_reader(
+        "element word { xsd:string { pattern = \"[a-z]*\" } }".as_bytes()
+    ).unwrap();
+    let word = "x".repeat(100000);
+    assert_eq!(validate(&schema, &format!("<word>{}</word>", word)), Ok(()));
+    assert!(validate(&schema, &format!("<word>{}0</word>", word)).is_err());
+}
@@ -0,0 +1,135 @@
+extern crate xml;
+
+use xml::attribute::OwnedAttribute;
+use xml::common::{Position, XmlVersion};
+use xml::dtd::Dtd;
+use xml::name::OwnedName;
+use xml::namespace::Namespace;
+use xml::reader::{EventReader, ParserConfig};
+use xml::sax::{self, ContentHandler, Locator, Flow};
+
+/// Records the callbacks with the positions of their events, and stops parsing after
+/// the element named `stop_at` is opened.
+#[derive(Default)]
+struct Recorder {
+    locator: Option<Locator>,
+    calls: Vec<String>,
+    stop_at: Option<&'static str>
+}
+
+impl Recorder {
+    fn record(&mut self, call: String) -> Flow {
+        let position = self.locator.as_ref().unwrap().position();
+        self.calls.push(format!("{} {}", position, call));
+        Flow::Continue
+    }
+}
+
+impl ContentHandler for Recorder {
+    fn set_document_locator(&mut self, locator: Locator) {
+        self.locator = Some(locator);
+    }
+
+    fn start_document(&mut self, version: XmlVersion, encoding: &str, standalone: Option<bool>) -> Flow {
+        self.record(format!("start_document({}, {}, {:?})", version, encoding, standalone))
+    }
+
+    fn end_document(&mut self) {
+        self.record("end_document".into());
+    }
+
+    fn processing_instruction(&mut self, name: &str, data: Option<&str>) -> Flow {
+        self.record(format!("processing_instruction({}, {:?})", name, data))
+    }
+
+    fn doctype(&mut self, name: &str, public_id: Option<&str>, system_id: Option<&str>, _: &Dtd) -> Flow {
+        self.record(format!("doctype({}, {:?}, {:?})", name, public_id, system_id))
+    }
+
+    fn start_element(&mut self, name: &OwnedName, attributes: &[OwnedAttribute], _: &Namespace) -> Flow {
+        let attributes: Vec<_> = attributes.iter().map(|a| a.to_string()).collect();
+        self.record(format!("start_element({}, [{}])", name, attributes.join(", ")));
+        if self.stop_at == Some(&name.local_name[..]) {
+            return Flow::Stop;
+        }
+        Flow::Continue
+    }
+
+    fn end_element(&mut self, name: &OwnedName) -> Flow {
+        self.record(format!("end_element({})", name))
+    }
+
+    fn characters(&mut self, data: &str) -> Flow {
+        self.record(format!("characters({:?})", data))
+    }
+
+    fn whitespace(&mut self, data: &str) -> Flow {
+        self.record(format!("whitespace({:?})", data))
+    }
+
+    fn comment(&mut self, data: &str) -> Flow {
+        self.record(format!("comment({:?})", data))
+    }
+}
+
+static DOCUMENT: &'static str = "<?xml version='1.0' standalone='yes'?>
+<!DOCTYPE doc SYSTEM 'doc.dtd'>
+<?pi data?>
+<doc a='1'>
+  <!-- note -->
+  <p>text<![CDATA[<raw>]]></p>
+  <stop/>
+  <q/>
+</doc>";
+
+#[test]
+fn callbacks() {
+    let mut handler = Recorder::default();
+    let mut reader = EventReader::new_with_config(DOCUMENT.as_bytes(), ParserConfig::new().ignore_comments(false));
+    assert_eq!(sax::parse(&mut reader, &mut handler).unwrap(), Flow::Continue);
+    assert_eq!(handler.calls, vec![
+        "1:1 start_document(1.0, UTF-8, Some(true))",
+        "2:1 doctype(doc, None, Some(\"doc.dtd\"))",
+        "3:1 processing_instruction(pi, Some(\"data\"))",
+        "4:1 start_element(doc, [a=\"1\"])",
+        "4:12 whitespace(\"\\n  \")",
+        "5:3 comment(\" note \")",
+        "5:16 whitespace(\"\\n  \")",
+        "6:3 start_element(p, [])",
+        "6:6 characters(\"text\")",
+        "6:10 characters(\"<raw>\")",
+        "6:27 end_element(p)",
+        "6:31 whitespace(\"\\n  \")",
+        "7:3 start_element(stop, [])",
+        "7:3 end_element(stop)",
+        "7:10 whitespace(\"\\n  \")",
+        "8:3 start_element(q, [])",
+        "8:3 end_element(q)",
+        "8:7 whitespace(\"\\n\")",
+        "9:1 end_element(doc)",
+        "9:7 end_document",
+    ]);
+}
+
+#[test]
+fn stopping_early() {
+    let mut handler = Recorder { stop_at: Some("stop"), ..Recorder::default() };
+    let mut reader = EventReader::from_str(DOCUMENT);
+    assert_eq!(sax::parse(&mut reader, &mut handler).unwrap(), Flow::Stop);
+    assert_eq!(handler.calls.last().unwrap(), "7:3 start_element(stop, [])");
+
+    // parsing continues after the element
+    handler.calls.clear();
+    assert_eq!(sax::parse(&mut reader, &mut handler).unwrap(), Flow::Continue);
+    assert_eq!(handler.calls[0], "7:3 end_element(stop)");
+    assert_eq!(handler.calls.last().unwrap(), "9:7 end_document");
+}
+
+#[test]
+fn errors() {
+    let mut handler = Recorder::default();
+    let mut reader = EventReader::from_str("<a><b></a>");
+    let e = sax::parse(&mut reader, &mut handler).unwrap_err();
+    assert_eq!(e.to_string(), "1:10 Unexpected closing tag: a, expected b");
+    assert_eq!(handler.calls.len(), 3);
+}
@@ -0,0 +1,380 @@
+extern crate xml;
+
+use xml::EventReader;
+use xml::reader::{ErrorKind, MapResolver};
+use xml::schema::Schema;
+
+static ORDER_SCHEMA: &'static str = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema"
+           xmlns="urn:orders" targetNamespace="urn:orders" elementFormDefault="qualified">
+  <xs:element name="order" type="Order"/>
+  <xs:complexType name="Order">
+    <xs:sequence>
+      <xs:element name="customer" type="xs:string"/>
+      <xs:choice>
+        <xs:element name="pickup" type="Empty"/>
+        <xs:element name="address" type="Address"/>
+      </xs:choice>
+      <xs:element name="item" type="Item" maxOccurs="unbounded"/>
+    </xs:sequence>
+    <xs:attribute name="id" type="OrderId" use="required"/>
+    <xs:attribute name="date" type="xs:date"/>
+  </xs:complexType>
+  <xs:complexType name="Empty"/>
+  <xs:complexType name="Address">
+    <xs:sequence>
+      <xs:element name="street" type="xs:string"/>
+      <xs:element name="city" type="xs:string"/>
+    </xs:sequence>
+  </xs:complexType>
+  <xs:complexType name="UsAddress">
+    <xs:complexContent>
+      <xs:extension base="Address">
+        <xs:sequence>
+          <xs:element name="zip" type="Zip"/>
+        </xs:sequence>
+      </xs:extension>
+    </xs:complexContent>
+  </xs:complexType>
+  <xs:complexType name="Item">
+    <xs:simpleContent>
+      <xs:extension base="xs:string">
+        <xs:attribute name="quantity" default="1">
+          <xs:simpleType>
+            <xs:restriction base="xs:positiveInteger">
+              <xs:maxInclusive value="99"/>
+            </xs:restriction>
+          </xs:simpleType>
+        </xs:attribute>
+        <xs:attribute name="price" type="Price"/>
+      </xs:extension>
+    </xs:simpleContent>
+  </xs:complexType>
+  <xs:simpleType name="OrderId">
+    <xs:restriction base="xs:token">
+      <xs:pattern value="[A-Z]{2}-\d{4}"/>
+    </xs:restriction>
+  </xs:simpleType>
+  <xs:simpleType name="Zip">
+    <xs:restriction base="xs:string">
+      <xs:length value="5"/>
+    </xs:restriction>
+  </xs:simpleType>
+  <xs:simpleType name="Price">
+    <xs:restriction base="xs:decimal">
+      <xs:minExclusive value="0"/>
+      <xs:fractionDigits value="2"/>
+    </xs:restriction>
+  </xs:simpleType>
+</xs:schema>"#;
+
+fn validate(schema: &Schema, doc: &str) -> Result<(), String> {
+    schema.validate(EventReader::from_str(doc)).map_err(|e| {
+        match *e.kind() {
+            ErrorKind::Validity(_) => {}
+            ref kind => panic!("Unexpected error kind: {:?}", kind)
+        }
+        e.to_string()
+    })
+}
+
+fn order(body: &str) -> String {
+    format!("<order xmlns=\"urn:orders\" id=\"AB-1234\">\n{}\n</order>", body)
+}
+
+#[test]
+fn complex_types() {
+    let schema = Schema::from_reader(ORDER_SCHEMA.as_bytes()).unwrap();
+
+    let valid = [
+        "<customer>Jane</customer><pickup/><item>Tea</item>",
+        "<customer>Jane</customer><address><street>Main St.</street><city>Springfield</city></address>\
+         <item quantity='2' price='3.50'>Tea</item><item>Milk</item>",
+        "<customer/>\n<pickup>  </pickup>\n<item/>",
+    ];
+    for body in &valid {
+        assert_eq!(validate(&schema, &order(body)), Ok(()), "{}", body);
+    }
+
+    let invalid = [
+        ("<pickup/><item>Tea</item>",
+         "2:1 Element {urn:orders}pickup is not allowed here in the content of element {urn:orders}order; \
+          expected {urn:orders}customer"),
+        ("<customer>Jane</customer><pickup/><address/><item>Tea</item>",
+         "2:35 Element {urn:orders}address is not allowed here in the content of element {urn:orders}order; \
+          expected {urn:orders}item"),
+        ("<customer>Jane</customer><pickup/>",
+         "3:1 Content of element {urn:orders}order is incomplete; expected {urn:orders}item"),
+        ("<customer>Jane</customer><pickup>x</pickup><item>Tea</item>",
+         "2:34 Character data is not allowed in the content of element {urn:orders}pickup"),
+        ("<customer><b>Jane</b></customer><pickup/><item>Tea</item>",
+         "2:11 Element {urn:orders}b is not allowed here in the content of element {urn:orders}customer; \
+          expected no more elements"),
+        ("<customer>Jane</customer><pickup/><item quantity='100'>Tea</item>",
+         "2:35 Invalid value of attribute quantity of element {urn:orders}item: \
+          \"100\" does not satisfy the maxInclusive facet"),
+        ("<customer>Jane</customer><pickup/><item price='3.505'>Tea</item>",
+         "2:35 Invalid value of attribute price of element {urn:orders}item: \
+          \"3.505\" does not satisfy the fractionDigits facet"),
+        ("<customer>Jane</customer><pickup/><item price='free'>Tea</item>",
+         "2:35 Invalid value of attribute price of element {urn:orders}item: \"free\" is not a valid decimal"),
+        ("<customer>Jane</customer><pickup/><item size='1'>Tea</item>",
+         "2:35 Attribute size is not allowed on element {urn:orders}item"),
+    ];
+    for &(body, error) in &invalid {
+        assert_eq!(validate(&schema, &order(body)), Err(error.into()), "{}", body);
+    }
+
+    assert_eq!(
+        validate(&schema, "<order xmlns='urn:orders' id='A-1'><customer/><pickup/><item/></order>"),
+        Err("1:1 Invalid value of attribute id of element {urn:orders}order: \
+             \"A-1\" does not satisfy the pattern facet".into())
+    );
+    assert_eq!(
+        validate(&schema, "<order xmlns='urn:orders'><customer/><pickup/><item/></order>"),
+        Err("1:1 Required attribute id of element {urn:orders}order is missing".into())
+    );
+    assert_eq!(
+        validate(&schema, "<order id='AB-1234'><customer/><pickup/><item/></order>"),
+        Err("1:1 Element order is not declared in the schema".into())
+    );
+    assert_eq!(
+        validate(&schema, "<order xmlns='urn:orders' id='AB-1234' date='2017-02-30'><customer/><pickup/><item/></order>"),
+        Err("1:1 Invalid value of attribute date of element {urn:orders}order: \
+             \"2017-02-30\" is not a valid date".into())
+    );
+}
+
+#[test]
+fn xsi_type() {
+    let schema = Schema::from_reader(ORDER_SCHEMA.as_bytes()).unwrap();
+    let address = |attributes: &str, body: &str| order(&format!(
+        "<customer/><address xmlns:o='urn:orders' xmlns:xsi='http://www.w3.org/2001/XMLSchema-instance' {}>\
+         <street/><city/>{}</address><item/>", attributes, body
+    ));
+
+    assert_eq!(validate(&schema, &address("xsi:type='o:UsAddress'", "<zip>12345</zip>")), Ok(()));
+    assert_eq!(
+        validate(&schema, &address("xsi:type='o:UsAddress'", "")),
+        Err("2:135 Content of element {urn:orders}address is incomplete; expected {urn:orders}zip".into())
+    );
+    assert_eq!(
+        validate(&schema, &address("xsi:type='o:UsAddress'", "<zip>1234</zip>")),
+        Err("2:135 Invalid content of element {urn:orders}zip: \"1234\" does not satisfy the length facet".into())
+    );
+    assert_eq!(
+        validate(&schema, &address("", "<zip>12345</zip>")),
+        Err("2:113 Element {urn:orders}zip is not allowed here in the content of element {urn:orders}address; \
+             expected no more elements".into())
+    );
+    assert_eq!(
+        validate(&schema, &address("xsi:type='o:Item'", "")),
+        Err("2:12 Type {urn:orders}Item given in xsi:type is not derived from {urn:orders}Address".into())
+    );
+    assert_eq!(
+        validate(&schema, &address("xsi:type='o:Unknown'", "")),
+        Err("2:12 Type {urn:orders}Unknown given in xsi:type is not defined".into())
+    );
+}
+
+#[test]
+fn simple_types() {
+    let schema = Schema::from_reader(r#"
+<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+  <xs:element name="values">
+    <xs:complexType>
+      <xs:choice minOccurs="0" maxOccurs="unbounded">
+        <xs:element name="size" type="Size"/>
+        <xs:element name="sizes" type="Sizes"/>
+        <xs:element name="dimension" type="Dimension"/>
+        <xs:element name="code" type="Code"/>
+        <xs:element name="flag" type="xs:boolean" fixed="true"/>
+      </xs:choice>
+    </xs:complexType>
+  </xs:element>
+  <xs:simpleType name="Size">
+    <xs:restriction base="xs:token">
+      <xs:enumeration value="small"/>
+      <xs:enumeration value="large"/>
+    </xs:restriction>
+  </xs:simpleType>
+  <xs:simpleType name="Sizes">
+    <xs:restriction>
+      <xs:simpleType>
+        <xs:list itemType="Size"/>
+      </xs:simpleType>
+      <xs:maxLength value="2"/>
+    </xs:restriction>
+  </xs:simpleType>
+  <xs:simpleType name="Dimension">
+    <xs:union memberTypes="Size xs:nonNegativeInteger"/>
+  </xs:simpleType>
+  <xs:simpleType name="Code">
+    <xs:restriction base="xs:string">
+      <xs:pattern value="\p{Lu}+"/>
+      <xs:pattern value="\d+"/>
+    </xs:restriction>
+  </xs:simpleType>
+</xs:schema>"#.as_bytes()).unwrap();
+
+    assert_eq!(validate(&schema, "<values><size> small </size><sizes>small\n large</sizes>\
+                                  <dimension>large</dimension><dimension>42</dimension>\
+                                  <code>ABC</code><code>123</code><flag>1</flag><flag/></values>"), Ok(()));
+
+    let invalid = [
+        ("<size>medium</size>", "1:9 Invalid content of element size: \"medium\" does not satisfy the enumeration facet"),
+        ("<sizes>small large small</sizes>", "1:9 Invalid content of element sizes: \
+                                             \"small large small\" does not satisfy the maxLength facet"),
+        ("<sizes>small huge</sizes>", "1:9 Invalid content of element sizes: \
+                                      \"huge\" does not satisfy the enumeration facet"),
+        ("<dimension>-1</dimension>", "1:9 Invalid content of element dimension: \
+                                      \"-1\" does not match any member type of the union"),
+        ("<code>Abc</code>", "1:9 Invalid content of element code: \"Abc\" does not satisfy the pattern facet"),
+        ("<flag>false</flag>", "1:9 Element flag must have the fixed value \"true\""),
+    ];
+    for &(body, error) in &invalid {
+        assert_eq!(validate(&schema, &format!("<values>{}</values>", body)), Err(error.into()), "{}", body);
+    }
+}
+
+#[test]
+fn patterns_of_long_values() {
+    let schema = Schema::from_reader(r#"
+<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+  <xs:element name="word">
+    <xs:simpleType>
+      <xs:restriction base="xs:string">
+        <xs:pattern value="[a-z]*"/>
+      </xs:restriction>
+    </xs:simpleType>
+  </xs:element>
+  <xs:element name="as">
+    <xs:simpleType>
+      <xs:restriction base="xs:string">
+        <xs:pattern value="(a*)*b"/>
+      </xs:restriction>
+    </xs:simpleType>
+  </xs:element>
+</xs:schema>"#.as_bytes()).unwrap();
+
+    let word = "x".repeat(100000);
+    assert_eq!(validate(&schema, &format!("<word>{}</word>", word)), Ok(()));
+    assert!(validate(&schema, &format!("<word>{}0</word>", word)).is_err());
+    assert_eq!(validate(&schema, &format!("<as>{}</as>", "a".repeat(30))),
+               Err(format!("1:1 Invalid content of element as: \"{}\" does not satisfy the pattern facet", "a".repeat(30))));
+}
+
+#[test]
+fn wildcards_and_substitution_groups() {
+    let schema = Schema::from_reader(r###"
+<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema" xmlns:s="urn:shapes" targetNamespace="urn:shapes">
+  <xs:element name="drawing">
+    <xs:complexType>
+      <xs:sequence>
+        <xs:element ref="s:shape" maxOccurs="unbounded"/>
+        <xs:any namespace="##other" processContents="lax" minOccurs="0"/>
+      </xs:sequence>
+      <xs:anyAttribute namespace="urn:meta" processContents="skip"/>
+    </xs:complexType>
+  </xs:element>
+  <xs:element name="shape" abstract="true" type="s:Shape"/>
+  <xs:element name="circle" substitutionGroup="s:shape"/>
+  <xs:element name="square" substitutionGroup="s:shape">
+    <xs:complexType>
+      <xs:complexContent>
+        <xs:extension base="s:Shape">
+          <xs:attribute name="size" type="xs:double" use="required"/>
+        </xs:extension>
+      </xs:complexContent>
+    </xs:complexType>
+  </xs:element>
+  <xs:complexType name="Shape">
+    <xs:attribute name="color" type="xs:NCName"/>
+  </xs:complexType>
+</xs:schema>"###.as_bytes()).unwrap();
+
+    let drawing = |body: &str| format!(
+        "<s:drawing xmlns:s='urn:shapes' xmlns:m='urn:meta' m:author='J'>{}</s:drawing>", body
+    );
+    assert_eq!(validate(&schema, &drawing("<s:circle color='red'/><s:square size='2.5'/>\
+                                           <note xmlns='urn:notes'><any-content/></note>")), Ok(()));
+    assert_eq!(
+        validate(&schema, &drawing("<s:shape/>")),
+        Err("1:65 Element {urn:shapes}shape is abstract".into())
+    );
+    assert_eq!(
+        validate(&schema, &drawing("<s:square/>")),
+        Err("1:65 Required attribute size of element {urn:shapes}square is missing".into())
+    );
+    assert_eq!(
+        validate(&schema, &drawing("<s:circle/><s:circle/><s:drawing/>")),
+        Err("1:87 Element {urn:shapes}drawing is not allowed here in the content of element {urn:shapes}drawing; \
+             expected {urn:shapes}shape, any element".into())
+    );
+    assert_eq!(
+        validate(&schema, &drawing("<s:circle/><note/>")),
+        Err("1:76 Element note is not allowed here in the content of element {urn:shapes}drawing; \
+             expected {urn:shapes}shape, any element".into())
+    );
+}
+
+#[test]
+fn includes() {
+    let resolver = MapResolver::new()
+        .add("types.xsd", r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+                               <xs:simpleType name="Percent">
+                                 <xs:restriction base="xs:integer">
+                                   <xs:minInclusive value="0"/>
+                                   <xs:maxInclusive value="100"/>
+                                 </xs:restriction>
+                               </xs:simpleType>
+                             </xs:schema>"#)
+        .add("broken.xsd", r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+                                <xs:element name="a" type="Missing"/>
+                              </xs:schema>"#);
+    let main = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema" xmlns:t="urn:t" targetNamespace="urn:t">
+                    <xs:include schemaLocation="types.xsd"/>
+                    <xs:element name="progress" type="t:Percent"/>
+                  </xs:schema>"#;
+    let schema = Schema::from_reader_with_resolver(main.as_bytes(), &resolver).unwrap();
+    assert_eq!(validate(&schema, "<progress xmlns='urn:t'>50</progress>"), Ok(()));
+    assert_eq!(
+        validate(&schema, "<progress xmlns='urn:t'>150</progress>"),
+        Err("1:1 Invalid content of element {urn:t}progress: \"150\" does not satisfy the maxInclusive facet".into())
+    );
+
+    let e = Schema::from_reader(main.as_bytes()).err().unwrap();
+    assert_eq!(e.to_string(), "2:21 Cannot resolve schema document: types.xsd");
+
+    let e = Schema::from_reader_with_resolver(main.replace("types.xsd", "broken.xsd").as_bytes(), &resolver).err().unwrap();
+    assert_eq!(e.to_string(), "2:21 Error in schema document broken.xsd: 2:33 Undefined type: {urn:t}Missing");
+}
+
+#[test]
+fn schema_errors() {
+    let errors = [
+        ("<schema/>", "1:1 Root element of a schema document must be xs:schema"),
+        ("<xs:element/>", "1:56 Attribute name is required on xs:element"),
+        ("<xs:element name='a' type='b'/>", "1:56 Undefined type: b"),
+        ("<xs:element name='a' type='p:b'/>", "1:56 Undeclared namespace prefix: p"),
+        ("<xs:element name='a'/><xs:element name='a'/>", "1:78 Duplicate definition of element a"),
+        ("<xs:simpleType name='a'><xs:restriction base='xs:string'><xs:pattern value='[a'/></xs:restriction></xs:simpleType>",
+         "1:113 Unclosed character class in pattern: [a"),
+        ("<xs:complexType name='a'><xs:sequence><xs:group ref='g'/></xs:sequence></xs:complexType>\
+          <xs:group name='g'><xs:sequence><xs:group ref='g'/></xs:sequence></xs:group>",
+         "1:144 Circular definition of model group g"),
+        ("<xs:simpleType name='a'><xs:restriction base='b'/></xs:simpleType>\
+          <xs:simpleType name='b'><xs:restriction base='a'/></xs:simpleType>",
+         "1:56 Circular definition of type a"),
+        ("<xs:group name='g'><xs:sequence><xs:element name='a' minOccurs='2' maxOccurs='1'/></xs:sequence></xs:group>",
+         "1:88 maxOccurs must not be less than minOccurs"),
+        ("<xs:redefine schemaLocation='a.xsd'/>", "1:56 xs:redefine is not supported"),
+    ];
+    for &(body, error) in &errors {
+        let doc = format!("<xs:schema xmlns:xs='http://www.w3.org/2001/XMLSchema'>{}</xs:schema>", body);
+        let doc = if body == "<schema/>" { body.to_owned() } else { doc };
+        match Schema::from_reader(doc.as_bytes()) {
+            Ok(_) => panic!("Schema error expected: {}", body),
+            Err(e) => assert_eq!(e.to_string(), error, "{}", body)
+        }
+    }
+}
@@ -0,0 +1,313 @@
+extern crate xml;
+
+use std::borrow::Cow;
+
+use xml::common::Position;
+use xml::reader::{ParserConfig, EventReader, XmlEvent, SliceReader, BorrowedEvent};
+
+macro_rules! assert_borrowed {
+    ($data:expr) => {
+        match $data {
+            Cow::Borrowed(_) => {}
+            Cow::Owned(ref data) => panic!("{:?} is not borrowed", data)
+        }
+    }
+}
+
+macro_rules! assert_owned {
+    ($data:expr) => {
+        match $data {
+            Cow::Owned(_) => {}
+            Cow::Borrowed(data) => panic!("{:?} is not owned", data)
+        }
+    }
+}
+
+macro_rules! assert_borrowed_from {
+    ($input:expr, $data:expr) => {
+        match *$data {
+            Cow::Borrowed(data) => {
+                let start = $input.as_ptr() as usize;
+                let offset = data.as_ptr() as usize;
+                assert!(offset >= start && offset + data.len() <= start + $input.len(),
+                        "{:?} is not borrowed from the input", data);
+            }
+            Cow::Owned(ref data) => panic!("{:?} is not borrowed", data)
+        }
+    }
+}
+
+/// Checks that `SliceReader` produces the same events at the same positions as `EventReader`.
+fn compare(input: &[u8], config: ParserConfig) {
+    let mut expected = EventReader::new_with_config(input, config.clone());
+    let mut actual = SliceReader::new_with_config(input, config);
+    loop {
+        let e = expected.next();
+        let a = actual.next();
+        match (&e, &a) {
+            (&Ok(ref e), &Ok(ref a)) => {
+                assert_eq!(BorrowedEvent::from(e.clone()), *a);
+                assert_eq!(expected.position(), actual.position(), "position of {:?}", a);
+            }
+            (&Err(ref e), &Err(ref a)) => {
+                assert_eq!(e.msg(), a.msg());
+                assert_eq!(e.position(), a.position(), "position of {:?}", a);
+            }
+            _ => panic!("Expected {:?}, found {:?}", e, a)
+        }
+        match e {
+            Ok(XmlEvent::EndDocument) | Err(_) => break,
+            _ => {}
+        }
+    }
+}
+
+fn short_config() -> ParserConfig {
+    ParserConfig::new()
+        .ignore_comments(true)
+        .whitespace_to_characters(true)
+        .cdata_to_characters(true)
+        .trim_whitespace(true)
+        .coalesce_characters(true)
+}
+
+fn full_config() -> ParserConfig {
+    ParserConfig::new()
+        .ignore_comments(false)
+        .whitespace_to_characters(false)
+        .cdata_to_characters(false)
+        .trim_whitespace(false)
+        .coalesce_characters(false)
+}
+
+#[test]
+fn samples() {
+    let samples: [&[u8]; 5] = [
+        include_bytes!("documents/sample_1.xml"),
+        include_bytes!("documents/sample_2.xml"),
+        include_bytes!("documents/sample_3.xml"),
+        include_bytes!("documents/sample_4.xml"),
+        include_bytes!("documents/sample_5.xml"),
+    ];
+    for sample in samples.iter() {
+        compare(sample, short_config());
+        compare(sample, full_config());
+        compare(sample, ParserConfig::new());
+    }
+}
+
+#[test]
+fn same_events_as_event_reader() {
+    let documents = [
+        "<a/>",
+        "  <a x='1' y=\"2\" />\n",
+        "<?xml version='1.1' standalone='no'?><a>\r\nx\u{85}y\u{2028}z\r</a>",
+        "<a xmlns='urn:a' xmlns:b='urn:b'><b:c b:d='e'>text</b:c><c xmlns=''/></a>",
+        "<a>  <b/> text &amp; more &#x41; </b></a>",
+        "<a>x<!-- c -->y<![CDATA[ z ]]>w<?pi  data ?></a>",
+        "<a>  <![CDATA[ x ]]>  <!-- c -->  </a>",
+        "<a>\n  <b>\n    x <![CDATA[y]]> z\n  </b>\n  <!-- c\r\n -->\n</a>\n",
+        "<a b=' x\ty\r\n &#9; &lt; '/>",
+        "<a>\u{444}\u{1F600} <b>\u{444}</b></a>",
+        "\u{feff}<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<a>\n  <b/>\n</a>\n<c/>",
+        "<!-- c -->\n<?pi x?><a/>",
+        "<!-- c --><?xml version='1.1' encoding='utf-8'?>\r\n<a>\u{85}</a>",
+        "<?XML x?><a/>",
+    ];
+    for doc in documents.iter() {
+        compare(doc.as_bytes(), short_config());
+        compare(doc.as_bytes(), full_config());
+        compare(doc.as_bytes(), ParserConfig::new());
+        compare(doc.as_bytes(), ParserConfig::new().normalize_attribute_values(false));
+        compare(doc.as_bytes(), ParserConfig::new().cdata_to_characters(true).coalesce_characters(false).ignore_comments(false));
+        compare(doc.as_bytes(), ParserConfig::new().ignore_comments(false).whitespace_to_characters(true));
+    }
+}
+
+#[test]
+fn same_errors_as_event_reader() {
+    let documents = [
+        "",
+        "   ",
+        "<a>",
+        "<a></b>",
+        "<a>]]></a>",
+        "<a/>x",
+        "<a><b></a>",
+        "<a x='1' x='2'/>",
+        "<a x='<'/>",
+        "<a x='<b'/>",
+        "<a><=</a>",
+        "<a><!x</a>",
+        "< a/>",
+        "<1/>",
+        "<:a/>",
+        "<a:b:c/>",
+        "<p:a/>",
+        "<a p:b='c'/>",
+        "<a xmlns:xmlns='urn:x'/>",
+        "<a xmlns:p=''/>",
+        "<xml:a/>",
+        "<a>&unknown;</a>",
+        "<a>&;</a>",
+        "<a>&#xD800;</a>",
+        "<a><!-- x -- y --></a>",
+        "<a><?xml version='1.0'?></a>",
+        "<a><?pi\u{85}?></a>",
+        "<?xml version='2.0'?><a/>",
+        "<?xml version='1.0' standalone='maybe'?><a/>",
+        "<a:/>",
+        "<a <b/>",
+        "<a/></a>",
+        "x<a/>",
+        "<!-- c -->x<a/>",
+        "<?pi?><1/>",
+        "<!-- c --><?xml version='1.0'?><?xml version='1.0'?><a/>",
+        "<?pi?><?xml version='2.0'?><a/>",
+        "<?xml?><a/>",
+        "<?XML?><a/>",
+        "<a/><?xml version='1.0'?>",
+        "<a/><?Xml x?>",
+        "<!-- c -->",
+        "<?pi x?>\n<=a/>",
+    ];
+    for doc in documents.iter() {
+        compare(doc.as_bytes(), ParserConfig::new());
+    }
+}
+
+#[test]
+fn extra_entities() {
+    let config = ParserConfig::new().add_entity("nbsp", " ").add_entity("greeting", "hello");
+    compare(b"<a x='&greeting;'>say &greeting;&nbsp;world</a>", config);
+}
+
+#[test]
+fn data_is_borrowed() {
+    let config = ParserConfig::new().ignore_comments(false);
+    let mut reader = SliceReader::new_with_config(b"<a:b xmlns:a='urn:a' c='d'>text<!--comment--></a:b>", config);
+    assert_eq!(reader.next().unwrap(), BorrowedEvent::StartDocument {
+        version: xml::common::XmlVersion::Version10,
+        encoding: "UTF-8".into(),
+        standalone: None
+    });
+    match reader.next().unwrap() {
+        BorrowedEvent::StartElement { name, attributes } => {
+            assert_borrowed!(name.local_name);
+            assert_borrowed!(*name.namespace.as_ref().unwrap());
+            assert_borrowed!(attributes[0].value);
+        }
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    match reader.next().unwrap() {
+        BorrowedEvent::Characters(ref data) => assert_borrowed!(*data),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    match reader.next().unwrap() {
+        BorrowedEvent::Comment(ref data) => assert_borrowed!(*data),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    // the namespace is declared by an enclosing tag
+    match reader.next().unwrap() {
+        BorrowedEvent::EndElement { name } => {
+            assert_borrowed!(name.local_name);
+            assert_borrowed!(*name.namespace.as_ref().unwrap());
+        }
+        e => panic!("Unexpected event: {:?}", e)
+    }
+}
+
+#[test]
+fn plain_documents_are_not_copied() {
+    let mut input = String::from("<?xml version='1.0' encoding='utf-8'?>\n<!-- items -->\n");
+    input.push_str("<list xmlns='urn:list' xmlns:x='urn:x'>\n");
+    for i in 0..1000 {
+        input.push_str(&format!("  <x:item id='{0}' title=\"item {0}\">text {0}</x:item>\n", i));
+        input.push_str(&format!("  <?pi data {0}?><!-- comment {0} --><![CDATA[<{0}>]]>\n", i));
+    }
+    input.push_str("</list>\n");
+
+    let config = ParserConfig::new().ignore_comments(false);
+    let mut reader = SliceReader::new_with_config(input.as_bytes(), config);
+    let mut events = 0;
+    loop {
+        match reader.next().unwrap() {
+            BorrowedEvent::StartDocument { ref encoding, .. } => assert_borrowed_from!(input, encoding),
+            BorrowedEvent::EndDocument => break,
+            BorrowedEvent::ProcessingInstruction { ref name, ref data } => {
+                assert_borrowed_from!(input, name);
+                assert_borrowed_from!(input, data.as_ref().unwrap());
+            }
+            BorrowedEvent::StartElement { ref name, ref attributes } => {
+                assert_borrowed_from!(input, &name.local_name);
+                assert_borrowed_from!(input, name.namespace.as_ref().unwrap());
+                for attribute in attributes {
+                    assert_borrowed_from!(input, &attribute.name.local_name);
+                    assert_borrowed_from!(input, &attribute.value);
+                }
+            }
+            BorrowedEvent::EndElement { ref name } => {
+                assert_borrowed_from!(input, &name.local_name);
+                assert_borrowed_from!(input, name.namespace.as_ref().unwrap());
+            }
+            BorrowedEvent::CData(ref data) | BorrowedEvent::Comment(ref data) |
+            BorrowedEvent::Characters(ref data) | BorrowedEvent::Whitespace(ref data) =>
+                assert_borrowed_from!(input, data),
+            e => panic!("Unexpected event: {:?}", e)
+        }
+        events += 1;
+    }
+    // the declaration, the comment and the root tags, and eight events for each item
+    assert_eq!(events, 3 + 1000 * 8 + 2);
+}
+
+#[test]
+fn data_is_copied_when_replaced() {
+    let mut reader = SliceReader::from_str("<a b='c\td'>x &lt; y\r\nz</a>");
+    reader.next().unwrap();
+    match reader.next().unwrap() {
+        BorrowedEvent::StartElement { attributes, .. } => {
+            assert_eq!(attributes[0].value, "c d");
+            assert_owned!(attributes[0].value);
+        }
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    match reader.next().unwrap() {
+        BorrowedEvent::Characters(ref data) => {
+            assert_eq!(data, "x < y\nz");
+            assert_owned!(*data);
+        }
+        e => panic!("Unexpected event: {:?}", e)
+    }
+}
+
+#[test]
+fn documents_with_copied_data() {
+    let latin1 = b"<?xml version='1.0' encoding='ISO-8859-1'?><a>caf\xE9</a>";
+    let mut reader = SliceReader::new(latin1);
+    reader.next().unwrap();
+    reader.next().unwrap();
+    assert_eq!(reader.next().unwrap(), BorrowedEvent::Characters("caf\u{e9}".into()));
+    compare(latin1, ParserConfig::new());
+
+    let doctype = "<!DOCTYPE a [<!ENTITY e 'value'>]><a>&e;</a>";
+    let mut reader = SliceReader::from_str(doctype);
+    reader.next().unwrap();
+    match reader.next().unwrap() {
+        BorrowedEvent::Doctype { name, .. } => assert_eq!(name, "a"),
+        e => panic!("Unexpected event: {:?}", e)
+    }
+    reader.next().unwrap();
+    assert_eq!(reader.next().unwrap(), BorrowedEvent::Characters("value".into()));
+}
+
+#[test]
+fn iterator_stops_after_end() {
+    let events: Vec<_> = SliceReader::from_str("<a>x</a>").into_iter().collect();
+    assert_eq!(events.len(), 5);
+    assert!(events.iter().all(|e| e.is_ok()));
+
+    let events: Vec<_> = SliceReader::from_str("<a>").into_iter().collect();
+    assert_eq!(events.len(), 3);
+    assert!(events[2].is_err());
+}
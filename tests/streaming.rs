@@ -87,7 +87,9 @@ fn reading_streamed_content2() {
     assert_match!(reader.next(), Some(Err(_)));
     write_and_reset_position(reader.source_mut(), b"<child-3></child-3>");
     assert_match!(reader.next(), Some(Ok(XmlEvent::StartElement { ref name, .. })) if name.local_name == "child-3");
+    // the rest of the written data is already buffered by the reader
     write_and_reset_position(reader.source_mut(), b"<child-4 type='get'");
+    assert_match!(reader.next(), Some(Ok(XmlEvent::EndElement { ref name })) if name.local_name == "child-3");
     match reader.next() {
        None |
        Some(Ok(_)) => {